@@ -0,0 +1,191 @@
+//! Parsing for `server --addr` and the client `--endpoint`. Both accept IPv4,
+//! IPv6 and hostnames; the port defaults to 8787 when left out.
+
+use std::net::{IpAddr, SocketAddr};
+
+use anyhow::{Context, Result, bail};
+use reqwest::Url;
+
+pub const DEFAULT_PORT: u16 = 8787;
+
+/// Resolves every `--addr` value to the socket addresses to listen on. A
+/// hostname may resolve to several (e.g. `localhost` to `127.0.0.1` and
+/// `::1`); duplicates are dropped.
+pub async fn resolve_bind_addrs(values: &[String]) -> Result<Vec<SocketAddr>> {
+    let mut addrs = Vec::new();
+    for value in values {
+        for addr in resolve_bind_addr(value).await? {
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+    }
+    if addrs.is_empty() {
+        bail!("no listen address given");
+    }
+    Ok(addrs)
+}
+
+async fn resolve_bind_addr(value: &str) -> Result<Vec<SocketAddr>> {
+    let (host, port) = split_host_port(value)?;
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(vec![SocketAddr::new(ip, port)]);
+    }
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .with_context(|| format!("failed to resolve --addr '{value}'"))?
+        .collect();
+    if addrs.is_empty() {
+        bail!("--addr '{value}' did not resolve to any address");
+    }
+    Ok(addrs)
+}
+
+/// Splits `host:port`, `[ipv6]:port`, `[ipv6]`, a bare IP or a bare host.
+fn split_host_port(value: &str) -> Result<(&str, u16)> {
+    let invalid = || {
+        anyhow::anyhow!(
+            "invalid --addr '{value}': expected host:port, [ipv6]:port or an IP address"
+        )
+    };
+    let trimmed = value.trim();
+    if trimmed.is_empty() {
+        return Err(invalid());
+    }
+    if let Some(rest) = trimmed.strip_prefix('[') {
+        let (host, tail) = rest.split_once(']').ok_or_else(invalid)?;
+        if host.parse::<std::net::Ipv6Addr>().is_err() {
+            return Err(invalid());
+        }
+        let port = match tail {
+            "" => DEFAULT_PORT,
+            tail => parse_port(tail.strip_prefix(':').ok_or_else(invalid)?).ok_or_else(invalid)?,
+        };
+        return Ok((host, port));
+    }
+    // An unbracketed IPv6 address can't carry a port, so it's taken as is.
+    if trimmed.parse::<IpAddr>().is_ok() {
+        return Ok((trimmed, DEFAULT_PORT));
+    }
+    match trimmed.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && !host.contains(':') => {
+            Ok((host, parse_port(port).ok_or_else(invalid)?))
+        }
+        Some(_) => Err(invalid()),
+        None => Ok((trimmed, DEFAULT_PORT)),
+    }
+}
+
+fn parse_port(port: &str) -> Option<u16> {
+    port.parse().ok()
+}
+
+/// Normalizes `--endpoint` into a base URL. `http://` is assumed when no
+/// scheme is given; hostnames are resolved by the HTTP client per request.
+pub fn endpoint_url(value: &str) -> Result<String> {
+    let value = value.trim();
+    let with_scheme = if value.contains("://") {
+        value.to_string()
+    } else {
+        format!("http://{value}")
+    };
+    let url = Url::parse(&with_scheme).map_err(|err| {
+        let hint = if value.matches(':').count() > 1 && !value.contains('[') {
+            " (put IPv6 addresses in brackets, e.g. http://[::1]:8787)"
+        } else {
+            ""
+        };
+        anyhow::anyhow!("invalid --endpoint '{value}': {err}{hint}")
+    })?;
+    if !matches!(url.scheme(), "http" | "https") {
+        bail!(
+            "invalid --endpoint '{value}': scheme must be http or https, not {}",
+            url.scheme()
+        );
+    }
+    if matches!(url.host_str(), None | Some("")) {
+        bail!("invalid --endpoint '{value}': missing host");
+    }
+    Ok(url.as_str().trim_end_matches('/').to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::{endpoint_url, resolve_bind_addrs};
+
+    async fn resolve(values: &[&str]) -> anyhow::Result<Vec<SocketAddr>> {
+        let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        resolve_bind_addrs(&values).await
+    }
+
+    #[tokio::test]
+    async fn bind_addresses_accept_ipv4_ipv6_and_bare_ips() {
+        let addrs = resolve(&["127.0.0.1:9000", "[::1]:9001", "0.0.0.0", "::", "[::1]"])
+            .await
+            .unwrap();
+        let expected: Vec<SocketAddr> = [
+            "127.0.0.1:9000",
+            "[::1]:9001",
+            "0.0.0.0:8787",
+            "[::]:8787",
+            "[::1]:8787",
+        ]
+        .iter()
+        .map(|s| s.parse().unwrap())
+        .collect();
+        assert_eq!(addrs, expected);
+
+        let addrs = resolve(&["127.0.0.1:9000", "127.0.0.1:9000"])
+            .await
+            .unwrap();
+        assert_eq!(addrs.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn bad_bind_addresses_are_rejected() {
+        for bad in [
+            "",
+            "127.0.0.1:http",
+            "[::1",
+            "[nope]:80",
+            "::1:80:",
+            "host:99999",
+        ] {
+            let err = resolve(&[bad]).await.unwrap_err().to_string();
+            assert!(err.contains("invalid --addr"), "{bad}: {err}");
+        }
+    }
+
+    #[tokio::test]
+    async fn localhost_resolves_to_loopback() {
+        let addrs = resolve(&["localhost:9000"]).await.unwrap();
+        assert!(
+            addrs
+                .iter()
+                .all(|a| a.ip().is_loopback() && a.port() == 9000)
+        );
+    }
+
+    #[test]
+    fn endpoints_are_normalized() {
+        assert_eq!(
+            endpoint_url("http://127.0.0.1:8787/").unwrap(),
+            "http://127.0.0.1:8787"
+        );
+        assert_eq!(endpoint_url("[::1]:8787").unwrap(), "http://[::1]:8787");
+        assert_eq!(
+            endpoint_url("buds.local:8787").unwrap(),
+            "http://buds.local:8787"
+        );
+        assert_eq!(
+            endpoint_url("https://pi/earctl").unwrap(),
+            "https://pi/earctl"
+        );
+
+        assert!(endpoint_url("ftp://pi").is_err());
+        let err = endpoint_url("::1:8787").unwrap_err().to_string();
+        assert!(err.contains("brackets"), "{err}");
+    }
+}
@@ -1,8 +1,15 @@
+use std::{fmt, future::Future, time::Duration};
+
+use schemars::JsonSchema;
+use serde::Serialize;
 use tokio::process::Command;
 
 use crate::error::EarError;
 
 const NOTHING_SPP_UUID: &str = "aeac4a03-dff5-498f-843a-34487cf133eb";
+/// Wait before the first retry of a `bluetoothctl`/`sdptool` call; doubled
+/// after each further failure.
+const SHELL_RETRY_DELAY: Duration = Duration::from_millis(250);
 
 #[derive(Debug, Clone)]
 pub struct BluetoothDevice {
@@ -10,11 +17,42 @@ pub struct BluetoothDevice {
     pub name: String,
 }
 
+/// Normalizes a Bluetooth address to the uppercase, colon-separated form
+/// (`00:11:22:33:44:55`). Accepts `:` or `-` as separators.
+pub fn normalize_address(input: &str) -> Result<String, EarError> {
+    let trimmed = input.trim();
+    let octets: Vec<&str> = trimmed.split([':', '-']).collect();
+    let valid = octets.len() == 6
+        && octets
+            .iter()
+            .all(|octet| octet.len() == 2 && octet.chars().all(|c| c.is_ascii_hexdigit()));
+    if !valid {
+        return Err(EarError::Detection(format!(
+            "invalid Bluetooth address '{}'; expected six hex octets like 00:11:22:33:44:55",
+            trimmed
+        )));
+    }
+    Ok(octets.join(":").to_uppercase())
+}
+
+pub fn parse_address(input: &str) -> Result<bluer::Address, EarError> {
+    let normalized = normalize_address(input)?;
+    normalized
+        .parse()
+        .map_err(|_| EarError::Detection(format!("invalid Bluetooth address '{}'", input.trim())))
+}
+
+/// Finds the connected device to talk to, optionally only among those
+/// connected through the `adapter` named like `hci1`. `retries` applies to
+/// the `bluetoothctl` fallback.
 pub async fn resolve_connected_device(
     preferred_address: Option<String>,
     preferred_name: Option<String>,
+    adapter: Option<&str>,
+    retries: u8,
 ) -> Result<BluetoothDevice, EarError> {
     if let Some(address) = preferred_address {
+        let address = normalize_address(&address)?;
         verify_device_connected(&address).await?;
         let name = device_name(&address)
             .await
@@ -22,7 +60,18 @@ pub async fn resolve_connected_device(
         return Ok(BluetoothDevice { address, name });
     }
 
-    let connected = list_connected_devices().await?;
+    let connected = match adapter {
+        Some(adapter) => scan_devices()
+            .await?
+            .into_iter()
+            .filter(|device| device.connected && device.adapter == adapter)
+            .map(|device| BluetoothDevice {
+                address: device.address,
+                name: device.name.unwrap_or_default(),
+            })
+            .collect(),
+        None => list_connected_devices(retries).await?,
+    };
     if connected.is_empty() {
         return Err(EarError::Detection(
             "no connected Bluetooth devices were found; please connect your earbuds first"
@@ -47,26 +96,403 @@ pub async fn resolve_connected_device(
     Ok(first)
 }
 
-pub async fn list_connected_devices() -> Result<Vec<BluetoothDevice>, EarError> {
-    let output = run_command("bluetoothctl", &["devices", "Connected"]).await?;
-    let devices = output
-        .lines()
-        .filter_map(|line| {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() < 3 {
-                return None;
+/// A device known to BlueZ, as reported over D-Bus.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ScannedDevice {
+    pub adapter: String,
+    pub address: String,
+    pub name: Option<String>,
+    pub connected: bool,
+    pub paired: bool,
+    /// Properties BlueZ failed to report for this device; the fields they
+    /// feed are left at their defaults.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Enumerates paired or connected devices on every adapter through the BlueZ
+/// D-Bus API, without relying on `bluetoothctl`. Only failing to reach BlueZ
+/// fails the scan; an adapter that can't be listed is skipped and a device
+/// whose properties can't be read is kept with its `error`.
+pub async fn scan_devices() -> Result<Vec<ScannedDevice>, EarError> {
+    let session = bluer::Session::new().await.map_err(bluez_error)?;
+    let mut devices = Vec::new();
+    for adapter_name in session.adapter_names().await.map_err(bluez_error)? {
+        let addresses = match session.adapter(&adapter_name) {
+            Ok(adapter) => adapter
+                .device_addresses()
+                .await
+                .map(|addresses| (adapter, addresses)),
+            Err(err) => Err(err),
+        };
+        let (adapter, addresses) = match addresses {
+            Ok(found) => found,
+            Err(err) => {
+                tracing::warn!("skipping adapter {}: {}", adapter_name, err);
+                continue;
             }
-            let address = parts[1].to_string();
-            let name = parts[2..].join(" ");
-            Some(BluetoothDevice { address, name })
-        })
-        .collect();
+        };
+        for address in addresses {
+            if let Some(device) = scan_device(&adapter, &adapter_name, address).await {
+                devices.push(device);
+            }
+        }
+    }
     Ok(devices)
 }
 
+/// `None` for devices that are neither connected nor paired.
+async fn scan_device(
+    adapter: &bluer::Adapter,
+    adapter_name: &str,
+    address: bluer::Address,
+) -> Option<ScannedDevice> {
+    let mut errors = Vec::new();
+    let mut entry = ScannedDevice {
+        adapter: adapter_name.to_string(),
+        address: address.to_string(),
+        name: None,
+        connected: false,
+        paired: false,
+        error: None,
+    };
+    let device = match adapter.device(address) {
+        Ok(device) => device,
+        Err(err) => {
+            entry.error = Some(err.to_string());
+            return Some(entry);
+        }
+    };
+    match device.is_connected().await {
+        Ok(connected) => entry.connected = connected,
+        Err(err) => errors.push(format!("connected: {}", err)),
+    }
+    match device.is_paired().await {
+        Ok(paired) => entry.paired = paired,
+        Err(err) => errors.push(format!("paired: {}", err)),
+    }
+    if errors.is_empty() && !entry.connected && !entry.paired {
+        return None;
+    }
+    match device.name().await {
+        Ok(name) => entry.name = name,
+        Err(err) => errors.push(format!("name: {}", err)),
+    }
+    if !errors.is_empty() {
+        entry.error = Some(errors.join("; "));
+    }
+    Some(entry)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Ok,
+    Failed,
+    /// Not run because an earlier check failed or there was nothing to check.
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct PreflightCheck {
+    /// `bluez`, `adapter` or `device`.
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+/// What stands between this host and an RFCOMM connection to the buds.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct PreflightReport {
+    /// `true` when no check failed.
+    pub ok: bool,
+    pub checks: Vec<PreflightCheck>,
+}
+
+impl PreflightReport {
+    fn new(checks: Vec<PreflightCheck>) -> Self {
+        let ok = checks
+            .iter()
+            .all(|check| check.status != CheckStatus::Failed);
+        Self { ok, checks }
+    }
+}
+
+/// Lists the details of the failed checks.
+impl fmt::Display for PreflightReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let failed: Vec<&str> = self
+            .checks
+            .iter()
+            .filter(|check| check.status == CheckStatus::Failed)
+            .map(|check| check.detail.as_str())
+            .collect();
+        write!(f, "{}", failed.join("; "))
+    }
+}
+
+fn check(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> PreflightCheck {
+    PreflightCheck {
+        name,
+        status,
+        detail: detail.into(),
+    }
+}
+
+/// Checks that BlueZ answers on D-Bus, that an adapter is powered and, given
+/// an address, that the device is paired and connected. Each failure says
+/// how to fix it; checks after a failed one are skipped.
+pub async fn preflight(address: Option<bluer::Address>) -> PreflightReport {
+    let mut checks = Vec::new();
+    let session = match bluer::Session::new().await {
+        Ok(session) => {
+            checks.push(check(
+                "bluez",
+                CheckStatus::Ok,
+                "BlueZ is reachable over D-Bus",
+            ));
+            session
+        }
+        Err(err) => {
+            checks.push(check(
+                "bluez",
+                CheckStatus::Failed,
+                format!(
+                    "cannot reach BlueZ over D-Bus ({}); is bluetooth.service running and may this user use it?",
+                    err
+                ),
+            ));
+            checks.push(check(
+                "adapter",
+                CheckStatus::Skipped,
+                "BlueZ is unreachable",
+            ));
+            checks.push(check(
+                "device",
+                CheckStatus::Skipped,
+                "BlueZ is unreachable",
+            ));
+            return PreflightReport::new(checks);
+        }
+    };
+
+    let adapter = preflight_adapter(&session).await;
+    let adapter_ok = adapter.status == CheckStatus::Ok;
+    checks.push(adapter);
+    checks.push(match address {
+        None => check("device", CheckStatus::Skipped, "no device address given"),
+        Some(_) if !adapter_ok => check("device", CheckStatus::Skipped, "no powered adapter"),
+        Some(address) => preflight_device(&session, address).await,
+    });
+    PreflightReport::new(checks)
+}
+
+async fn preflight_adapter(session: &bluer::Session) -> PreflightCheck {
+    let names = match session.adapter_names().await {
+        Ok(names) => names,
+        Err(err) => {
+            return check(
+                "adapter",
+                CheckStatus::Failed,
+                format!("cannot list Bluetooth adapters: {}", err),
+            );
+        }
+    };
+    if names.is_empty() {
+        return check("adapter", CheckStatus::Failed, "no Bluetooth adapter found");
+    }
+    for name in &names {
+        let powered = match session.adapter(name) {
+            Ok(adapter) => adapter.is_powered().await.unwrap_or(false),
+            Err(_) => false,
+        };
+        if powered {
+            return check("adapter", CheckStatus::Ok, format!("{} is powered", name));
+        }
+    }
+    check(
+        "adapter",
+        CheckStatus::Failed,
+        format!(
+            "{} is powered off; run `bluetoothctl power on`",
+            names.join(", ")
+        ),
+    )
+}
+
+async fn preflight_device(session: &bluer::Session, address: bluer::Address) -> PreflightCheck {
+    let mut paired_on = None;
+    for name in session.adapter_names().await.unwrap_or_default() {
+        let Ok(device) = session
+            .adapter(&name)
+            .and_then(|adapter| adapter.device(address))
+        else {
+            continue;
+        };
+        let paired = device.is_paired().await.unwrap_or(false);
+        let connected = device.is_connected().await.unwrap_or(false);
+        if connected {
+            return check(
+                "device",
+                CheckStatus::Ok,
+                format!("{} is connected on {}", address, name),
+            );
+        }
+        if paired {
+            paired_on = Some(name);
+        }
+    }
+    let detail = match paired_on {
+        Some(name) => format!(
+            "{} is paired on {} but not connected; connect it from your Bluetooth settings",
+            address, name
+        ),
+        None => format!("{} is not paired; pair the buds first", address),
+    };
+    check("device", CheckStatus::Failed, detail)
+}
+
+/// A local Bluetooth controller.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct AdapterInfo {
+    /// BlueZ name such as `hci0`, accepted as `adapter` by the connect routes.
+    pub name: String,
+    pub address: String,
+    pub powered: bool,
+    /// The first powered adapter, used when a request names none and the
+    /// device isn't connected on another one.
+    pub default: bool,
+}
+
+pub async fn list_adapters() -> Result<Vec<AdapterInfo>, EarError> {
+    let session = bluer::Session::new().await.map_err(bluez_error)?;
+    let mut adapters = Vec::new();
+    for name in session.adapter_names().await.map_err(bluez_error)? {
+        let adapter = session.adapter(&name).map_err(bluez_error)?;
+        adapters.push(AdapterInfo {
+            address: adapter.address().await.map_err(bluez_error)?.to_string(),
+            powered: adapter.is_powered().await.map_err(bluez_error)?,
+            default: false,
+            name,
+        });
+    }
+    if let Some(first) = adapters.iter_mut().find(|adapter| adapter.powered) {
+        first.default = true;
+    }
+    Ok(adapters)
+}
+
+/// Picks the local adapter address to connect to `device` from. A named
+/// adapter must exist and be powered. Otherwise this is the adapter the
+/// device is connected on, falling back to the default one; `None` (let the
+/// kernel choose) when BlueZ can't be queried.
+pub async fn select_adapter(
+    name: Option<&str>,
+    device: bluer::Address,
+) -> Result<Option<bluer::Address>, EarError> {
+    let adapters = match (list_adapters().await, name) {
+        (Ok(adapters), _) => adapters,
+        (Err(err), Some(_)) => return Err(err),
+        (Err(err), None) => {
+            tracing::debug!("{}; leaving the adapter choice to the kernel", err);
+            return Ok(None);
+        }
+    };
+    let chosen = match name {
+        Some(name) => {
+            let adapter = adapters
+                .iter()
+                .find(|adapter| adapter.name == name)
+                .ok_or_else(|| {
+                    let known: Vec<&str> = adapters.iter().map(|a| a.name.as_str()).collect();
+                    EarError::Detection(format!(
+                        "no Bluetooth adapter named '{}'; found {}",
+                        name,
+                        known.join(", ")
+                    ))
+                })?;
+            if !adapter.powered {
+                return Err(EarError::Detection(format!(
+                    "Bluetooth adapter {} is powered off",
+                    name
+                )));
+            }
+            adapter
+        }
+        None => {
+            let connected_on = scan_devices()
+                .await
+                .unwrap_or_default()
+                .into_iter()
+                .find(|scanned| scanned.connected && scanned.address == device.to_string())
+                .map(|scanned| scanned.adapter);
+            let by_device =
+                connected_on.and_then(|on| adapters.iter().find(|adapter| adapter.name == on));
+            match by_device.or_else(|| adapters.iter().find(|adapter| adapter.default)) {
+                Some(adapter) => adapter,
+                None => return Ok(None),
+            }
+        }
+    };
+    chosen.address.parse().map(Some).map_err(|_| {
+        EarError::Detection(format!(
+            "adapter {} reported an invalid address",
+            chosen.name
+        ))
+    })
+}
+
+fn bluez_error(err: bluer::Error) -> EarError {
+    EarError::Detection(format!("BlueZ query failed: {}", err))
+}
+
+async fn find_scanned_device(address: &str) -> Result<Option<ScannedDevice>, EarError> {
+    let devices = scan_devices().await?;
+    Ok(devices
+        .into_iter()
+        .find(|device| device.address.eq_ignore_ascii_case(address)))
+}
+
+/// Connected devices from BlueZ over D-Bus, else from `bluetoothctl`, which
+/// is re-run up to `retries` times when it fails.
+pub async fn list_connected_devices(
+    #[cfg_attr(not(feature = "shell-fallback"), allow(unused_variables))] retries: u8,
+) -> Result<Vec<BluetoothDevice>, EarError> {
+    match scan_devices().await {
+        Ok(devices) => Ok(devices
+            .into_iter()
+            .filter(|device| device.connected)
+            .map(|device| BluetoothDevice {
+                address: device.address,
+                name: device.name.unwrap_or_default(),
+            })
+            .collect()),
+        #[cfg(feature = "shell-fallback")]
+        Err(err) => {
+            warn_shell_fallback(&err);
+            with_retries("bluetoothctl devices", retries, SHELL_RETRY_DELAY, || {
+                shell::list_connected_devices()
+            })
+            .await
+        }
+        #[cfg(not(feature = "shell-fallback"))]
+        Err(err) => Err(err),
+    }
+}
+
 async fn verify_device_connected(address: &str) -> Result<(), EarError> {
-    let output = run_command("bluetoothctl", &["info", address]).await?;
-    if !output.to_lowercase().contains("connected: yes") {
+    let address = normalize_address(address)?;
+    let connected = match find_scanned_device(&address).await {
+        Ok(device) => device.is_some_and(|device| device.connected),
+        #[cfg(feature = "shell-fallback")]
+        Err(err) => {
+            warn_shell_fallback(&err);
+            shell::is_device_connected(&address).await?
+        }
+        #[cfg(not(feature = "shell-fallback"))]
+        Err(err) => return Err(err),
+    };
+    if !connected {
         return Err(EarError::Detection(format!(
             "bluetooth device {} is not currently connected",
             address
@@ -76,18 +502,65 @@ async fn verify_device_connected(address: &str) -> Result<(), EarError> {
 }
 
 async fn device_name(address: &str) -> Option<String> {
-    run_command("bluetoothctl", &["info", address])
-        .await
-        .ok()
-        .and_then(|info| {
-            info.lines()
-                .find(|line| line.trim_start().starts_with("Name:"))
-                .map(|line| {
-                    line.split_once(':')
-                        .map(|(_, value)| value.trim().to_string())
-                })
-                .flatten()
-        })
+    match find_scanned_device(address).await {
+        Ok(device) => device.and_then(|device| device.name),
+        #[cfg(feature = "shell-fallback")]
+        Err(err) => {
+            warn_shell_fallback(&err);
+            shell::device_name(address).await
+        }
+        #[cfg(not(feature = "shell-fallback"))]
+        Err(_) => None,
+    }
+}
+
+#[cfg(feature = "shell-fallback")]
+fn warn_shell_fallback(err: &EarError) {
+    tracing::warn!("{}; falling back to bluetoothctl", err);
+}
+
+/// Text-parsing fallbacks over `bluetoothctl`, used when the BlueZ D-Bus API
+/// is unreachable.
+#[cfg(feature = "shell-fallback")]
+mod shell {
+    use super::{BluetoothDevice, normalize_address, run_command};
+    use crate::error::EarError;
+
+    pub async fn list_connected_devices() -> Result<Vec<BluetoothDevice>, EarError> {
+        let output = run_command("bluetoothctl", &["devices", "Connected"]).await?;
+        let devices = output
+            .lines()
+            .filter_map(|line| {
+                let parts: Vec<&str> = line.split_whitespace().collect();
+                if parts.len() < 3 {
+                    return None;
+                }
+                let address = normalize_address(parts[1]).ok()?;
+                let name = parts[2..].join(" ");
+                Some(BluetoothDevice { address, name })
+            })
+            .collect();
+        Ok(devices)
+    }
+
+    pub async fn is_device_connected(address: &str) -> Result<bool, EarError> {
+        let output = run_command("bluetoothctl", &["info", address]).await?;
+        Ok(output.to_lowercase().contains("connected: yes"))
+    }
+
+    pub async fn device_name(address: &str) -> Option<String> {
+        run_command("bluetoothctl", &["info", address])
+            .await
+            .ok()
+            .and_then(|info| {
+                info.lines()
+                    .find(|line| line.trim_start().starts_with("Name:"))
+                    .and_then(|line| {
+                        line.split_once(':')
+                            .map(|(_, value)| value.trim().to_string())
+                    })
+            })
+    }
 }
 
 async fn run_command(cmd: &str, args: &[&str]) -> Result<String, EarError> {
@@ -106,7 +579,54 @@ async fn run_command(cmd: &str, args: &[&str]) -> Result<String, EarError> {
     Ok(String::from_utf8_lossy(&output.stdout).to_string())
 }
 
-pub async fn detect_rfcomm_channel(address: &str) -> Result<u8, EarError> {
+/// Runs `attempt` until it succeeds, retrying at most `retries` times and
+/// doubling `delay` after each failure.
+async fn with_retries<T, F, Fut>(
+    label: &str,
+    retries: u8,
+    mut delay: Duration,
+    mut attempt: F,
+) -> Result<T, EarError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, EarError>>,
+{
+    let mut failures = 0;
+    loop {
+        match attempt().await {
+            Err(err) if failures < retries => {
+                failures += 1;
+                tracing::warn!(
+                    "{} failed: {}; retrying in {:?} ({}/{})",
+                    label,
+                    err,
+                    delay,
+                    failures,
+                    retries
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Finds the NT LINK RFCOMM channel with `sdptool`. Right after the buds
+/// connect their service records may not be published yet, so a failed or
+/// empty search is repeated up to `retries` times.
+pub async fn detect_rfcomm_channel(address: &str, retries: u8) -> Result<u8, EarError> {
+    let address = normalize_address(address)?;
+    with_retries(
+        "RFCOMM channel detection",
+        retries,
+        SHELL_RETRY_DELAY,
+        || search_rfcomm_channel(&address),
+    )
+    .await
+}
+
+async fn search_rfcomm_channel(address: &str) -> Result<u8, EarError> {
     let output = run_command("sdptool", &["search", "--bdaddr", address, "SP"]).await?;
     let mut tracking_target = false;
     for line in output.lines() {
@@ -136,3 +656,70 @@ pub async fn detect_rfcomm_channel(address: &str) -> Result<u8, EarError> {
             .into(),
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicU8, Ordering},
+        time::Duration,
+    };
+
+    use super::{CheckStatus, PreflightReport, check, normalize_address, with_retries};
+    use crate::error::EarError;
+
+    #[tokio::test]
+    async fn shell_calls_are_retried_a_bounded_number_of_times() {
+        let calls = AtomicU8::new(0);
+        let result = with_retries("test", 2, Duration::from_millis(1), || async {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            if call < 2 {
+                Err(EarError::Detection("not yet".into()))
+            } else {
+                Ok(call)
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 2);
+
+        calls.store(0, Ordering::SeqCst);
+        let result: Result<(), EarError> =
+            with_retries("test", 1, Duration::from_millis(1), || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(EarError::Detection("never".into()))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn preflight_report_lists_only_failures() {
+        let report = PreflightReport::new(vec![
+            check("bluez", CheckStatus::Ok, "BlueZ is reachable over D-Bus"),
+            check("adapter", CheckStatus::Failed, "hci0 is powered off"),
+            check("device", CheckStatus::Skipped, "no powered adapter"),
+        ]);
+        assert!(!report.ok);
+        assert_eq!(report.to_string(), "hci0 is powered off");
+    }
+
+    #[test]
+    fn normalize_address_accepts_colon_and_dash_forms() {
+        assert_eq!(
+            normalize_address("00:11:22:33:44:55").unwrap(),
+            "00:11:22:33:44:55"
+        );
+        assert_eq!(
+            normalize_address(" aa-bb-cc-dd-ee-ff ").unwrap(),
+            "AA:BB:CC:DD:EE:FF"
+        );
+    }
+
+    #[test]
+    fn normalize_address_rejects_malformed_input() {
+        assert!(normalize_address("00:11:22:33:44").is_err());
+        assert!(normalize_address("00:11:22:33:44:5").is_err());
+        assert!(normalize_address("00:11:22:33:44:GG").is_err());
+        assert!(normalize_address("001122334455").is_err());
+    }
+}
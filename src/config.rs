@@ -0,0 +1,195 @@
+//! Optional config file with defaults for the client and server flags. Values
+//! from the file become clap defaults, so a flag on the command line always
+//! wins. TOML unless the path ends in `.json`:
+//!
+//! ```toml
+//! endpoint = "http://192.168.1.20:8787"
+//! token = "secret"
+//! sku = "01"
+//!
+//! [server]
+//! addr = "0.0.0.0:8787"
+//! auth_token = "secret"
+//! timeout_ms = 3000
+//! ```
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// `--endpoint` for client commands.
+    pub endpoint: Option<String>,
+    /// `--token` for client commands.
+    pub token: Option<String>,
+    /// `auto-connect --sku`, for buds whose serial doesn't identify them.
+    pub sku: Option<String>,
+    pub server: ServerConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub addr: Option<String>,
+    pub auth_token: Option<String>,
+    pub timeout_ms: Option<u64>,
+}
+
+impl Config {
+    /// Loads `--config PATH` if given, otherwise the default location. Only
+    /// an explicitly named file has to exist.
+    pub fn load(explicit: Option<PathBuf>) -> Result<Self> {
+        let (path, required) = match explicit {
+            Some(path) => (path, true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&path, &text),
+            Err(err) if !required && err.kind() == std::io::ErrorKind::NotFound => {
+                Ok(Self::default())
+            }
+            Err(err) => {
+                Err(err).with_context(|| format!("failed to read config {}", path.display()))
+            }
+        }
+    }
+
+    fn parse(path: &Path, text: &str) -> Result<Self> {
+        let parsed = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(text).map_err(anyhow::Error::from)
+        } else {
+            toml::from_str(text).map_err(anyhow::Error::from)
+        };
+        parsed.with_context(|| format!("invalid config {}", path.display()))
+    }
+
+    /// Installs the configured values as defaults on the CLI definition.
+    pub fn apply(self, command: clap::Command) -> clap::Command {
+        let mut command = command;
+        if let Some(endpoint) = self.endpoint {
+            command = command.mut_arg("endpoint", |arg| arg.default_value(endpoint));
+        }
+        if let Some(token) = self.token {
+            command = command.mut_arg("token", |arg| arg.default_value(token));
+        }
+        if let Some(sku) = self.sku {
+            command = command.mut_subcommand("auto-connect", |sub| {
+                sub.mut_arg("sku", |arg| arg.default_value(sku))
+            });
+        }
+        let server = self.server;
+        command.mut_subcommand("server", |mut sub| {
+            if let Some(addr) = server.addr {
+                sub = sub.mut_arg("addr", |arg| arg.default_value(addr));
+            }
+            if let Some(token) = server.auth_token {
+                sub = sub.mut_arg("auth_token", |arg| arg.default_value(token));
+            }
+            if let Some(timeout) = server.timeout_ms {
+                sub = sub.mut_arg("timeout_ms", |arg| arg.default_value(timeout.to_string()));
+            }
+            sub
+        })
+    }
+}
+
+/// `$XDG_CONFIG_HOME/earctl/config.toml`, falling back to `~/.config`.
+fn default_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("earctl").join("config.toml"))
+}
+
+/// `$XDG_STATE_HOME/earctl/state.json`, falling back to `~/.local/state`.
+pub fn default_state_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state"))
+        })?;
+    Some(base.join("earctl").join("state.json"))
+}
+
+/// Finds `--config PATH` or `--config=PATH` before clap runs, since the file
+/// decides clap's defaults.
+pub fn path_from_args(args: &[OsString]) -> Option<PathBuf> {
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--config" {
+            return iter.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsString, path::Path};
+
+    use clap::{CommandFactory, FromArgMatches};
+
+    use super::{Config, path_from_args};
+    use crate::{Cli, Commands};
+
+    fn parse(config: &str, args: &[&str]) -> Cli {
+        let config = Config::parse(Path::new("config.toml"), config).unwrap();
+        let matches = config
+            .apply(Cli::command())
+            .try_get_matches_from(args)
+            .unwrap();
+        Cli::from_arg_matches(&matches).unwrap()
+    }
+
+    #[test]
+    fn flags_override_config_values() {
+        let config = r#"
+            endpoint = "http://10.0.0.2:8787"
+
+            [server]
+            addr = "0.0.0.0:9000"
+            timeout_ms = 3000
+        "#;
+        let cli = parse(config, &["earctl", "battery"]);
+        assert_eq!(cli.endpoint, "http://10.0.0.2:8787");
+
+        let cli = parse(config, &["earctl", "server", "--addr", "127.0.0.1:1"]);
+        let Commands::Server(opts) = cli.command else {
+            panic!("expected the server command");
+        };
+        assert_eq!(opts.addr, ["127.0.0.1:1"]);
+        assert_eq!(opts.timeout_ms, Some(3000));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(Config::parse(Path::new("config.toml"), "endpont = \"x\"").is_err());
+        assert!(Config::parse(Path::new("config.json"), r#"{ "sku": "01" }"#).is_ok());
+    }
+
+    #[test]
+    fn config_flag_is_found_before_parsing() {
+        let args: Vec<OsString> = ["earctl", "--config=/tmp/a.toml", "battery"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+        assert_eq!(path_from_args(&args).unwrap(), Path::new("/tmp/a.toml"));
+    }
+}
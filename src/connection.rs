@@ -1,37 +1,187 @@
-use std::time::Duration;
-
-use bluer::rfcomm::{
-    SocketAddr, Stream,
-    stream::{OwnedReadHalf, OwnedWriteHalf},
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{
+        Arc,
+        atomic::{AtomicU8, AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
+
+use bluer::rfcomm::{Socket, SocketAddr, Stream};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    sync::Mutex,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::{Mutex, broadcast, watch},
     time,
 };
+use tracing::Instrument;
 
-use crate::{error::EarError, protocol::EarPacket};
+use crate::{
+    error::EarError,
+    protocol::{EarPacket, MAX_PACKET_LEN, command, command_name, format_hex},
+    types::DisconnectReason,
+};
 
 const READ_BUFFER_SIZE: usize = 512;
+const MAX_BUFFERED_BYTES: usize = 16 * 1024;
 const DEFAULT_TIMEOUT_MS: u64 = 2000;
+const DEFAULT_OPERATION_ID_MAX: u8 = 250;
+const DEFAULT_MAX_CRC_FAILURES: u8 = 3;
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+/// How long [`EarConnection::send_command_confirmed`] callers usually wait.
+pub const DEFAULT_ACK_TIMEOUT: Duration = Duration::from_millis(300);
+/// Times a timed-out read is re-sent unless configured otherwise.
+pub const DEFAULT_READ_RETRIES: u8 = 1;
+/// Frames kept for errors when packet debugging is enabled.
+pub const DEFAULT_PACKET_HISTORY: usize = 8;
+const NOTIFICATION_CAPACITY: usize = 32;
+/// How long a merged reply waits for its next packet once one has matched.
+/// Split replies arrive back to back, so a part the device doesn't report
+/// (e.g. a bud in the case) shouldn't hold the read for the full timeout.
+const MERGE_FOLLOW_UP: Duration = Duration::from_millis(250);
+
+/// Commands the buds are known to answer slowly, with the reply timeout
+/// each one gets unless overridden.
+const SLOW_COMMAND_TIMEOUTS_MS: &[(u16, u64)] = &[(command::REQUEST_SERIAL, 4000)];
+
+/// Sizing for the RFCOMM read path.
+#[derive(Debug, Clone, Copy)]
+pub struct ReadBufferConfig {
+    /// Bytes requested from the socket per read.
+    pub chunk_size: usize,
+    /// Upper bound for bytes held while waiting for a complete packet. When a
+    /// device floods unparseable data, the oldest bytes are dropped.
+    pub max_buffered: usize,
+}
+
+impl Default for ReadBufferConfig {
+    fn default() -> Self {
+        Self {
+            chunk_size: READ_BUFFER_SIZE,
+            max_buffered: MAX_BUFFERED_BYTES,
+        }
+    }
+}
+
+impl ReadBufferConfig {
+    /// The cap must hold a partial frame plus one fresh chunk, or valid
+    /// packets could be cut.
+    fn clamped(self) -> Self {
+        let chunk_size = self.chunk_size.max(1);
+        Self {
+            chunk_size,
+            max_buffered: self.max_buffered.max(MAX_PACKET_LEN + chunk_size),
+        }
+    }
+}
+
+/// Outcome of [`EarConnection::send_command_confirmed`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandAck {
+    pub operation_id: u8,
+    /// The expected reply echoing `operation_id` arrived in time. Not every
+    /// firmware acknowledges writes, so `false` doesn't mean the write was
+    /// lost.
+    pub acknowledged: bool,
+}
+
+/// When the device last sent a parseable packet, shared so it can be checked
+/// without waiting for the connection lock.
+#[derive(Debug, Clone, Default)]
+pub struct LastSeen(Arc<AtomicU64>);
+
+impl LastSeen {
+    fn touch(&self) {
+        self.0
+            .store(unix_millis(SystemTime::now()), Ordering::Relaxed);
+    }
+
+    /// Unix time in milliseconds, or `None` before the first packet.
+    pub fn unix_ms(&self) -> Option<u64> {
+        match self.0.load(Ordering::Relaxed) {
+            0 => None,
+            millis => Some(millis),
+        }
+    }
+
+    pub fn elapsed(&self) -> Option<Duration> {
+        let millis = self.unix_ms()?;
+        let now = unix_millis(SystemTime::now());
+        Some(Duration::from_millis(now.saturating_sub(millis)))
+    }
+}
+
+/// One span per request/response exchange, so the write, the reads and the
+/// matcher's decisions can be told apart when requests interleave.
+fn transact_span(command: u16, label: &'static str) -> tracing::Span {
+    tracing::debug_span!("transact", label, command = command_name(command))
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+type BoxedReader = Box<dyn AsyncRead + Send + Unpin>;
+type BoxedWriter = Box<dyn AsyncWrite + Send + Unpin>;
 
 pub struct EarConnection {
     port_path: String,
-    reader: Mutex<OwnedReadHalf>,
-    writer: Mutex<OwnedWriteHalf>,
-    read_buffer: Mutex<Vec<u8>>,
-    operation_id: Mutex<u8>,
-    timeout: Duration,
+    reader: Mutex<BoxedReader>,
+    writer: Mutex<BoxedWriter>,
+    /// Never held across an await, so a read can be cancelled without
+    /// losing buffered bytes.
+    read_buffer: std::sync::Mutex<Vec<u8>>,
+    /// Packets nobody asked for: read by [`listen`](Self::listen) or passed
+    /// over while a request waited for its reply.
+    notifications: broadcast::Sender<EarPacket>,
+    /// Ack commands that timed out and haven't been seen since; confirmed
+    /// writes expecting them don't wait.
+    silent_acks: std::sync::Mutex<HashSet<u16>>,
+    operation_id: Arc<AtomicU8>,
+    operation_id_max: u8,
+    timeout: Option<Duration>,
+    command_timeouts: HashMap<u16, Duration>,
+    read_retries: u8,
+    max_crc_failures: u8,
+    /// Frames dropped for a bad CRC over the connection's lifetime.
+    crc_errors: Arc<AtomicU64>,
+    /// Hex of the last `packet_history` frames received, oldest first.
+    recent_packets: std::sync::Mutex<VecDeque<String>>,
+    packet_history: usize,
+    buffers: ReadBufferConfig,
+    last_seen: LastSeen,
+    closed: watch::Sender<Option<DisconnectReason>>,
+    dry_run: bool,
 }
 
 impl EarConnection {
-    pub async fn open(address: bluer::Address, channel: u8) -> Result<Self, EarError> {
+    /// Opens an RFCOMM stream to `address`, from the local `adapter` address
+    /// when given; otherwise the kernel picks the adapter.
+    pub async fn open(
+        address: bluer::Address,
+        channel: u8,
+        adapter: Option<bluer::Address>,
+        buffers: ReadBufferConfig,
+    ) -> Result<Self, EarError> {
         let socket_addr = SocketAddr::new(address, channel);
         let port_path = socket_addr.to_string();
 
         tracing::info!("Connecting to RFCOMM {}", port_path);
 
-        let stream = Stream::connect(socket_addr).await.map_err(|e| {
+        let connected = match adapter {
+            Some(local) => {
+                tracing::debug!("Binding RFCOMM socket to adapter {}", local);
+                async {
+                    let socket = Socket::new()?;
+                    socket.bind(SocketAddr::new(local, 0))?;
+                    socket.connect(socket_addr).await
+                }
+                .await
+            }
+            None => Stream::connect(socket_addr).await,
+        };
+        let stream = connected.map_err(|e| {
             EarError::Io(std::io::Error::new(
                 std::io::ErrorKind::Other,
                 format!("RFCOMM connect failed: {}", e),
@@ -40,56 +190,326 @@ impl EarConnection {
 
         let (reader, writer) = stream.into_split();
 
-        Ok(Self {
-            port_path,
-            reader: Mutex::new(reader),
-            writer: Mutex::new(writer),
-            read_buffer: Mutex::new(Vec::with_capacity(READ_BUFFER_SIZE)),
-            operation_id: Mutex::new(1),
-            timeout: Duration::from_millis(DEFAULT_TIMEOUT_MS),
-        })
+        Ok(Self::from_io(port_path, reader, writer).with_read_buffer(buffers))
+    }
+
+    /// Builds a connection over arbitrary byte streams, e.g. an in-memory
+    /// duplex pipe standing in for the RFCOMM socket.
+    pub fn from_io<R, W>(port_path: impl Into<String>, reader: R, writer: W) -> Self
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        Self {
+            port_path: port_path.into(),
+            reader: Mutex::new(Box::new(reader)),
+            writer: Mutex::new(Box::new(writer)),
+            read_buffer: std::sync::Mutex::new(Vec::with_capacity(READ_BUFFER_SIZE)),
+            notifications: broadcast::channel(NOTIFICATION_CAPACITY).0,
+            silent_acks: std::sync::Mutex::default(),
+            operation_id: Arc::new(AtomicU8::new(1)),
+            operation_id_max: DEFAULT_OPERATION_ID_MAX,
+            timeout: None,
+            command_timeouts: HashMap::new(),
+            read_retries: DEFAULT_READ_RETRIES,
+            max_crc_failures: DEFAULT_MAX_CRC_FAILURES,
+            crc_errors: Arc::default(),
+            recent_packets: std::sync::Mutex::default(),
+            packet_history: 0,
+            buffers: ReadBufferConfig::default(),
+            last_seen: LastSeen::default(),
+            closed: watch::channel(None).0,
+            dry_run: false,
+        }
+    }
+
+    pub fn with_read_buffer(mut self, buffers: ReadBufferConfig) -> Self {
+        self.buffers = buffers.clamped();
+        self
+    }
+
+    /// A connection that never touches the device: commands are logged and
+    /// acknowledged, reads fail with [`EarError::DryRun`].
+    pub fn dry_run(address: bluer::Address, channel: u8) -> Self {
+        let port_path = format!("dry-run:{}", SocketAddr::new(address, channel));
+        tracing::info!("Opening dry-run connection {}", port_path);
+        let mut connection = Self::from_io(port_path, tokio::io::empty(), tokio::io::sink());
+        connection.dry_run = true;
+        connection
+    }
+
+    pub fn last_seen(&self) -> LastSeen {
+        self.last_seen.clone()
+    }
+
+    /// Holds `Some(reason)` once the link has ended.
+    pub fn closed(&self) -> watch::Receiver<Option<DisconnectReason>> {
+        self.closed.subscribe()
+    }
+
+    /// Records why the link ended. Only the first reason is kept.
+    pub fn mark_closed(&self, reason: DisconnectReason) {
+        self.closed.send_if_modified(|state| {
+            if state.is_some() {
+                return false;
+            }
+            *state = Some(reason);
+            true
+        });
+    }
+
+    /// [`EarError::NotConnected`] once the link has ended, so commands on a
+    /// session whose stream died fail fast instead of timing out.
+    fn ensure_open(&self) -> Result<(), EarError> {
+        match *self.closed.borrow() {
+            Some(_) => Err(EarError::NotConnected),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
     }
 
     pub fn port_path(&self) -> &str {
         &self.port_path
     }
 
+    /// Reply timeout for every command without its own override, replacing
+    /// both the 2 s default and the longer built-in ones for slow commands.
     pub fn set_timeout(&mut self, timeout: Duration) {
-        self.timeout = timeout;
+        self.timeout = Some(timeout);
+    }
+
+    /// Reply timeout for one command code, taking precedence over
+    /// [`set_timeout`](Self::set_timeout).
+    pub fn set_command_timeout(&mut self, command: u16, timeout: Duration) {
+        self.command_timeouts.insert(command, timeout);
+    }
+
+    /// How long a reply to `command` is waited for.
+    pub fn timeout_for(&self, command: u16) -> Duration {
+        if let Some(timeout) = self.command_timeouts.get(&command) {
+            return *timeout;
+        }
+        if let Some(timeout) = self.timeout {
+            return timeout;
+        }
+        let ms = SLOW_COMMAND_TIMEOUTS_MS
+            .iter()
+            .find(|(code, _)| *code == command)
+            .map_or(DEFAULT_TIMEOUT_MS, |(_, ms)| *ms);
+        Duration::from_millis(ms)
+    }
+
+    /// How many times a timed-out read is re-sent before giving up.
+    pub fn set_read_retries(&mut self, retries: u8) {
+        self.read_retries = retries;
+    }
+
+    /// Frames with a bad CRC in a row after which a read fails with
+    /// [`EarError::CrcMismatch`]; fewer are dropped and reading goes on.
+    pub fn set_max_crc_failures(&mut self, failures: u8) {
+        self.max_crc_failures = failures.max(1);
+    }
+
+    /// Shared count of frames dropped for a bad CRC, readable without the
+    /// connection lock.
+    pub fn crc_errors(&self) -> Arc<AtomicU64> {
+        self.crc_errors.clone()
+    }
+
+    /// Keep the hex of the last `count` received frames, corrupt ones and any
+    /// junk skipped before them included, and attach it to timeout and CRC
+    /// errors. Zero (the default) records nothing.
+    pub fn set_packet_history(&mut self, count: usize) {
+        self.packet_history = count;
+    }
+
+    fn record_received(&self, frame: String) {
+        let mut recent = self.recent_packets.lock().unwrap();
+        if recent.len() >= self.packet_history {
+            recent.pop_front();
+        }
+        recent.push_back(frame);
+    }
+
+    fn recent_packets(&self) -> Vec<String> {
+        self.recent_packets
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    fn timeout_error(&self, label: &'static str) -> EarError {
+        EarError::Timeout(label, self.recent_packets())
+    }
+
+    /// Highest operation id sent before wrapping back to 1. Some devices
+    /// reject ids above a model-specific value.
+    pub fn set_operation_id_max(&mut self, max: u8) {
+        self.operation_id_max = max.max(1);
+    }
+
+    /// The most recently used operation id.
+    pub async fn operation_id(&self) -> u8 {
+        self.operation_id.load(Ordering::SeqCst)
+    }
+
+    /// Shared most recent operation id, readable without the connection
+    /// lock.
+    pub fn operation_id_handle(&self) -> Arc<AtomicU8> {
+        self.operation_id.clone()
+    }
+
+    /// Restarts the sequence as if the connection had just been opened.
+    pub async fn reset_operation_id(&self) {
+        self.operation_id.store(1, Ordering::SeqCst);
     }
 
     async fn next_operation_id(&self) -> u8 {
-        let mut op_id = self.operation_id.lock().await;
-        *op_id = if *op_id >= 250 {
-            1
-        } else {
-            op_id.wrapping_add(1).max(1)
+        let max = self.operation_id_max;
+        let next = |op_id: u8| {
+            if op_id >= max {
+                1
+            } else {
+                op_id.wrapping_add(1).max(1)
+            }
         };
-        *op_id
+        let previous = self
+            .operation_id
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |op_id| {
+                Some(next(op_id))
+            })
+            .unwrap_or_else(|op_id| op_id);
+        next(previous)
+    }
+
+    /// Flushes and shuts down the write half and drops the read half, so the
+    /// device sees the channel close now rather than whenever the last
+    /// handle goes away. The protocol has no goodbye command.
+    pub async fn close(&self) -> Result<(), EarError> {
+        if self.dry_run {
+            tracing::info!("dry-run: closing {}", self.port_path);
+            return Ok(());
+        }
+        let result = self.writer.lock().await.shutdown().await;
+        *self.reader.lock().await = Box::new(tokio::io::empty());
+        self.read_buffer.lock().unwrap().clear();
+        result.map_err(|e| {
+            EarError::Io(std::io::Error::other(format!("RFCOMM close failed: {}", e)))
+        })?;
+        tracing::info!("Closed RFCOMM {}", self.port_path);
+        Ok(())
     }
 
     pub async fn send_command(&self, command: u16, payload: &[u8]) -> Result<u8, EarError> {
+        self.ensure_open()?;
         let operation = self.next_operation_id().await;
+        if self.dry_run {
+            tracing::info!(
+                "dry-run: command 0x{:04x} ({}) operation {} payload [{}]",
+                command,
+                command_name(command),
+                operation,
+                format_hex(payload)
+            );
+            return Ok(operation);
+        }
         let packet = EarPacket::encode(command, operation, payload);
 
         let mut writer = self.writer.lock().await;
-        writer.write_all(&packet).await.map_err(|e| {
-            EarError::Io(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("RFCOMM write failed: {}", e),
-            ))
-        })?;
-        writer.flush().await.map_err(|e| {
-            EarError::Io(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("RFCOMM flush failed: {}", e),
-            ))
-        })?;
+        writer
+            .write_all(&packet)
+            .await
+            .map_err(|e| self.write_error("write", e))?;
+        writer
+            .flush()
+            .await
+            .map_err(|e| self.write_error("flush", e))?;
 
         tracing::debug!("sent command 0x{:04x} operation {}", command, operation);
         Ok(operation)
     }
 
+    /// [`send_command`](Self::send_command), then waits up to `wait` for an
+    /// `ack` packet carrying the same operation id. Other packets read
+    /// meanwhile go to [`notifications`](Self::notifications). Once an ack has timed out, later
+    /// writes expecting it return unacknowledged without waiting, until the
+    /// buds send it again. Dry-run commands count as acknowledged.
+    pub async fn send_command_confirmed(
+        &self,
+        command: u16,
+        payload: &[u8],
+        ack: u16,
+        wait: Duration,
+    ) -> Result<CommandAck, EarError> {
+        let operation_id = self.send_command(command, payload).await?;
+        if self.dry_run {
+            return Ok(CommandAck {
+                operation_id,
+                acknowledged: true,
+            });
+        }
+        if self.silent_acks.lock().unwrap().contains(&ack) {
+            tracing::debug!(
+                "not waiting for 0x{:04x}; the buds haven't sent it since it last timed out",
+                ack
+            );
+            return Ok(CommandAck {
+                operation_id,
+                acknowledged: false,
+            });
+        }
+        let deadline = time::Instant::now() + wait;
+        let acknowledged = loop {
+            match self.read_packet_until(deadline).await {
+                Ok(packet) if packet.command == ack && packet.operation_id == operation_id => {
+                    break true;
+                }
+                Ok(packet) => self.forward(packet),
+                Err(EarError::Timeout(..)) => break false,
+                Err(err) => return Err(err),
+            }
+        };
+        if !acknowledged {
+            tracing::debug!(
+                "command 0x{:04x} operation {} was not acknowledged within {:?}",
+                command,
+                operation_id,
+                wait
+            );
+            self.silent_acks.lock().unwrap().insert(ack);
+        }
+        Ok(CommandAck {
+            operation_id,
+            acknowledged,
+        })
+    }
+
+    /// A write failing because the peer went away ends the link and reports
+    /// [`EarError::NotConnected`]; anything else stays an I/O error.
+    fn write_error(&self, action: &str, error: std::io::Error) -> EarError {
+        use std::io::ErrorKind;
+        if matches!(
+            error.kind(),
+            ErrorKind::BrokenPipe | ErrorKind::ConnectionReset | ErrorKind::NotConnected
+        ) {
+            tracing::warn!("RFCOMM {} failed, stream closed: {}", action, error);
+            self.mark_closed(DisconnectReason::RemoteClosed);
+            return EarError::NotConnected;
+        }
+        EarError::Io(std::io::Error::other(format!(
+            "RFCOMM {} failed: {}",
+            action, error
+        )))
+    }
+
+    /// Sends `command` and waits for a matching reply. On timeout the request
+    /// is re-sent up to the configured retry count, so only use this for
+    /// reads that are safe to repeat.
     pub async fn transact<F, T>(
         &self,
         command: u16,
@@ -100,56 +520,576 @@ impl EarConnection {
     where
         F: FnMut(&EarPacket) -> Option<T>,
     {
-        self.send_command(command, payload).await?;
-        let deadline = time::Instant::now() + self.timeout;
+        async {
+            let mut attempt = 0;
+            loop {
+                match self.exchange(command, payload, &mut matcher, label).await {
+                    Err(EarError::Timeout(..)) if attempt < self.read_retries => {
+                        attempt += 1;
+                        self.before_retry(label, attempt).await;
+                    }
+                    result => return result.map(|(_, value)| value),
+                }
+            }
+        }
+        .instrument(transact_span(command, label))
+        .await
+    }
+
+    /// Single-attempt [`transact`](Self::transact), for commands that must
+    /// not be sent twice. Also returns the operation id the command was sent
+    /// with, which the reply doesn't necessarily echo.
+    pub async fn transact_once<F, T>(
+        &self,
+        command: u16,
+        payload: &[u8],
+        matcher: F,
+        label: &'static str,
+    ) -> Result<(u8, T), EarError>
+    where
+        F: FnMut(&EarPacket) -> Option<T>,
+    {
+        self.exchange(command, payload, matcher, label)
+            .instrument(transact_span(command, label))
+            .await
+    }
+
+    async fn exchange<F, T>(
+        &self,
+        command: u16,
+        payload: &[u8],
+        mut matcher: F,
+        label: &'static str,
+    ) -> Result<(u8, T), EarError>
+    where
+        F: FnMut(&EarPacket) -> Option<T>,
+    {
+        let operation = self.send_command(command, payload).await?;
+        let deadline = time::Instant::now() + self.timeout_for(command);
         loop {
-            let packet = self.read_packet().await?;
+            let packet = self.read_packet_until(deadline).await?;
             if let Some(value) = matcher(&packet) {
-                return Ok(value);
+                return Ok((operation, value));
             }
+            self.forward(packet);
             if time::Instant::now() >= deadline {
-                return Err(EarError::Timeout(label));
+                return Err(self.timeout_error(label));
             }
         }
     }
 
+    /// Like [`transact`](Self::transact), but for replies split across several
+    /// packets. `merge` folds each packet into `acc` and returns `None` for
+    /// unrelated packets or `Some(done)` once it matched; reading stops when
+    /// `done` is true. After the first match, reading also stops when no
+    /// further packet matches within a short follow-up window, and the
+    /// partial result is returned instead of an error.
+    pub async fn transact_merge<F, T>(
+        &self,
+        command: u16,
+        payload: &[u8],
+        acc: T,
+        mut merge: F,
+        label: &'static str,
+    ) -> Result<T, EarError>
+    where
+        F: FnMut(&mut T, &EarPacket) -> Option<bool>,
+        T: Clone,
+    {
+        async {
+            let mut attempt = 0;
+            loop {
+                match self
+                    .merge_once(command, payload, acc.clone(), &mut merge, label)
+                    .await
+                {
+                    Err(EarError::Timeout(..)) if attempt < self.read_retries => {
+                        attempt += 1;
+                        self.before_retry(label, attempt).await;
+                    }
+                    result => return result,
+                }
+            }
+        }
+        .instrument(transact_span(command, label))
+        .await
+    }
+
+    async fn merge_once<F, T>(
+        &self,
+        command: u16,
+        payload: &[u8],
+        mut acc: T,
+        mut merge: F,
+        label: &'static str,
+    ) -> Result<T, EarError>
+    where
+        F: FnMut(&mut T, &EarPacket) -> Option<bool>,
+    {
+        self.send_command(command, payload).await?;
+        let mut deadline = time::Instant::now() + self.timeout_for(command);
+        let mut matched = false;
+        loop {
+            let packet = match self.read_packet_until(deadline).await {
+                Ok(packet) => packet,
+                Err(EarError::Timeout(..)) if matched => return Ok(acc),
+                Err(EarError::Timeout(_, packets)) => {
+                    return Err(EarError::Timeout(label, packets));
+                }
+                Err(err) => return Err(err),
+            };
+            match merge(&mut acc, &packet) {
+                Some(true) => return Ok(acc),
+                Some(false) => {
+                    matched = true;
+                    deadline = deadline.min(time::Instant::now() + MERGE_FOLLOW_UP);
+                }
+                None => self.forward(packet),
+            }
+        }
+    }
+
+    /// Packets the buds sent without being asked, or that arrived while a
+    /// request waited for something else.
+    pub fn notifications(&self) -> broadcast::Receiver<EarPacket> {
+        self.notifications.subscribe()
+    }
+
+    fn forward(&self, packet: EarPacket) {
+        tracing::debug!(
+            "notification 0x{:04x} operation {}",
+            packet.command,
+            packet.operation_id
+        );
+        // Nobody subscribed is fine; the packet is dropped as before.
+        let _ = self.notifications.send(packet);
+    }
+
+    /// Waits up to `wait` for one unsolicited packet and passes it to
+    /// [`notifications`](Self::notifications). Safe to cancel: bytes already
+    /// read stay buffered for the next read.
+    pub async fn listen(&self, wait: Duration) -> Result<(), EarError> {
+        let packet = self.read_packet_until(time::Instant::now() + wait).await?;
+        self.forward(packet);
+        Ok(())
+    }
+
+    async fn before_retry(&self, label: &'static str, attempt: u8) {
+        tracing::debug!(
+            "{} timed out; retrying ({}/{})",
+            label,
+            attempt,
+            self.read_retries
+        );
+        time::sleep(RETRY_DELAY).await;
+    }
+
     pub async fn read_packet(&self) -> Result<EarPacket, EarError> {
-        let deadline = time::Instant::now() + self.timeout;
-        let mut chunk = vec![0u8; READ_BUFFER_SIZE];
+        let timeout = self
+            .timeout
+            .unwrap_or(Duration::from_millis(DEFAULT_TIMEOUT_MS));
+        self.read_packet_until(time::Instant::now() + timeout).await
+    }
+
+    async fn read_packet_until(&self, deadline: time::Instant) -> Result<EarPacket, EarError> {
+        if self.dry_run {
+            return Err(EarError::DryRun("device reads"));
+        }
+        let mut chunk = vec![0u8; self.buffers.chunk_size];
+        let mut crc_failures = 0;
 
         loop {
             {
-                let mut buffer = self.read_buffer.lock().await;
-                if let Some(result) = EarPacket::try_parse(&mut buffer)? {
-                    tracing::debug!("parsed packet: command=0x{:04x}", result.command);
-                    return Ok(result);
+                let mut buffer = self.read_buffer.lock().unwrap();
+                let before = (self.packet_history > 0).then(|| buffer.clone());
+                let parsed = EarPacket::try_parse(&mut buffer);
+                if let Some(before) = before {
+                    match &parsed {
+                        Ok(Some(packet)) => {
+                            let end = before.len() - buffer.len();
+                            self.record_received(format_hex(
+                                &before[end - packet.encoded_len()..end],
+                            ));
+                        }
+                        Err(EarError::CrcMismatch(frames)) => {
+                            for frame in frames {
+                                self.record_received(frame.clone());
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                match parsed {
+                    Ok(Some(result)) => {
+                        tracing::debug!("parsed packet: command=0x{:04x}", result.command);
+                        self.last_seen.touch();
+                        self.silent_acks.lock().unwrap().remove(&result.command);
+                        return Ok(result);
+                    }
+                    Ok(None) => {}
+                    // The bad header byte has been dropped; a good frame may follow.
+                    Err(EarError::CrcMismatch(_)) => {
+                        self.crc_errors.fetch_add(1, Ordering::Relaxed);
+                        crc_failures += 1;
+                        tracing::warn!(
+                            "dropped a frame with a bad CRC ({}/{} in a row)",
+                            crc_failures,
+                            self.max_crc_failures
+                        );
+                        if crc_failures >= self.max_crc_failures {
+                            return Err(EarError::CrcMismatch(self.recent_packets()));
+                        }
+                        continue;
+                    }
+                    Err(err) => return Err(err),
                 }
             }
 
+            // Frames already buffered are still handed out above.
+            self.ensure_open()?;
             let remaining = deadline.saturating_duration_since(time::Instant::now());
             if remaining.is_zero() {
-                return Err(EarError::Timeout("read packet"));
+                return Err(self.timeout_error("read packet"));
             }
 
             let mut reader = self.reader.lock().await;
             match time::timeout(remaining, reader.read(&mut chunk)).await {
                 Ok(Ok(0)) => {
-                    return Err(EarError::Io(std::io::Error::new(
-                        std::io::ErrorKind::UnexpectedEof,
-                        "RFCOMM stream closed",
-                    )));
+                    tracing::warn!("RFCOMM stream {} closed by the device", self.port_path);
+                    self.mark_closed(DisconnectReason::RemoteClosed);
+                    return Err(EarError::NotConnected);
                 }
                 Ok(Ok(n)) => {
-                    let mut buffer = self.read_buffer.lock().await;
+                    let mut buffer = self.read_buffer.lock().unwrap();
                     buffer.extend_from_slice(&chunk[..n]);
+                    let overflow = buffer.len().saturating_sub(self.buffers.max_buffered);
+                    if overflow > 0 {
+                        tracing::warn!(
+                            "read buffer exceeded {} bytes; dropping {} unparsed bytes",
+                            self.buffers.max_buffered,
+                            overflow
+                        );
+                        buffer.drain(..overflow);
+                    }
                 }
                 Ok(Err(e)) => {
                     return Err(EarError::Io(e));
                 }
                 Err(_) => {
-                    return Err(EarError::Timeout("read packet"));
+                    return Err(self.timeout_error("read packet"));
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::atomic::Ordering, time::Duration};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{CommandAck, EarConnection, ReadBufferConfig};
+    use crate::{
+        error::EarError,
+        protocol::{EarPacket, MAX_PACKET_LEN, ack_command, command, format_hex, response},
+    };
+
+    #[tokio::test]
+    async fn read_buffer_is_capped_when_device_sends_garbage() {
+        let (mut device, host) = tokio::io::duplex(64 * 1024);
+        let mut connection = EarConnection::from_io("test", host, tokio::io::sink())
+            .with_read_buffer(ReadBufferConfig {
+                chunk_size: 128,
+                max_buffered: 0,
+            });
+        connection.set_timeout(Duration::from_millis(50));
+
+        // A lone magic byte followed by a long run of bytes that never form a
+        // valid header keeps the parser waiting.
+        let mut garbage = vec![0x55u8];
+        garbage.resize(4096, 0x00);
+        device.write_all(&garbage).await.unwrap();
+
+        let result = connection.read_packet().await;
+        assert!(matches!(result, Err(EarError::Timeout(..))));
+        assert!(connection.read_buffer.lock().unwrap().len() <= MAX_PACKET_LEN + 128);
+    }
+
+    #[test]
+    fn command_timeouts_resolve_override_then_global_then_builtin() {
+        let mut connection = EarConnection::from_io("test", tokio::io::empty(), tokio::io::sink());
+        assert_eq!(
+            connection.timeout_for(command::REQUEST_BATTERY),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            connection.timeout_for(command::REQUEST_SERIAL),
+            Duration::from_secs(4)
+        );
+
+        connection.set_timeout(Duration::from_millis(500));
+        assert_eq!(
+            connection.timeout_for(command::REQUEST_SERIAL),
+            Duration::from_millis(500)
+        );
+        connection.set_command_timeout(command::REQUEST_SERIAL, Duration::from_secs(6));
+        assert_eq!(
+            connection.timeout_for(command::REQUEST_SERIAL),
+            Duration::from_secs(6)
+        );
+    }
+
+    #[tokio::test]
+    async fn corrupt_frames_are_skipped_until_the_limit() {
+        let corrupt = || {
+            let mut frame = EarPacket::encode(response::BATTERY_PRIMARY, 1, &[0x01, 0x02, 0x50]);
+            *frame.last_mut().unwrap() ^= 0xFF;
+            frame
+        };
+        let mut stream = corrupt();
+        stream.extend(EarPacket::encode(
+            response::BATTERY_PRIMARY,
+            1,
+            &[0x01, 0x02, 0x51],
+        ));
+        let connection =
+            EarConnection::from_io("test", std::io::Cursor::new(stream), tokio::io::sink());
+        let packet = connection.read_packet().await.unwrap();
+        assert_eq!(packet.payload, vec![0x01, 0x02, 0x51]);
+        assert_eq!(connection.crc_errors().load(Ordering::Relaxed), 1);
+
+        let mut connection = EarConnection::from_io(
+            "test",
+            std::io::Cursor::new([corrupt(), corrupt()].concat()),
+            tokio::io::sink(),
+        );
+        connection.set_max_crc_failures(2);
+        assert!(matches!(
+            connection.read_packet().await,
+            Err(EarError::CrcMismatch(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn crc_errors_carry_recent_frames_when_enabled() {
+        let good = EarPacket::encode(response::EQ_PRIMARY, 1, &[2]);
+        let mut corrupt = EarPacket::encode(response::EQ_PRIMARY, 2, &[3]);
+        *corrupt.last_mut().unwrap() ^= 0xFF;
+        let stream = [good.clone(), corrupt.clone(), corrupt.clone()].concat();
+
+        let mut connection = EarConnection::from_io(
+            "test",
+            std::io::Cursor::new(stream.clone()),
+            tokio::io::sink(),
+        );
+        connection.set_max_crc_failures(2);
+        connection.read_packet().await.unwrap();
+        assert!(matches!(
+            connection.read_packet().await,
+            Err(EarError::CrcMismatch(packets)) if packets.is_empty()
+        ));
+
+        connection =
+            EarConnection::from_io("test", std::io::Cursor::new(stream), tokio::io::sink());
+        connection.set_max_crc_failures(2);
+        connection.set_packet_history(2);
+        connection.read_packet().await.unwrap();
+        let Err(EarError::CrcMismatch(packets)) = connection.read_packet().await else {
+            panic!("expected a CRC mismatch");
+        };
+        assert_eq!(packets, vec![format_hex(&corrupt), format_hex(&corrupt)]);
+    }
+
+    #[tokio::test]
+    async fn a_dead_stream_reports_not_connected() {
+        // The device end is gone: writes hit a broken pipe.
+        let (device, host) = tokio::io::duplex(64);
+        let (host_read, host_write) = tokio::io::split(host);
+        drop(device);
+        let connection = EarConnection::from_io("test", host_read, host_write);
+        assert!(matches!(
+            connection.send_command(command::REQUEST_BATTERY, &[]).await,
+            Err(EarError::NotConnected)
+        ));
+        assert!(connection.closed().borrow().is_some());
+        assert!(matches!(
+            connection.read_packet().await,
+            Err(EarError::NotConnected)
+        ));
+
+        // The device closed its side: reads hit end of stream.
+        let connection = EarConnection::from_io("test", tokio::io::empty(), tokio::io::sink());
+        assert!(matches!(
+            connection.read_packet().await,
+            Err(EarError::NotConnected)
+        ));
+        assert!(matches!(
+            connection.send_command(command::REQUEST_BATTERY, &[]).await,
+            Err(EarError::NotConnected)
+        ));
+    }
+
+    #[tokio::test]
+    async fn close_shuts_down_the_stream() {
+        let (mut device, host) = tokio::io::duplex(1024);
+        let (host_read, host_write) = tokio::io::split(host);
+        let connection = EarConnection::from_io("test", host_read, host_write);
+
+        connection.close().await.unwrap();
+        let mut buffer = [0u8; 8];
+        assert_eq!(device.read(&mut buffer).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn timed_out_read_is_retried() {
+        let (device, host) = tokio::io::duplex(4096);
+        let (host_read, host_write) = tokio::io::split(host);
+        let mut connection = EarConnection::from_io("test", host_read, host_write);
+        connection.set_timeout(Duration::from_millis(50));
+        connection.set_read_retries(1);
+
+        // The fake device drops the first request and answers the second.
+        let responder = tokio::spawn(async move {
+            let (mut rx, mut tx) = tokio::io::split(device);
+            let mut buffer = Vec::new();
+            let mut requests = 0;
+            loop {
+                let mut chunk = [0u8; 64];
+                let n = rx.read(&mut chunk).await.unwrap();
+                buffer.extend_from_slice(&chunk[..n]);
+                while let Some(request) = EarPacket::try_parse(&mut buffer).unwrap() {
+                    requests += 1;
+                    if requests == 2 {
+                        let reply =
+                            EarPacket::encode(response::EQ_PRIMARY, request.operation_id, &[3]);
+                        tx.write_all(&reply).await.unwrap();
+                        return requests;
+                    }
+                }
+            }
+        });
+
+        let mode = connection
+            .transact(
+                command::REQUEST_EQ,
+                &[],
+                |packet| packet.payload.first().copied(),
+                "eq",
+            )
+            .await
+            .unwrap();
+        assert_eq!(mode, 3);
+        assert_eq!(responder.await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn merged_reply_stops_shortly_after_its_last_packet() {
+        let (mut device, host) = tokio::io::duplex(1024);
+        let (host_read, host_write) = tokio::io::split(host);
+        let mut connection = EarConnection::from_io("test", host_read, host_write);
+        connection.set_timeout(Duration::from_secs(10));
+        device
+            .write_all(&EarPacket::encode(
+                response::BATTERY_PRIMARY,
+                1,
+                &[0x01, 0x02, 0x50],
+            ))
+            .await
+            .unwrap();
+
+        let started = tokio::time::Instant::now();
+        let packets = connection
+            .transact_merge(
+                command::REQUEST_BATTERY,
+                &[],
+                0,
+                |count, packet| {
+                    (packet.command == response::BATTERY_PRIMARY).then(|| {
+                        *count += 1;
+                        false
+                    })
+                },
+                "battery",
+            )
+            .await
+            .unwrap();
+        assert_eq!(packets, 1);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn confirmed_writes_wait_for_their_ack() {
+        let (device, host) = tokio::io::duplex(4096);
+        let (host_read, host_write) = tokio::io::split(host);
+        let connection = EarConnection::from_io("test", host_read, host_write);
+        let ack = ack_command(command::CMD_SET_LED_CASE_COLORS);
+
+        // Acks only the first write, after a notification and a packet that
+        // reuses the operation id under another command.
+        tokio::spawn(async move {
+            let (mut rx, mut tx) = tokio::io::split(device);
+            let mut buffer = Vec::new();
+            let mut acked = false;
+            loop {
+                let mut chunk = [0u8; 64];
+                let Ok(n @ 1..) = rx.read(&mut chunk).await else {
+                    return;
+                };
+                buffer.extend_from_slice(&chunk[..n]);
+                while let Some(request) = EarPacket::try_parse(&mut buffer).unwrap() {
+                    if acked {
+                        continue;
+                    }
+                    acked = true;
+                    let id = request.operation_id;
+                    for packet in [
+                        EarPacket::encode(response::EQ_PRIMARY, id.wrapping_add(7), &[3]),
+                        EarPacket::encode(response::ANC_PRIMARY, id, &[1]),
+                        EarPacket::encode(ack, id, &[]),
+                    ] {
+                        tx.write_all(&packet).await.unwrap();
+                    }
+                }
+            }
+        });
+
+        let mut notifications = connection.notifications();
+        let wait = Duration::from_millis(100);
+        let first = connection
+            .send_command_confirmed(command::CMD_SET_LED_CASE_COLORS, &[1], ack, wait)
+            .await
+            .unwrap();
+        assert!(first.acknowledged);
+        // The packets read before the ack are passed on, in order.
+        assert_eq!(
+            notifications.recv().await.unwrap().command,
+            response::EQ_PRIMARY
+        );
+        assert_eq!(
+            notifications.recv().await.unwrap().command,
+            response::ANC_PRIMARY
+        );
+
+        let second = connection
+            .send_command_confirmed(command::CMD_SET_LED_CASE_COLORS, &[1], ack, wait)
+            .await
+            .unwrap();
+        assert_eq!(
+            second,
+            CommandAck {
+                operation_id: first.operation_id + 1,
+                acknowledged: false
+            }
+        );
+
+        // Having timed out once, the ack isn't waited for again.
+        let started = tokio::time::Instant::now();
+        let third = connection
+            .send_command_confirmed(command::CMD_SET_LED_CASE_COLORS, &[1], ack, wait)
+            .await
+            .unwrap();
+        assert!(!third.acknowledged);
+        assert!(started.elapsed() < wait);
+    }
+}
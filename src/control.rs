@@ -0,0 +1,355 @@
+//! Line-oriented control socket for shell scripts. Each request is one line
+//! such as `anc set high` or `battery`, and each reply is one line of JSON:
+//! the same body the HTTP API returns, or `{ "error": ..., "code": ... }`.
+//!
+//! ```sh
+//! echo "battery" | socat - UNIX-CONNECT:/run/earctl.sock
+//! ```
+
+use std::{
+    fs::{DirBuilder, Permissions},
+    io,
+    os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::Serialize;
+use serde_json::{Value, json};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    task::JoinHandle,
+};
+use tracing::{Instrument, info, warn};
+
+use crate::{
+    error::EarError,
+    service::EarManager,
+    types::{AncLevel, EarSide, LatencyMode},
+};
+
+const HELP: &str = "commands: session, disconnect, battery [fresh], anc [get|set <level>], \
+                    eq [get|set <mode>], latency [get|set normal|low], in-ear, firmware, \
+                    ear-fit, ring on|off [left|right], ping";
+
+#[derive(Debug, PartialEq)]
+enum ControlCommand {
+    Ping,
+    Disconnect,
+    Device(DeviceCommand),
+}
+
+/// Commands that run against the open session.
+#[derive(Debug, PartialEq)]
+enum DeviceCommand {
+    Session,
+    Battery { fresh: bool },
+    AncGet,
+    AncSet(AncLevel),
+    EqGet,
+    EqSet(u8),
+    LatencyGet,
+    LatencySet(LatencyMode),
+    InEar,
+    Firmware,
+    EarFit,
+    Ring { enable: bool, side: Option<EarSide> },
+}
+
+impl ControlCommand {
+    fn parse(line: &str) -> Result<Self, EarError> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let command = match words.as_slice() {
+            ["ping"] => Self::Ping,
+            ["disconnect"] => Self::Disconnect,
+            words => Self::Device(DeviceCommand::parse(words)?),
+        };
+        Ok(command)
+    }
+}
+
+impl DeviceCommand {
+    fn parse(words: &[&str]) -> Result<Self, EarError> {
+        let command = match words {
+            ["session"] => Self::Session,
+            ["battery"] => Self::Battery { fresh: false },
+            ["battery", "fresh"] => Self::Battery { fresh: true },
+            ["anc"] | ["anc", "get"] => Self::AncGet,
+            ["anc", "set", level] => Self::AncSet(level.parse().map_err(invalid)?),
+            ["eq"] | ["eq", "get"] => Self::EqGet,
+            ["eq", "set", mode] => Self::EqSet(
+                mode.parse()
+                    .map_err(|_| invalid("EQ mode must be a number"))?,
+            ),
+            ["latency"] | ["latency", "get"] => Self::LatencyGet,
+            ["latency", "set", mode] => Self::LatencySet(parse_latency_mode(mode)?),
+            ["in-ear"] => Self::InEar,
+            ["firmware"] => Self::Firmware,
+            ["ear-fit"] => Self::EarFit,
+            ["ring", toggle, rest @ ..] if rest.len() <= 1 => {
+                let enable = match *toggle {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(invalid("ring expects `on` or `off`")),
+                };
+                let side = rest
+                    .first()
+                    .map(|side| side.parse::<EarSide>())
+                    .transpose()
+                    .map_err(invalid)?;
+                Self::Ring { enable, side }
+            }
+            _ => return Err(EarError::InvalidInput(format!("unknown command; {}", HELP))),
+        };
+        Ok(command)
+    }
+}
+
+fn invalid(message: &str) -> EarError {
+    EarError::InvalidInput(message.to_string())
+}
+
+fn parse_latency_mode(mode: &str) -> Result<LatencyMode, EarError> {
+    match mode {
+        "normal" => Ok(LatencyMode::Normal),
+        "low" => Ok(LatencyMode::Low),
+        _ => Err(invalid("latency mode must be normal or low")),
+    }
+}
+
+fn to_json<T: Serialize>(value: T) -> Result<Value, EarError> {
+    serde_json::to_value(value).map_err(|err| EarError::Io(io::Error::other(err)))
+}
+
+async fn execute(manager: &EarManager, command: ControlCommand) -> Result<Value, EarError> {
+    let ok = || json!({ "status": "ok" });
+    match command {
+        ControlCommand::Ping => Ok(ok()),
+        ControlCommand::Disconnect => {
+            manager.disconnect().await?;
+            Ok(ok())
+        }
+        ControlCommand::Device(command) => {
+            let session = manager.session().await?;
+            match command {
+                DeviceCommand::Session => to_json(session.info().await),
+                DeviceCommand::Battery { fresh: false } => to_json(session.read_battery().await?),
+                DeviceCommand::Battery { fresh: true } => to_json(session.refresh_battery().await?),
+                DeviceCommand::AncGet => to_json(session.read_anc().await?),
+                DeviceCommand::AncSet(level) => {
+                    session.set_anc(level).await?;
+                    Ok(ok())
+                }
+                DeviceCommand::EqGet => to_json(session.read_eq().await?),
+                DeviceCommand::EqSet(mode) => {
+                    session.set_eq_mode(mode).await?;
+                    Ok(ok())
+                }
+                DeviceCommand::LatencyGet => to_json(session.read_latency().await?),
+                DeviceCommand::LatencySet(mode) => to_json(session.set_latency_mode(mode).await?),
+                DeviceCommand::InEar => to_json(session.read_in_ear().await?),
+                DeviceCommand::Firmware => to_json(session.read_firmware().await?),
+                DeviceCommand::EarFit => to_json(session.read_ear_fit_result().await?),
+                DeviceCommand::Ring { enable, side } => {
+                    session.ring_buds(enable, side).await?;
+                    Ok(ok())
+                }
+            }
+        }
+    }
+}
+
+async fn respond(manager: &EarManager, line: &str) -> Value {
+    let result = match ControlCommand::parse(line) {
+        Ok(command) => execute(manager, command).await,
+        Err(err) => Err(err),
+    };
+    result.unwrap_or_else(|err| json!({ "error": err.to_string(), "code": err.code() }))
+}
+
+async fn handle_client(manager: Arc<EarManager>, stream: UnixStream) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let span = tracing::info_span!("control", id = %uuid::Uuid::new_v4(), command = line);
+        let mut reply = respond(&manager, line).instrument(span).await.to_string();
+        reply.push('\n');
+        writer.write_all(reply.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+/// Serves the control protocol on a Unix socket at `path`, replacing a stale
+/// socket file left by a previous run. Anything else at `path` is left alone.
+/// The protocol takes no token, so only the server's own user may connect:
+/// the socket is bound inside a fresh 0700 directory next to `path`, made
+/// 0600 and then renamed into place, so it is never reachable with the
+/// looser mode the umask would give it.
+pub struct ControlSocket {
+    path: PathBuf,
+    listener: UnixListener,
+}
+
+impl ControlSocket {
+    pub fn bind(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(&path)?,
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} exists and is not a socket", path.display()),
+                ));
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let staging = parent.join(format!(".earctl-{}", uuid::Uuid::new_v4()));
+        DirBuilder::new().mode(0o700).create(&staging)?;
+        let staged = staging.join("control.sock");
+        let bound = Self::bind_private(&staged, &path);
+        let _ = std::fs::remove_file(&staged);
+        let _ = std::fs::remove_dir(&staging);
+        Ok(Self {
+            path,
+            listener: bound?,
+        })
+    }
+
+    fn bind_private(staged: &Path, path: &Path) -> io::Result<UnixListener> {
+        let listener = UnixListener::bind(staged)?;
+        std::fs::set_permissions(staged, Permissions::from_mode(0o600))?;
+        std::fs::rename(staged, path)?;
+        Ok(listener)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn spawn(self, manager: Arc<EarManager>) -> JoinHandle<()> {
+        info!("control socket listening on {}", self.path.display());
+        tokio::spawn(async move {
+            loop {
+                match self.listener.accept().await {
+                    Ok((stream, _)) => {
+                        let manager = manager.clone();
+                        tokio::spawn(async move {
+                            if let Err(err) = handle_client(manager, stream).await {
+                                warn!("control socket client error: {}", err);
+                            }
+                        });
+                    }
+                    Err(err) => warn!("control socket accept failed: {}", err),
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::PermissionsExt, sync::Arc};
+
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    use super::{ControlCommand, ControlSocket, DeviceCommand};
+    use crate::{
+        service::EarManager,
+        types::{AncLevel, EarSide, LatencyMode},
+    };
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            ControlCommand::parse("anc set high").unwrap(),
+            ControlCommand::Device(DeviceCommand::AncSet(AncLevel::NoiseCancellationHigh))
+        );
+        assert_eq!(
+            ControlCommand::parse("  battery   fresh ").unwrap(),
+            ControlCommand::Device(DeviceCommand::Battery { fresh: true })
+        );
+        assert_eq!(
+            ControlCommand::parse("latency set low").unwrap(),
+            ControlCommand::Device(DeviceCommand::LatencySet(LatencyMode::Low))
+        );
+        assert_eq!(
+            ControlCommand::parse("ring on left").unwrap(),
+            ControlCommand::Device(DeviceCommand::Ring {
+                enable: true,
+                side: Some(EarSide::Left)
+            })
+        );
+        assert!(ControlCommand::parse("eq set loud").is_err());
+        assert!(ControlCommand::parse("reboot").is_err());
+    }
+
+    #[tokio::test]
+    async fn replies_with_one_json_line_per_command() {
+        let path = std::env::temp_dir().join(format!("earctl-{}.sock", uuid::Uuid::new_v4()));
+        let socket = ControlSocket::bind(&path).unwrap();
+        let server = socket.spawn(Arc::new(EarManager::new()));
+
+        let stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        writer.write_all(b"ping\nbattery\n").await.unwrap();
+
+        let ping: serde_json::Value =
+            serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(ping["status"], "ok");
+        let battery: serde_json::Value =
+            serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(battery["code"], "no_session");
+
+        server.abort();
+
+        // The stale socket from the first run is replaced.
+        let socket = ControlSocket::bind(&path).unwrap();
+        drop(socket);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn socket_is_private_to_its_owner() {
+        let dir = std::env::temp_dir().join(format!("earctl-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("control.sock");
+
+        // SAFETY: umask only swaps the process file mode mask.
+        let previous = unsafe { libc::umask(0) };
+        let socket = ControlSocket::bind(&path);
+        unsafe { libc::umask(previous) };
+        let socket = socket.unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // The staging directory is gone; only the socket is left.
+        let entries: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+        let stream = tokio::net::UnixStream::connect(&path).await;
+        assert!(stream.is_ok());
+
+        drop(socket);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn refuses_to_replace_files_that_are_not_sockets() {
+        let path = std::env::temp_dir().join(format!("earctl-{}.txt", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "notes").unwrap();
+        let err = ControlSocket::bind(&path).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::AddrInUse);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "notes");
+        let _ = std::fs::remove_file(&path);
+    }
+}
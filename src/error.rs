@@ -2,9 +2,13 @@ use std::io;
 
 use thiserror::Error;
 
+use crate::{bluetooth::PreflightReport, types::GestureConflict};
+
 #[derive(Debug, Error)]
 pub enum EarError {
-    #[error("serial port is not connected")]
+    /// The session exists but its RFCOMM stream has ended, unlike
+    /// [`EarError::NoSession`] where nothing was ever connected.
+    #[error("the device connection has closed; reconnect to continue")]
     NotConnected,
     #[error("serial session already active")]
     AlreadyConnected,
@@ -12,14 +16,39 @@ pub enum EarError {
     NoSession,
     #[error("operation '{0}' is not supported by the connected model")]
     Unsupported(&'static str),
+    #[error("operation '{0}' is disabled on this server")]
+    Disabled(&'static str),
+    #[error("missing or invalid bearer token")]
+    Unauthorized,
+    #[error("{0} are unavailable in dry-run mode")]
+    DryRun(&'static str),
+    #[error("invalid input: {0}")]
+    InvalidInput(String),
+    #[error("{0} not found")]
+    NotFound(String),
     #[error("model metadata is missing")]
     UnknownModel,
+    /// The second field holds the hex of the frames received last, when
+    /// [`set_packet_history`](crate::connection::EarConnection::set_packet_history)
+    /// is on; otherwise it's empty.
     #[error("timed out while waiting for {0}")]
-    Timeout(&'static str),
+    Timeout(&'static str, Vec<String>),
     #[error("failed to decode packet header")]
     InvalidPacket,
+    #[error("device did not apply {setting} {requested}; it reports {actual}")]
+    NotApplied {
+        setting: &'static str,
+        requested: String,
+        actual: String,
+    },
+    /// Carries recent frames like [`EarError::Timeout`], including the
+    /// corrupt ones.
     #[error("incorrect packet checksum")]
-    CrcMismatch,
+    CrcMismatch(Vec<String>),
+    #[error("cannot connect: {0}")]
+    Preflight(PreflightReport),
+    #[error("gesture conflicts with {} other slot(s): {}", .0.len(), .0.first().map(ToString::to_string).unwrap_or_default())]
+    GestureConflict(Vec<GestureConflict>),
     #[error("failed to detect device identity: {0}")]
     Detection(String),
     #[error("command `{command}` failed: {output}")]
@@ -27,3 +56,31 @@ pub enum EarError {
     #[error("io error: {0}")]
     Io(#[from] io::Error),
 }
+
+impl EarError {
+    /// Stable, machine-readable identifier for the variant, sent to API
+    /// clients alongside the human-readable message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            EarError::NotConnected => "not_connected",
+            EarError::AlreadyConnected => "already_connected",
+            EarError::NoSession => "no_session",
+            EarError::Unsupported(_) => "unsupported",
+            EarError::Disabled(_) => "disabled",
+            EarError::Unauthorized => "unauthorized",
+            EarError::DryRun(_) => "dry_run",
+            EarError::InvalidInput(_) => "invalid_input",
+            EarError::NotFound(_) => "not_found",
+            EarError::UnknownModel => "unknown_model",
+            EarError::Timeout(..) => "timeout",
+            EarError::InvalidPacket => "invalid_packet",
+            EarError::NotApplied { .. } => "not_applied",
+            EarError::CrcMismatch(_) => "crc_mismatch",
+            EarError::Preflight(_) => "preflight_failed",
+            EarError::GestureConflict(_) => "gesture_conflict",
+            EarError::Detection(_) => "detection_failed",
+            EarError::CommandFailed { .. } => "command_failed",
+            EarError::Io(_) => "io",
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use uuid::Uuid;
+
+use crate::types::{AncStatus, BatteryStatus, DisconnectReason, EqMode};
+
+const EVENT_CHANNEL_CAPACITY: usize = 64;
+
+/// State changes observed on the device, broadcast to every subscriber.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EarEvent {
+    InEarChanged {
+        left_in_ear: Option<bool>,
+        right_in_ear: Option<bool>,
+    },
+    BatteryChanged {
+        battery: BatteryStatus,
+    },
+    /// A read found a different ANC mode than the previous one.
+    AncChanged {
+        anc: AncStatus,
+    },
+    /// A read found a different EQ mode than the previous one.
+    EqChanged {
+        eq: EqMode,
+    },
+    /// The session saw no API activity for `--idle-timeout-mins` and is
+    /// being closed; a `disconnected` event with reason `idle` follows.
+    IdleDisconnected {
+        session_id: Uuid,
+        idle_secs: u64,
+    },
+    /// Sent once when a session's connection ends, for whatever reason.
+    Disconnected {
+        session_id: Uuid,
+        reason: DisconnectReason,
+    },
+    /// [`EarManager::reconnect`](crate::EarManager::reconnect) replaced
+    /// `previous_session_id`, whose `disconnected` event may arrive just
+    /// before or after this one. Nothing reconnects on its own, so a fresh
+    /// `connect` after a drop sends no such event. Subscribers keep their
+    /// stream; the bus outlives every session.
+    Reconnected {
+        previous_session_id: Uuid,
+        session_id: Uuid,
+    },
+    /// The last known state of the active session, sent first on every
+    /// `/api/events` stream so late subscribers don't wait for a change.
+    /// Everything is `None` when nothing has been read yet.
+    Snapshot {
+        session_id: Option<Uuid>,
+        battery: Option<BatteryStatus>,
+        anc: Option<AncStatus>,
+        eq: Option<EqMode>,
+        left_in_ear: Option<bool>,
+        right_in_ear: Option<bool>,
+    },
+}
+
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<EarEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        Self { sender }
+    }
+
+    pub fn publish(&self, event: EarEvent) {
+        tracing::debug!("event: {:?}", event);
+        // Sending only fails when nobody is subscribed, which is fine.
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<EarEvent> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,182 @@
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::{sync::broadcast::error::RecvError, task::JoinHandle};
+use tracing::{info, warn};
+
+use crate::{
+    events::{EarEvent, EventBus},
+    types::{BatteryReading, BatteryStatus, EarSide},
+};
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+const WEBHOOK_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// POSTs to a webhook when a bud or the case drops below a battery threshold.
+/// Fires once per crossing; a side re-arms after it climbs back to the
+/// threshold or starts charging. Charging sides never fire, matching
+/// [`BatteryStatus::sides_below`].
+/// Levels come from battery events: updates the buds push on their own and
+/// every read, including heartbeats and MQTT polls. Without any of those,
+/// nothing is checked.
+pub struct LowBatteryHook {
+    url: String,
+    threshold: u8,
+    client: reqwest::Client,
+}
+
+#[derive(Debug, Serialize)]
+struct LowBatteryPayload {
+    event: &'static str,
+    side: EarSide,
+    percent: u8,
+    threshold: u8,
+}
+
+impl LowBatteryHook {
+    pub fn new(url: impl Into<String>, threshold: u8) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        Self {
+            url: url.into(),
+            threshold,
+            client,
+        }
+    }
+
+    /// Watches battery events on `events` until the bus is closed.
+    pub fn spawn(self, events: &EventBus) -> JoinHandle<()> {
+        let mut receiver = events.subscribe();
+        tokio::spawn(async move {
+            let mut tracker = ThresholdTracker::new(self.threshold);
+            loop {
+                match receiver.recv().await {
+                    Ok(EarEvent::BatteryChanged { battery }) => {
+                        for (side, percent) in tracker.crossings(&battery) {
+                            self.notify(side, percent).await;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("low-battery hook skipped {} events", skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+
+    async fn notify(&self, side: EarSide, percent: u8) {
+        let payload = LowBatteryPayload {
+            event: "low_battery",
+            side,
+            percent,
+            threshold: self.threshold,
+        };
+        info!(
+            "{:?} battery at {}%, calling low-battery hook",
+            side, percent
+        );
+        if let Err(err) = self.post(&payload).await {
+            warn!("low-battery hook failed ({}), retrying once", err);
+            tokio::time::sleep(WEBHOOK_RETRY_DELAY).await;
+            if let Err(err) = self.post(&payload).await {
+                warn!("low-battery hook failed again: {}", err);
+            }
+        }
+    }
+
+    async fn post(&self, payload: &LowBatteryPayload) -> Result<(), reqwest::Error> {
+        self.client
+            .post(&self.url)
+            .json(payload)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Remembers which sides are already below the threshold so each drop is
+/// reported only once.
+struct ThresholdTracker {
+    threshold: u8,
+    below: [bool; 3],
+}
+
+impl ThresholdTracker {
+    fn new(threshold: u8) -> Self {
+        Self {
+            threshold,
+            below: [false; 3],
+        }
+    }
+
+    fn crossings(&mut self, battery: &BatteryStatus) -> Vec<(EarSide, u8)> {
+        let readings = [
+            (EarSide::Left, &battery.left),
+            (EarSide::Right, &battery.right),
+            (EarSide::Case, &battery.case),
+        ];
+        let mut crossed = Vec::new();
+        for (below, (side, reading)) in self.below.iter_mut().zip(readings) {
+            // A disconnected side keeps its previous state.
+            let BatteryReading::Level { percent, charging } = *reading else {
+                continue;
+            };
+            let is_below = !charging && percent < self.threshold;
+            if is_below && !*below {
+                crossed.push((side, percent));
+            }
+            *below = is_below;
+        }
+        crossed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ThresholdTracker;
+    use crate::types::{BatteryReading, BatteryStatus, EarSide};
+
+    fn status(left: u8, right: u8) -> BatteryStatus {
+        charging_status(left, right, false)
+    }
+
+    fn charging_status(left: u8, right: u8, left_charging: bool) -> BatteryStatus {
+        let mut status = BatteryStatus::empty();
+        status.left = BatteryReading::Level {
+            percent: left,
+            charging: left_charging,
+        };
+        status.right = BatteryReading::Level {
+            percent: right,
+            charging: false,
+        };
+        status
+    }
+
+    #[test]
+    fn fires_once_per_crossing() {
+        let mut tracker = ThresholdTracker::new(20);
+        assert!(tracker.crossings(&status(50, 50)).is_empty());
+        assert_eq!(tracker.crossings(&status(19, 50)).len(), 1);
+        assert!(tracker.crossings(&status(15, 50)).is_empty());
+        assert!(tracker.crossings(&status(25, 50)).is_empty());
+        assert_eq!(tracker.crossings(&status(18, 10)).len(), 2);
+    }
+
+    #[test]
+    fn charging_sides_do_not_fire() {
+        let mut tracker = ThresholdTracker::new(20);
+        assert!(tracker.crossings(&charging_status(10, 50, true)).is_empty());
+        // Unplugged while still low, it fires.
+        let crossed = tracker.crossings(&charging_status(10, 50, false));
+        assert_eq!(crossed, [(EarSide::Left, 10)]);
+        // Plugging back in re-arms the side.
+        assert!(tracker.crossings(&charging_status(12, 50, true)).is_empty());
+        assert_eq!(tracker.crossings(&charging_status(11, 50, false)).len(), 1);
+    }
+}
@@ -1,15 +1,26 @@
 pub mod bluetooth;
 pub mod connection;
+pub mod control;
 pub mod error;
+pub mod events;
+pub mod hooks;
 pub mod models;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+mod openapi;
+pub mod profiles;
 pub mod protocol;
 pub mod server;
 pub mod service;
 pub mod types;
 
-pub use connection::EarConnection;
+pub use connection::{CommandAck, EarConnection, LastSeen, ReadBufferConfig};
+pub use control::ControlSocket;
 pub use error::EarError;
+pub use events::{EarEvent, EventBus};
+pub use hooks::LowBatteryHook;
 pub use models::{ModelBase, ModelInfo};
+pub use profiles::ProfileStore;
 pub use server::{ApiState, serve as serve_http};
-pub use service::{EarManager, EarSessionHandle};
+pub use service::{EarManager, EarManagerBuilder, EarSessionHandle};
 pub use types::*;
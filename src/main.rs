@@ -1,14 +1,33 @@
-use std::{io::{self, Write}, net::SocketAddr, sync::Arc};
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
-use anyhow::{Result, anyhow};
-use clap::{ArgAction, Parser, Subcommand, builder::BoolishValueParser};
+use anyhow::{Context, Result, anyhow};
+use clap::{
+    ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum,
+    builder::BoolishValueParser,
+};
+use clap_complete::Shell;
 use ear_api::{
-    AncLevel, ApiState, BatteryStatus, CustomEq, EarManager, EarSide, EnhancedBassState, EqMode,
-    SerialIdentity, SessionInfo, serve_http,
+    AncCapabilities, AncLevel, AncStatus, ApiState, BatteryStatus, ControlSocket, CustomEq,
+    CustomEqState, EarFitProgress, EarFitResult, EarFitState, EarManager, EarSide,
+    EnhancedBassState, EqMode, EqPresets, FirmwareInfo, GestureBackup, GestureConflictPolicy,
+    GestureImportReport, GestureType, LedColor, LedColorSet, LowBatteryHook, ProfileStore,
+    RfcommChannel, SerialIdentity, SessionInfo, SpatialAudioState, serve_http,
 };
 use reqwest::{Client, Method};
-use serde::{Serialize, de::DeserializeOwned};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use serde_json::{Map, Value};
+use tracing_subscriber::{EnvFilter, filter::LevelFilter};
+
+mod addr;
+mod config;
+mod output;
+
+use output::{OutputFormat, print_battery, print_firmware, print_output};
 
 #[derive(Parser)]
 #[command(
@@ -21,9 +40,45 @@ struct Cli {
         long,
         global = true,
         default_value = "http://127.0.0.1:8787",
-        help = "HTTP endpoint for the running API server"
+        help = "HTTP endpoint for the running API server (host:port, [ipv6]:port or a URL)"
     )]
     endpoint: String,
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = OutputFormat::Pretty,
+        help = "Output format for client commands"
+    )]
+    format: OutputFormat,
+    #[arg(
+        long,
+        global = true,
+        help = "Bearer token sent to servers started with --auth-token"
+    )]
+    token: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATH",
+        help = "Config file with default flag values (default: ~/.config/earctl/config.toml)"
+    )]
+    config: Option<PathBuf>,
+    #[arg(
+        short,
+        long,
+        global = true,
+        action = ArgAction::Count,
+        help = "Log more: -v info, -vv debug (client HTTP traffic), -vvv trace"
+    )]
+    verbose: u8,
+    #[arg(
+        long,
+        global = true,
+        value_name = "LEVEL",
+        help = "Log level (off, error, warn, info, debug, trace); overrides -v and RUST_LOG"
+    )]
+    log_level: Option<LevelFilter>,
     #[command(subcommand)]
     command: Commands,
 }
@@ -34,9 +89,46 @@ enum Commands {
     Connect(ConnectArgs),
     AutoConnect(AutoConnectArgs),
     Disconnect,
+    /// Close the session and connect again to the same buds, e.g. after a power cycle
+    Reconnect,
     Session,
+    /// Name the active session; omit the label to clear it
+    Label {
+        label: Option<String>,
+    },
     Detect,
-    Battery,
+    /// List local Bluetooth adapters
+    Adapters,
+    /// Check BlueZ, the adapter and (with --address) the buds before connecting
+    Diagnostics {
+        #[arg(long, help = "Bluetooth device address to check")]
+        address: Option<String>,
+    },
+    /// Show battery levels; `--format bars` draws colored level bars
+    Battery(BatteryArgs),
+    /// Show the firmware version of each bud and the case
+    Firmware,
+    /// Reset EQ, ANC, latency and gestures to their defaults
+    Reset {
+        #[arg(
+            long,
+            help = "Confirm; current EQ, ANC, latency and gesture settings are lost"
+        )]
+        yes: bool,
+    },
+    /// Map a gesture to volume up on the right bud and volume down on the left
+    Volume {
+        #[arg(long, help = "Gesture to use, e.g. triple-tap")]
+        gesture: GestureType,
+    },
+    EarFit {
+        #[command(subcommand)]
+        action: EarFitCommand,
+    },
+    Gestures {
+        #[command(subcommand)]
+        action: Option<GesturesCommand>,
+    },
     Anc {
         #[command(subcommand)]
         action: AncCommand,
@@ -65,45 +157,333 @@ enum Commands {
         #[command(subcommand)]
         action: SwitchCommand,
     },
+    /// Transparency while you talk (command unknown, so no model supported)
+    ConversationMode {
+        #[command(subcommand)]
+        action: SwitchCommand,
+    },
+    /// Spatial audio and head tracking (no model supported yet)
+    SpatialAudio {
+        #[command(subcommand)]
+        action: SpatialAudioCommand,
+    },
+    /// Case LED colors (Ear (1) only)
+    LedCase {
+        #[command(subcommand)]
+        action: LedCaseCommand,
+    },
     Ring(RingArgs),
+    /// Saved sound profiles (ANC, EQ, custom EQ, enhanced bass, latency)
+    Profiles {
+        #[command(subcommand)]
+        action: ProfileCommand,
+    },
+    /// Re-read a value on an interval and print it on every tick
+    Poll(PollArgs),
+    /// Print a shell completion script to stdout
+    #[command(hide = true)]
+    Completions {
+        shell: Shell,
+    },
 }
 
 #[derive(Parser)]
 struct ServerOpts {
-    #[arg(long, default_value = "127.0.0.1:8787")]
-    addr: String,
+    #[arg(
+        long,
+        default_value = "127.0.0.1:8787",
+        help = "Listen address: host:port, [ipv6]:port or a bare IP (port 8787); repeat to listen on several"
+    )]
+    addr: Vec<String>,
+    #[arg(
+        long,
+        help = "Enable POST /api/raw for sending arbitrary commands (can misconfigure the device)"
+    )]
+    unsafe_raw: bool,
+    #[arg(
+        long,
+        help = "Log commands instead of sending them to the device; reads return an error"
+    )]
+    dry_run: bool,
+    #[arg(
+        long,
+        help = "Include the hex of the last frames received from the buds in timeout and checksum errors"
+    )]
+    debug_packets: bool,
+    #[arg(
+        long,
+        value_name = "MS",
+        help = "Wait this long for any device reply, replacing the 2s default and the longer built-in timeouts for slow commands"
+    )]
+    timeout_ms: Option<u64>,
+    #[arg(
+        long,
+        default_value_t = 500,
+        value_name = "MS",
+        help = "Serve battery, ANC and EQ reads from cache for this long (0 disables)"
+    )]
+    cache_ttl_ms: u64,
+    #[arg(
+        long,
+        default_value_t = 1,
+        value_name = "COUNT",
+        help = "Re-send a device read this many times after a timeout"
+    )]
+    read_retries: u8,
+    #[arg(
+        long,
+        default_value_t = 3,
+        value_name = "COUNT",
+        help = "Fail a device read after this many corrupt frames in a row; fewer are skipped"
+    )]
+    max_crc_failures: u8,
+    #[arg(
+        long,
+        default_value_t = 30,
+        value_name = "SECS",
+        help = "Read the battery when the buds have been silent this long, to notice dropped links (0 disables)"
+    )]
+    heartbeat_secs: u64,
+    #[arg(
+        long,
+        default_value_t = 3,
+        value_name = "COUNT",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Close the session after this many failed heartbeats in a row"
+    )]
+    heartbeat_failures: u32,
+    #[arg(
+        long,
+        default_value_t = 0,
+        value_name = "MINS",
+        help = "Disconnect the buds after this many minutes without API requests, freeing them for a phone (0 disables)"
+    )]
+    idle_timeout_mins: u64,
+    #[arg(
+        long = "command-timeout",
+        value_name = "CMD=MS",
+        value_parser = parse_command_timeout,
+        help = "Wait this long for replies to one command code, e.g. 0xC006=6000 (repeatable)"
+    )]
+    command_timeouts: Vec<(u16, Duration)>,
+    #[arg(
+        long,
+        default_value_t = 0,
+        value_name = "MS",
+        help = "Coalesce ANC and custom EQ writes within this window, sending only the last (0 disables)"
+    )]
+    write_debounce_ms: u64,
+    #[arg(
+        long,
+        default_value_t = GestureConflictPolicy::Off,
+        value_name = "POLICY",
+        help = "Check gesture writes for a slot on the other bud with the same gesture and action: off, warn or reject"
+    )]
+    gesture_conflicts: GestureConflictPolicy,
+    #[arg(
+        long,
+        default_value_t = 5,
+        value_name = "SECS",
+        help = "How long to wait for open connections on Ctrl-C/SIGTERM before exiting"
+    )]
+    shutdown_timeout_secs: u64,
+    #[arg(
+        long,
+        default_value_t = 2,
+        value_name = "COUNT",
+        help = "Re-run a failed sdptool or bluetoothctl call this many times, waiting 250ms and doubling"
+    )]
+    shell_retries: u8,
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "JSON file holding saved profiles and battery alert thresholds [default: ~/.local/state/earctl/state.json]"
+    )]
+    state_file: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "POST a JSON notification to this URL when a battery drops below the threshold"
+    )]
+    on_low_battery: Option<String>,
+    #[arg(
+        long,
+        default_value_t = 20,
+        value_name = "PERCENT",
+        value_parser = clap::value_parser!(u8).range(1..=100),
+        help = "Battery percentage that triggers --on-low-battery"
+    )]
+    low_battery_threshold: u8,
+    #[cfg(feature = "mqtt")]
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "Publish battery, ANC and EQ changes to this MQTT broker (mqtt://host:port) and accept commands from it"
+    )]
+    mqtt_broker: Option<String>,
+    #[cfg(feature = "mqtt")]
+    #[arg(
+        long,
+        default_value = "earctl",
+        value_name = "PREFIX",
+        help = "Topic prefix for --mqtt-broker: state goes to PREFIX/battery, PREFIX/anc and PREFIX/eq, commands come from PREFIX/anc/set and PREFIX/eq/set"
+    )]
+    mqtt_topic: String,
+    #[cfg(feature = "mqtt")]
+    #[arg(
+        long,
+        default_value_t = 60,
+        value_name = "SECS",
+        help = "Re-read battery, ANC and EQ this often for --mqtt-broker (0 only publishes what other requests read)"
+    )]
+    mqtt_poll_secs: u64,
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Also accept line commands (e.g. `anc set high`) on this Unix socket"
+    )]
+    control_socket: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Allow --control-socket together with --auth-token; the socket takes no token and is only guarded by its 0600 mode"
+    )]
+    control_socket_without_auth: bool,
+    #[arg(
+        long,
+        help = "Require `Authorization: Bearer <token>` on /api/* routes"
+    )]
+    auth_token: Option<String>,
+    #[arg(
+        long = "auth-exempt",
+        value_name = "PATH_PREFIX",
+        help = "Path prefix that skips the token check (repeatable)"
+    )]
+    auth_exempt: Vec<String>,
+    #[arg(
+        long = "cors-origin",
+        value_name = "ORIGIN",
+        help = "Origin allowed to call the API from a browser (repeatable, `*` for any)"
+    )]
+    cors_origins: Vec<String>,
 }
 
 #[derive(Parser)]
 struct ConnectArgs {
     #[arg(long, help = "Bluetooth device address (e.g., 00:11:22:33:44:55)")]
     address: String,
-    #[arg(long, default_value = "1", help = "RFCOMM channel (default: 1)")]
-    channel: u8,
+    #[arg(
+        long,
+        default_value = "1",
+        help = "RFCOMM channel, or `auto` to look it up with sdptool (falls back to 1)"
+    )]
+    channel: RfcommChannel,
     #[arg(long)]
     model_id: Option<String>,
     #[arg(long)]
     sku: Option<String>,
     #[arg(long)]
     base: Option<ModelBaseArg>,
+    #[arg(long, help = "Local Bluetooth adapter to connect through, e.g. hci1")]
+    adapter: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum ProfileCommand {
+    List,
+    /// Save the buds' current sound settings under a name
+    Save {
+        name: String,
+    },
+    /// Apply a saved profile to the buds
+    Apply {
+        name: String,
+    },
 }
 
 #[derive(Subcommand)]
 enum AncCommand {
     Get,
-    Set { level: AncLevel },
+    /// List the levels the connected model accepts
+    Levels,
+    Set {
+        level: AncLevel,
+        #[arg(long, help = "Read the mode back and fail if the buds ignored it")]
+        verify: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum EarFitCommand {
+    /// Start an ear-fit test; music should not be playing
+    Start,
+    /// Start a test and wait for both buds, printing partial results on timeout
+    Run {
+        #[arg(long, value_name = "SECS", default_value_t = 20)]
+        timeout: u64,
+    },
+    /// Stop waiting on a running test; the buds still finish it
+    Cancel,
+    /// Show the latest result for each bud
+    Result {
+        #[arg(
+            long,
+            value_name = "SECS",
+            help = "Wait up to this long for both buds to finish the test"
+        )]
+        wait: Option<u64>,
+    },
+}
+
+#[derive(Subcommand)]
+enum LedCaseCommand {
+    Get,
+    /// Change some pixels; the rest keep their current color
+    Set {
+        #[arg(
+            long = "pixel",
+            num_args = 2,
+            value_names = ["INDEX", "RRGGBB"],
+            required = true,
+            help = "Pixel index (from 0) and hex color; repeat for more pixels"
+        )]
+        pixels: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum GesturesCommand {
+    /// Show the current gesture mappings (the default)
+    Get,
+    /// Write every gesture slot to a JSON file
+    Export { file: PathBuf },
+    /// Restore gesture slots from a file written by `gestures export`
+    Import {
+        file: PathBuf,
+        /// Import a backup taken from a different model
+        #[arg(long)]
+        force: bool,
+    },
 }
 
 #[derive(Subcommand)]
 enum EqCommand {
     Get,
-    Set { mode: u8 },
+    Set {
+        mode: u8,
+    },
+    /// List the presets the connected model offers
+    Presets,
 }
 
 #[derive(Subcommand)]
 enum CustomEqCommand {
-    Get,
+    Get {
+        #[arg(long, default_value_t = 0, help = "Custom preset slot")]
+        slot: u8,
+    },
     Set {
+        #[arg(long, default_value_t = 0, help = "Custom preset slot")]
+        slot: u8,
         #[arg(long)]
         bass: f32,
         #[arg(long)]
@@ -111,6 +491,8 @@ enum CustomEqCommand {
         #[arg(long)]
         treble: f32,
     },
+    /// List every custom preset slot the buds store
+    Presets,
 }
 
 #[derive(Subcommand)]
@@ -138,6 +520,70 @@ enum EnhancedBassCommand {
     },
 }
 
+#[derive(Subcommand)]
+enum SpatialAudioCommand {
+    Get,
+    Set {
+        #[arg(long, value_parser = BoolishValueParser::new(), action = ArgAction::Set)]
+        enabled: bool,
+        #[arg(long, help = "Follow head movement")]
+        head_tracking: bool,
+    },
+}
+
+#[derive(Parser)]
+struct BatteryArgs {
+    #[arg(long, help = "Keep printing readings until Ctrl-C")]
+    watch: bool,
+    #[arg(
+        long,
+        default_value_t = 5,
+        value_name = "SECS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "watch",
+        help = "Seconds between readings with --watch"
+    )]
+    interval: u64,
+}
+
+#[derive(Parser)]
+struct PollArgs {
+    target: PollTarget,
+    #[arg(
+        long,
+        default_value_t = 5,
+        value_name = "SECS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Seconds between reads"
+    )]
+    interval: u64,
+    #[arg(long, help = "Print a single reading and exit")]
+    once: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum PollTarget {
+    Battery,
+    Anc,
+    Eq,
+    InEar,
+    Latency,
+    Session,
+}
+
+impl PollTarget {
+    fn path(self) -> &'static str {
+        match self {
+            PollTarget::Battery => "/api/battery",
+            PollTarget::Anc => "/api/anc",
+            PollTarget::Eq => "/api/eq",
+            PollTarget::InEar => "/api/in-ear",
+            PollTarget::Latency => "/api/latency",
+            PollTarget::Session => "/api/session",
+        }
+    }
+}
+
 #[derive(Parser)]
 struct RingArgs {
     #[arg(long, value_parser = BoolishValueParser::new(), action = ArgAction::Set)]
@@ -160,19 +606,26 @@ struct AutoConnectArgs {
     baud_rate: Option<u32>,
     #[arg(long)]
     sku: Option<String>,
+    #[arg(
+        long,
+        help = "Only consider devices connected through this adapter, e.g. hci1"
+    )]
+    adapter: Option<String>,
 }
 
 #[derive(Clone)]
 struct ApiClient {
     client: Client,
     base: String,
+    token: Option<String>,
 }
 
 impl ApiClient {
-    fn new(base: String) -> Self {
+    fn new(base: String, token: Option<String>) -> Self {
         Self {
             client: Client::new(),
             base,
+            token,
         }
     }
 
@@ -213,28 +666,51 @@ impl ApiClient {
         B: Serialize,
     {
         let url = self.url(path);
-        let mut req = self.client.request(method, url);
+        tracing::debug!("{} {}", method, url);
+        let mut req = self.client.request(method.clone(), &url);
+        if let Some(token) = &self.token {
+            req = req.bearer_auth(token);
+        }
         if let Some(payload) = body {
+            if tracing::enabled!(tracing::Level::DEBUG) {
+                tracing::debug!("request body: {}", serde_json::to_string(&payload)?);
+            }
             req = req.json(&payload);
         }
         let resp = req.send().await?;
-        if resp.status().is_success() {
-            Ok(resp.json().await?)
+        let status = resp.status();
+        let text = resp.text().await?;
+        tracing::debug!("{} {} -> {}: {}", method, url, status, text);
+        if status.is_success() {
+            Ok(serde_json::from_str(&text)?)
         } else {
-            let status = resp.status();
-            let text = resp.text().await?;
-            Err(anyhow!("request failed ({status}): {text}"))
+            match serde_json::from_str::<ErrorBody>(&text) {
+                Ok(ErrorBody {
+                    error,
+                    code: Some(code),
+                }) => Err(anyhow!("request failed ({status}, {code}): {error}")),
+                _ => Err(anyhow!("request failed ({status}): {text}")),
+            }
         }
     }
 }
 
+/// Error body returned by the API: a message plus a stable `code`.
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    error: String,
+    #[serde(default)]
+    code: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize)]
 struct ConnectRequest {
     address: String,
-    #[serde(default = "default_rfcomm_channel")]
-    channel: u8,
+    channel: RfcommChannel,
     #[serde(skip_serializing_if = "Option::is_none")]
     model: Option<ModelSelector>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    adapter: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -247,6 +723,8 @@ struct AutoConnectRequestBody {
     channel: Option<u8>,
     #[serde(skip_serializing_if = "Option::is_none")]
     sku: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    adapter: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -272,35 +750,143 @@ impl std::str::FromStr for ModelBaseArg {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let args: Vec<_> = std::env::args_os().collect();
+    let config = config::Config::load(config::path_from_args(&args))?;
+    let matches = config.apply(Cli::command()).get_matches_from(args);
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    init_logging(
+        cli.verbose,
+        cli.log_level,
+        matches!(cli.command, Commands::Server(_)),
+    );
     match cli.command {
         Commands::Server(opts) => run_server(opts).await,
+        Commands::Completions { shell } => {
+            let mut command = Cli::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(shell, &mut command, name, &mut io::stdout());
+            Ok(())
+        }
         _ => run_client(cli).await,
     }
 }
 
+/// `--log-level` wins over `-v`; without either, `RUST_LOG` applies as
+/// before. The level covers earctl itself, while dependencies such as hyper
+/// stay at warnings. The client logs to stderr so its output stays parseable.
+fn init_logging(verbose: u8, level: Option<LevelFilter>, server: bool) {
+    let level = level.or(match verbose {
+        0 => None,
+        1 => Some(LevelFilter::INFO),
+        2 => Some(LevelFilter::DEBUG),
+        _ => Some(LevelFilter::TRACE),
+    });
+    let filter = match level {
+        Some(level) => EnvFilter::new(format!(
+            "{},earctl={level},ear_api={level}",
+            level.min(LevelFilter::WARN)
+        )),
+        None => EnvFilter::from_default_env(),
+    };
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    if server {
+        subscriber.init();
+    } else {
+        subscriber.with_writer(io::stderr).init();
+    }
+}
+
 async fn run_server(opts: ServerOpts) -> Result<()> {
-    tracing_subscriber::fmt::init();
-    let manager = Arc::new(EarManager::new());
-    let addr: SocketAddr = opts.addr.parse()?;
-    let state = ApiState { manager };
-    serve_http(state, addr).await?;
+    if opts.control_socket.is_some()
+        && opts.auth_token.is_some()
+        && !opts.control_socket_without_auth
+    {
+        anyhow::bail!(
+            "--control-socket does not check --auth-token; pass --control-socket-without-auth to run both"
+        );
+    }
+    if opts.dry_run {
+        tracing::warn!("dry-run mode: commands will be logged, not sent to the device");
+    }
+    let mut builder = EarManager::builder()
+        .dry_run(opts.dry_run)
+        .cache_ttl(Duration::from_millis(opts.cache_ttl_ms))
+        .read_retries(opts.read_retries)
+        .max_crc_failures(opts.max_crc_failures)
+        .heartbeat(Duration::from_secs(opts.heartbeat_secs))
+        .heartbeat_failures(opts.heartbeat_failures)
+        .idle_timeout(Duration::from_secs(
+            opts.idle_timeout_mins.saturating_mul(60),
+        ))
+        .write_debounce(Duration::from_millis(opts.write_debounce_ms))
+        .gesture_conflicts(opts.gesture_conflicts)
+        .debug_packets(opts.debug_packets);
+    if let Some(timeout) = opts.timeout_ms {
+        builder = builder.timeout(Duration::from_millis(timeout));
+    }
+    for (command, timeout) in opts.command_timeouts {
+        builder = builder.command_timeout(command, timeout);
+    }
+    let manager = Arc::new(builder.build());
+    if let Some(url) = opts.on_low_battery {
+        LowBatteryHook::new(url, opts.low_battery_threshold).spawn(manager.events());
+    }
+    #[cfg(feature = "mqtt")]
+    if let Some(broker) = &opts.mqtt_broker {
+        ear_api::mqtt::MqttBridge::new(broker, opts.mqtt_topic.as_str())?
+            .poll_interval(Duration::from_secs(opts.mqtt_poll_secs))
+            .spawn(manager.clone());
+    }
+    let addrs = addr::resolve_bind_addrs(&opts.addr).await?;
+    let profiles = match opts.state_file.or_else(config::default_state_path) {
+        Some(path) => ProfileStore::open(&path)
+            .with_context(|| format!("failed to load state file {}", path.display()))?,
+        None => {
+            tracing::warn!("no state file location; profiles are kept in memory only");
+            ProfileStore::in_memory()
+        }
+    };
+    let control = match &opts.control_socket {
+        Some(path) => {
+            let socket = ControlSocket::bind(path)?;
+            socket.spawn(manager.clone());
+            Some(path.clone())
+        }
+        None => None,
+    };
+    let state = ApiState {
+        manager,
+        unsafe_raw: opts.unsafe_raw,
+        auth_token: opts.auth_token,
+        auth_exempt: opts.auth_exempt,
+        cors_origins: opts.cors_origins,
+        shutdown_timeout: Duration::from_secs(opts.shutdown_timeout_secs),
+        shell_retries: opts.shell_retries,
+        profiles: Arc::new(profiles),
+    };
+    let result = serve_http(state, &addrs).await;
+    if let Some(path) = control {
+        let _ = std::fs::remove_file(path);
+    }
+    result?;
     Ok(())
 }
 
 async fn run_client(cli: Cli) -> Result<()> {
-    let client = ApiClient::new(cli.endpoint);
+    let client = ApiClient::new(addr::endpoint_url(&cli.endpoint)?, cli.token);
+    let format = cli.format;
     match cli.command {
-        Commands::Server(_) => unreachable!(),
+        Commands::Server(_) | Commands::Completions { .. } => unreachable!(),
         Commands::Connect(args) => {
             let selector = build_selector(&args);
             let req = ConnectRequest {
                 address: args.address,
                 channel: args.channel,
                 model: selector,
+                adapter: args.adapter,
             };
             let resp: SessionInfo = client.post("/api/session/connect", req).await?;
-            print_json(&resp)?;
+            print_output(format, &resp)?;
         }
         Commands::AutoConnect(args) => {
             let body = AutoConnectRequestBody {
@@ -308,81 +894,296 @@ async fn run_client(cli: Cli) -> Result<()> {
                 name: args.name.clone(),
                 channel: args.channel,
                 sku: args.sku.clone(),
+                adapter: args.adapter.clone(),
             };
             let resp: SessionInfo = client.post("/api/session/auto-connect", body).await?;
-            print_json(&resp)?;
+            print_output(format, &resp)?;
         }
         Commands::Disconnect => {
             let resp: Value = client.delete("/api/session").await?;
-            print_json(&resp)?;
+            print_output(format, &resp)?;
+        }
+        Commands::Reconnect => {
+            let info: SessionInfo = client
+                .post("/api/session/reconnect", serde_json::json!({}))
+                .await?;
+            print_output(format, &info)?;
         }
         Commands::Session => {
             let info: SessionInfo = client.get("/api/session").await?;
-            print_json(&info)?;
+            print_output(format, &info)?;
+        }
+        Commands::Label { label } => {
+            let info: SessionInfo = client
+                .post("/api/session/label", serde_json::json!({ "label": label }))
+                .await?;
+            print_output(format, &info)?;
+        }
+        Commands::Adapters => {
+            let adapters: Value = client.get("/api/bluetooth/adapters").await?;
+            print_output(format, &adapters)?;
+        }
+        Commands::Diagnostics { address } => {
+            let body = serde_json::json!({ "address": address });
+            let report: Value = client.post("/api/diagnostics", body).await?;
+            print_output(format, &report)?;
         }
         Commands::Detect => {
             let resp: SerialIdentity = client
                 .post("/api/session/detect", serde_json::json!({}))
                 .await?;
-            print_json(&resp)?;
+            print_output(format, &resp)?;
+        }
+        Commands::Battery(args) if args.watch => {
+            let args = PollArgs {
+                target: PollTarget::Battery,
+                interval: args.interval,
+                once: false,
+            };
+            poll(&client, format, args).await?;
         }
-        Commands::Battery => {
+        Commands::Battery(_) => {
             let battery: BatteryStatus = client.get("/api/battery").await?;
-            print_json(&battery)?;
+            print_battery(format, &battery)?;
         }
+        Commands::Firmware => {
+            let firmware: FirmwareInfo = client.get("/api/firmware").await?;
+            print_firmware(format, &firmware)?;
+        }
+        Commands::Reset { yes } => {
+            if !yes {
+                anyhow::bail!(
+                    "a reset overwrites EQ, ANC, latency and gestures; pass --yes to continue"
+                );
+            }
+            let body = serde_json::json!({ "confirm": true });
+            let state: Value = client.post("/api/reset", body).await?;
+            print_output(format, &state)?;
+        }
+        Commands::Volume { gesture } => {
+            let resp: Value = client
+                .post(
+                    "/api/volume/gestures",
+                    serde_json::json!({ "gesture": gesture }),
+                )
+                .await?;
+            print_output(format, &resp)?;
+        }
+        Commands::EarFit { action } => match action {
+            EarFitCommand::Start => {
+                let resp: Value = client.post("/api/ear-fit", serde_json::json!({})).await?;
+                print_output(format, &resp)?;
+            }
+            EarFitCommand::Run { timeout } => {
+                let path = format!(
+                    "/api/ear-fit/run?timeout_ms={}",
+                    timeout.saturating_mul(1000)
+                );
+                let progress: EarFitProgress = client.post(&path, serde_json::json!({})).await?;
+                print_output(format, &progress)?;
+                if progress.state != EarFitState::Complete {
+                    return Err(anyhow!(
+                        "ear-fit test did not complete ({:?})",
+                        progress.state
+                    ));
+                }
+            }
+            EarFitCommand::Cancel => {
+                let resp: Value = client
+                    .post("/api/ear-fit/cancel", serde_json::json!({}))
+                    .await?;
+                print_output(format, &resp)?;
+            }
+            EarFitCommand::Result { wait } => {
+                let path = match wait {
+                    Some(secs) => format!("/api/ear-fit?wait_ms={}", secs.saturating_mul(1000)),
+                    None => "/api/ear-fit".to_string(),
+                };
+                let result: EarFitResult = client.get(&path).await?;
+                print_output(format, &result)?;
+            }
+        },
+        Commands::Gestures {
+            action: None | Some(GesturesCommand::Get),
+        } => {
+            let gestures: Value = client.get("/api/gestures").await?;
+            print_output(format, &gestures)?;
+        }
+        Commands::Gestures {
+            action: Some(GesturesCommand::Export { file }),
+        } => {
+            let backup: GestureBackup = client.get("/api/gestures/export").await?;
+            std::fs::write(&file, serde_json::to_string_pretty(&backup)?)?;
+            println!(
+                "Saved {} gesture slots to {}",
+                backup.gestures.len(),
+                file.display()
+            );
+        }
+        Commands::Gestures {
+            action: Some(GesturesCommand::Import { file, force }),
+        } => {
+            let backup: GestureBackup = serde_json::from_str(&std::fs::read_to_string(&file)?)?;
+            let path = if force {
+                "/api/gestures/import?force=true"
+            } else {
+                "/api/gestures/import"
+            };
+            let report: GestureImportReport = client.post(path, backup).await?;
+            print_output(format, &report)?;
+            if report.failed > 0 {
+                return Err(anyhow!("{} gesture slots failed to apply", report.failed));
+            }
+        }
+        Commands::Profiles { action } => match action {
+            ProfileCommand::List => {
+                let profiles: Value = client.get("/api/profiles").await?;
+                print_output(format, &profiles)?;
+            }
+            ProfileCommand::Save { name } => {
+                let profile: Value = client
+                    .post(&format!("/api/profiles/{}", name), serde_json::json!({}))
+                    .await?;
+                print_output(format, &profile)?;
+            }
+            ProfileCommand::Apply { name } => {
+                let report: Value = client
+                    .post(
+                        &format!("/api/profiles/{}/apply", name),
+                        serde_json::json!({}),
+                    )
+                    .await?;
+                print_output(format, &report)?;
+            }
+        },
         Commands::Anc { action } => match action {
             AncCommand::Get => {
-                let anc: AncLevel = client.get("/api/anc").await?;
-                print_json(&anc)?;
+                let anc: AncStatus = client.get("/api/anc").await?;
+                print_output(format, &anc)?;
             }
-            AncCommand::Set { level } => {
-                let body = serde_json::json!({ "level": level });
+            AncCommand::Levels => {
+                let capabilities: AncCapabilities = client.get("/api/anc/capabilities").await?;
+                print_output(format, &capabilities)?;
+            }
+            AncCommand::Set { level, verify } => {
+                let body = serde_json::json!({ "level": level, "verify": verify });
                 let resp: Value = client.post("/api/anc", body).await?;
-                print_json(&resp)?;
+                print_output(format, &resp)?;
             }
         },
         Commands::Eq { action } => match action {
             EqCommand::Get => {
                 let eq: EqMode = client.get("/api/eq").await?;
-                print_json(&eq)?;
+                print_output(format, &eq)?;
             }
             EqCommand::Set { mode } => {
                 let body = serde_json::json!({ "mode": mode });
                 let resp: Value = client.post("/api/eq", body).await?;
-                print_json(&resp)?;
+                print_output(format, &resp)?;
+            }
+            EqCommand::Presets => {
+                let presets: EqPresets = client.get("/api/eq/presets").await?;
+                print_output(format, &presets)?;
             }
         },
         Commands::CustomEq { action } => match action {
-            CustomEqCommand::Get => {
-                let eq: CustomEq = client.get("/api/eq/custom").await?;
-                print_json(&eq)?;
+            CustomEqCommand::Get { slot } => {
+                let path = format!("/api/eq/custom?slot={}", slot);
+                let eq: CustomEqState = client.get(&path).await?;
+                print_output(format, &eq)?;
             }
-            CustomEqCommand::Set { bass, mid, treble } => {
+            CustomEqCommand::Set {
+                slot,
+                bass,
+                mid,
+                treble,
+            } => {
                 let body = CustomEq { bass, mid, treble };
-                let resp: Value = client.post("/api/eq/custom", body).await?;
-                print_json(&resp)?;
+                let path = format!("/api/eq/custom?slot={}", slot);
+                let resp: Value = client.post(&path, body).await?;
+                print_output(format, &resp)?;
+            }
+            CustomEqCommand::Presets => {
+                let presets: Vec<CustomEqState> = client.get("/api/eq/custom/presets").await?;
+                print_output(format, &presets)?;
             }
         },
         Commands::Latency { action } => {
-            handle_switch_command(&client, "/api/latency", "low_latency_enabled", action).await?;
+            handle_switch_command(
+                &client,
+                format,
+                "/api/latency",
+                "low_latency_enabled",
+                action,
+            )
+            .await?;
         }
         Commands::InEar { action } => {
-            handle_switch_command(&client, "/api/in-ear", "detection_enabled", action).await?;
+            handle_switch_command(&client, format, "/api/in-ear", "detection_enabled", action)
+                .await?;
         }
         Commands::EnhancedBass { action } => match action {
             EnhancedBassCommand::Get => {
                 let resp: EnhancedBassState = client.get("/api/enhanced-bass").await?;
-                print_json(&resp)?;
+                print_output(format, &resp)?;
             }
             EnhancedBassCommand::Set { enabled, level } => {
                 let body = EnhancedBassState { enabled, level };
                 let resp: Value = client.post("/api/enhanced-bass", body).await?;
-                print_json(&resp)?;
+                print_output(format, &resp)?;
             }
         },
         Commands::PersonalizedAnc { action } => {
-            handle_switch_command(&client, "/api/personalized-anc", "enabled", action).await?;
+            handle_switch_command(&client, format, "/api/personalized-anc", "enabled", action)
+                .await?;
         }
+        Commands::ConversationMode { action } => {
+            handle_switch_command(&client, format, "/api/conversation-mode", "enabled", action)
+                .await?;
+        }
+        Commands::SpatialAudio { action } => match action {
+            SpatialAudioCommand::Get => {
+                let resp: SpatialAudioState = client.get("/api/spatial-audio").await?;
+                print_output(format, &resp)?;
+            }
+            SpatialAudioCommand::Set {
+                enabled,
+                head_tracking,
+            } => {
+                let body = SpatialAudioState {
+                    enabled,
+                    head_tracking,
+                };
+                let resp: Value = client.post("/api/spatial-audio", body).await?;
+                print_output(format, &resp)?;
+            }
+        },
+        Commands::LedCase { action } => match action {
+            LedCaseCommand::Get => {
+                let colors: LedColorSet = client.get("/api/led-case").await?;
+                print_output(format, &colors)?;
+            }
+            LedCaseCommand::Set { pixels } => {
+                let mut body: LedColorSet = client.get("/api/led-case").await?;
+                for pair in pixels.chunks(2) {
+                    let index: usize = pair[0]
+                        .parse()
+                        .map_err(|_| anyhow!("invalid pixel index '{}'", pair[0]))?;
+                    let count = body.pixels.len();
+                    let slot = body.pixels.get_mut(index).ok_or_else(|| {
+                        anyhow!(
+                            "pixel {} is out of range; the case has {} pixels",
+                            index,
+                            count
+                        )
+                    })?;
+                    *slot = parse_led_color(&pair[1]).map_err(|err| anyhow!(err))?;
+                }
+                let resp: Value = client.post("/api/led-case", body).await?;
+                print_output(format, &resp)?;
+            }
+        },
+        Commands::Poll(args) => poll(&client, format, args).await?,
         Commands::Ring(args) => {
             if args.enable {
                 print!("Warning: This will play a loud tone on your earbuds. Type 'y' to confirm: ");
@@ -402,14 +1203,73 @@ async fn run_client(cli: Cli) -> Result<()> {
                 "side": args.side
             });
             let resp: Value = client.post("/api/ring", body).await?;
-            print_json(&resp)?;
+            print_output(format, &resp)?;
         }
     }
     Ok(())
 }
 
+fn parse_command_timeout(value: &str) -> Result<(u16, Duration), String> {
+    let (command, ms) = value
+        .split_once('=')
+        .ok_or_else(|| "expected CMD=MS, e.g. 0xC006=6000".to_string())?;
+    let command = ear_api::protocol::parse_hex_u16(command).map_err(|err| err.to_string())?;
+    let ms: u64 = ms
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid timeout '{}'", ms))?;
+    Ok((command, Duration::from_millis(ms)))
+}
+
+fn parse_led_color(value: &str) -> Result<LedColor, String> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return Err("expected a color like ff8800".to_string());
+    }
+    let mut rgb = [0u8; 3];
+    for (channel, index) in rgb.iter_mut().zip([0, 2, 4]) {
+        *channel = u8::from_str_radix(&hex[index..index + 2], 16)
+            .map_err(|_| format!("invalid hex color '{}'", value))?;
+    }
+    Ok(LedColor(rgb))
+}
+
+/// Fetches `args.target` every `args.interval` seconds until Ctrl-C. Errors
+/// reported by the server are printed and polling continues; an unreachable
+/// server ends the loop with an error.
+async fn poll(client: &ApiClient, format: OutputFormat, args: PollArgs) -> Result<()> {
+    let mut ticker = tokio::time::interval(Duration::from_secs(args.interval));
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+        let result = match args.target {
+            PollTarget::Battery => match client.get::<BatteryStatus>(args.target.path()).await {
+                Ok(battery) => print_battery(format, &battery),
+                Err(err) => Err(err),
+            },
+            target => match client.get::<Value>(target.path()).await {
+                Ok(value) => print_output(format, &value),
+                Err(err) => Err(err),
+            },
+        };
+        match result {
+            Err(err) if args.once || err.downcast_ref::<reqwest::Error>().is_some() => {
+                return Err(err);
+            }
+            Err(err) => eprintln!("{}", err),
+            Ok(()) => {}
+        }
+        if args.once {
+            return Ok(());
+        }
+    }
+}
+
 async fn handle_switch_command(
     client: &ApiClient,
+    format: OutputFormat,
     path: &str,
     field: &str,
     action: SwitchCommand,
@@ -417,13 +1277,13 @@ async fn handle_switch_command(
     match action {
         SwitchCommand::Get => {
             let resp: Value = client.get(path).await?;
-            print_json(&resp)?;
+            print_output(format, &resp)?;
         }
         SwitchCommand::Set { enabled } => {
             let mut payload = Map::new();
             payload.insert(field.to_string(), Value::Bool(enabled));
             let resp: Value = client.post(path, Value::Object(payload)).await?;
-            print_json(&resp)?;
+            print_output(format, &resp)?;
         }
     }
     Ok(())
@@ -439,8 +1299,3 @@ fn build_selector(args: &ConnectArgs) -> Option<ModelSelector> {
         base: args.base.as_ref().map(|b| b.0.clone()),
     })
 }
-
-fn print_json<T: Serialize>(value: &T) -> Result<()> {
-    println!("{}", serde_json::to_string_pretty(value)?);
-    Ok(())
-}
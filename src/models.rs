@@ -1,10 +1,18 @@
 use once_cell::sync::Lazy;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, fmt, str::FromStr};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+use crate::types::{
+    AncLevel, CUSTOM_EQ_MODE, EarSide, GestureAction, GestureCapabilities, GestureType,
+};
+
+/// Serializes as the code string from [`ModelBase::code`], so the wire form
+/// round-trips through [`ModelBase::from_code`]. This is the one JSON enum
+/// that isn't snake_case: the codes are identifiers printed on the buds.
+/// Codes are accepted in any case; unrecognized ones deserialize as `Unknown`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, JsonSchema)]
 pub enum ModelBase {
-    Unknown,
     B181,
     B157,
     B155,
@@ -15,23 +23,44 @@ pub enum ModelBase {
     B168,
     B172,
     B174,
+    #[serde(rename = "UNKNOWN")]
+    Unknown,
 }
 
 impl ModelBase {
+    const KNOWN: [Self; 10] = [
+        Self::B181,
+        Self::B157,
+        Self::B155,
+        Self::B163,
+        Self::B171,
+        Self::B162,
+        Self::B164,
+        Self::B168,
+        Self::B172,
+        Self::B174,
+    ];
+
+    /// Inverse of [`as_str`](Self::as_str); unrecognized codes are `Unknown`.
     pub fn from_code(code: &str) -> Self {
-        match code {
-            "B181" => Self::B181,
-            "B157" => Self::B157,
-            "B155" => Self::B155,
-            "B163" => Self::B163,
-            "B171" => Self::B171,
-            "B162" => Self::B162,
-            "B164" => Self::B164,
-            "B168" => Self::B168,
-            "B172" => Self::B172,
-            "B174" => Self::B174,
-            _ => Self::Unknown,
-        }
+        Self::KNOWN
+            .into_iter()
+            .find(|base| base.as_str() == code)
+            .unwrap_or(Self::Unknown)
+    }
+
+    /// The base code as reported by the device, e.g. `"B181"`. Same as
+    /// [`as_str`](Self::as_str).
+    pub fn code(&self) -> &'static str {
+        self.as_str()
+    }
+
+    /// The digits of the base code (`B181` is 181); `Unknown` is 0.
+    pub fn numeric_id(&self) -> u16 {
+        self.as_str()
+            .strip_prefix('B')
+            .and_then(|digits| digits.parse().ok())
+            .unwrap_or(0)
     }
 
     pub fn as_str(&self) -> &'static str {
@@ -70,22 +99,218 @@ impl ModelBase {
         !matches!(self, Self::B181)
     }
 
+    /// Custom EQ presets the buds store. The Nothing X "my presets" list
+    /// lives in the app; no model exposes more than the active slot 0 over
+    /// the protocol.
+    pub fn custom_eq_slots(self) -> u8 {
+        u8::from(self.supports_custom_eq())
+    }
+
+    /// Derived from [`ModelInfo::anc_capable`] so the model table stays the
+    /// single source of truth. Unknown bases are allowed through.
+    pub fn supports_anc(self) -> bool {
+        MODEL_LIST
+            .iter()
+            .filter(|model| model.base == self)
+            .all(|model| model.anc_capable)
+    }
+
+    /// ANC levels the model accepts, or `None` for `Unknown`, where any level
+    /// is sent. The Ear (1) only has light and maximum noise cancellation
+    /// and the CMF Buds Pro has no adaptive mode; the buds ignore a level
+    /// they lack rather than reject it.
+    pub fn anc_levels(self) -> Option<&'static [AncLevel]> {
+        match self {
+            Self::Unknown => None,
+            _ if !self.supports_anc() => Some(&[]),
+            Self::B181 => Some(&ANC_LEVELS[..4]),
+            Self::B163 => Some(&ANC_LEVELS[..5]),
+            _ => Some(ANC_LEVELS),
+        }
+    }
+
+    /// EQ preset ids the model accepts, or `None` when it isn't restricted.
+    /// The Ear (open) only ships the four standard presets plus custom.
+    pub fn eq_modes(self) -> Option<&'static [u8]> {
+        match self {
+            Self::B174 => Some(&[0, 1, 2, 3, CUSTOM_EQ_MODE]),
+            _ => None,
+        }
+    }
+
+    /// EQ preset ids and the names the Nothing X app shows for them. Nothing
+    /// and CMF buds number their presets differently; `Unknown` has no table.
+    pub fn eq_presets(self) -> &'static [(u8, &'static str)] {
+        match self {
+            Self::B181 => &NOTHING_EQ_PRESETS[..4],
+            Self::B157 | Self::B155 | Self::B171 | Self::B162 | Self::B174 => NOTHING_EQ_PRESETS,
+            Self::B163 | Self::B164 | Self::B168 | Self::B172 => CMF_EQ_PRESETS,
+            Self::Unknown => &[],
+        }
+    }
+
+    /// Which gesture slots and actions the Nothing X app offers for the
+    /// model, or `None` for `Unknown`, where anything is accepted.
+    pub fn gesture_capabilities(self) -> Option<GestureCapabilities> {
+        let gestures: &[GestureType] = match self {
+            Self::B181 | Self::B157 | Self::B174 => &[
+                GestureType::DoubleTap,
+                GestureType::TripleTap,
+                GestureType::TapAndHold,
+            ],
+            Self::B155
+            | Self::B171
+            | Self::B162
+            | Self::B163
+            | Self::B164
+            | Self::B168
+            | Self::B172 => &[
+                GestureType::DoubleTap,
+                GestureType::TripleTap,
+                GestureType::TapAndHold,
+                GestureType::DoubleTapAndHold,
+            ],
+            Self::Unknown => return None,
+        };
+        let actions = ALL_GESTURE_ACTIONS
+            .iter()
+            .copied()
+            .filter(|action| *action != GestureAction::NoiseControl || self.supports_anc())
+            // The Ear (1) changes volume by swiping, not through a gesture slot.
+            .filter(|action| !action.is_volume() || self != Self::B181)
+            .collect();
+        Some(GestureCapabilities {
+            model: self,
+            restricted: true,
+            sides: vec![EarSide::Left, EarSide::Right],
+            gestures: gestures.to_vec(),
+            actions,
+        })
+    }
+
+    /// The action [`crate::service::EarSessionHandle::factory_reset`] gives a
+    /// gesture slot, or `None` to leave it alone (including every slot on
+    /// `Unknown`). The buds don't report their out-of-box mapping, so this
+    /// follows the Ear (1) defaults, with the voice assistant in place of
+    /// noise control on models without ANC.
+    pub fn default_gesture_action(self, gesture: GestureType) -> Option<GestureAction> {
+        if self == Self::Unknown {
+            return None;
+        }
+        match gesture {
+            GestureType::SingleTap => None,
+            GestureType::DoubleTap => Some(GestureAction::PlayPause),
+            GestureType::TripleTap => Some(GestureAction::NextTrack),
+            GestureType::TapAndHold if self.supports_anc() => Some(GestureAction::NoiseControl),
+            GestureType::TapAndHold | GestureType::DoubleTapAndHold => {
+                Some(GestureAction::VoiceAssistant)
+            }
+        }
+    }
+
+    /// The Neckband Pro has no case to report a battery level for.
+    pub fn has_charging_case(self) -> bool {
+        !matches!(self, Self::B164)
+    }
+
     pub fn supports_listening_modes(self) -> bool {
         matches!(self, Self::B168 | Self::B172)
     }
+
+    /// Whether auto-pause can be switched without the wear sensor. The known
+    /// `CMD_SET_IN_EAR` payload has a single flag covering both, and no model
+    /// is known to split them.
+    pub fn supports_separate_auto_pause(self) -> bool {
+        false
+    }
+
+    /// Conversation mode, which switches to transparency while the wearer
+    /// talks. Nothing X offers it on newer ANC models, but its command code
+    /// is unknown for all of them, so none is marked and it can't be read
+    /// or set.
+    pub fn supports_conversation_mode(self) -> bool {
+        false
+    }
+
+    /// Spatial audio and head tracking. The command that switches them
+    /// hasn't been identified for any model, so none is marked; see the
+    /// TODO in `EarSessionHandle::set_spatial_audio`.
+    pub fn supports_spatial_audio(self) -> bool {
+        false
+    }
+
+    /// Switching the buds into firmware-update (DFU) mode. Nothing X only
+    /// sends the trigger right before uploading an image over a transfer
+    /// protocol this crate doesn't implement, so the command is unknown and
+    /// none is marked.
+    pub fn supports_dfu(self) -> bool {
+        false
+    }
 }
 
+/// Ordered so each restricted model's levels are a prefix.
+const ANC_LEVELS: &[AncLevel] = &[
+    AncLevel::Off,
+    AncLevel::Transparency,
+    AncLevel::NoiseCancellationLow,
+    AncLevel::NoiseCancellationHigh,
+    AncLevel::NoiseCancellationMid,
+    AncLevel::NoiseCancellationAdaptive,
+];
+
+const ALL_GESTURE_ACTIONS: &[GestureAction] = &[
+    GestureAction::NoAction,
+    GestureAction::PlayPause,
+    GestureAction::NextTrack,
+    GestureAction::PreviousTrack,
+    GestureAction::VoiceAssistant,
+    GestureAction::NoiseControl,
+    GestureAction::VolumeUp,
+    GestureAction::VolumeDown,
+];
+
+const NOTHING_EQ_PRESETS: &[(u8, &str)] = &[
+    (0, "Balanced"),
+    (1, "Voice"),
+    (2, "More Treble"),
+    (3, "More Bass"),
+    (CUSTOM_EQ_MODE, "Custom"),
+];
+
+const CMF_EQ_PRESETS: &[(u8, &str)] = &[
+    (0, "Dirac Opteo"),
+    (1, "Rock"),
+    (2, "Electronic"),
+    (3, "Pop"),
+    (4, "Enhance Vocals"),
+    (CUSTOM_EQ_MODE, "Custom"),
+    (6, "Classical"),
+];
+
 impl fmt::Display for ModelBase {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.as_str())
     }
 }
 
+impl Serialize for ModelBase {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.code())
+    }
+}
+
+impl<'de> Deserialize<'de> for ModelBase {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        Ok(code.parse().unwrap_or(ModelBase::Unknown))
+    }
+}
+
 impl FromStr for ModelBase {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(ModelBase::from_code(s))
+        Ok(ModelBase::from_code(&s.to_ascii_uppercase()))
     }
 }
 
@@ -306,6 +531,127 @@ const SKU_TO_MODEL_PAIRS: &[(&str, &str)] = &[
     ("11200005", "flaaffy_white"),
 ];
 
+/// Which serial numbers a [`SerialRule`] applies to.
+enum SerialPattern {
+    Exact(&'static str),
+    /// A prefix, optionally restricted to the two-digit year at `[6..8]`.
+    Prefix {
+        prefix: &'static str,
+        years: &'static [&'static str],
+    },
+}
+
+/// Where a [`SerialRule`] takes the SKU from.
+enum SkuSource {
+    Fixed(&'static str),
+    Slice(usize, usize),
+}
+
+struct SerialRule {
+    name: &'static str,
+    pattern: SerialPattern,
+    sku: SkuSource,
+}
+
+/// Checked in order; the first matching rule wins. To support a new serial
+/// format, add a rule here and its SKU to `SKU_TO_MODEL_PAIRS`. The newer
+/// models (Ear, Ear (a), CMF Buds, CMF Buds Pro 2, CMF Neckband Pro) carry
+/// their SKU at `[4..6]` of an `SH` or `13` serial, so the embedded-SKU rules
+/// cover them once the SKU is listed; Ear (open) has its own `MA` rule.
+const SERIAL_RULES: &[SerialRule] = &[
+    SerialRule {
+        name: "test-serial",
+        pattern: SerialPattern::Exact("12345678901234567"),
+        sku: SkuSource::Fixed("01"),
+    },
+    SerialRule {
+        name: "ear-stick",
+        pattern: SerialPattern::Prefix {
+            prefix: "MA",
+            years: &["22", "23"],
+        },
+        sku: SkuSource::Fixed("14"),
+    },
+    SerialRule {
+        name: "ear-open",
+        pattern: SerialPattern::Prefix {
+            prefix: "MA",
+            years: &["24"],
+        },
+        sku: SkuSource::Fixed("11200005"),
+    },
+    SerialRule {
+        name: "sh-embedded-sku",
+        pattern: SerialPattern::Prefix {
+            prefix: "SH",
+            years: &[],
+        },
+        sku: SkuSource::Slice(4, 6),
+    },
+    SerialRule {
+        name: "13-embedded-sku",
+        pattern: SerialPattern::Prefix {
+            prefix: "13",
+            years: &[],
+        },
+        sku: SkuSource::Slice(4, 6),
+    },
+];
+
+const SERIAL_YEAR: std::ops::Range<usize> = 6..8;
+
+/// A SKU derived from a serial number, with the rule that produced it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkuMatch {
+    pub sku: String,
+    pub rule: &'static str,
+}
+
+impl SerialRule {
+    fn apply(&self, serial: &str) -> Option<SkuMatch> {
+        match self.pattern {
+            SerialPattern::Exact(expected) => (serial == expected).then_some(()),
+            SerialPattern::Prefix { prefix, years } => {
+                let year_ok =
+                    years.is_empty() || serial.get(SERIAL_YEAR).is_some_and(|y| years.contains(&y));
+                (serial.len() >= 6 && serial.starts_with(prefix) && year_ok).then_some(())
+            }
+        }?;
+        let sku = match self.sku {
+            SkuSource::Fixed(sku) => sku,
+            SkuSource::Slice(start, end) => serial.get(start..end)?,
+        };
+        Some(SkuMatch {
+            sku: sku.to_string(),
+            rule: self.name,
+        })
+    }
+}
+
+/// Maps a serial number to a SKU using [`SERIAL_RULES`]. Unmatched serials
+/// are logged with their parsed fields so they can be reported and added.
+pub fn sku_from_serial(serial: &str) -> Option<SkuMatch> {
+    let matched = SERIAL_RULES.iter().find_map(|rule| rule.apply(serial));
+    if matched.is_none() {
+        tracing::debug!(
+            "no SKU rule for serial {} (prefix {:?}, year {:?}, sku field {:?})",
+            serial_for_log(serial),
+            serial.get(..2),
+            serial.get(SERIAL_YEAR),
+            serial.get(4..6)
+        );
+    }
+    matched
+}
+
+/// The fields the rules look at (prefix, SKU and year, `[..8]`) with the
+/// unit-specific rest masked, so logs can be shared.
+pub(crate) fn serial_for_log(serial: &str) -> String {
+    let shown: String = serial.chars().take(SERIAL_YEAR.end).collect();
+    let hidden = serial.chars().count() - shown.chars().count();
+    format!("{}{}", shown, "*".repeat(hidden))
+}
+
 pub static MODEL_BY_ID: Lazy<HashMap<&'static str, &'static ModelInfo>> = Lazy::new(|| {
     let mut map = HashMap::new();
     for info in MODEL_LIST {
@@ -324,6 +670,34 @@ pub static SKU_TO_MODEL: Lazy<HashMap<&'static str, &'static ModelInfo>> = Lazy:
     map
 });
 
+/// Bluetooth name fragments, most specific first so "CMF Buds Pro 2" isn't
+/// taken for "CMF Buds Pro". A bare "Nothing Ear" has no entry: it's a prefix
+/// of every Ear name, so it can't single out one model.
+const NAME_TO_BASE: &[(&str, ModelBase)] = &[
+    ("ear (1)", ModelBase::B181),
+    ("ear (stick)", ModelBase::B157),
+    ("ear (2)", ModelBase::B155),
+    ("ear (a)", ModelBase::B162),
+    ("ear (open)", ModelBase::B174),
+    ("cmf buds pro 2", ModelBase::B172),
+    ("cmf buds pro", ModelBase::B163),
+    ("cmf neckband pro", ModelBase::B164),
+    ("cmf buds", ModelBase::B168),
+];
+
+/// Guesses the model from the Bluetooth device name, for buds that don't
+/// answer the serial number request. Colour variants can't be told apart, so
+/// only the base and the product name are meaningful. Names that don't pick
+/// out one model, such as a bare "Nothing Ear", give `None`.
+pub fn model_from_name(name: &str) -> Option<&'static ModelInfo> {
+    let name = name.to_lowercase();
+    let base = NAME_TO_BASE
+        .iter()
+        .find(|(fragment, _)| name.contains(fragment))
+        .map(|(_, base)| *base)?;
+    MODEL_LIST.iter().find(|info| info.base == base)
+}
+
 pub fn model_from_id(id: &str) -> Option<&'static ModelInfo> {
     MODEL_BY_ID.get(id).copied()
 }
@@ -331,3 +705,247 @@ pub fn model_from_id(id: &str) -> Option<&'static ModelInfo> {
 pub fn model_from_sku(sku: &str) -> Option<&'static ModelInfo> {
     SKU_TO_MODEL.get(sku).copied()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        MODEL_LIST, ModelBase, model_from_name, model_from_sku, serial_for_log, sku_from_serial,
+    };
+    use crate::types::{
+        AncLevel, EarSide, GESTURE_COMMON_DEFAULT, GestureAction, GestureCapabilities, GestureSlot,
+        GestureType,
+    };
+
+    #[test]
+    fn model_base_serializes_as_its_code() {
+        let bases = [
+            ModelBase::B181,
+            ModelBase::B157,
+            ModelBase::B155,
+            ModelBase::B163,
+            ModelBase::B171,
+            ModelBase::B162,
+            ModelBase::B164,
+            ModelBase::B168,
+            ModelBase::B172,
+            ModelBase::B174,
+            ModelBase::Unknown,
+        ];
+        for base in bases {
+            let json = serde_json::to_value(base).unwrap();
+            assert_eq!(json, serde_json::json!(base.code()));
+            assert_eq!(serde_json::from_value::<ModelBase>(json).unwrap(), base);
+        }
+        let parse = |code: &str| serde_json::from_value::<ModelBase>(serde_json::json!(code));
+        assert_eq!(parse("b172").unwrap(), ModelBase::B172);
+        assert_eq!(parse("B999").unwrap(), ModelBase::Unknown);
+        assert!(serde_json::from_value::<ModelBase>(serde_json::json!(181)).is_err());
+    }
+
+    #[test]
+    fn anc_support_per_base() {
+        let cases = [
+            (ModelBase::B181, true),
+            (ModelBase::B157, false),
+            (ModelBase::B155, true),
+            (ModelBase::B163, true),
+            (ModelBase::B171, true),
+            (ModelBase::B162, true),
+            (ModelBase::B164, true),
+            (ModelBase::B168, true),
+            (ModelBase::B172, true),
+            (ModelBase::B174, false),
+            (ModelBase::Unknown, true),
+        ];
+        for (base, expected) in cases {
+            assert_eq!(base.supports_anc(), expected, "{}", base);
+        }
+    }
+
+    #[test]
+    fn anc_levels_per_base() {
+        let ear_1 = ModelBase::B181.anc_levels().unwrap();
+        assert!(ear_1.contains(&AncLevel::NoiseCancellationHigh));
+        assert!(!ear_1.contains(&AncLevel::NoiseCancellationMid));
+        assert!(!ear_1.contains(&AncLevel::NoiseCancellationAdaptive));
+        let buds_pro = ModelBase::B163.anc_levels().unwrap();
+        assert!(buds_pro.contains(&AncLevel::NoiseCancellationMid));
+        assert!(!buds_pro.contains(&AncLevel::NoiseCancellationAdaptive));
+        assert_eq!(ModelBase::B155.anc_levels().unwrap().len(), 6);
+        assert!(ModelBase::B157.anc_levels().unwrap().is_empty());
+        assert!(ModelBase::Unknown.anc_levels().is_none());
+    }
+
+    #[test]
+    fn default_gestures_are_assignable() {
+        for model in MODEL_LIST.iter() {
+            let capabilities = model.base.gesture_capabilities().unwrap();
+            for gesture in &capabilities.gestures {
+                let action = model.base.default_gesture_action(*gesture).unwrap();
+                assert!(
+                    capabilities.actions.contains(&action),
+                    "{} {} -> {:?}",
+                    model.base,
+                    gesture,
+                    action
+                );
+            }
+        }
+        assert_eq!(
+            ModelBase::Unknown.default_gesture_action(GestureType::DoubleTap),
+            None
+        );
+    }
+
+    #[test]
+    fn gesture_capabilities_gate_slots() {
+        let slot = |side: EarSide, gesture: GestureType, action: GestureAction| GestureSlot {
+            device: side.device_id(),
+            common: GESTURE_COMMON_DEFAULT,
+            gesture_type: gesture.to_device(),
+            action: action.to_device(),
+        };
+        let ear_2 = ModelBase::B155.gesture_capabilities().unwrap();
+        let hold_anc = slot(
+            EarSide::Left,
+            GestureType::TapAndHold,
+            GestureAction::NoiseControl,
+        );
+        assert!(ear_2.check(&hold_anc).is_ok());
+        assert!(
+            ear_2
+                .check(&slot(
+                    EarSide::Case,
+                    GestureType::DoubleTap,
+                    GestureAction::PlayPause
+                ))
+                .is_err()
+        );
+
+        let stick = ModelBase::B157.gesture_capabilities().unwrap();
+        assert!(stick.check(&hold_anc).is_err());
+        let ear_1 = ModelBase::B181.gesture_capabilities().unwrap();
+        assert!(
+            ear_1
+                .check(&slot(
+                    EarSide::Right,
+                    GestureType::DoubleTap,
+                    GestureAction::VolumeUp
+                ))
+                .is_err()
+        );
+
+        assert!(ModelBase::Unknown.gesture_capabilities().is_none());
+        let unknown = GestureCapabilities::unrestricted(ModelBase::Unknown);
+        let raw = GestureSlot {
+            device: 0x09,
+            common: 0,
+            gesture_type: 0x42,
+            action: 0x42,
+        };
+        assert!(unknown.check(&raw).is_ok());
+        // Bytes the tables have no name for pass through on known models too.
+        assert!(ear_2.check(&raw).is_ok());
+        assert!(
+            stick
+                .check(&GestureSlot {
+                    action: 0x42,
+                    ..hold_anc
+                })
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn restricted_eq_modes_all_have_preset_names() {
+        for model in MODEL_LIST {
+            let presets = model.base.eq_presets();
+            assert!(!presets.is_empty(), "{}", model.base);
+            if let Some(modes) = model.base.eq_modes() {
+                let named: Vec<u8> = presets.iter().map(|(mode, _)| *mode).collect();
+                assert_eq!(named, modes, "{}", model.base);
+            }
+        }
+        assert!(ModelBase::Unknown.eq_presets().is_empty());
+    }
+
+    #[test]
+    fn base_serializes_as_code_and_round_trips() {
+        for model in MODEL_LIST {
+            let json = serde_json::to_string(&model.base).unwrap();
+            assert_eq!(json, format!("\"{}\"", model.base.code()));
+            let parsed: ModelBase = serde_json::from_str(&json).unwrap();
+            assert_eq!(parsed, model.base);
+            assert_eq!(model.base.code().parse::<ModelBase>(), Ok(model.base));
+            let lowercase = model.base.code().to_ascii_lowercase();
+            assert_eq!(lowercase.parse::<ModelBase>(), Ok(model.base));
+            assert_eq!(model.base.code(), format!("B{}", model.base.numeric_id()));
+        }
+        assert_eq!(
+            serde_json::to_string(&ModelBase::Unknown).unwrap(),
+            "\"UNKNOWN\""
+        );
+        let unknown: ModelBase = serde_json::from_str("\"B999\"").unwrap();
+        assert_eq!(unknown, ModelBase::Unknown);
+        assert_eq!(ModelBase::Unknown.numeric_id(), 0);
+    }
+
+    #[test]
+    fn anc_support_matches_every_model_entry() {
+        for model in MODEL_LIST {
+            assert_eq!(model.base.supports_anc(), model.anc_capable, "{}", model.id);
+        }
+    }
+
+    #[test]
+    fn model_detected_from_bluetooth_name() {
+        let cases = [
+            ("Nothing Ear (1)", ModelBase::B181),
+            ("Nothing Ear (a)", ModelBase::B162),
+            ("Ear (stick)", ModelBase::B157),
+            ("CMF Buds Pro 2", ModelBase::B172),
+            ("CMF Buds Pro", ModelBase::B163),
+            ("cmf buds", ModelBase::B168),
+            ("CMF Neckband Pro", ModelBase::B164),
+        ];
+        for (name, base) in cases {
+            assert_eq!(
+                model_from_name(name).map(|info| info.base),
+                Some(base),
+                "{}",
+                name
+            );
+        }
+        assert!(model_from_name("Pixel Buds Pro").is_none());
+        assert!(model_from_name("Nothing Ear").is_none());
+        assert!(model_from_name("Nothing Ear (3)").is_none());
+    }
+
+    #[test]
+    fn sku_rules_preserve_existing_serial_mappings() {
+        let cases = [
+            ("12345678901234567", Some("01"), "test-serial"),
+            ("MA0000221234", Some("14"), "ear-stick"),
+            ("MA0000231234", Some("14"), "ear-stick"),
+            ("MA0000241234", Some("11200005"), "ear-open"),
+            ("SH0076XXXXXX", Some("76"), "sh-embedded-sku"),
+            ("130063XXXXXX", Some("63"), "13-embedded-sku"),
+            // Newer models, one SKU each.
+            ("SH0061XXXXXX", Some("61"), "sh-embedded-sku"),
+            ("SH0048XXXXXX", Some("48"), "sh-embedded-sku"),
+            ("SH0054XXXXXX", Some("54"), "sh-embedded-sku"),
+            ("130079XXXXXX", Some("79"), "13-embedded-sku"),
+        ];
+        for (serial, sku, rule) in cases {
+            let matched = sku_from_serial(serial).unwrap();
+            assert_eq!(Some(matched.sku.as_str()), sku, "{}", serial);
+            assert_eq!(matched.rule, rule, "{}", serial);
+            assert!(model_from_sku(&matched.sku).is_some(), "{}", serial);
+        }
+        for serial in ["MA0000211234", "MA0000", "XX0012345678", "SH00", ""] {
+            assert_eq!(sku_from_serial(serial), None, "{}", serial);
+        }
+        assert_eq!(serial_for_log("SH10241200017"), "SH102412*****");
+        assert_eq!(serial_for_log("SH00"), "SH00");
+    }
+}
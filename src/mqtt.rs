@@ -0,0 +1,316 @@
+//! Bridge to an MQTT broker for home automation, behind the `mqtt` feature.
+//! State is published retained, as the same JSON the HTTP API returns,
+//! whenever a read finds it changed and again on every broker connect:
+//!
+//! | topic               | payload                         |
+//! |---------------------|---------------------------------|
+//! | `<prefix>/battery`  | `GET /api/battery` body         |
+//! | `<prefix>/anc`      | `GET /api/anc` body             |
+//! | `<prefix>/eq`       | `GET /api/eq` body              |
+//! | `<prefix>/anc/set`  | command: a level, e.g. `high`   |
+//! | `<prefix>/eq/set`   | command: a preset number        |
+
+use std::{sync::Arc, time::Duration};
+
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS};
+use serde::Serialize;
+use tokio::{sync::broadcast::error::RecvError, task::JoinHandle, time};
+use tracing::{debug, info, warn};
+
+use crate::{error::EarError, events::EarEvent, service::EarManager, types::AncLevel};
+
+const DEFAULT_PORT: u16 = 1883;
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const REQUEST_CAPACITY: usize = 32;
+/// Pause after a failed broker connection; the next poll reconnects.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, PartialEq)]
+enum MqttCommand {
+    Anc(AncLevel),
+    Eq(u8),
+}
+
+impl MqttCommand {
+    /// `None` for topics that aren't commands.
+    fn parse(prefix: &str, topic: &str, payload: &[u8]) -> Option<Result<Self, EarError>> {
+        let command = topic.strip_prefix(prefix)?.strip_prefix('/')?;
+        let text = String::from_utf8_lossy(payload);
+        let text = text.trim().trim_matches('"');
+        let parsed = match command {
+            "anc/set" => parse_anc_level(text).map(Self::Anc),
+            "eq/set" => text
+                .parse()
+                .map(Self::Eq)
+                .map_err(|_| invalid("EQ mode must be a number")),
+            _ => return None,
+        };
+        Some(parsed)
+    }
+}
+
+/// Takes the short names the CLI and control socket use (`high`) as well as
+/// the API's (`noise_cancellation_high`), so published state can be echoed.
+fn parse_anc_level(text: &str) -> Result<AncLevel, EarError> {
+    text.parse()
+        .or_else(|_| serde_json::from_value(serde_json::Value::from(text)))
+        .map_err(|_| invalid("unknown ANC level"))
+}
+
+fn invalid(message: &str) -> EarError {
+    EarError::InvalidInput(message.to_string())
+}
+
+/// `mqtt://host[:port]`, `tcp://host[:port]` or a bare `host[:port]`. TLS
+/// brokers aren't supported.
+fn parse_broker(url: &str) -> Result<(String, u16), EarError> {
+    let address = match url.split_once("://") {
+        Some(("mqtt" | "tcp", rest)) => rest,
+        Some((scheme, _)) => {
+            return Err(EarError::InvalidInput(format!(
+                "unsupported MQTT scheme '{}'; use mqtt://host:port",
+                scheme
+            )));
+        }
+        None => url,
+    };
+    let address = address.trim_end_matches('/');
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => {
+            let port = port
+                .parse()
+                .map_err(|_| EarError::InvalidInput(format!("invalid MQTT port in '{}'", url)))?;
+            (host, port)
+        }
+        None => (address, DEFAULT_PORT),
+    };
+    if host.is_empty() {
+        return Err(EarError::InvalidInput(format!(
+            "missing MQTT host in '{}'",
+            url
+        )));
+    }
+    Ok((host.to_string(), port))
+}
+
+/// Publishes state changes to a broker and applies the commands it sends.
+pub struct MqttBridge {
+    host: String,
+    port: u16,
+    prefix: String,
+    poll_interval: Option<Duration>,
+}
+
+impl MqttBridge {
+    pub fn new(broker: &str, prefix: impl Into<String>) -> Result<Self, EarError> {
+        let (host, port) = parse_broker(broker)?;
+        let prefix = prefix.into().trim_end_matches('/').to_string();
+        if prefix.is_empty() {
+            return Err(invalid("the MQTT topic prefix must not be empty"));
+        }
+        Ok(Self {
+            host,
+            port,
+            prefix,
+            poll_interval: None,
+        })
+    }
+
+    /// Re-read battery, ANC and EQ this often, so changes made on the buds
+    /// or from the phone reach the broker. Zero (the default) only publishes
+    /// what other requests read. Polls don't count as API activity.
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = Some(interval).filter(|interval| !interval.is_zero());
+        self
+    }
+
+    /// Runs until the event bus is closed.
+    pub fn spawn(self, manager: Arc<EarManager>) -> JoinHandle<()> {
+        let client_id = format!("earctl-{}", std::process::id());
+        let mut options = MqttOptions::new(client_id, self.host.clone(), self.port);
+        options.set_keep_alive(KEEP_ALIVE);
+        let (client, eventloop) = AsyncClient::new(options, REQUEST_CAPACITY);
+        info!(
+            "Bridging to MQTT broker {}:{} under '{}'",
+            self.host, self.port, self.prefix
+        );
+        tokio::spawn(self.run(manager, client, eventloop))
+    }
+
+    async fn run(self, manager: Arc<EarManager>, client: AsyncClient, mut eventloop: EventLoop) {
+        let mut events = manager.events().subscribe();
+        let mut poll = self.poll_interval.map(time::interval);
+        let mut polling: Option<JoinHandle<()>> = None;
+        // Set after a failed connection; the event loop isn't polled until
+        // then, while device events and polls carry on.
+        let mut retry_at: Option<time::Instant> = None;
+        loop {
+            tokio::select! {
+                notification = eventloop.poll(), if retry_at.is_none() => match notification {
+                    // Sessions are clean, so subscriptions go with every reconnect.
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        info!("Connected to MQTT broker {}:{}", self.host, self.port);
+                        for command in ["anc/set", "eq/set"] {
+                            let topic = self.topic(command);
+                            if let Err(err) = client.try_subscribe(&topic, QoS::AtLeastOnce) {
+                                warn!("failed to subscribe to {}: {}", topic, err);
+                            }
+                        }
+                        self.publish_snapshot(&client, manager.event_snapshot().await);
+                    }
+                    Ok(Event::Incoming(Packet::Publish(message))) => {
+                        match MqttCommand::parse(&self.prefix, &message.topic, &message.payload) {
+                            Some(Ok(command)) => {
+                                tokio::spawn(apply(manager.clone(), command));
+                            }
+                            Some(Err(err)) => warn!("ignoring MQTT command on {}: {}", message.topic, err),
+                            None => {}
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(err) => {
+                        warn!("MQTT connection failed: {}; retrying in {:?}", err, RECONNECT_DELAY);
+                        retry_at = Some(time::Instant::now() + RECONNECT_DELAY);
+                    }
+                },
+                _ = async { time::sleep_until(retry_at.unwrap()).await }, if retry_at.is_some() => {
+                    retry_at = None;
+                }
+                event = events.recv() => match event {
+                    Ok(EarEvent::BatteryChanged { battery }) => self.publish(&client, "battery", &battery),
+                    Ok(EarEvent::AncChanged { anc }) => self.publish(&client, "anc", &anc),
+                    Ok(EarEvent::EqChanged { eq }) => self.publish(&client, "eq", &eq),
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("MQTT bridge skipped {} events", skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                _ = async { poll.as_mut().unwrap().tick().await }, if poll.is_some() => {
+                    // A slow read skips the next poll instead of piling up.
+                    if polling.as_ref().is_none_or(JoinHandle::is_finished) {
+                        polling = Some(tokio::spawn(refresh(manager.clone())));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Republishes the last known state, which a broker without
+    /// persistence loses on restart.
+    fn publish_snapshot(&self, client: &AsyncClient, snapshot: EarEvent) {
+        let EarEvent::Snapshot {
+            battery, anc, eq, ..
+        } = snapshot
+        else {
+            return;
+        };
+        if let Some(battery) = battery {
+            self.publish(client, "battery", &battery);
+        }
+        if let Some(anc) = anc {
+            self.publish(client, "anc", &anc);
+        }
+        if let Some(eq) = eq {
+            self.publish(client, "eq", &eq);
+        }
+    }
+
+    fn topic(&self, suffix: &str) -> String {
+        format!("{}/{}", self.prefix, suffix)
+    }
+
+    /// Queues without waiting, since the event loop that drains the queue
+    /// is polled by the same task.
+    fn publish<T: Serialize>(&self, client: &AsyncClient, suffix: &str, value: &T) {
+        let topic = self.topic(suffix);
+        let Ok(payload) = serde_json::to_vec(value) else {
+            return;
+        };
+        if let Err(err) = client.try_publish(&topic, QoS::AtLeastOnce, true, payload) {
+            warn!("failed to publish {}: {}", topic, err);
+        }
+    }
+}
+
+/// Reads publish their own change events, which the bridge picks up.
+async fn refresh(manager: Arc<EarManager>) {
+    let Some(session) = manager.peek_session().await else {
+        return;
+    };
+    if let Err(err) = session.refresh_battery().await {
+        debug!("MQTT poll: battery read failed: {}", err);
+    }
+    if let Err(err) = session.refresh_anc().await {
+        debug!("MQTT poll: ANC read failed: {}", err);
+    }
+    if let Err(err) = session.refresh_eq().await {
+        debug!("MQTT poll: EQ read failed: {}", err);
+    }
+}
+
+/// Writes, then reads back so the new state is published.
+async fn apply(manager: Arc<EarManager>, command: MqttCommand) {
+    let result = async {
+        let session = manager.session().await?;
+        match command {
+            MqttCommand::Anc(level) => session.set_anc_verified(level).await.map(drop),
+            MqttCommand::Eq(mode) => {
+                session.set_eq_mode(mode).await?;
+                session.refresh_eq().await.map(drop)
+            }
+        }
+    }
+    .await;
+    if let Err(err) = result {
+        warn!("MQTT command failed: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MqttBridge, MqttCommand, parse_broker};
+    use crate::{error::EarError, types::AncLevel};
+
+    #[test]
+    fn broker_urls_default_to_the_mqtt_port() {
+        assert_eq!(
+            parse_broker("mqtt://broker.lan").unwrap(),
+            ("broker.lan".to_string(), 1883)
+        );
+        assert_eq!(
+            parse_broker("tcp://10.0.0.2:1884/").unwrap(),
+            ("10.0.0.2".to_string(), 1884)
+        );
+        assert_eq!(
+            parse_broker("localhost:1885").unwrap(),
+            ("localhost".to_string(), 1885)
+        );
+        for url in ["mqtts://broker.lan", "mqtt://:1883", "broker:port"] {
+            assert!(matches!(parse_broker(url), Err(EarError::InvalidInput(_))));
+        }
+        assert!(MqttBridge::new("mqtt://broker.lan", "/").is_err());
+    }
+
+    #[test]
+    fn commands_are_read_from_set_topics() {
+        let parse = |topic, payload: &str| MqttCommand::parse("earctl", topic, payload.as_bytes());
+        assert_eq!(
+            parse("earctl/anc/set", "high").unwrap().unwrap(),
+            MqttCommand::Anc(AncLevel::NoiseCancellationHigh)
+        );
+        assert_eq!(
+            parse("earctl/anc/set", "\"transparency\"")
+                .unwrap()
+                .unwrap(),
+            MqttCommand::Anc(AncLevel::Transparency)
+        );
+        assert_eq!(
+            parse("earctl/eq/set", " 2\n").unwrap().unwrap(),
+            MqttCommand::Eq(2)
+        );
+        assert!(parse("earctl/eq/set", "bass").unwrap().is_err());
+        assert!(parse("earctl/anc", "high").is_none());
+        assert!(parse("other/anc/set", "high").is_none());
+    }
+}
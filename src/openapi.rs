@@ -0,0 +1,412 @@
+//! OpenAPI 3 description of the HTTP API, built from the same serde types the
+//! handlers use so the schemas can't drift from the wire format.
+
+use std::collections::BTreeMap;
+
+use once_cell::sync::Lazy;
+use schemars::{
+    JsonSchema,
+    r#gen::{SchemaGenerator, SchemaSettings},
+};
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+
+use crate::{
+    bluetooth::{AdapterInfo, PreflightReport, ScannedDevice},
+    server::{
+        AckResponse, AncRequest, AncWriteResponse, AutoConnectRequest, BatchRequest, BatchResponse,
+        BootstrapResponse, ConnectRequest, ConnectResponse, DiagnosticsRequest, ErrorBody,
+        GestureRequest, GestureWriteResponse, LatencyRequest, ModelSelector, RawCommandRequest,
+        RawCommandResponse, ResetRequest, RingRequest, SessionLabelRequest, SetEqRequest,
+        VolumeGesturesRequest,
+    },
+    types::{
+        AncCapabilities, AncStatus, AutoPauseState, BatteryAlertConfig, BatteryAlertState,
+        BatteryStatus, ConversationModeState, CustomEq, CustomEqState, DeviceState, EarFitProgress,
+        EarFitResult, EnhancedBassState, EqMode, EqPresets, FirmwareInfo, GestureBackup,
+        GestureCapabilities, GestureConfig, GestureImportReport, InEarState, LatencyState,
+        LedColorSet, ListeningMode, ModelSummary, PersonalizedAncState, SerialIdentity,
+        SessionInfo, SoundProfile, SpatialAudioState,
+    },
+};
+
+pub(crate) static DOCUMENT: Lazy<Value> = Lazy::new(build);
+
+/// `{ "status": "ok" }`, returned by write endpoints.
+#[derive(Serialize, JsonSchema)]
+struct StatusResponse {
+    status: String,
+}
+
+struct Spec {
+    generator: SchemaGenerator,
+    paths: Map<String, Value>,
+}
+
+impl Spec {
+    fn new() -> Self {
+        Self {
+            generator: SchemaSettings::openapi3().into_generator(),
+            paths: Map::new(),
+        }
+    }
+
+    fn get<Res: JsonSchema>(&mut self, path: &str, summary: &str) -> &mut Value {
+        let response = self.schema::<Res>();
+        self.operation("get", path, summary, None, response)
+    }
+
+    fn post<Req: JsonSchema, Res: JsonSchema>(&mut self, path: &str, summary: &str) -> &mut Value {
+        let request = self.schema::<Req>();
+        let response = self.schema::<Res>();
+        self.operation("post", path, summary, Some(request), response)
+    }
+
+    fn post_empty<Res: JsonSchema>(&mut self, path: &str, summary: &str) -> &mut Value {
+        let response = self.schema::<Res>();
+        self.operation("post", path, summary, None, response)
+    }
+
+    fn delete<Res: JsonSchema>(&mut self, path: &str, summary: &str) -> &mut Value {
+        let response = self.schema::<Res>();
+        self.operation("delete", path, summary, None, response)
+    }
+
+    fn schema<T: JsonSchema>(&mut self) -> Value {
+        serde_json::to_value(self.generator.subschema_for::<T>()).unwrap_or_default()
+    }
+
+    fn operation(
+        &mut self,
+        method: &str,
+        path: &str,
+        summary: &str,
+        request: Option<Value>,
+        response: Value,
+    ) -> &mut Value {
+        let mut operation = json!({
+            "summary": summary,
+            "responses": {
+                "200": {
+                    "description": "Success",
+                    "content": { "application/json": { "schema": response } },
+                },
+                "default": {
+                    "description": "Error",
+                    "content": {
+                        "application/json": {
+                            "schema": { "$ref": "#/components/schemas/ErrorBody" },
+                        },
+                    },
+                },
+            },
+        });
+        if let Some(schema) = request {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { "application/json": { "schema": schema } },
+            });
+        }
+        let item = self
+            .paths
+            .entry(path.to_string())
+            .or_insert_with(|| json!({}));
+        item[method] = operation;
+        &mut item[method]
+    }
+
+    fn finish(mut self) -> Value {
+        self.schema::<ErrorBody>();
+        let schemas: Map<String, Value> = self
+            .generator
+            .take_definitions()
+            .into_iter()
+            .map(|(name, schema)| (name, serde_json::to_value(schema).unwrap_or_default()))
+            .collect();
+        json!({
+            "openapi": "3.0.3",
+            "info": {
+                "title": "earctl",
+                "version": env!("CARGO_PKG_VERSION"),
+                "description": "Control Nothing and CMF earbuds over BlueZ RFCOMM.",
+            },
+            "paths": self.paths,
+            "components": {
+                "schemas": schemas,
+                "securitySchemes": {
+                    "bearer": { "type": "http", "scheme": "bearer" },
+                },
+            },
+            // Only enforced when the server runs with --auth-token.
+            "security": [{}, { "bearer": [] }],
+        })
+    }
+}
+
+fn path_param(operation: &mut Value, name: &str, description: &str) {
+    let parameter = json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "description": description,
+        "schema": { "type": "string" },
+    });
+    match operation["parameters"].as_array_mut() {
+        Some(parameters) => parameters.push(parameter),
+        None => operation["parameters"] = json!([parameter]),
+    }
+}
+
+fn query_param(operation: &mut Value, name: &str, description: &str, schema: Value) {
+    let parameter = json!({
+        "name": name,
+        "in": "query",
+        "required": false,
+        "description": description,
+        "schema": schema,
+    });
+    match operation["parameters"].as_array_mut() {
+        Some(parameters) => parameters.push(parameter),
+        None => operation["parameters"] = json!([parameter]),
+    }
+}
+
+/// Marks a read as cacheable, accepting `?fresh=true` to bypass the cache.
+fn fresh_query(operation: &mut Value) {
+    query_param(
+        operation,
+        "fresh",
+        "Skip the read cache and query the device.",
+        json!({ "type": "boolean", "default": false }),
+    );
+}
+
+fn slot_query(operation: &mut Value) {
+    query_param(
+        operation,
+        "slot",
+        "Custom EQ preset slot; only slot 0 exists on current models.",
+        json!({ "type": "integer", "minimum": 0, "default": 0 }),
+    );
+}
+
+fn build() -> Value {
+    let mut spec = Spec::new();
+
+    spec.get::<SessionInfo>("/api/session", "Show the active session");
+    spec.delete::<StatusResponse>("/api/session", "Close the active session");
+    spec.post::<ConnectRequest, ConnectResponse>(
+        "/api/session/connect",
+        "Connect with an explicit address and channel",
+    );
+    spec.post::<AutoConnectRequest, SessionInfo>(
+        "/api/session/auto-connect",
+        "Detect the connected buds and open a session",
+    );
+    spec.post_empty::<SessionInfo>(
+        "/api/session/reconnect",
+        "Close the session and connect again with the same parameters",
+    );
+    spec.post::<AutoConnectRequest, BootstrapResponse>(
+        "/api/session/bootstrap",
+        "Auto-connect, detect the model and read the initial state",
+    );
+    query_param(
+        spec.post_empty::<SerialIdentity>("/api/session/detect", "Read the serial number and SKU"),
+        "force",
+        "Query the device even if the identity was already detected this session.",
+        json!({ "type": "boolean", "default": false }),
+    );
+    spec.post::<ModelSelector, ModelSummary>("/api/session/model", "Override model metadata");
+    spec.post_empty::<SessionInfo>(
+        "/api/session/operation-id/reset",
+        "Restart the operation id sequence",
+    );
+    spec.post::<SessionLabelRequest, SessionInfo>(
+        "/api/session/label",
+        "Set or clear the session label",
+    );
+    spec.get::<Vec<ScannedDevice>>(
+        "/api/bluetooth/devices",
+        "List paired and connected devices known to BlueZ",
+    );
+
+    spec.get::<Vec<AdapterInfo>>(
+        "/api/bluetooth/adapters",
+        "List local Bluetooth adapters and the default one",
+    );
+    spec.post::<DiagnosticsRequest, PreflightReport>(
+        "/api/diagnostics",
+        "Check BlueZ, the adapter and optionally a device before connecting",
+    );
+
+    fresh_query(spec.get::<BatteryStatus>("/api/battery", "Read battery levels"));
+    spec.get::<BatteryAlertState>(
+        "/api/battery/alert",
+        "Read the low-battery threshold and which sides are below it",
+    );
+    spec.post::<BatteryAlertConfig, BatteryAlertState>(
+        "/api/battery/alert",
+        "Set or clear the session's low-battery threshold",
+    );
+    fresh_query(spec.get::<AncStatus>("/api/anc", "Read the ANC mode"));
+    spec.post::<AncRequest, AncWriteResponse>("/api/anc", "Set the ANC mode");
+    spec.get::<AncCapabilities>(
+        "/api/anc/capabilities",
+        "List the ANC levels the model accepts",
+    );
+    fresh_query(spec.get::<EqMode>("/api/eq", "Read the EQ preset"));
+    spec.post::<SetEqRequest, StatusResponse>("/api/eq", "Select an EQ preset");
+    spec.get::<EqPresets>(
+        "/api/eq/presets",
+        "List the EQ presets the model offers and the selected one",
+    );
+    slot_query(spec.get::<CustomEqState>("/api/eq/custom", "Read the custom EQ bands"));
+    slot_query(spec.post::<CustomEq, StatusResponse>("/api/eq/custom", "Set the custom EQ bands"));
+    spec.get::<Vec<CustomEqState>>(
+        "/api/eq/custom/presets",
+        "Read every custom EQ slot the buds store",
+    );
+    spec.get::<ListeningMode>(
+        "/api/listening-mode",
+        "Read the listening mode (CMF Buds and Buds Pro 2)",
+    );
+    spec.get::<EnhancedBassState>("/api/enhanced-bass", "Read enhanced bass");
+    spec.post::<EnhancedBassState, StatusResponse>("/api/enhanced-bass", "Set enhanced bass");
+    spec.get::<PersonalizedAncState>("/api/personalized-anc", "Read personalized ANC");
+    spec.post::<PersonalizedAncState, StatusResponse>(
+        "/api/personalized-anc",
+        "Toggle personalized ANC",
+    );
+    spec.post::<VolumeGesturesRequest, GestureWriteResponse>(
+        "/api/volume/gestures",
+        "Map a gesture to volume up on the right bud and volume down on the left",
+    );
+    spec.get::<InEarState>("/api/in-ear", "Read in-ear detection and worn state");
+    spec.post::<InEarState, StatusResponse>("/api/in-ear", "Toggle in-ear detection");
+    spec.get::<AutoPauseState>(
+        "/api/auto-pause",
+        "Read media auto-pause (follows in-ear detection)",
+    );
+    spec.post::<AutoPauseState, StatusResponse>(
+        "/api/auto-pause",
+        "Set auto-pause on its own; no known model supports this",
+    );
+    spec.get::<ConversationModeState>(
+        "/api/conversation-mode",
+        "Read conversation mode; its command is unknown, so no model supports this",
+    );
+    spec.post::<ConversationModeState, StatusResponse>(
+        "/api/conversation-mode",
+        "Toggle conversation mode; its command is unknown, so no model supports this",
+    );
+    spec.get::<SpatialAudioState>(
+        "/api/spatial-audio",
+        "Read spatial audio; no known model supports this yet",
+    );
+    spec.post::<SpatialAudioState, StatusResponse>(
+        "/api/spatial-audio",
+        "Toggle spatial audio and head tracking; no known model supports this yet",
+    );
+    spec.get::<LatencyState>("/api/latency", "Read low-latency mode");
+    spec.post::<LatencyRequest, LatencyState>("/api/latency", "Set the latency mode");
+    spec.get::<FirmwareInfo>("/api/firmware", "Read firmware versions");
+    spec.post_empty::<StatusResponse>(
+        "/api/firmware/dfu",
+        "Enter firmware-update mode; its trigger is unknown, so no model supports this",
+    );
+    query_param(
+        spec.get::<EarFitResult>("/api/ear-fit", "Read the last ear-fit result"),
+        "wait_ms",
+        "Poll until both buds have a verdict, up to this many milliseconds (max 30000).",
+        json!({ "type": "integer", "minimum": 0 }),
+    );
+    spec.post_empty::<StatusResponse>("/api/ear-fit", "Start an ear-fit test");
+    query_param(
+        spec.post_empty::<EarFitProgress>(
+            "/api/ear-fit/run",
+            "Start an ear-fit test and wait for the result, returning partial progress on timeout",
+        ),
+        "timeout_ms",
+        "How long to wait for both buds, in milliseconds (default 20000, max 30000).",
+        json!({ "type": "integer", "minimum": 0 }),
+    );
+    spec.post_empty::<StatusResponse>(
+        "/api/ear-fit/cancel",
+        "End any ear-fit run waiting on results; the buds finish the test on their own",
+    );
+    spec.get::<Vec<GestureConfig>>("/api/gestures", "Read gesture mappings");
+    spec.post::<GestureRequest, GestureWriteResponse>("/api/gestures", "Update a gesture mapping");
+    spec.get::<GestureCapabilities>(
+        "/api/gestures/capabilities",
+        "List the gesture sides, types and actions the model accepts",
+    );
+    spec.get::<GestureBackup>("/api/gestures/export", "Back up every gesture slot");
+    query_param(
+        spec.post::<GestureBackup, GestureImportReport>(
+            "/api/gestures/import",
+            "Restore a gesture backup, reporting each slot",
+        ),
+        "force",
+        "Import a backup whose model_base differs from the connected buds.",
+        json!({ "type": "boolean", "default": false }),
+    );
+    spec.get::<LedColorSet>("/api/led-case", "Read case LED colors");
+    spec.post::<LedColorSet, AckResponse>("/api/led-case", "Set case LED colors");
+    spec.post::<RingRequest, StatusResponse>("/api/ring", "Play the Find My Buds tone");
+    spec.post::<ResetRequest, DeviceState>(
+        "/api/reset",
+        "Reset EQ, ANC, latency and gestures to defaults",
+    );
+    spec.post::<BatchRequest, BatchResponse>(
+        "/api/batch",
+        "Apply several settings in order, reporting each operation",
+    );
+    spec.get::<BTreeMap<String, SoundProfile>>("/api/profiles", "List saved sound profiles");
+    let save = spec.post_empty::<SoundProfile>(
+        "/api/profiles/{name}",
+        "Save the current sound settings as a profile",
+    );
+    path_param(
+        save,
+        "name",
+        "Profile name: letters, digits, '-', '_' or '.'",
+    );
+    let apply = spec.post_empty::<BatchResponse>(
+        "/api/profiles/{name}/apply",
+        "Apply a saved profile, reporting each setting",
+    );
+    path_param(apply, "name", "Profile name");
+    spec.post::<RawCommandRequest, RawCommandResponse>(
+        "/api/raw",
+        "Send a raw command (requires --unsafe-raw)",
+    );
+
+    let events = spec.get::<crate::events::EarEvent>(
+        "/api/events",
+        "WebSocket stream of device events, one JSON message per event",
+    );
+    events["responses"]["101"] = json!({ "description": "Switching to WebSocket" });
+
+    spec.get::<StatusResponse>("/healthz", "Liveness probe");
+    spec.get::<Value>("/readyz", "Readiness probe; 503 until a session is open");
+
+    spec.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DOCUMENT;
+
+    #[test]
+    fn document_references_resolve() {
+        let document = DOCUMENT.to_string();
+        let schemas = DOCUMENT["components"]["schemas"].as_object().unwrap();
+        for reference in document.split("\"#/components/schemas/").skip(1) {
+            let name = &reference[..reference.find('"').unwrap()];
+            assert!(schemas.contains_key(name), "missing schema {}", name);
+        }
+        assert!(DOCUMENT["paths"]["/api/battery"]["get"].is_object());
+        assert!(DOCUMENT["paths"]["/api/anc"]["post"]["requestBody"].is_object());
+    }
+}
@@ -0,0 +1,290 @@
+use std::io::IsTerminal;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use ear_api::{BatteryReading, BatteryStatus, FirmwareInfo};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// Compact single-line JSON
+    Json,
+    /// Indented JSON
+    #[default]
+    Pretty,
+    /// One `key=value` pair per line
+    Plain,
+    /// Aligned columns
+    Table,
+    /// Colored level bars for battery readings; other output prints as a table
+    Bars,
+}
+
+const BAR_WIDTH: usize = 20;
+
+pub fn print_output<T: Serialize>(format: OutputFormat, value: &T) -> Result<()> {
+    let value = serde_json::to_value(value)?;
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string(&value)?),
+        OutputFormat::Pretty => println!("{}", serde_json::to_string_pretty(&value)?),
+        OutputFormat::Plain => {
+            for (key, text) in flatten(&value) {
+                if key.is_empty() {
+                    println!("{}", text);
+                } else {
+                    println!("{}={}", key, text);
+                }
+            }
+        }
+        OutputFormat::Table | OutputFormat::Bars => print!("{}", render_table(&value)),
+    }
+    Ok(())
+}
+
+/// Battery readings are reshaped into one row per side for the plain and
+/// table formats; the JSON formats keep the API shape.
+pub fn print_battery(format: OutputFormat, battery: &BatteryStatus) -> Result<()> {
+    match format {
+        OutputFormat::Json | OutputFormat::Pretty => print_output(format, battery),
+        OutputFormat::Bars => {
+            let color = std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+            print!("{}", render_battery_bars(battery, color));
+            Ok(())
+        }
+        OutputFormat::Plain | OutputFormat::Table => {
+            let mut sides = Map::new();
+            for (side, reading) in [
+                ("left", &battery.left),
+                ("right", &battery.right),
+                ("case", &battery.case),
+            ] {
+                let mut row = match reading {
+                    BatteryReading::Level { percent, charging } => serde_json::json!({
+                        "status": "connected",
+                        "percent": percent,
+                        "charging": charging,
+                    }),
+                    BatteryReading::Disconnected => serde_json::json!({ "status": "disconnected" }),
+                };
+                if side == "case" {
+                    if let Some(charging_buds) = battery.case_charging_buds {
+                        row["charging_buds"] = Value::from(charging_buds);
+                    }
+                }
+                sides.insert(side.to_string(), row);
+            }
+            print_output(format, &Value::Object(sides))
+        }
+    }
+}
+
+/// The plain and table formats show one version per part; the JSON formats
+/// keep the API shape.
+pub fn print_firmware(format: OutputFormat, firmware: &FirmwareInfo) -> Result<()> {
+    if matches!(format, OutputFormat::Json | OutputFormat::Pretty) {
+        return print_output(format, firmware);
+    }
+    let mut rows = Map::new();
+    match (&firmware.left, &firmware.right) {
+        (Some(left), Some(right)) => {
+            rows.insert("left".to_string(), Value::from(left.as_str()));
+            rows.insert("right".to_string(), Value::from(right.as_str()));
+        }
+        _ => {
+            rows.insert("buds".to_string(), Value::from(firmware.version.as_str()));
+        }
+    }
+    if let Some(case) = &firmware.case {
+        rows.insert("case".to_string(), Value::from(case.as_str()));
+    }
+    print_output(format, &Value::Object(rows))
+}
+
+/// One line per side: a bar colored green, yellow or red by level, the
+/// percentage and a charging marker. A side that isn't reporting says so
+/// instead of showing an empty bar, which would read as 0%.
+fn render_battery_bars(battery: &BatteryStatus, color: bool) -> String {
+    let paint = |code: &str, text: String| {
+        if color {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text
+        }
+    };
+    let mut out = String::new();
+    for (side, reading) in [
+        ("left", &battery.left),
+        ("right", &battery.right),
+        ("case", &battery.case),
+    ] {
+        let line = match reading {
+            BatteryReading::Level { percent, charging } => {
+                let percent = (*percent).min(100);
+                let filled = (usize::from(percent) * BAR_WIDTH + 50) / 100;
+                let code = match percent {
+                    50.. => "32",
+                    20..=49 => "33",
+                    _ => "31",
+                };
+                let bar = paint(code, "█".repeat(filled));
+                let empty = "░".repeat(BAR_WIDTH - filled);
+                let marker = if *charging { " charging" } else { "" };
+                format!("{:<5} {}{} {:>3}%{}", side, bar, empty, percent, marker)
+            }
+            BatteryReading::Disconnected => {
+                format!("{:<5} {}", side, paint("2", "not connected".to_string()))
+            }
+        };
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
+
+fn flatten(value: &Value) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+    flatten_into("", value, &mut pairs);
+    pairs
+}
+
+fn flatten_into(prefix: &str, value: &Value, pairs: &mut Vec<(String, String)>) {
+    let join = |key: &str| {
+        if prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", prefix, key)
+        }
+    };
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, inner) in map {
+                flatten_into(&join(key), inner, pairs);
+            }
+        }
+        Value::Array(items) if !items.is_empty() => {
+            for (index, inner) in items.iter().enumerate() {
+                flatten_into(&join(&index.to_string()), inner, pairs);
+            }
+        }
+        _ => pairs.push((prefix.to_string(), scalar_text(value))),
+    }
+}
+
+fn scalar_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Null => "null".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn render_table(value: &Value) -> String {
+    match value {
+        Value::Array(items) if !items.is_empty() && items.iter().all(Value::is_object) => {
+            let rows: Vec<Vec<(String, String)>> = items.iter().map(flatten).collect();
+            keyed_table(None, rows)
+        }
+        Value::Object(map) if !map.is_empty() && map.values().all(Value::is_object) => {
+            let names: Vec<String> = map.keys().cloned().collect();
+            let rows: Vec<Vec<(String, String)>> = map.values().map(flatten).collect();
+            keyed_table(Some(names), rows)
+        }
+        Value::Object(_) | Value::Array(_) => {
+            let rows = flatten(value)
+                .into_iter()
+                .map(|(key, text)| vec![key, text])
+                .collect();
+            format_table(&["key".to_string(), "value".to_string()], rows)
+        }
+        scalar => format!("{}\n", scalar_text(scalar)),
+    }
+}
+
+/// Builds a table whose columns are the union of the flattened keys of each
+/// row, optionally prefixed by a `name` column.
+fn keyed_table(names: Option<Vec<String>>, rows: Vec<Vec<(String, String)>>) -> String {
+    let mut columns: Vec<String> = Vec::new();
+    for row in &rows {
+        for (key, _) in row {
+            if !columns.contains(key) {
+                columns.push(key.clone());
+            }
+        }
+    }
+    let mut headers = Vec::with_capacity(columns.len() + 1);
+    if names.is_some() {
+        headers.push("name".to_string());
+    }
+    headers.extend(columns.iter().cloned());
+
+    let cells = rows
+        .into_iter()
+        .enumerate()
+        .map(|(index, row)| {
+            let mut cells = Vec::with_capacity(headers.len());
+            if let Some(names) = &names {
+                cells.push(names[index].clone());
+            }
+            for column in &columns {
+                let cell = row
+                    .iter()
+                    .find(|(key, _)| key == column)
+                    .map(|(_, text)| text.clone())
+                    .unwrap_or_else(|| "-".to_string());
+                cells.push(cell);
+            }
+            cells
+        })
+        .collect();
+    format_table(&headers, cells)
+}
+
+fn format_table(headers: &[String], rows: Vec<Vec<String>>) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut out = String::new();
+    let header_cells: Vec<String> = headers.iter().map(|header| header.to_uppercase()).collect();
+    for row in std::iter::once(header_cells).chain(rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        out.push_str(line.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use ear_api::{BatteryReading, BatteryStatus};
+
+    use super::render_battery_bars;
+
+    #[test]
+    fn battery_bars_tell_disconnected_from_empty() {
+        let mut battery = BatteryStatus::empty();
+        battery.left = BatteryReading::Level {
+            percent: 0,
+            charging: true,
+        };
+        battery.right = BatteryReading::Level {
+            percent: 100,
+            charging: false,
+        };
+        let plain = render_battery_bars(&battery, false);
+        let lines: Vec<&str> = plain.lines().collect();
+        assert_eq!(lines[0], format!("left  {}   0% charging", "░".repeat(20)));
+        assert_eq!(lines[1], format!("right {} 100%", "█".repeat(20)));
+        assert_eq!(lines[2], "case  not connected");
+
+        let colored = render_battery_bars(&battery, true);
+        assert!(colored.contains("\x1b[32m"));
+    }
+}
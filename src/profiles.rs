@@ -0,0 +1,232 @@
+//! Named sound profiles kept by the server and persisted in its state file,
+//! a JSON document that also holds per-device settings keyed by Bluetooth
+//! address:
+//!
+//! ```json
+//! {
+//!   "profiles": { "commute": { "anc": "noise_cancellation_high", "eq_mode": 2 } },
+//!   "battery_alerts": { "00:11:22:33:44:55": 20 }
+//! }
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{error::EarError, types::SoundProfile};
+
+const MAX_PROFILE_NAME_LEN: usize = 64;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StateFile {
+    #[serde(default)]
+    profiles: BTreeMap<String, SoundProfile>,
+    /// Low-battery thresholds in percent, by device address.
+    #[serde(default)]
+    battery_alerts: BTreeMap<String, u8>,
+}
+
+/// Profiles by name and per-device settings. Every save rewrites the state
+/// file, if there is one.
+pub struct ProfileStore {
+    path: Option<PathBuf>,
+    state: Mutex<StateFile>,
+}
+
+impl ProfileStore {
+    /// A store that forgets its profiles when the server stops.
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            state: Mutex::new(StateFile::default()),
+        }
+    }
+
+    /// Loads the state file at `path`; a missing file starts empty and is
+    /// created on the first save.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, EarError> {
+        let path = path.into();
+        let state = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|err| {
+                EarError::InvalidInput(format!("invalid state file {}: {}", path.display(), err))
+            })?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => StateFile::default(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self {
+            path: Some(path),
+            state: Mutex::new(state),
+        })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub async fn list(&self) -> BTreeMap<String, SoundProfile> {
+        self.state.lock().await.profiles.clone()
+    }
+
+    pub async fn get(&self, name: &str) -> Result<SoundProfile, EarError> {
+        self.state
+            .lock()
+            .await
+            .profiles
+            .get(name)
+            .cloned()
+            .ok_or_else(|| EarError::NotFound(format!("profile '{}'", name)))
+    }
+
+    /// Stores `profile` under `name`, replacing any profile of that name.
+    /// Nothing changes if the state file can't be written.
+    pub async fn save(&self, name: &str, profile: SoundProfile) -> Result<(), EarError> {
+        validate_name(name)?;
+        let mut state = self.state.lock().await;
+        let previous = state.profiles.insert(name.to_string(), profile);
+        if let Err(err) = self.persist(&state) {
+            match previous {
+                Some(previous) => state.profiles.insert(name.to_string(), previous),
+                None => state.profiles.remove(name),
+            };
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// The low-battery threshold saved for the device at `address`.
+    pub async fn battery_alert(&self, address: &str) -> Option<u8> {
+        self.state.lock().await.battery_alerts.get(address).copied()
+    }
+
+    /// Saves the low-battery threshold for the device at `address`; `None`
+    /// forgets it. Nothing changes if the state file can't be written.
+    pub async fn set_battery_alert(
+        &self,
+        address: &str,
+        threshold: Option<u8>,
+    ) -> Result<(), EarError> {
+        let mut state = self.state.lock().await;
+        let previous = match threshold {
+            Some(threshold) => state.battery_alerts.insert(address.to_string(), threshold),
+            None => state.battery_alerts.remove(address),
+        };
+        if let Err(err) = self.persist(&state) {
+            match previous {
+                Some(previous) => state.battery_alerts.insert(address.to_string(), previous),
+                None => state.battery_alerts.remove(address),
+            };
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Writes through a temporary file so a crash never leaves half a file.
+    fn persist(&self, state: &StateFile) -> Result<(), EarError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let text = serde_json::to_string_pretty(state).map_err(std::io::Error::other)?;
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, text)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+fn validate_name(name: &str) -> Result<(), EarError> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_PROFILE_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(EarError::InvalidInput(format!(
+            "profile names are 1-{} characters of letters, digits, '-', '_' or '.'",
+            MAX_PROFILE_NAME_LEN
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProfileStore;
+    use crate::{
+        error::EarError,
+        types::{AncLevel, SoundProfile},
+    };
+
+    fn profile(eq_mode: u8) -> SoundProfile {
+        SoundProfile {
+            anc: Some(AncLevel::Transparency),
+            eq_mode: Some(eq_mode),
+            ..SoundProfile::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn profiles_survive_a_restart() {
+        let dir = std::env::temp_dir().join(format!("earctl-profiles-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("state.json");
+
+        let store = ProfileStore::open(&path).unwrap();
+        store.save("commute", profile(2)).await.unwrap();
+        store.save("commute", profile(3)).await.unwrap();
+        store.save("desk", profile(0)).await.unwrap();
+
+        let reopened = ProfileStore::open(&path).unwrap();
+        assert_eq!(reopened.get("commute").await.unwrap(), profile(3));
+        assert_eq!(reopened.list().await.len(), 2);
+        assert!(matches!(
+            reopened.get("gym").await,
+            Err(EarError::NotFound(_))
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn battery_alerts_are_kept_per_address() {
+        let dir = std::env::temp_dir().join(format!("earctl-profiles-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("state.json");
+
+        let store = ProfileStore::open(&path).unwrap();
+        store.save("desk", profile(0)).await.unwrap();
+        store
+            .set_battery_alert("00:11:22:33:44:55", Some(20))
+            .await
+            .unwrap();
+        store
+            .set_battery_alert("66:77:88:99:AA:BB", Some(10))
+            .await
+            .unwrap();
+        store
+            .set_battery_alert("66:77:88:99:AA:BB", None)
+            .await
+            .unwrap();
+
+        let reopened = ProfileStore::open(&path).unwrap();
+        assert_eq!(reopened.battery_alert("00:11:22:33:44:55").await, Some(20));
+        assert_eq!(reopened.battery_alert("66:77:88:99:AA:BB").await, None);
+        assert_eq!(reopened.list().await.len(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn bad_names_are_rejected() {
+        let store = ProfileStore::in_memory();
+        for name in ["", "../etc", "a b", &"x".repeat(65)] {
+            assert!(matches!(
+                store.save(name, profile(0)).await,
+                Err(EarError::InvalidInput(_))
+            ));
+        }
+    }
+}
@@ -3,6 +3,9 @@ use crate::error::EarError;
 pub const HEADER_MAGIC: [u8; 3] = [0x55, 0x60, 0x01];
 const HEADER_LEN: usize = 8;
 const CRC_LEN: usize = 2;
+pub const MAX_PAYLOAD_LEN: usize = u8::MAX as usize;
+/// Largest frame on the wire: header, a full payload and the CRC.
+pub const MAX_PACKET_LEN: usize = HEADER_LEN + MAX_PAYLOAD_LEN + CRC_LEN;
 
 #[derive(Debug, Clone)]
 pub struct EarPacket {
@@ -11,6 +14,10 @@ pub struct EarPacket {
     pub payload: Vec<u8>,
 }
 
+/// Command ids as used by the Nothing X app. There is no command for the
+/// touch-control volume step; volume is only reachable through the
+/// `VolumeUp`/`VolumeDown` gesture actions (`0x12`/`0x13`) sent with
+/// [`command::CMD_SET_GESTURE`].
 pub mod command {
     pub const REQUEST_SERIAL: u16 = 0xC006;
     pub const REQUEST_BATTERY: u16 = 0xC007;
@@ -42,6 +49,40 @@ pub mod command {
     pub const CMD_SET_ENHANCED_BASS: u16 = 0xF051;
 }
 
+/// Human-readable name of a command id, for logging.
+pub fn command_name(id: u16) -> &'static str {
+    match id {
+        command::REQUEST_SERIAL => "request serial",
+        command::REQUEST_BATTERY => "request battery",
+        command::REQUEST_LED_CASE_COLORS => "request case LED colors",
+        command::REQUEST_GESTURES => "request gestures",
+        command::REQUEST_ANC => "request ANC",
+        command::REQUEST_EQ => "request EQ",
+        command::REQUEST_PERSONALIZED_ANC => "request personalized ANC",
+        command::REQUEST_IN_EAR_STATUS => "request in-ear status",
+        command::REQUEST_LATENCY_STATUS => "request latency",
+        command::REQUEST_FIRMWARE => "request firmware",
+        command::REQUEST_CUSTOM_EQ => "request custom EQ",
+        command::REQUEST_ADVANCED_EQ => "request advanced EQ",
+        command::REQUEST_ENHANCED_BASS => "request enhanced bass",
+        command::REQUEST_LISTENING_MODE => "request listening mode",
+        command::CMD_RING => "ring",
+        command::CMD_SET_GESTURE => "set gesture",
+        command::CMD_SET_IN_EAR => "set in-ear detection",
+        command::CMD_SET_LED_CASE_COLORS => "set case LED colors",
+        command::CMD_SET_ANC => "set ANC",
+        command::CMD_SET_EQ => "set EQ",
+        command::CMD_SET_PERSONALIZED_ANC => "set personalized ANC",
+        command::CMD_START_EAR_FIT_TEST => "ear fit test",
+        command::CMD_SET_LISTENING_MODE => "set listening mode",
+        command::CMD_SET_LATENCY => "set latency",
+        command::CMD_SET_CUSTOM_EQ => "set custom EQ",
+        command::CMD_SET_ADVANCED_EQ_ENABLED => "set advanced EQ",
+        command::CMD_SET_ENHANCED_BASS => "set enhanced bass",
+        _ => "unknown",
+    }
+}
+
 pub mod response {
     pub const SERIAL: u16 = 0x4006;
     pub const BATTERY_PRIMARY: u16 = 0xE001;
@@ -62,6 +103,12 @@ pub mod response {
     pub const EAR_FIT_RESULT: u16 = 0xE00D;
 }
 
+/// Reply command a write is acknowledged with: the command with its top bit
+/// cleared, the way reads (`0xC0xx`) are answered with `0x40xx`.
+pub fn ack_command(command: u16) -> u16 {
+    command & 0x7FFF
+}
+
 impl EarPacket {
     pub fn encode(command: u16, operation_id: u8, payload: &[u8]) -> Vec<u8> {
         let mut packet = Vec::with_capacity(HEADER_LEN + payload.len() + CRC_LEN);
@@ -76,6 +123,14 @@ impl EarPacket {
         packet
     }
 
+    /// Bytes the packet takes on the wire, header and CRC included.
+    pub fn encoded_len(&self) -> usize {
+        HEADER_LEN + self.payload.len() + CRC_LEN
+    }
+
+    /// Takes the next frame off `buffer`, skipping bytes before a header. On
+    /// a CRC mismatch only the header byte is consumed and the error holds
+    /// the frame it claimed, in hex.
     pub fn try_parse(buffer: &mut Vec<u8>) -> Result<Option<EarPacket>, EarError> {
         loop {
             if buffer.len() < HEADER_LEN {
@@ -95,18 +150,23 @@ impl EarPacket {
                 buffer.drain(0..1);
                 continue;
             }
+            // The length is a single byte, so a corrupt header can hold back
+            // at most MAX_PACKET_LEN bytes. Byte 6 is reserved and ignored.
             let payload_len = buffer[5] as usize;
             let total_len = HEADER_LEN + payload_len + CRC_LEN;
             if buffer.len() < total_len {
                 return Ok(None);
             }
-            let packet_bytes: Vec<u8> = buffer.drain(0..total_len).collect();
-            let crc_expected =
-                u16::from_le_bytes([packet_bytes[total_len - 2], packet_bytes[total_len - 1]]);
-            let crc_actual = crc16(&packet_bytes[..total_len - CRC_LEN]);
+            let crc_expected = u16::from_le_bytes([buffer[total_len - 2], buffer[total_len - 1]]);
+            let crc_actual = crc16(&buffer[..total_len - CRC_LEN]);
             if crc_actual != crc_expected {
-                return Err(EarError::CrcMismatch);
+                // A corrupt length can claim bytes of the next frame, so
+                // only the header byte is dropped and the next call rescans.
+                let frame = format_hex(&buffer[..total_len]);
+                buffer.drain(0..1);
+                return Err(EarError::CrcMismatch(vec![frame]));
             }
+            let packet_bytes: Vec<u8> = buffer.drain(0..total_len).collect();
 
             let command = u16::from_le_bytes([packet_bytes[3], packet_bytes[4]]);
             let operation_id = packet_bytes[7];
@@ -136,9 +196,69 @@ pub fn crc16(buffer: &[u8]) -> u16 {
     crc
 }
 
+/// Parses a command or response id such as `0xF00F` or `f00f`.
+pub fn parse_hex_u16(input: &str) -> Result<u16, EarError> {
+    let trimmed = input.trim();
+    let digits = trimmed
+        .strip_prefix("0x")
+        .or_else(|| trimmed.strip_prefix("0X"))
+        .unwrap_or(trimmed);
+    u16::from_str_radix(digits, 16)
+        .map_err(|_| EarError::InvalidInput(format!("'{}' is not a 16-bit hex value", input)))
+}
+
+/// Parses a hex byte string, tolerating `0x` prefixes and whitespace, comma or
+/// colon separators (e.g. `"0101 00"`, `"0x01 0x01 0x00"`, `"01:01:00"`).
+pub fn parse_hex_bytes(input: &str) -> Result<Vec<u8>, EarError> {
+    let mut digits = String::with_capacity(input.len());
+    for token in input.split(|c: char| c.is_whitespace() || c == ',' || c == ':') {
+        let token = token
+            .strip_prefix("0x")
+            .or_else(|| token.strip_prefix("0X"))
+            .unwrap_or(token);
+        digits.push_str(token);
+    }
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(EarError::InvalidInput(format!(
+            "'{}' is not a valid hex payload",
+            input
+        )));
+    }
+    if digits.len() & 1 != 0 {
+        return Err(EarError::InvalidInput(format!(
+            "hex payload '{}' has an odd number of digits",
+            input
+        )));
+    }
+    let bytes: Vec<u8> = digits
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| (hex_digit(pair[0]) << 4) | hex_digit(pair[1]))
+        .collect();
+    if bytes.len() > MAX_PAYLOAD_LEN {
+        return Err(EarError::InvalidInput(format!(
+            "payload is {} bytes; the maximum is {}",
+            bytes.len(),
+            MAX_PAYLOAD_LEN
+        )));
+    }
+    Ok(bytes)
+}
+
+fn hex_digit(digit: u8) -> u8 {
+    (digit as char).to_digit(16).unwrap_or_default() as u8
+}
+
+pub fn format_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{EarPacket, HEADER_MAGIC, crc16};
+    use super::{
+        EarPacket, HEADER_MAGIC, MAX_PAYLOAD_LEN, crc16, format_hex, parse_hex_bytes, parse_hex_u16,
+    };
+    use crate::error::EarError;
 
     #[test]
     fn encode_and_parse_round_trip() {
@@ -187,6 +307,43 @@ mod tests {
         assert!(rolling_buffer.is_empty());
     }
 
+    #[test]
+    fn try_parse_ignores_reserved_length_byte() {
+        let mut buffer = EarPacket::encode(0x4007, 3, &[0x01]);
+        // A non-zero byte 6 must not be read as the high byte of the length.
+        buffer[6] = 0x02;
+        let crc = crc16(&buffer[..buffer.len() - 2]);
+        let crc_at = buffer.len() - 2;
+        buffer[crc_at..].copy_from_slice(&crc.to_le_bytes());
+
+        let packet = EarPacket::try_parse(&mut buffer)
+            .unwrap()
+            .expect("frame with a non-zero reserved byte should parse");
+        assert_eq!(packet.command, 0x4007);
+        assert_eq!(packet.payload, vec![0x01]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn try_parse_recovers_the_frame_after_a_corrupt_length() {
+        let mut corrupt = EarPacket::encode(0x4007, 2, &[0x01]);
+        // Claims five payload bytes, so the frame would run into the next one.
+        corrupt[5] = 0x05;
+        let valid = EarPacket::encode(0x4007, 3, &[0x01]);
+        let mut buffer = [corrupt, valid].concat();
+
+        assert!(matches!(
+            EarPacket::try_parse(&mut buffer),
+            Err(EarError::CrcMismatch(_))
+        ));
+        let packet = EarPacket::try_parse(&mut buffer)
+            .unwrap()
+            .expect("valid packet after the corrupt header should parse");
+        assert_eq!(packet.command, 0x4007);
+        assert_eq!(packet.operation_id, 3);
+        assert!(buffer.is_empty());
+    }
+
     #[test]
     fn crc16_matches_known_value() {
         let bytes = [
@@ -197,4 +354,30 @@ mod tests {
         .concat();
         assert_eq!(crc16(&bytes), 0xFA6A);
     }
+
+    #[test]
+    fn parse_hex_accepts_common_spellings() {
+        assert_eq!(parse_hex_u16("0xF00F").unwrap(), 0xF00F);
+        assert_eq!(parse_hex_u16(" 401e ").unwrap(), 0x401E);
+        assert!(parse_hex_u16("0x1FFFF").is_err());
+
+        assert_eq!(parse_hex_bytes("0101 00").unwrap(), vec![0x01, 0x01, 0x00]);
+        assert_eq!(
+            parse_hex_bytes("0x01 0x01\t0x00").unwrap(),
+            vec![0x01, 0x01, 0x00]
+        );
+        assert_eq!(parse_hex_bytes("aa:BB,cc").unwrap(), vec![0xAA, 0xBB, 0xCC]);
+        assert!(parse_hex_bytes("").unwrap().is_empty());
+        assert!(parse_hex_bytes("010").is_err());
+        assert!(parse_hex_bytes("zz").is_err());
+    }
+
+    #[test]
+    fn parse_hex_enforces_payload_limit() {
+        let at_limit = "00".repeat(MAX_PAYLOAD_LEN);
+        assert_eq!(parse_hex_bytes(&at_limit).unwrap().len(), MAX_PAYLOAD_LEN);
+        let over_limit = "00".repeat(MAX_PAYLOAD_LEN + 1);
+        assert!(parse_hex_bytes(&over_limit).is_err());
+        assert_eq!(format_hex(&[0x01, 0xAB]), "01ab");
+    }
 }
@@ -1,43 +1,95 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{collections::BTreeMap, future::IntoFuture, net::SocketAddr, sync::Arc, time::Duration};
 
+use anyhow::Context;
 use axum::{
     Json, Router,
-    extract::State,
-    http::StatusCode,
+    extract::{
+        Path, Query, Request, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::{HeaderName, HeaderValue, Method, StatusCode, header},
+    middleware::{self, Next},
     response::{IntoResponse, Response},
     routing::{get, post},
 };
-use serde::Deserialize;
-use tracing::warn;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast::error::RecvError, watch};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing::{Instrument, info, warn};
+use uuid::Uuid;
 
 use crate::{
     bluetooth,
     error::EarError,
+    events::EarEvent,
     models::ModelBase,
+    openapi,
+    profiles::ProfileStore,
+    protocol::{format_hex, parse_hex_bytes, parse_hex_u16},
     service::{EarManager, EarSessionHandle},
     types::{
-        AncLevel, CustomEq, EarFitResult, EarSide, EnhancedBassState, EqMode, FirmwareInfo,
-        GestureSlot, InEarState, LatencyState, LedColorSet, ModelSummary, PersonalizedAncState,
-        SerialIdentity, SessionInfo,
+        AncCapabilities, AncLevel, AncStatus, AutoPauseState, BatteryAlertConfig,
+        BatteryAlertState, ConversationModeState, CustomEq, CustomEqState, DeviceState,
+        EarFitProgress, EarFitResult, EarSide, EnhancedBassState, EqMode, EqPresets, FirmwareInfo,
+        GESTURE_COMMON_DEFAULT, GestureAction, GestureBackup, GestureCapabilities, GestureConfig,
+        GestureConflict, GestureImportReport, GestureSlot, GestureType, InEarState, LatencyMode,
+        LatencyState, LedColorSet, ListeningMode, ModelSummary, PersonalizedAncState,
+        RfcommChannel, SerialIdentity, SessionInfo, SoundProfile, SpatialAudioState,
     },
 };
 
+const MAX_EAR_FIT_WAIT_MS: u64 = 30_000;
+const DEFAULT_EAR_FIT_RUN_MS: u64 = 20_000;
+const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+const MAX_REQUEST_ID_LEN: usize = 128;
+
 #[derive(Clone)]
 pub struct ApiState {
     pub manager: Arc<EarManager>,
+    /// Enables `POST /api/raw`, which can write arbitrary settings to the device.
+    pub unsafe_raw: bool,
+    /// When set, `/api/*` requests must carry `Authorization: Bearer <token>`.
+    pub auth_token: Option<String>,
+    /// Path prefixes that skip the bearer-token check.
+    pub auth_exempt: Vec<String>,
+    /// Origins allowed to call the API from a browser; `*` allows any.
+    pub cors_origins: Vec<String>,
+    /// How long to wait for open connections after a shutdown signal.
+    pub shutdown_timeout: Duration,
+    /// Extra attempts for `bluetoothctl`/`sdptool` calls that fail.
+    pub shell_retries: u8,
+    /// Saved sound profiles, persisted in the state file.
+    pub profiles: Arc<ProfileStore>,
 }
 
 pub fn router(state: ApiState) -> Router {
-    Router::new()
+    let cors = cors_layer(&state.cors_origins);
+    let router = Router::new()
         .route("/api/session", get(get_session).delete(disconnect))
         .route("/api/session/connect", post(connect))
         .route("/api/session/detect", post(detect_serial))
         .route("/api/session/auto-connect", post(auto_connect))
+        .route("/api/session/reconnect", post(reconnect))
+        .route("/api/session/bootstrap", post(bootstrap))
         .route("/api/session/model", post(update_model))
+        .route("/api/session/operation-id/reset", post(reset_operation_id))
+        .route("/api/session/label", post(set_session_label))
+        .route("/api/bluetooth/devices", get(list_bluetooth_devices))
+        .route("/api/bluetooth/adapters", get(list_bluetooth_adapters))
+        .route("/api/diagnostics", post(diagnostics))
         .route("/api/battery", get(read_battery))
+        .route(
+            "/api/battery/alert",
+            get(battery_alert).post(set_battery_alert),
+        )
         .route("/api/anc", get(read_anc).post(set_anc))
+        .route("/api/anc/capabilities", get(anc_capabilities))
         .route("/api/eq", get(read_eq).post(set_eq))
+        .route("/api/eq/presets", get(eq_presets))
         .route("/api/eq/custom", get(get_custom_eq).post(set_custom_eq))
+        .route("/api/eq/custom/presets", get(list_custom_eq_presets))
+        .route("/api/listening-mode", get(read_listening_mode))
         .route(
             "/api/enhanced-bass",
             get(get_enhanced_bass).post(set_enhanced_bass),
@@ -46,96 +98,553 @@ pub fn router(state: ApiState) -> Router {
             "/api/personalized-anc",
             get(get_personalized_anc).post(set_personalized_anc),
         )
+        .route("/api/volume/gestures", post(set_volume_gestures))
         .route("/api/in-ear", get(read_in_ear).post(set_in_ear))
+        .route("/api/auto-pause", get(read_auto_pause).post(set_auto_pause))
+        .route(
+            "/api/conversation-mode",
+            get(get_conversation_mode).post(set_conversation_mode),
+        )
+        .route(
+            "/api/spatial-audio",
+            get(get_spatial_audio).post(set_spatial_audio),
+        )
         .route("/api/latency", get(read_latency).post(set_latency))
         .route("/api/firmware", get(read_firmware))
+        .route("/api/firmware/dfu", post(enter_dfu_mode))
         .route("/api/ear-fit", get(read_ear_fit).post(start_ear_fit))
+        .route("/api/ear-fit/run", post(run_ear_fit))
+        .route("/api/ear-fit/cancel", post(cancel_ear_fit))
         .route("/api/gestures", get(read_gestures).post(set_gesture))
+        .route("/api/gestures/capabilities", get(gesture_capabilities))
+        .route("/api/gestures/export", get(export_gestures))
+        .route("/api/gestures/import", post(import_gestures))
         .route(
             "/api/led-case",
             get(read_led_case_colors).post(set_led_case_colors),
         )
         .route("/api/ring", post(ring_buds))
-        .with_state(state)
+        .route("/api/reset", post(factory_reset))
+        .route("/api/batch", post(run_batch))
+        .route("/api/profiles", get(list_profiles))
+        .route("/api/profiles/:name", post(save_profile))
+        .route("/api/profiles/:name/apply", post(apply_profile))
+        .route("/api/raw", post(send_raw))
+        .route("/api/events", get(events))
+        .route("/openapi.json", get(openapi_document))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .fallback(not_found)
+        .layer(middleware::from_fn_with_state(state.clone(), require_auth))
+        .layer(middleware::from_fn(negotiate_format))
+        .layer(middleware::from_fn(request_span));
+    // CORS wraps the auth layer so preflight requests are answered without a token.
+    match cors {
+        Some(cors) => router.layer(cors).with_state(state),
+        None => router.with_state(state),
+    }
+}
+
+fn cors_layer(origins: &[String]) -> Option<CorsLayer> {
+    if origins.is_empty() {
+        return None;
+    }
+    let allow_origin = if origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        let values: Vec<HeaderValue> = origins
+            .iter()
+            .filter_map(|origin| match HeaderValue::from_str(origin) {
+                Ok(value) => Some(value),
+                Err(_) => {
+                    warn!("ignoring invalid CORS origin '{}'", origin);
+                    None
+                }
+            })
+            .collect();
+        AllowOrigin::list(values)
+    };
+    Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods([Method::GET, Method::POST, Method::DELETE])
+            .allow_headers([
+                header::CONTENT_TYPE,
+                header::AUTHORIZATION,
+                REQUEST_ID_HEADER,
+            ])
+            .expose_headers([REQUEST_ID_HEADER]),
+    )
 }
 
-pub async fn serve(state: ApiState, addr: SocketAddr) -> anyhow::Result<()> {
+/// Runs each request in a span carrying its `X-Request-Id`, so device
+/// traffic logged while serving it can be traced back. An id is generated
+/// when the client sends none, and the id is echoed in the response.
+async fn request_span(request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let span = tracing::info_span!(
+        "request",
+        id = %request_id,
+        method = %request.method(),
+        path = request.uri().path(),
+    );
+    let mut response = next.run(request).instrument(span).await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+async fn require_auth(State(state): State<ApiState>, request: Request, next: Next) -> Response {
+    let Some(expected) = state.auth_token.as_deref() else {
+        return next.run(request).await;
+    };
+    let path = request.uri().path();
+    let exempt = state
+        .auth_exempt
+        .iter()
+        .any(|prefix| path.starts_with(prefix.as_str()));
+    if !path.starts_with("/api/") || exempt {
+        return next.run(request).await;
+    }
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match provided {
+        Some(token) if constant_time_eq(token.as_bytes(), expected.as_bytes()) => {
+            next.run(request).await
+        }
+        _ => {
+            let mut response = ApiError::from(EarError::Unauthorized).into_response();
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                header::HeaderValue::from_static("Bearer"),
+            );
+            response
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Serves the API on every address in `addrs` until SIGINT/SIGTERM, then
+/// drains in-flight requests on all of them (bounded by `shutdown_timeout`)
+/// and closes the device session. Binding `[::]` accepts IPv4 too where the
+/// OS maps it (the Linux default); otherwise list both.
+pub async fn serve(state: ApiState, addrs: &[SocketAddr]) -> anyhow::Result<()> {
+    let manager = state.manager.clone();
+    let shutdown_timeout = state.shutdown_timeout;
     let app = router(state);
-    axum::serve(tokio::net::TcpListener::bind(addr).await?, app).await?;
+    let (stop, stopped) = watch::channel(false);
+    let mut servers = Vec::with_capacity(addrs.len());
+    for &addr in addrs {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("failed to bind {addr}"))?;
+        info!("listening on {}", addr);
+        let mut stopped = stopped.clone();
+        servers.push(
+            axum::serve(listener, app.clone())
+                .with_graceful_shutdown(async move {
+                    let _ = stopped.wait_for(|stop| *stop).await;
+                })
+                .into_future(),
+        );
+    }
+    let server = futures::future::try_join_all(servers);
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => return Ok(result.map(drop)?),
+        _ = shutdown_signal() => {}
+    }
+    info!("shutting down");
+    let _ = stop.send(true);
+    // Open WebSocket streams never finish on their own, so draining is bounded.
+    match tokio::time::timeout(shutdown_timeout, &mut server).await {
+        Ok(result) => drop(result?),
+        Err(_) => warn!(
+            "connections still open after {:?}; closing anyway",
+            shutdown_timeout
+        ),
+    }
+    match manager.disconnect().await {
+        Ok(()) => info!("closed device session"),
+        Err(EarError::NoSession) => {}
+        Err(err) => warn!("failed to close device session: {}", err),
+    }
     Ok(())
 }
 
-type ApiResult<T> = Result<Json<T>, ApiError>;
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            warn!("failed to listen for Ctrl-C: {}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                warn!("failed to listen for SIGTERM: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+type ApiResult<T> = Result<Reply<T>, ApiError>;
+
+const MSGPACK_TYPES: [&str; 3] = [
+    "application/x-msgpack",
+    "application/msgpack",
+    "application/vnd.msgpack",
+];
+
+/// Body encodings a client can pick with `Accept`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum ResponseFormat {
+    #[default]
+    Json,
+    MessagePack,
+}
+
+impl ResponseFormat {
+    /// The listed format with the highest `q`, the earlier one on a tie.
+    /// Wildcards, unknown types and a missing header mean JSON.
+    fn from_accept(accept: Option<&HeaderValue>) -> Self {
+        let Some(accept) = accept.and_then(|value| value.to_str().ok()) else {
+            return Self::Json;
+        };
+        let mut best = (0.0f32, Self::Json);
+        for range in accept.split(',') {
+            let mut parts = range.split(';');
+            let media = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+            let q = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            let format = if MSGPACK_TYPES.contains(&media.as_str()) {
+                Self::MessagePack
+            } else {
+                Self::Json
+            };
+            if q > best.0 {
+                best = (q, format);
+            }
+        }
+        best.1
+    }
+}
+
+tokio::task_local! {
+    /// The format negotiated for the request being handled.
+    static RESPONSE_FORMAT: ResponseFormat;
+}
+
+/// A response body, encoded as JSON or, when the client sends
+/// `Accept: application/x-msgpack`, as MessagePack with named fields.
+#[derive(Debug)]
+struct Reply<T>(T);
+
+impl<T: Serialize> IntoResponse for Reply<T> {
+    fn into_response(self) -> Response {
+        let format = RESPONSE_FORMAT
+            .try_with(|format| *format)
+            .unwrap_or_default();
+        match format {
+            ResponseFormat::Json => Json(self.0).into_response(),
+            ResponseFormat::MessagePack => match rmp_serde::to_vec_named(&self.0) {
+                Ok(body) => (
+                    [(
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static(MSGPACK_TYPES[0]),
+                    )],
+                    body,
+                )
+                    .into_response(),
+                Err(err) => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(serde_json::json!({
+                        "error": format!("failed to encode MessagePack response: {}", err),
+                        "code": "io",
+                    })),
+                )
+                    .into_response(),
+            },
+        }
+    }
+}
+
+/// Picks the response format from `Accept` for everything the request runs.
+async fn negotiate_format(request: Request, next: Next) -> Response {
+    let format = ResponseFormat::from_accept(request.headers().get(header::ACCEPT));
+    let mut response = RESPONSE_FORMAT.scope(format, next.run(request)).await;
+    response
+        .headers_mut()
+        .append(header::VARY, HeaderValue::from_static("accept"));
+    response
+}
+
+async fn openapi_document() -> Json<serde_json::Value> {
+    Json(openapi::DOCUMENT.clone())
+}
+
+/// Lists the `/api/*` routes from the OpenAPI document, so the hint can't
+/// drift from the router.
+async fn not_found(method: Method, uri: axum::http::Uri) -> Response {
+    let routes: Vec<&String> = openapi::DOCUMENT["paths"]
+        .as_object()
+        .map(|paths| {
+            paths
+                .keys()
+                .filter(|path| path.starts_with("/api/"))
+                .collect()
+        })
+        .unwrap_or_default();
+    let body = serde_json::json!({
+        "error": format!("no route for {} {}", method, uri.path()),
+        "code": "not_found",
+        "hint": "GET /openapi.json describes every route and its body",
+        "routes": routes,
+    });
+    (StatusCode::NOT_FOUND, Reply(body)).into_response()
+}
+
+async fn healthz() -> Reply<serde_json::Value> {
+    Reply(serde_json::json!({ "status": "ok" }))
+}
 
+/// Ready only while a device session is open.
+async fn readyz(State(state): State<ApiState>) -> Response {
+    match state.manager.session().await {
+        Ok(session) => (
+            StatusCode::OK,
+            Reply(serde_json::json!({
+                "status": "ready",
+                "session": session.info().await,
+            })),
+        )
+            .into_response(),
+        Err(err) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Reply(serde_json::json!({
+                "status": "not_ready",
+                "reason": err.to_string(),
+                "code": err.code(),
+            })),
+        )
+            .into_response(),
+    }
+}
+
+/// A bad `model` closes the new session again and fails the request, unless
+/// `ignore_model_errors` is set: then the session stays open with the model
+/// unknown and the error is returned in `warning`.
 async fn connect(
     State(state): State<ApiState>,
     Json(request): Json<ConnectRequest>,
-) -> ApiResult<SessionInfo> {
-    let address: bluer::Address = request.address.parse().map_err(|e| ApiError {
-        inner: EarError::Io(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            format!("Invalid Bluetooth address: {}", e),
-        )),
-    })?;
+) -> ApiResult<ConnectResponse> {
+    let address = bluetooth::parse_address(&request.address)?;
+    let channel = match request.channel {
+        RfcommChannel::Fixed(channel) => channel,
+        RfcommChannel::Auto => detect_channel(&address.to_string(), state.shell_retries).await,
+    };
 
-    let handle = state.manager.connect(address, request.channel).await?;
+    let handle = state
+        .manager
+        .connect_via(address, channel, request.adapter.as_deref())
+        .await?;
 
+    let mut warning = None;
     if let Some(model) = request.model {
-        apply_model_selector(&handle, model).await?;
+        if let Err(err) = apply_model_selector(&handle, model).await {
+            if !request.ignore_model_errors {
+                rollback_connect(&state, &err).await;
+                return Err(err.into());
+            }
+            warn!("Ignoring model selection error: {}", err);
+            warning = Some(format!("model not applied: {}", err));
+        }
+    }
+
+    Ok(Reply(ConnectResponse {
+        session: handle.info().await,
+        warning,
+    }))
+}
+
+/// Looks up the buds' RFCOMM channel over SDP, falling back to channel 1.
+async fn detect_channel(address: &str, retries: u8) -> u8 {
+    match bluetooth::detect_rfcomm_channel(address, retries).await {
+        Ok(channel) => {
+            info!("Detected RFCOMM channel {} for {}", channel, address);
+            channel
+        }
+        Err(err) => {
+            warn!(
+                "Failed to detect RFCOMM channel for {}: {}. Falling back to channel {}",
+                address,
+                err,
+                default_rfcomm_channel()
+            );
+            default_rfcomm_channel()
+        }
     }
+}
 
-    Ok(Json(handle.info().await))
+/// Closes a session whose setup failed, so it doesn't block the next
+/// connect with `already_connected`.
+async fn rollback_connect(state: &ApiState, cause: &EarError) {
+    warn!("Closing the new session after a failed setup: {}", cause);
+    if let Err(err) = state.manager.disconnect().await {
+        warn!("Failed to close the new session: {}", err);
+    }
 }
 
 async fn disconnect(State(state): State<ApiState>) -> ApiResult<serde_json::Value> {
     state.manager.disconnect().await?;
-    Ok(Json(serde_json::json!({ "status": "disconnected" })))
+    Ok(Reply(serde_json::json!({ "status": "disconnected" })))
 }
 
 async fn get_session(State(state): State<ApiState>) -> ApiResult<SessionInfo> {
     let session = state.manager.session().await?;
-    Ok(Json(session.info().await))
+    Ok(Reply(session.info().await))
+}
+
+async fn reset_operation_id(State(state): State<ApiState>) -> ApiResult<SessionInfo> {
+    let session = state.manager.session().await?;
+    session.reset_operation_id().await;
+    Ok(Reply(session.info().await))
+}
+
+async fn reconnect(State(state): State<ApiState>) -> ApiResult<SessionInfo> {
+    let session = state.manager.reconnect().await?;
+    Ok(Reply(session.info().await))
 }
 
-async fn detect_serial(State(state): State<ApiState>) -> ApiResult<SerialIdentity> {
+async fn set_session_label(
+    State(state): State<ApiState>,
+    Json(request): Json<SessionLabelRequest>,
+) -> ApiResult<SessionInfo> {
     let session = state.manager.session().await?;
-    let identity = session.detect_serial().await?;
-    Ok(Json(identity))
+    session.set_label(request.label).await?;
+    Ok(Reply(session.info().await))
+}
+
+async fn detect_serial(
+    State(state): State<ApiState>,
+    Query(query): Query<ForceQuery>,
+) -> ApiResult<SerialIdentity> {
+    let session = state.manager.session().await?;
+    let identity = if query.force {
+        session.refresh_serial().await?
+    } else {
+        session.detect_serial().await?
+    };
+    Ok(Reply(identity))
 }
 
 async fn auto_connect(
     State(state): State<ApiState>,
     Json(request): Json<AutoConnectRequest>,
 ) -> ApiResult<SessionInfo> {
-    let device =
-        bluetooth::resolve_connected_device(request.address.clone(), request.name.clone()).await?;
-    let channel = if let Some(ch) = request.channel {
-        ch
-    } else {
-        match bluetooth::detect_rfcomm_channel(&device.address).await {
-            Ok(ch) => ch,
-            Err(err) => {
-                warn!(
-                    "Failed to detect RFCOMM channel for {}: {}. Falling back to channel {}",
-                    device.address,
-                    err,
-                    default_rfcomm_channel()
-                );
-                default_rfcomm_channel()
-            }
-        }
+    let handle = auto_connect_session(&state, request, true).await?;
+    Ok(Reply(handle.info().await))
+}
+
+/// Connects to the resolved device. With `detect`, the model is identified
+/// from the serial number, falling back to the Bluetooth name; a failed
+/// detection is logged and leaves the model unknown.
+async fn auto_connect_session(
+    state: &ApiState,
+    request: AutoConnectRequest,
+    detect: bool,
+) -> Result<EarSessionHandle, EarError> {
+    let device = bluetooth::resolve_connected_device(
+        request.address.clone(),
+        request.name.clone(),
+        request.adapter.as_deref(),
+        state.shell_retries,
+    )
+    .await?;
+    let channel = match request.channel {
+        Some(ch) => ch,
+        None => detect_channel(&device.address, state.shell_retries).await,
     };
 
-    // Parse Bluetooth address for bluer
-    let bt_address: bluer::Address = device.address.parse().map_err(|_| {
-        EarError::Detection(format!("invalid Bluetooth address: {}", device.address))
-    })?;
+    let bt_address = bluetooth::parse_address(&device.address)?;
 
-    let handle = state.manager.connect(bt_address, channel).await?;
+    let handle = state
+        .manager
+        .connect_via(bt_address, channel, request.adapter.as_deref())
+        .await?;
+    handle.set_device_name(device.name.clone()).await;
     if let Some(sku) = request.sku {
-        let _ = handle.set_model_from_sku(&sku, None).await?;
+        if let Err(err) = handle.set_model_from_sku(&sku, None).await {
+            rollback_connect(state, &err).await;
+            return Err(err);
+        }
+    } else if detect {
+        if let Err(err) = handle.detect_serial().await {
+            warn!("Model detection failed for {}: {}", device.address, err);
+        }
     }
-    Ok(Json(handle.info().await))
+    Ok(handle)
+}
+
+/// Auto-connects, detects the model and reads the initial state in one call.
+/// Only a failed connect fails the request; later steps report their errors
+/// in `errors` and leave their field empty.
+async fn bootstrap(
+    State(state): State<ApiState>,
+    Json(request): Json<AutoConnectRequest>,
+) -> ApiResult<BootstrapResponse> {
+    let handle = auto_connect_session(&state, request, false).await?;
+    let mut errors = Vec::new();
+
+    let identity = match handle.detect_serial().await {
+        Ok(identity) => Some(identity),
+        Err(err) => {
+            errors.push(StepError::new("detect", &err));
+            None
+        }
+    };
+    let device_state = match handle.init_device().await {
+        Ok(device_state) => Some(device_state),
+        Err(err) => {
+            errors.push(StepError::new("init", &err));
+            None
+        }
+    };
+
+    Ok(Reply(BootstrapResponse {
+        session: handle.info().await,
+        identity,
+        state: device_state,
+        errors,
+    }))
 }
 
 async fn update_model(
@@ -144,34 +653,83 @@ async fn update_model(
 ) -> ApiResult<ModelSummary> {
     let session = state.manager.session().await?;
     let summary = apply_model_selector(&session, request).await?;
-    Ok(Json(summary))
+    Ok(Reply(summary))
+}
+
+async fn list_bluetooth_devices() -> ApiResult<Vec<bluetooth::ScannedDevice>> {
+    Ok(Reply(bluetooth::scan_devices().await?))
+}
+
+async fn list_bluetooth_adapters() -> ApiResult<Vec<bluetooth::AdapterInfo>> {
+    Ok(Reply(bluetooth::list_adapters().await?))
 }
 
-async fn read_battery(State(state): State<ApiState>) -> ApiResult<crate::types::BatteryStatus> {
+async fn diagnostics(
+    Json(request): Json<DiagnosticsRequest>,
+) -> ApiResult<bluetooth::PreflightReport> {
+    let address = request
+        .address
+        .as_deref()
+        .map(bluetooth::parse_address)
+        .transpose()?;
+    Ok(Reply(bluetooth::preflight(address).await))
+}
+
+async fn read_battery(
+    State(state): State<ApiState>,
+    Query(query): Query<ReadQuery>,
+) -> ApiResult<crate::types::BatteryStatus> {
     let session = state.manager.session().await?;
-    let status = session.read_battery().await?;
-    Ok(Json(status))
+    let status = if query.fresh {
+        session.refresh_battery().await?
+    } else {
+        session.read_battery().await?
+    };
+    Ok(Reply(status))
 }
 
-async fn read_anc(State(state): State<ApiState>) -> ApiResult<AncLevel> {
+async fn read_anc(
+    State(state): State<ApiState>,
+    Query(query): Query<ReadQuery>,
+) -> ApiResult<AncStatus> {
     let session = state.manager.session().await?;
-    let anc = session.read_anc().await?;
-    Ok(Json(anc))
+    let anc = if query.fresh {
+        session.refresh_anc().await?
+    } else {
+        session.read_anc().await?
+    };
+    Ok(Reply(anc))
 }
 
 async fn set_anc(
     State(state): State<ApiState>,
     Json(req): Json<AncRequest>,
-) -> ApiResult<serde_json::Value> {
+) -> ApiResult<AncWriteResponse> {
     let session = state.manager.session().await?;
+    if req.verify {
+        let anc = session.set_anc_verified(req.level).await?;
+        return Ok(Reply(AncWriteResponse::Verified(anc)));
+    }
     session.set_anc(req.level).await?;
-    Ok(Json(serde_json::json!({ "status": "ok" })))
+    Ok(Reply(AncWriteResponse::Sent { status: "ok" }))
+}
+
+async fn anc_capabilities(State(state): State<ApiState>) -> ApiResult<AncCapabilities> {
+    let session = state.manager.session().await?;
+    Ok(Reply(session.anc_capabilities().await))
 }
 
-async fn read_eq(State(state): State<ApiState>) -> ApiResult<EqMode> {
+async fn read_eq(
+    State(state): State<ApiState>,
+    Query(query): Query<ReadQuery>,
+) -> ApiResult<EqMode> {
     let session = state.manager.session().await?;
-    let eq = session.read_eq().await?;
-    Ok(Json(eq))
+    let eq = if query.fresh {
+        session.refresh_eq().await?
+    } else {
+        session.read_eq().await?
+    };
+    Ok(Reply(eq))
 }
 
 async fn set_eq(
@@ -180,28 +738,85 @@ async fn set_eq(
 ) -> ApiResult<serde_json::Value> {
     let session = state.manager.session().await?;
     session.set_eq_mode(req.mode).await?;
-    Ok(Json(serde_json::json!({ "status": "ok" })))
+    Ok(Reply(serde_json::json!({ "status": "ok" })))
 }
 
-async fn get_custom_eq(State(state): State<ApiState>) -> ApiResult<CustomEq> {
+async fn eq_presets(State(state): State<ApiState>) -> ApiResult<EqPresets> {
     let session = state.manager.session().await?;
-    let eq = session.get_custom_eq().await?;
-    Ok(Json(eq))
+    let presets = session.eq_presets().await?;
+    Ok(Reply(presets))
+}
+
+/// A session starts without a threshold; the one saved for its address is
+/// picked up the first time the alert is read.
+async fn battery_alert(State(state): State<ApiState>) -> ApiResult<BatteryAlertState> {
+    let session = state.manager.session().await?;
+    if session.battery_alert_threshold().await.is_none() {
+        if let Some(address) = session.address().await {
+            let saved = state.profiles.battery_alert(&address.to_string()).await;
+            if saved.is_some() {
+                session.set_battery_alert(saved).await?;
+            }
+        }
+    }
+    Ok(Reply(session.battery_alert().await?))
+}
+
+async fn set_battery_alert(
+    State(state): State<ApiState>,
+    Json(req): Json<BatteryAlertConfig>,
+) -> ApiResult<BatteryAlertState> {
+    let session = state.manager.session().await?;
+    let previous = session.battery_alert_threshold().await;
+    session.set_battery_alert(req.threshold).await?;
+    if let Some(address) = session.address().await {
+        let saved = state
+            .profiles
+            .set_battery_alert(&address.to_string(), req.threshold)
+            .await;
+        if let Err(err) = saved {
+            session.set_battery_alert(previous).await?;
+            return Err(err.into());
+        }
+    }
+    Ok(Reply(session.battery_alert().await?))
+}
+
+async fn read_listening_mode(State(state): State<ApiState>) -> ApiResult<ListeningMode> {
+    let session = state.manager.session().await?;
+    let mode = session.read_listening_mode().await?;
+    Ok(Reply(mode))
+}
+
+async fn get_custom_eq(
+    State(state): State<ApiState>,
+    Query(query): Query<SlotQuery>,
+) -> ApiResult<CustomEqState> {
+    let session = state.manager.session().await?;
+    let eq = session.read_custom_eq_state(query.slot).await?;
+    Ok(Reply(eq))
+}
+
+async fn list_custom_eq_presets(State(state): State<ApiState>) -> ApiResult<Vec<CustomEqState>> {
+    let session = state.manager.session().await?;
+    let presets = session.list_custom_eq_presets().await?;
+    Ok(Reply(presets))
 }
 
 async fn set_custom_eq(
     State(state): State<ApiState>,
+    Query(query): Query<SlotQuery>,
     Json(req): Json<CustomEq>,
 ) -> ApiResult<serde_json::Value> {
     let session = state.manager.session().await?;
-    session.set_custom_eq(req).await?;
-    Ok(Json(serde_json::json!({ "status": "ok" })))
+    session.set_custom_eq(query.slot, req).await?;
+    Ok(Reply(serde_json::json!({ "status": "ok" })))
 }
 
 async fn get_enhanced_bass(State(state): State<ApiState>) -> ApiResult<EnhancedBassState> {
     let session = state.manager.session().await?;
     let state = session.read_enhanced_bass().await?;
-    Ok(Json(state))
+    Ok(Reply(state))
 }
 
 async fn set_enhanced_bass(
@@ -210,13 +825,13 @@ async fn set_enhanced_bass(
 ) -> ApiResult<serde_json::Value> {
     let session = state.manager.session().await?;
     session.set_enhanced_bass(req.enabled, req.level).await?;
-    Ok(Json(serde_json::json!({ "status": "ok" })))
+    Ok(Reply(serde_json::json!({ "status": "ok" })))
 }
 
 async fn get_personalized_anc(State(state): State<ApiState>) -> ApiResult<PersonalizedAncState> {
     let session = state.manager.session().await?;
     let state = session.get_personalized_anc().await?;
-    Ok(Json(state))
+    Ok(Reply(state))
 }
 
 async fn set_personalized_anc(
@@ -225,13 +840,67 @@ async fn set_personalized_anc(
 ) -> ApiResult<serde_json::Value> {
     let session = state.manager.session().await?;
     session.set_personalized_anc(req.enabled).await?;
-    Ok(Json(serde_json::json!({ "status": "ok" })))
+    Ok(Reply(serde_json::json!({ "status": "ok" })))
+}
+
+async fn set_volume_gestures(
+    State(state): State<ApiState>,
+    Json(req): Json<VolumeGesturesRequest>,
+) -> ApiResult<GestureWriteResponse> {
+    let session = state.manager.session().await?;
+    let conflicts = session.set_volume_gestures(req.gesture).await?;
+    Ok(Reply(GestureWriteResponse {
+        status: "ok",
+        conflicts,
+    }))
 }
 
 async fn read_in_ear(State(state): State<ApiState>) -> ApiResult<InEarState> {
     let session = state.manager.session().await?;
     let resp = session.read_in_ear().await?;
-    Ok(Json(resp))
+    Ok(Reply(resp))
+}
+
+async fn read_auto_pause(State(state): State<ApiState>) -> ApiResult<AutoPauseState> {
+    let session = state.manager.session().await?;
+    Ok(Reply(session.read_auto_pause().await?))
+}
+
+async fn set_auto_pause(
+    State(state): State<ApiState>,
+    Json(req): Json<AutoPauseState>,
+) -> ApiResult<serde_json::Value> {
+    let session = state.manager.session().await?;
+    session.set_auto_pause(req).await?;
+    Ok(Reply(serde_json::json!({ "status": "ok" })))
+}
+
+async fn get_conversation_mode(State(state): State<ApiState>) -> ApiResult<ConversationModeState> {
+    let session = state.manager.session().await?;
+    Ok(Reply(session.get_conversation_mode().await?))
+}
+
+async fn set_conversation_mode(
+    State(state): State<ApiState>,
+    Json(req): Json<ConversationModeState>,
+) -> ApiResult<serde_json::Value> {
+    let session = state.manager.session().await?;
+    session.set_conversation_mode(req).await?;
+    Ok(Reply(serde_json::json!({ "status": "ok" })))
+}
+
+async fn get_spatial_audio(State(state): State<ApiState>) -> ApiResult<SpatialAudioState> {
+    let session = state.manager.session().await?;
+    Ok(Reply(session.get_spatial_audio().await?))
+}
+
+async fn set_spatial_audio(
+    State(state): State<ApiState>,
+    Json(req): Json<SpatialAudioState>,
+) -> ApiResult<serde_json::Value> {
+    let session = state.manager.session().await?;
+    session.set_spatial_audio(req).await?;
+    Ok(Reply(serde_json::json!({ "status": "ok" })))
 }
 
 async fn set_in_ear(
@@ -240,66 +909,133 @@ async fn set_in_ear(
 ) -> ApiResult<serde_json::Value> {
     let session = state.manager.session().await?;
     session.set_in_ear_detection(req.detection_enabled).await?;
-    Ok(Json(serde_json::json!({ "status": "ok" })))
+    Ok(Reply(serde_json::json!({ "status": "ok" })))
 }
 
 async fn read_latency(State(state): State<ApiState>) -> ApiResult<LatencyState> {
     let session = state.manager.session().await?;
     let resp = session.read_latency().await?;
-    Ok(Json(resp))
+    Ok(Reply(resp))
 }
 
+/// Echoes the applied state so clients don't need a follow-up read.
 async fn set_latency(
     State(state): State<ApiState>,
-    Json(req): Json<LatencyState>,
-) -> ApiResult<serde_json::Value> {
+    Json(req): Json<LatencyRequest>,
+) -> ApiResult<LatencyState> {
     let session = state.manager.session().await?;
-    session.set_latency(req.low_latency_enabled).await?;
-    Ok(Json(serde_json::json!({ "status": "ok" })))
+    let mode = req.mode()?;
+    session.set_latency_mode(mode).await?;
+    Ok(Reply(LatencyState::from_mode(mode)))
 }
 
 async fn read_firmware(State(state): State<ApiState>) -> ApiResult<FirmwareInfo> {
     let session = state.manager.session().await?;
-    Ok(Json(session.read_firmware().await?))
+    Ok(Reply(session.read_firmware().await?))
+}
+
+async fn enter_dfu_mode(State(state): State<ApiState>) -> ApiResult<serde_json::Value> {
+    let session = state.manager.session().await?;
+    session.enter_dfu_mode().await?;
+    Ok(Reply(serde_json::json!({ "status": "ok" })))
 }
 
 async fn start_ear_fit(State(state): State<ApiState>) -> ApiResult<serde_json::Value> {
     let session = state.manager.session().await?;
     session.launch_ear_fit_test().await?;
-    Ok(Json(serde_json::json!({ "status": "started" })))
+    Ok(Reply(serde_json::json!({ "status": "started" })))
+}
+
+async fn read_ear_fit(
+    State(state): State<ApiState>,
+    Query(query): Query<EarFitQuery>,
+) -> ApiResult<EarFitResult> {
+    let session = state.manager.session().await?;
+    let result = match query.wait_ms {
+        Some(wait_ms) => {
+            let wait = Duration::from_millis(wait_ms.min(MAX_EAR_FIT_WAIT_MS));
+            session.wait_for_ear_fit_result(wait).await?
+        }
+        None => session.read_ear_fit_result().await?,
+    };
+    Ok(Reply(result))
+}
+
+async fn run_ear_fit(
+    State(state): State<ApiState>,
+    Query(query): Query<EarFitRunQuery>,
+) -> ApiResult<EarFitProgress> {
+    let session = state.manager.session().await?;
+    let timeout = query
+        .timeout_ms
+        .unwrap_or(DEFAULT_EAR_FIT_RUN_MS)
+        .min(MAX_EAR_FIT_WAIT_MS);
+    Ok(Reply(
+        session
+            .await_ear_fit(Duration::from_millis(timeout))
+            .await?,
+    ))
 }
 
-async fn read_ear_fit(State(state): State<ApiState>) -> ApiResult<EarFitResult> {
+async fn cancel_ear_fit(State(state): State<ApiState>) -> ApiResult<serde_json::Value> {
     let session = state.manager.session().await?;
-    Ok(Json(session.read_ear_fit_result().await?))
+    session.cancel_ear_fit();
+    Ok(Reply(serde_json::json!({ "status": "cancelled" })))
 }
 
-async fn read_gestures(State(state): State<ApiState>) -> ApiResult<Vec<GestureSlot>> {
+async fn read_gestures(State(state): State<ApiState>) -> ApiResult<Vec<GestureConfig>> {
     let session = state.manager.session().await?;
-    Ok(Json(session.read_gestures().await?))
+    let slots = session.read_gestures().await?;
+    Ok(Reply(slots.into_iter().map(GestureConfig::from).collect()))
 }
 
 async fn set_gesture(
     State(state): State<ApiState>,
-    Json(req): Json<GestureSlot>,
-) -> ApiResult<serde_json::Value> {
+    Json(req): Json<GestureRequest>,
+) -> ApiResult<GestureWriteResponse> {
+    let slot = req.into_slot()?;
+    let session = state.manager.session().await?;
+    let conflicts = session.set_gesture(&slot).await?;
+    Ok(Reply(GestureWriteResponse {
+        status: "ok",
+        conflicts,
+    }))
+}
+
+async fn gesture_capabilities(State(state): State<ApiState>) -> ApiResult<GestureCapabilities> {
+    let session = state.manager.session().await?;
+    Ok(Reply(session.gesture_capabilities().await))
+}
+
+async fn export_gestures(State(state): State<ApiState>) -> ApiResult<GestureBackup> {
     let session = state.manager.session().await?;
-    session.set_gesture(&req).await?;
-    Ok(Json(serde_json::json!({ "status": "ok" })))
+    Ok(Reply(session.export_gestures().await?))
+}
+
+async fn import_gestures(
+    State(state): State<ApiState>,
+    Query(query): Query<ForceQuery>,
+    Json(backup): Json<GestureBackup>,
+) -> ApiResult<GestureImportReport> {
+    let session = state.manager.session().await?;
+    Ok(Reply(session.import_gestures(&backup, query.force).await?))
 }
 
 async fn read_led_case_colors(State(state): State<ApiState>) -> ApiResult<LedColorSet> {
     let session = state.manager.session().await?;
-    Ok(Json(session.read_led_case_colors().await?))
+    Ok(Reply(session.read_led_case_colors().await?))
 }
 
 async fn set_led_case_colors(
     State(state): State<ApiState>,
     Json(req): Json<LedColorSet>,
-) -> ApiResult<serde_json::Value> {
+) -> ApiResult<AckResponse> {
     let session = state.manager.session().await?;
-    session.set_led_case_colors(&req).await?;
-    Ok(Json(serde_json::json!({ "status": "ok" })))
+    let acknowledged = session.set_led_case_colors(&req).await?;
+    Ok(Reply(AckResponse {
+        status: "ok",
+        acknowledged,
+    }))
 }
 
 async fn ring_buds(
@@ -308,24 +1044,276 @@ async fn ring_buds(
 ) -> ApiResult<serde_json::Value> {
     let session = state.manager.session().await?;
     session.ring_buds(req.enable, req.side).await?;
-    Ok(Json(serde_json::json!({ "status": "ok" })))
+    Ok(Reply(serde_json::json!({ "status": "ok" })))
 }
 
-#[derive(Debug, Deserialize)]
-struct ConnectRequest {
+async fn factory_reset(
+    State(state): State<ApiState>,
+    Json(req): Json<ResetRequest>,
+) -> ApiResult<DeviceState> {
+    if !req.confirm {
+        return Err(EarError::InvalidInput(
+            "a reset overwrites EQ, ANC, latency and gestures; send \"confirm\": true".to_string(),
+        )
+        .into());
+    }
+    let session = state.manager.session().await?;
+    Ok(Reply(session.factory_reset().await?))
+}
+
+async fn run_batch(
+    State(state): State<ApiState>,
+    Json(req): Json<BatchRequest>,
+) -> ApiResult<BatchResponse> {
+    if req.ops.len() > MAX_BATCH_OPS {
+        return Err(EarError::InvalidInput(format!(
+            "a batch holds at most {} operations, got {}",
+            MAX_BATCH_OPS,
+            req.ops.len()
+        ))
+        .into());
+    }
+    // Reject the whole batch before anything is written.
+    for (index, op) in req.ops.iter().enumerate() {
+        op.validate().map_err(|err| {
+            EarError::InvalidInput(format!("operation {} ({}): {}", index, op.name(), err))
+        })?;
+    }
+    let session = state.manager.session().await?;
+    Ok(Reply(
+        apply_batch(&session, req.ops, req.stop_on_error).await,
+    ))
+}
+
+/// Runs `ops` in order, recording each outcome. After a failure the rest
+/// are skipped when `stop_on_error` is set.
+pub(crate) async fn apply_batch(
+    session: &EarSessionHandle,
+    ops: Vec<BatchOp>,
+    stop_on_error: bool,
+) -> BatchResponse {
+    let mut response = BatchResponse {
+        applied: 0,
+        failed: 0,
+        skipped: 0,
+        results: Vec::with_capacity(ops.len()),
+    };
+    for (index, op) in ops.into_iter().enumerate() {
+        let name = op.name();
+        if stop_on_error && response.failed > 0 {
+            response.skipped += 1;
+            response.results.push(BatchOpResult {
+                index,
+                op: name,
+                status: BatchOpStatus::Skipped,
+                error: None,
+                code: None,
+                anc: None,
+            });
+            continue;
+        }
+        let result = match op.apply(session).await {
+            Ok(anc) => {
+                response.applied += 1;
+                BatchOpResult {
+                    index,
+                    op: name,
+                    status: BatchOpStatus::Ok,
+                    error: None,
+                    code: None,
+                    anc,
+                }
+            }
+            Err(err) => {
+                warn!("batch operation {} ({}) failed: {}", index, name, err);
+                response.failed += 1;
+                BatchOpResult {
+                    index,
+                    op: name,
+                    status: BatchOpStatus::Failed,
+                    error: Some(err.to_string()),
+                    code: Some(err.code()),
+                    anc: None,
+                }
+            }
+        };
+        response.results.push(result);
+    }
+    response
+}
+
+async fn list_profiles(State(state): State<ApiState>) -> ApiResult<BTreeMap<String, SoundProfile>> {
+    Ok(Reply(state.profiles.list().await))
+}
+
+async fn save_profile(
+    State(state): State<ApiState>,
+    Path(name): Path<String>,
+) -> ApiResult<SoundProfile> {
+    let session = state.manager.session().await?;
+    let profile = session.capture_profile().await?;
+    state.profiles.save(&name, profile.clone()).await?;
+    info!("saved profile {}", name);
+    Ok(Reply(profile))
+}
+
+async fn apply_profile(
+    State(state): State<ApiState>,
+    Path(name): Path<String>,
+) -> ApiResult<BatchResponse> {
+    let profile = state.profiles.get(&name).await?;
+    let session = state.manager.session().await?;
+    Ok(Reply(
+        apply_batch(&session, profile_ops(profile), false).await,
+    ))
+}
+
+/// The writes that restore `profile`, custom bands before the preset since
+/// writing them selects the custom preset.
+fn profile_ops(profile: SoundProfile) -> Vec<BatchOp> {
+    let mut ops = Vec::new();
+    if let Some(eq) = profile.custom_eq {
+        ops.push(BatchOp::CustomEq(BatchCustomEq { slot: 0, eq }));
+    }
+    if let Some(mode) = profile.eq_mode {
+        ops.push(BatchOp::Eq(SetEqRequest { mode }));
+    }
+    if let Some(level) = profile.anc {
+        ops.push(BatchOp::Anc(AncRequest {
+            level,
+            verify: false,
+        }));
+    }
+    if let Some(state) = profile.enhanced_bass {
+        ops.push(BatchOp::EnhancedBass(state));
+    }
+    if let Some(mode) = profile.latency {
+        ops.push(BatchOp::Latency(LatencyRequest {
+            low_latency_enabled: None,
+            mode: Some(mode),
+        }));
+    }
+    ops
+}
+
+async fn send_raw(
+    State(state): State<ApiState>,
+    Json(req): Json<RawCommandRequest>,
+) -> ApiResult<RawCommandResponse> {
+    if !state.unsafe_raw {
+        return Err(EarError::Disabled("raw command").into());
+    }
+    let command = parse_hex_u16(&req.command)?;
+    let payload = match req.payload.as_deref() {
+        Some(hex) => parse_hex_bytes(hex)?,
+        None => Vec::new(),
+    };
+    let expect_response = req
+        .expect_response
+        .as_deref()
+        .map(parse_hex_u16)
+        .transpose()?;
+
+    let session = state.manager.session().await?;
+    warn!(
+        "sending raw command 0x{:04x} with payload {}",
+        command,
+        format_hex(&payload)
+    );
+    let (operation_id, packet) = session.send_raw(command, &payload, expect_response).await?;
+    Ok(Reply(RawCommandResponse {
+        operation_id,
+        command: packet
+            .as_ref()
+            .map(|packet| format!("0x{:04x}", packet.command)),
+        payload: packet.as_ref().map(|packet| format_hex(&packet.payload)),
+    }))
+}
+
+async fn events(State(state): State<ApiState>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| forward_events(state, socket))
+}
+
+async fn forward_events(state: ApiState, mut socket: WebSocket) {
+    // Subscribing before the snapshot is taken means no change is missed,
+    // at worst one is repeated.
+    let mut receiver = state.manager.events().subscribe();
+    let snapshot = state.manager.event_snapshot().await;
+    if !send_event(&mut socket, &snapshot).await {
+        return;
+    }
+    loop {
+        tokio::select! {
+            event = receiver.recv() => match event {
+                Ok(event) => {
+                    if !send_event(&mut socket, &event).await {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("event subscriber lagged; dropped {} events", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+/// `false` once the socket is gone. Events that fail to serialize are
+/// skipped.
+async fn send_event(socket: &mut WebSocket, event: &EarEvent) -> bool {
+    let Ok(text) = serde_json::to_string(event) else {
+        return true;
+    };
+    socket.send(Message::Text(text)).await.is_ok()
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub(crate) struct ConnectRequest {
     address: String,
-    #[serde(default = "default_rfcomm_channel")]
-    channel: u8,
+    /// A channel number, or `"auto"` to look it up (falling back to 1).
+    #[serde(default = "default_connect_channel")]
+    channel: RfcommChannel,
     #[serde(default)]
     model: Option<ModelSelector>,
+    /// Local adapter to connect through, e.g. `hci1`.
+    #[serde(default)]
+    adapter: Option<String>,
+    /// Keep the session when `model` can't be applied instead of failing.
+    #[serde(default)]
+    ignore_model_errors: bool,
+}
+
+/// Session info, plus a warning when part of the setup was skipped.
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct ConnectResponse {
+    #[serde(flatten)]
+    session: SessionInfo,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warning: Option<String>,
 }
 
 fn default_rfcomm_channel() -> u8 {
     1
 }
 
-#[derive(Debug, Deserialize)]
-struct AutoConnectRequest {
+fn default_connect_channel() -> RfcommChannel {
+    RfcommChannel::Fixed(default_rfcomm_channel())
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub(crate) struct DiagnosticsRequest {
+    /// Also check that this device is paired and connected.
+    #[serde(default)]
+    address: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub(crate) struct AutoConnectRequest {
     #[serde(default)]
     address: Option<String>,
     #[serde(default)]
@@ -334,10 +1322,39 @@ struct AutoConnectRequest {
     channel: Option<u8>,
     #[serde(default)]
     sku: Option<String>,
+    /// Only consider devices connected through this adapter, e.g. `hci1`.
+    #[serde(default)]
+    adapter: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
-struct ModelSelector {
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct BootstrapResponse {
+    session: SessionInfo,
+    identity: Option<SerialIdentity>,
+    state: Option<DeviceState>,
+    errors: Vec<StepError>,
+}
+
+/// A bootstrap step that failed without aborting the rest.
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct StepError {
+    step: &'static str,
+    error: String,
+    code: &'static str,
+}
+
+impl StepError {
+    fn new(step: &'static str, err: &EarError) -> Self {
+        Self {
+            step,
+            error: err.to_string(),
+            code: err.code(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub(crate) struct ModelSelector {
     #[serde(default)]
     model_id: Option<String>,
     #[serde(default)]
@@ -346,23 +1363,338 @@ struct ModelSelector {
     base: Option<ModelBase>,
 }
 
+/// `?fresh=true` bypasses the read cache and queries the device directly.
 #[derive(Debug, Deserialize)]
-struct AncRequest {
-    level: AncLevel,
+struct ReadQuery {
+    #[serde(default)]
+    fresh: bool,
 }
 
+/// `?slot=N` picks a custom EQ preset slot; defaults to the active slot 0.
 #[derive(Debug, Deserialize)]
-struct SetEqRequest {
-    mode: u8,
+struct SlotQuery {
+    #[serde(default)]
+    slot: u8,
 }
 
+/// `?force=true` re-reads the serial number instead of returning the
+/// identity detected earlier in the session; on a gesture import it accepts a
+/// backup taken from another model.
 #[derive(Debug, Deserialize)]
-struct RingRequest {
+struct ForceQuery {
+    #[serde(default)]
+    force: bool,
+}
+
+/// `?wait_ms=N` keeps polling until the ear-fit test completes.
+#[derive(Debug, Deserialize)]
+struct EarFitQuery {
+    #[serde(default)]
+    wait_ms: Option<u64>,
+}
+
+/// `?timeout_ms=N` bounds `POST /api/ear-fit/run`; 20 s by default.
+#[derive(Debug, Deserialize)]
+struct EarFitRunQuery {
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub(crate) struct AncRequest {
+    level: AncLevel,
+    /// Read the mode back and fail with `not_applied` if the buds ignored it.
+    #[serde(default)]
+    verify: bool,
+}
+
+/// `{ "label": null }` clears the label.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub(crate) struct SessionLabelRequest {
+    #[serde(default)]
+    label: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub(crate) struct SetEqRequest {
+    mode: u8,
+}
+
+/// Gesture update accepting either names (`side`, `gesture_name`, `action_name`)
+/// or the raw bytes; raw bytes win when both are given.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub(crate) struct GestureRequest {
+    #[serde(default)]
+    side: Option<EarSide>,
+    #[serde(default)]
+    device: Option<u8>,
+    #[serde(default)]
+    common: Option<u8>,
+    #[serde(default)]
+    gesture_name: Option<GestureType>,
+    #[serde(default)]
+    gesture_type: Option<u8>,
+    #[serde(default)]
+    action_name: Option<GestureAction>,
+    #[serde(default)]
+    action: Option<u8>,
+}
+
+impl GestureRequest {
+    fn into_slot(self) -> Result<GestureSlot, EarError> {
+        let device = match (self.device, self.side) {
+            (Some(device), _) => device,
+            (None, Some(EarSide::Case)) => {
+                return Err(EarError::InvalidInput(
+                    "gestures can only be assigned to the left or right bud".into(),
+                ));
+            }
+            (None, Some(side)) => side.device_id(),
+            (None, None) => {
+                return Err(EarError::InvalidInput(
+                    "gesture requires `side` or `device`".into(),
+                ));
+            }
+        };
+        let gesture_type = self
+            .gesture_type
+            .or(self.gesture_name.map(GestureType::to_device))
+            .ok_or_else(|| {
+                EarError::InvalidInput("gesture requires `gesture_name` or `gesture_type`".into())
+            })?;
+        let action = self
+            .action
+            .or(self.action_name.map(GestureAction::to_device))
+            .ok_or_else(|| {
+                EarError::InvalidInput("gesture requires `action_name` or `action`".into())
+            })?;
+        Ok(GestureSlot {
+            device,
+            common: self.common.unwrap_or(GESTURE_COMMON_DEFAULT),
+            gesture_type,
+            action,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub(crate) struct VolumeGesturesRequest {
+    gesture: GestureType,
+}
+
+/// Either a latency `mode`, or the simple `low_latency_enabled` toggle.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub(crate) struct LatencyRequest {
+    #[serde(default)]
+    low_latency_enabled: Option<bool>,
+    #[serde(default)]
+    mode: Option<LatencyMode>,
+}
+
+impl LatencyRequest {
+    fn mode(&self) -> Result<LatencyMode, EarError> {
+        match (self.mode, self.low_latency_enabled) {
+            (Some(mode), _) => Ok(mode),
+            (None, Some(true)) => Ok(LatencyMode::Low),
+            (None, Some(false)) => Ok(LatencyMode::Normal),
+            (None, None) => Err(EarError::InvalidInput(
+                "latency requires `mode` or `low_latency_enabled`".into(),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub(crate) struct RingRequest {
     enable: bool,
+    /// `left` or `right`; omit to ring both. The Ear (1) always rings both.
     #[serde(default)]
     side: Option<EarSide>,
 }
 
+/// The reset only runs with `"confirm": true`.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub(crate) struct ResetRequest {
+    #[serde(default)]
+    confirm: bool,
+}
+
+const MAX_BATCH_OPS: usize = 32;
+
+/// Settings to write in one request, applied in order.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub(crate) struct BatchRequest {
+    ops: Vec<BatchOp>,
+    /// Skip the remaining operations after the first failure.
+    #[serde(default = "default_stop_on_error")]
+    stop_on_error: bool,
+}
+
+fn default_stop_on_error() -> bool {
+    true
+}
+
+/// One write in a batch, tagged by `op`; the other fields match the body of
+/// the endpoint it stands for.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(tag = "op")]
+pub(crate) enum BatchOp {
+    /// `POST /api/anc`
+    #[serde(rename = "set_anc")]
+    Anc(AncRequest),
+    /// `POST /api/eq`
+    #[serde(rename = "set_eq")]
+    Eq(SetEqRequest),
+    /// `POST /api/eq/custom`, with the slot in the body.
+    #[serde(rename = "set_custom_eq")]
+    CustomEq(BatchCustomEq),
+    /// `POST /api/enhanced-bass`
+    #[serde(rename = "set_enhanced_bass")]
+    EnhancedBass(EnhancedBassState),
+    /// `POST /api/latency`
+    #[serde(rename = "set_latency")]
+    Latency(LatencyRequest),
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub(crate) struct BatchCustomEq {
+    #[serde(default)]
+    slot: u8,
+    #[serde(flatten)]
+    eq: CustomEq,
+}
+
+impl BatchOp {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Anc(_) => "set_anc",
+            Self::Eq(_) => "set_eq",
+            Self::CustomEq(_) => "set_custom_eq",
+            Self::EnhancedBass(_) => "set_enhanced_bass",
+            Self::Latency(_) => "set_latency",
+        }
+    }
+
+    /// Checks that don't need the device.
+    fn validate(&self) -> Result<(), EarError> {
+        match self {
+            Self::Latency(req) => req.mode().map(drop),
+            _ => Ok(()),
+        }
+    }
+
+    /// The ANC mode read back by a verified `set_anc`; `None` for the rest.
+    async fn apply(self, session: &EarSessionHandle) -> Result<Option<AncStatus>, EarError> {
+        match self {
+            Self::Anc(req) if req.verify => session.set_anc_verified(req.level).await.map(Some),
+            Self::Anc(req) => session.set_anc(req.level).await.map(|()| None),
+            Self::Eq(req) => session.set_eq_mode(req.mode).await.map(|()| None),
+            Self::CustomEq(req) => session.set_custom_eq(req.slot, req.eq).await.map(|()| None),
+            Self::EnhancedBass(req) => session
+                .set_enhanced_bass(req.enabled, req.level)
+                .await
+                .map(|()| None),
+            Self::Latency(req) => session.set_latency_mode(req.mode()?).await.map(|()| None),
+        }
+    }
+}
+
+/// `{ "status": "ok" }`, plus the conflicts found when the server runs with
+/// `--gesture-conflicts warn`.
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct GestureWriteResponse {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    conflicts: Vec<GestureConflict>,
+}
+
+/// `{ "status": "ok" }`, or the mode read back when the write asked to
+/// `verify`.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(untagged)]
+pub(crate) enum AncWriteResponse {
+    Verified(AncStatus),
+    Sent { status: &'static str },
+}
+
+/// `{ "status": "ok" }` for writes that wait for the buds to acknowledge.
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct AckResponse {
+    status: &'static str,
+    /// The buds acknowledged the write. Some firmware never does,
+    /// so `false` means unconfirmed rather than failed.
+    acknowledged: bool,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct BatchResponse {
+    applied: usize,
+    failed: usize,
+    skipped: usize,
+    /// One entry per operation, in request order.
+    results: Vec<BatchOpResult>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct BatchOpResult {
+    index: usize,
+    op: &'static str,
+    status: BatchOpStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// Error code, as in error responses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+    /// The ANC mode read back by a `set_anc` with `verify`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    anc: Option<AncStatus>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum BatchOpStatus {
+    Ok,
+    Failed,
+    Skipped,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub(crate) struct RawCommandRequest {
+    command: String,
+    #[serde(default)]
+    payload: Option<String>,
+    #[serde(default)]
+    expect_response: Option<String>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct RawCommandResponse {
+    /// Operation id the command was sent with.
+    operation_id: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    command: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload: Option<String>,
+}
+
+/// Body of every error response.
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct ErrorBody {
+    error: String,
+    /// Stable identifier to branch on, e.g. `no_session` or `timeout`.
+    code: &'static str,
+    /// Every pre-flight check, for `preflight_failed` errors.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    preflight: Option<bluetooth::PreflightReport>,
+    /// The colliding slots, for `gesture_conflict` errors.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    conflicts: Option<Vec<GestureConflict>>,
+    /// Hex of the frames the device sent last, oldest first, for `timeout`
+    /// and `crc_mismatch` errors when the server runs with `--debug-packets`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recent_packets: Option<Vec<String>>,
+}
+
 #[derive(Debug)]
 struct ApiError {
     inner: EarError,
@@ -377,17 +1709,43 @@ impl From<EarError> for ApiError {
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
         let status = match self.inner {
-            EarError::NoSession => StatusCode::NOT_FOUND,
+            EarError::NoSession | EarError::NotFound(_) => StatusCode::NOT_FOUND,
             EarError::AlreadyConnected => StatusCode::CONFLICT,
             EarError::Detection(_) => StatusCode::BAD_REQUEST,
             EarError::Unsupported(_) | EarError::UnknownModel => StatusCode::BAD_REQUEST,
-            EarError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            EarError::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            EarError::Disabled(_) => StatusCode::FORBIDDEN,
+            EarError::Unauthorized => StatusCode::UNAUTHORIZED,
+            EarError::DryRun(_) | EarError::NotConnected => StatusCode::SERVICE_UNAVAILABLE,
+            EarError::Timeout(..) => StatusCode::GATEWAY_TIMEOUT,
+            EarError::NotApplied { .. } | EarError::GestureConflict(_) => StatusCode::CONFLICT,
+            EarError::Preflight(_) => StatusCode::SERVICE_UNAVAILABLE,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         };
-        let body = serde_json::json!({
-            "error": format!("{}", self.inner),
-        });
-        (status, Json(body)).into_response()
+        let preflight = match &self.inner {
+            EarError::Preflight(report) => Some(report.clone()),
+            _ => None,
+        };
+        let conflicts = match &self.inner {
+            EarError::GestureConflict(conflicts) => Some(conflicts.clone()),
+            _ => None,
+        };
+        let recent_packets = match &self.inner {
+            EarError::Timeout(_, packets) | EarError::CrcMismatch(packets)
+                if !packets.is_empty() =>
+            {
+                Some(packets.clone())
+            }
+            _ => None,
+        };
+        let body = ErrorBody {
+            error: self.inner.to_string(),
+            code: self.inner.code(),
+            preflight,
+            conflicts,
+            recent_packets,
+        };
+        (status, Reply(body)).into_response()
     }
 }
 
@@ -406,3 +1764,204 @@ async fn apply_model_selector(
     }
     Err(EarError::UnknownModel)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use axum::{
+        Json,
+        extract::{Path, State},
+        http::{HeaderValue, StatusCode},
+        response::IntoResponse,
+    };
+
+    use super::{
+        AncWriteResponse, ApiError, ApiState, BatchOpStatus, BatchRequest, ConnectRequest,
+        ModelSelector, ProfileStore, Reply, ResetRequest, ResponseFormat, apply_profile, connect,
+        factory_reset, run_batch,
+    };
+    use crate::{
+        error::EarError,
+        service::EarManager,
+        types::{AncLevel, AncStatus, CustomEq, LatencyMode, RfcommChannel, SoundProfile},
+    };
+
+    fn state() -> ApiState {
+        ApiState {
+            manager: Arc::new(EarManager::builder().dry_run(true).build()),
+            unsafe_raw: false,
+            auth_token: None,
+            auth_exempt: Vec::new(),
+            cors_origins: Vec::new(),
+            shutdown_timeout: Duration::from_secs(1),
+            shell_retries: 0,
+            profiles: Arc::new(ProfileStore::in_memory()),
+        }
+    }
+
+    fn request(model_id: &str, ignore_model_errors: bool) -> ConnectRequest {
+        ConnectRequest {
+            address: "00:11:22:33:44:55".to_string(),
+            channel: RfcommChannel::Fixed(1),
+            model: Some(ModelSelector {
+                model_id: Some(model_id.to_string()),
+                sku: None,
+                base: None,
+            }),
+            adapter: None,
+            ignore_model_errors,
+        }
+    }
+
+    #[tokio::test]
+    async fn bad_model_id_does_not_leave_a_session_behind() {
+        let state = state();
+        let result = connect(State(state.clone()), Json(request("no-such-model", false))).await;
+        assert!(result.is_err());
+        assert!(state.manager.session().await.is_err());
+
+        let Reply(response) = connect(State(state.clone()), Json(request("no-such-model", true)))
+            .await
+            .unwrap();
+        assert!(response.warning.is_some());
+        assert!(response.session.model.is_none());
+        assert!(state.manager.session().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn batch_runs_in_order_and_stops_on_error() {
+        let state = state();
+        let mut request = request("", false);
+        request.model = None;
+        let _ = connect(State(state.clone()), Json(request)).await.unwrap();
+
+        let body = |stop_on_error: bool| {
+            serde_json::from_value::<BatchRequest>(serde_json::json!({
+                "stop_on_error": stop_on_error,
+                "ops": [
+                    { "op": "set_eq", "mode": 2 },
+                    { "op": "set_enhanced_bass", "enabled": true, "level": 2 },
+                    { "op": "set_custom_eq", "bass": 2.0, "mid": 0.0, "treble": -1.0 },
+                ],
+            }))
+            .unwrap()
+        };
+        let Reply(report) = run_batch(State(state.clone()), Json(body(true)))
+            .await
+            .unwrap();
+        let statuses: Vec<_> = report.results.iter().map(|r| r.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                BatchOpStatus::Ok,
+                BatchOpStatus::Failed,
+                BatchOpStatus::Skipped
+            ]
+        );
+        assert_eq!(report.results[1].code, Some("unsupported"));
+
+        let Reply(report) = run_batch(State(state.clone()), Json(body(false)))
+            .await
+            .unwrap();
+        assert_eq!((report.applied, report.failed, report.skipped), (2, 1, 0));
+
+        // Invalid operations reject the batch before anything runs.
+        let invalid = serde_json::from_value::<BatchRequest>(serde_json::json!({
+            "ops": [{ "op": "set_eq", "mode": 1 }, { "op": "set_latency" }],
+        }))
+        .unwrap();
+        let err = run_batch(State(state), Json(invalid)).await.unwrap_err();
+        assert!(matches!(err.inner, EarError::InvalidInput(_)));
+    }
+
+    #[tokio::test]
+    async fn profiles_apply_every_setting_they_hold() {
+        let state = state();
+        let profile = SoundProfile {
+            anc: Some(AncLevel::Transparency),
+            eq_mode: Some(5),
+            custom_eq: Some(CustomEq {
+                bass: 3.0,
+                mid: 0.0,
+                treble: 0.0,
+            }),
+            enhanced_bass: None,
+            latency: Some(LatencyMode::Low),
+        };
+        state.profiles.save("commute", profile).await.unwrap();
+
+        let err = apply_profile(State(state.clone()), Path("gym".to_string()))
+            .await
+            .unwrap_err();
+        assert!(matches!(err.inner, EarError::NotFound(_)));
+
+        let mut request = request("", false);
+        request.model = None;
+        let _ = connect(State(state.clone()), Json(request)).await.unwrap();
+        let Reply(report) = apply_profile(State(state), Path("commute".to_string()))
+            .await
+            .unwrap();
+        let ops: Vec<_> = report.results.iter().map(|r| r.op).collect();
+        assert_eq!(ops, ["set_custom_eq", "set_eq", "set_anc", "set_latency"]);
+        assert_eq!(report.failed + report.skipped, 0);
+    }
+
+    #[test]
+    fn verified_anc_writes_answer_with_the_mode_read_back() {
+        let verified = AncWriteResponse::Verified(AncStatus {
+            level: AncLevel::NoiseCancellationAdaptive,
+            adaptive_strength: Some(2),
+        });
+        assert_eq!(
+            serde_json::to_value(verified).unwrap(),
+            serde_json::json!({ "level": "noise_cancellation_adaptive", "adaptive_strength": 2 })
+        );
+        let sent = AncWriteResponse::Sent { status: "ok" };
+        assert_eq!(
+            serde_json::to_value(sent).unwrap(),
+            serde_json::json!({ "status": "ok" })
+        );
+    }
+
+    #[test]
+    fn a_dead_stream_is_distinct_from_no_session() {
+        let status = |inner| ApiError { inner }.into_response().status();
+        assert_eq!(status(EarError::NoSession), StatusCode::NOT_FOUND);
+        assert_eq!(
+            status(EarError::NotConnected),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    #[tokio::test]
+    async fn reset_needs_confirmation() {
+        let state = state();
+        let err = factory_reset(State(state), Json(ResetRequest { confirm: false }))
+            .await
+            .unwrap_err();
+        assert!(matches!(err.inner, EarError::InvalidInput(_)));
+    }
+
+    #[test]
+    fn accept_header_picks_the_preferred_format() {
+        let format = |accept: &str| {
+            ResponseFormat::from_accept(Some(&HeaderValue::from_str(accept).unwrap()))
+        };
+        assert_eq!(ResponseFormat::from_accept(None), ResponseFormat::Json);
+        assert_eq!(format("application/x-msgpack"), ResponseFormat::MessagePack);
+        assert_eq!(
+            format("application/json;q=0.5, application/msgpack"),
+            ResponseFormat::MessagePack
+        );
+        assert_eq!(
+            format("application/json, application/x-msgpack"),
+            ResponseFormat::Json
+        );
+        assert_eq!(
+            format("application/x-msgpack;q=0, */*"),
+            ResponseFormat::Json
+        );
+        assert_eq!(format("text/html"), ResponseFormat::Json);
+    }
+}
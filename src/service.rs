@@ -1,79 +1,691 @@
-use std::sync::Arc;
+use std::{
+    ops::Deref,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU8, AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
 
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::{Mutex, MutexGuard, Notify, RwLock, broadcast, watch};
+use tracing::Instrument;
 use uuid::Uuid;
 
 use crate::{
-    connection::EarConnection,
+    bluetooth,
+    connection::{
+        DEFAULT_ACK_TIMEOUT, DEFAULT_PACKET_HISTORY, DEFAULT_READ_RETRIES, EarConnection, LastSeen,
+        ReadBufferConfig,
+    },
     error::EarError,
-    models::{ModelBase, model_from_id, model_from_sku},
-    protocol::{command, response},
+    events::{EarEvent, EventBus},
+    models::{
+        ModelBase, model_from_id, model_from_name, model_from_sku, serial_for_log, sku_from_serial,
+    },
+    protocol::{EarPacket, MAX_PAYLOAD_LEN, ack_command, command, response},
     types::{
-        AncLevel, BatteryReading, BatteryStatus, CustomEq, EarFitResult, EarSide,
-        EnhancedBassState, EqMode, FirmwareInfo, GestureSlot, InEarState, LatencyState, LedColor,
-        LedColorSet, ModelSummary, PersonalizedAncState, SerialIdentity, SessionInfo,
+        AncCapabilities, AncLevel, AncStatus, AutoPauseState, BatteryAlertState, BatteryReading,
+        BatteryStatus, CUSTOM_EQ_MAX_DB, CUSTOM_EQ_MIN_DB, ConversationModeState, CustomEq,
+        CustomEqState, DetectionSource, DeviceState, DisconnectReason, EarFitProgress,
+        EarFitResult, EarFitState, EarSide, EnhancedBassState, EqMode, EqPreset, EqPresets,
+        EqSource, FirmwareInfo, GESTURE_BACKUP_VERSION, GESTURE_COMMON_DEFAULT, GestureAction,
+        GestureBackup, GestureCapabilities, GestureConfig, GestureConflict, GestureConflictPolicy,
+        GestureImportReport, GestureImportResult, GestureSlot, GestureType, InEarState,
+        LatencyMode, LatencyState, LedColor, LedColorSet, ListeningMode, ModelSummary,
+        PersonalizedAncState, SerialIdentity, SessionInfo, SoundProfile, SpatialAudioState,
     },
 };
 
+const DEFAULT_CACHE_TTL_MS: u64 = 500;
+const DEFAULT_HEARTBEAT_FAILURES: u32 = 3;
+const MAX_SESSION_LABEL_LEN: usize = 64;
+/// Levels are percentages; anything higher isn't a reading.
+const BATTERY_MAX_PERCENT: u8 = 100;
+/// The pixel count is a single byte.
+const LED_MAX_PIXELS: usize = u8::MAX as usize;
+const CASE_LED_LABEL: &str = "case LED colors (Nothing Ear (1) only)";
+const CASE_LED_STYLE_LABEL: &str = "case LED brightness and pattern";
+const SPATIAL_AUDIO_LABEL: &str = "spatial audio";
+const CONVERSATION_MODE_LABEL: &str = "conversation mode";
+/// Time the buds get to switch ANC modes before a verifying read.
+const ANC_SETTLE_DELAY: Duration = Duration::from_millis(100);
+const EAR_FIT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How long the notification listener holds an idle connection before
+/// checking whether the session is still around.
+const LISTEN_WINDOW: Duration = Duration::from_secs(5);
+/// Pause after a failed read in the notification listener.
+const LISTEN_RETRY_DELAY: Duration = Duration::from_secs(1);
+
 pub struct EarManager {
-    session: RwLock<Option<Arc<EarSession>>>,
+    session: Arc<RwLock<Option<Arc<EarSession>>>>,
+    /// Set while a connect is opening its socket, so the session lock isn't
+    /// held across the Bluetooth handshake.
+    connecting: AtomicBool,
+    options: EarManagerBuilder,
+    events: EventBus,
 }
 
-impl EarManager {
-    pub fn new() -> Self {
+/// Configures an [`EarManager`]; every setting has a sensible default, so
+/// `EarManager::new()` is equivalent to `EarManager::builder().build()`.
+#[derive(Debug, Clone)]
+pub struct EarManagerBuilder {
+    dry_run: bool,
+    timeout: Option<Duration>,
+    command_timeouts: Vec<(u16, Duration)>,
+    cache_ttl: Duration,
+    read_buffer: ReadBufferConfig,
+    read_retries: u8,
+    max_crc_failures: Option<u8>,
+    operation_id_max: Option<u8>,
+    heartbeat_interval: Option<Duration>,
+    heartbeat_failures: u32,
+    write_debounce: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    gesture_conflicts: GestureConflictPolicy,
+    debug_packets: bool,
+}
+
+impl Default for EarManagerBuilder {
+    fn default() -> Self {
         Self {
-            session: RwLock::new(None),
+            dry_run: false,
+            timeout: None,
+            command_timeouts: Vec::new(),
+            cache_ttl: Duration::from_millis(DEFAULT_CACHE_TTL_MS),
+            read_buffer: ReadBufferConfig::default(),
+            read_retries: DEFAULT_READ_RETRIES,
+            max_crc_failures: None,
+            operation_id_max: None,
+            heartbeat_interval: None,
+            heartbeat_failures: DEFAULT_HEARTBEAT_FAILURES,
+            write_debounce: None,
+            idle_timeout: None,
+            gesture_conflicts: GestureConflictPolicy::Off,
+            debug_packets: false,
+        }
+    }
+}
+
+impl EarManagerBuilder {
+    /// When enabled, sessions log commands instead of writing them to the device.
+    pub fn dry_run(mut self, enabled: bool) -> Self {
+        self.dry_run = enabled;
+        self
+    }
+
+    /// How long a device read waits for its reply.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// How long a reply to one command code is waited for, overriding both
+    /// [`timeout`](Self::timeout) and the built-in value for that command.
+    pub fn command_timeout(mut self, command: u16, timeout: Duration) -> Self {
+        self.command_timeouts.push((command, timeout));
+        self
+    }
+
+    /// How long battery, ANC and EQ reads are served from cache. Zero disables caching.
+    pub fn cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// Read chunk size and buffer cap for new RFCOMM connections.
+    pub fn read_buffer(mut self, read_buffer: ReadBufferConfig) -> Self {
+        self.read_buffer = read_buffer;
+        self
+    }
+
+    /// How often a timed-out read is re-sent. Writes are never retried.
+    pub fn read_retries(mut self, retries: u8) -> Self {
+        self.read_retries = retries;
+        self
+    }
+
+    /// Frames with a bad CRC in a row after which a read fails; fewer are
+    /// dropped and the read waits for the next frame. Defaults to 3.
+    pub fn max_crc_failures(mut self, failures: u8) -> Self {
+        self.max_crc_failures = Some(failures);
+        self
+    }
+
+    /// Wrap operation ids after `max` instead of the default 250.
+    pub fn operation_id_max(mut self, max: u8) -> Self {
+        self.operation_id_max = Some(max);
+        self
+    }
+
+    /// Probe the device with a battery read whenever it has been silent for
+    /// `interval`. Off by default; dry-run sessions are never probed.
+    pub fn heartbeat(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = Some(interval).filter(|interval| !interval.is_zero());
+        self
+    }
+
+    /// Consecutive failed heartbeats after which the session is closed.
+    pub fn heartbeat_failures(mut self, failures: u32) -> Self {
+        self.heartbeat_failures = failures.max(1);
+        self
+    }
+
+    /// Coalesce ANC and custom EQ writes arriving within `window` of each
+    /// other, sending only the last value. Zero (the default) sends every write.
+    pub fn write_debounce(mut self, window: Duration) -> Self {
+        self.write_debounce = Some(window).filter(|window| !window.is_zero());
+        self
+    }
+
+    /// Close the session after `timeout` without API activity, freeing the
+    /// RFCOMM channel for other devices. Zero (the default) never closes it.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout).filter(|timeout| !timeout.is_zero());
+        self
+    }
+
+    /// Whether [`EarSessionHandle::set_gesture`] first reads every slot to
+    /// look for a [`GestureConflict`], and what it does about one.
+    pub fn gesture_conflicts(mut self, policy: GestureConflictPolicy) -> Self {
+        self.gesture_conflicts = policy;
+        self
+    }
+
+    /// Attach the last [`DEFAULT_PACKET_HISTORY`] received frames, as hex, to
+    /// timeout and CRC errors. Off by default, since they carry whatever the
+    /// device sent.
+    pub fn debug_packets(mut self, enabled: bool) -> Self {
+        self.debug_packets = enabled;
+        self
+    }
+
+    pub fn build(self) -> EarManager {
+        EarManager {
+            session: Arc::new(RwLock::new(None)),
+            connecting: AtomicBool::new(false),
+            options: self,
+            events: EventBus::new(),
         }
     }
+}
+
+impl Default for EarManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EarManager {
+    pub fn new() -> Self {
+        Self::builder().build()
+    }
+
+    pub fn builder() -> EarManagerBuilder {
+        EarManagerBuilder::default()
+    }
+
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
 
     pub async fn connect(
         &self,
         address: bluer::Address,
         channel: u8,
     ) -> Result<EarSessionHandle, EarError> {
-        let mut guard = self.session.write().await;
-        if guard.is_some() {
+        self.connect_via(address, channel, None).await
+    }
+
+    /// [`Self::connect`] through the local adapter named `adapter` (e.g.
+    /// `hci1`). Without one, the adapter the buds are connected on is used,
+    /// else the first powered adapter; see [`bluetooth::select_adapter`].
+    pub async fn connect_via(
+        &self,
+        address: bluer::Address,
+        channel: u8,
+        adapter: Option<&str>,
+    ) -> Result<EarSessionHandle, EarError> {
+        let _reservation =
+            ConnectReservation::acquire(&self.connecting).ok_or(EarError::AlreadyConnected)?;
+        if self.session.read().await.is_some() {
             return Err(EarError::AlreadyConnected);
         }
 
-        let connection = EarConnection::open(address, channel).await?;
-        let port_path = connection.port_path().to_string();
+        let options = &self.options;
+        let connection = if options.dry_run {
+            EarConnection::dry_run(address, channel)
+        } else {
+            let local = bluetooth::select_adapter(adapter, address).await?;
+            match EarConnection::open(address, channel, local, options.read_buffer).await {
+                Ok(connection) => connection,
+                Err(err) => {
+                    // Explain the usual causes instead of a bare socket error.
+                    let report = bluetooth::preflight(Some(address)).await;
+                    return Err(if report.ok {
+                        err
+                    } else {
+                        EarError::Preflight(report)
+                    });
+                }
+            }
+        };
+        tracing::info!("Connected to RFCOMM {}", connection.port_path());
+
+        let target = ConnectTarget {
+            address,
+            channel,
+            adapter: adapter.map(str::to_string),
+        };
+        Ok(self.install(connection, Some(target)).await)
+    }
+
+    /// Makes `connection` the active session, e.g. one built with
+    /// [`EarConnection::from_io`] over an in-memory pipe for tests. Such a
+    /// session has no address, so it can't be reconnected.
+    pub async fn attach(&self, connection: EarConnection) -> Result<EarSessionHandle, EarError> {
+        let _reservation =
+            ConnectReservation::acquire(&self.connecting).ok_or(EarError::AlreadyConnected)?;
+        if self.session.read().await.is_some() {
+            return Err(EarError::AlreadyConnected);
+        }
+        Ok(self.install(connection, None).await)
+    }
 
-        tracing::info!("Connected to RFCOMM {}", port_path);
+    async fn install(
+        &self,
+        connection: EarConnection,
+        target: Option<ConnectTarget>,
+    ) -> EarSessionHandle {
+        let session = self.new_session(connection);
+        *session.target.write().await = target;
+        *self.session.write().await = Some(session.clone());
+        self.spawn_heartbeat(session.clone());
+        self.spawn_idle_timeout(session.clone());
+        self.spawn_notification_listener(session.clone());
+        EarSessionHandle { inner: session }
+    }
 
-        let session = Arc::new(EarSession {
-            id: Uuid::new_v4(),
-            port_path,
-            connection: Mutex::new(connection),
-            model: RwLock::new(None),
+    /// Applies the manager's connection options and wraps `connection` in a
+    /// fresh session.
+    fn new_session(&self, mut connection: EarConnection) -> Arc<EarSession> {
+        let options = &self.options;
+        connection.set_read_retries(options.read_retries);
+        if let Some(failures) = options.max_crc_failures {
+            connection.set_max_crc_failures(failures);
+        }
+        if let Some(timeout) = options.timeout {
+            connection.set_timeout(timeout);
+        }
+        for (command, timeout) in &options.command_timeouts {
+            connection.set_command_timeout(*command, *timeout);
+        }
+        if let Some(max) = options.operation_id_max {
+            connection.set_operation_id_max(max);
+        }
+        if options.debug_packets {
+            connection.set_packet_history(DEFAULT_PACKET_HISTORY);
+        }
+        let id = Uuid::new_v4();
+        let mut closed = connection.closed();
+        let events = self.events.clone();
+        tokio::spawn(async move {
+            // Ends without an event if the connection is dropped unclosed.
+            let Ok(reason) = closed.wait_for(Option::is_some).await.map(|reason| *reason) else {
+                return;
+            };
+            if let Some(reason) = reason {
+                tracing::info!("Session {} disconnected: {:?}", id, reason);
+                events.publish(EarEvent::Disconnected {
+                    session_id: id,
+                    reason,
+                });
+            }
         });
-        let handle = EarSessionHandle {
-            inner: session.clone(),
-        };
-        *guard = Some(session);
+        Arc::new(EarSession {
+            id,
+            port_path: connection.port_path().to_string(),
+            connection: CommandQueue::new(connection),
+            model: RwLock::new(None),
+            events: self.events.clone(),
+            last_worn: Mutex::new(None),
+            last_battery: Mutex::new(None),
+            last_anc: Mutex::new(None),
+            last_eq: Mutex::new(None),
+            led_count: Mutex::new(None),
+            battery_alert: RwLock::new(None),
+            device_name: Mutex::new(None),
+            identity: Mutex::new(None),
+            label: RwLock::new(None),
+            cache: ReadCache::new(options.cache_ttl),
+            debounce: WriteDebounce::new(options.write_debounce),
+            target: RwLock::new(None),
+            last_activity: std::sync::Mutex::new(Instant::now()),
+            ear_fit_cancel: watch::channel(0).0,
+            gesture_conflicts: options.gesture_conflicts,
+        })
+    }
+
+    /// Closes the active session and connects again with the address,
+    /// channel and adapter it was opened with, e.g. after the buds were
+    /// power-cycled. The model, label, identity and battery alert carry over
+    /// to the new session. If the connect fails no session is left open.
+    pub async fn reconnect(&self) -> Result<EarSessionHandle, EarError> {
+        let old = self.session().await?.inner;
+        let target = old.target.read().await.clone().ok_or_else(|| {
+            EarError::InvalidInput(
+                "the session was not opened from a Bluetooth address; connect again instead"
+                    .to_string(),
+            )
+        })?;
+        {
+            let mut guard = self.session.write().await;
+            match guard.take() {
+                Some(session) if session.id == old.id => {}
+                other => {
+                    *guard = other;
+                    return Err(EarError::AlreadyConnected);
+                }
+            }
+        }
+        if let Err(err) = close_session(&old, DisconnectReason::Closed).await {
+            // The old link is usually dead already when a reconnect is wanted.
+            tracing::debug!("closing session {} before reconnect: {}", old.id, err);
+        }
 
+        let handle = self
+            .connect_via(target.address, target.channel, target.adapter.as_deref())
+            .await?;
+        let new = &handle.inner;
+        *new.model.write().await = old.model.read().await.clone();
+        *new.label.write().await = old.label.read().await.clone();
+        *new.identity.lock().await = old.identity.lock().await.clone();
+        *new.device_name.lock().await = old.device_name.lock().await.clone();
+        *new.battery_alert.write().await = *old.battery_alert.read().await;
+        // Carried over so subscribers only hear about real changes.
+        *new.last_battery.lock().await = old.last_battery.lock().await.clone();
+        *new.last_worn.lock().await = *old.last_worn.lock().await;
+        *new.last_anc.lock().await = *old.last_anc.lock().await;
+        *new.last_eq.lock().await = old.last_eq.lock().await.clone();
+        tracing::info!("Session {} reconnected as {}", old.id, new.id);
+        self.events.publish(EarEvent::Reconnected {
+            previous_session_id: old.id,
+            session_id: new.id,
+        });
         Ok(handle)
     }
 
+    /// The active session without counting as activity, for background
+    /// work that shouldn't hold off the idle timeout.
+    pub(crate) async fn peek_session(&self) -> Option<EarSessionHandle> {
+        let inner = self.session.read().await.clone()?;
+        Some(EarSessionHandle { inner })
+    }
+
+    /// An [`EarEvent::Snapshot`] of the active session's last battery, ANC
+    /// and EQ readings and worn state. Nothing is read from the device and it
+    /// doesn't count as activity for the idle timeout.
+    pub async fn event_snapshot(&self) -> EarEvent {
+        let Some(session) = self.peek_session().await.map(|handle| handle.inner) else {
+            return EarEvent::Snapshot {
+                session_id: None,
+                battery: None,
+                anc: None,
+                eq: None,
+                left_in_ear: None,
+                right_in_ear: None,
+            };
+        };
+        let (left_in_ear, right_in_ear) = session.last_worn.lock().await.unwrap_or_default();
+        let battery = session.last_battery.lock().await.clone();
+        let anc = *session.last_anc.lock().await;
+        let eq = session.last_eq.lock().await.clone();
+        EarEvent::Snapshot {
+            session_id: Some(session.id),
+            battery,
+            anc,
+            eq,
+            left_in_ear,
+            right_in_ear,
+        }
+    }
+
+    /// The active session. Every call counts as activity for
+    /// [`EarManagerBuilder::idle_timeout`].
     pub async fn session(&self) -> Result<EarSessionHandle, EarError> {
         let guard = self.session.read().await;
-        guard
-            .as_ref()
-            .cloned()
-            .map(|inner| EarSessionHandle { inner })
-            .ok_or(EarError::NoSession)
+        let inner = guard.as_ref().cloned().ok_or(EarError::NoSession)?;
+        inner.touch();
+        Ok(EarSessionHandle { inner })
     }
 
     pub async fn disconnect(&self) -> Result<(), EarError> {
         let mut guard = self.session.write().await;
-        if guard.is_none() {
+        let Some(session) = guard.take() else {
             return Err(EarError::NoSession);
+        };
+        drop(guard);
+        close_session(&session, DisconnectReason::Closed).await
+    }
+
+    /// Closes the session once `heartbeat_failures` probes in a row fail,
+    /// so a link that dropped without closing the socket stops looking
+    /// connected. The task ends when the session is replaced or closed.
+    fn spawn_heartbeat(&self, session: Arc<EarSession>) {
+        let options = &self.options;
+        let Some(interval) = options.heartbeat_interval else {
+            return;
+        };
+        if options.dry_run {
+            return;
         }
-        *guard = None;
-        Ok(())
+        let max_failures = options.heartbeat_failures;
+        let slot = self.session.clone();
+        let id = session.id;
+        drop(session);
+        let span = tracing::info_span!("heartbeat", session = %id);
+        tokio::spawn(
+            async move {
+                let mut failures = 0;
+                loop {
+                    tokio::time::sleep(interval).await;
+                    let inner = match slot.read().await.as_ref() {
+                        Some(session) if session.id == id => session.clone(),
+                        _ => return,
+                    };
+                    let recently_seen = inner
+                        .connection
+                        .last_seen()
+                        .elapsed()
+                        .is_some_and(|elapsed| elapsed < interval);
+                    if recently_seen {
+                        failures = 0;
+                        continue;
+                    }
+                    let handle = EarSessionHandle { inner };
+                    match handle.refresh_battery().await {
+                        Ok(_) => failures = 0,
+                        Err(err) => {
+                            failures += 1;
+                            tracing::warn!(
+                                "Heartbeat {}/{} failed for session {}: {}",
+                                failures,
+                                max_failures,
+                                id,
+                                err
+                            );
+                        }
+                    }
+                    if failures < max_failures {
+                        continue;
+                    }
+                    let mut guard = slot.write().await;
+                    let session = match guard.take() {
+                        Some(session) if session.id == id => session,
+                        other => {
+                            *guard = other;
+                            return;
+                        }
+                    };
+                    drop(guard);
+                    tracing::warn!(
+                        "Device stopped responding; marking session {} disconnected",
+                        id
+                    );
+                    if let Err(err) = close_session(&session, DisconnectReason::Unresponsive).await
+                    {
+                        tracing::debug!("closing unresponsive session {}: {}", id, err);
+                    }
+                    return;
+                }
+            }
+            .instrument(span),
+        );
+    }
+
+    /// Reads what the buds send on their own while no request holds the
+    /// connection, giving it up as soon as one queues, and hands every
+    /// unsolicited packet, including those a request passed over, to
+    /// [`EarSessionHandle::handle_notification`]. Ends with the session.
+    fn spawn_notification_listener(&self, session: Arc<EarSession>) {
+        if self.options.dry_run {
+            return;
+        }
+        let mut notifications = session.connection.notifications();
+        let span = tracing::info_span!("notifications", session = %session.id);
+        let weak = Arc::downgrade(&session);
+        drop(session);
+        let dispatch = weak.clone();
+        tokio::spawn(
+            async move {
+                loop {
+                    let packet = match notifications.recv().await {
+                        Ok(packet) => packet,
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            tracing::warn!("skipped {} notifications", skipped);
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return,
+                    };
+                    let Some(inner) = dispatch.upgrade() else {
+                        return;
+                    };
+                    EarSessionHandle { inner }
+                        .handle_notification(&packet)
+                        .await;
+                }
+            }
+            .instrument(span.clone()),
+        );
+        tokio::spawn(
+            async move {
+                loop {
+                    let Some(session) = weak.upgrade() else {
+                        return;
+                    };
+                    let queue = &session.connection;
+                    if queue.is_closed() {
+                        return;
+                    }
+                    let result = {
+                        let conn = queue.listen_turn().await;
+                        tokio::select! {
+                            biased;
+                            _ = queue.wanted.notified() => Ok(()),
+                            result = conn.listen(LISTEN_WINDOW) => result,
+                        }
+                    };
+                    match result {
+                        Ok(()) | Err(EarError::Timeout(..) | EarError::CrcMismatch(_)) => {}
+                        Err(EarError::NotConnected) => return,
+                        Err(err) => {
+                            tracing::debug!("notification read failed: {}", err);
+                            drop(session);
+                            tokio::time::sleep(LISTEN_RETRY_DELAY).await;
+                        }
+                    }
+                }
+            }
+            .instrument(span),
+        );
+    }
+
+    /// Closes the session once nothing has asked for it in `idle_timeout`.
+    /// Heartbeats and event streams don't count as activity.
+    fn spawn_idle_timeout(&self, session: Arc<EarSession>) {
+        let Some(timeout) = self.options.idle_timeout else {
+            return;
+        };
+        let slot = self.session.clone();
+        let events = self.events.clone();
+        let id = session.id;
+        drop(session);
+        let span = tracing::info_span!("idle_timeout", session = %id);
+        tokio::spawn(
+            async move {
+                let mut wait = timeout;
+                loop {
+                    tokio::time::sleep(wait).await;
+                    let mut guard = slot.write().await;
+                    let idle = match guard.as_ref() {
+                        Some(session) if session.id == id => session.idle_for(),
+                        _ => return,
+                    };
+                    if idle < timeout {
+                        wait = timeout - idle;
+                        continue;
+                    }
+                    let Some(session) = guard.take() else {
+                        return;
+                    };
+                    drop(guard);
+                    tracing::info!("Session {} idle for {:?}; disconnecting", id, idle);
+                    events.publish(EarEvent::IdleDisconnected {
+                        session_id: id,
+                        idle_secs: idle.as_secs(),
+                    });
+                    if let Err(err) = close_session(&session, DisconnectReason::Idle).await {
+                        tracing::debug!("closing idle session {}: {}", id, err);
+                    }
+                    return;
+                }
+            }
+            .instrument(span),
+        );
+    }
+}
+
+/// Claims the right to connect; released on drop, including when the connect
+/// future is cancelled.
+struct ConnectReservation<'a>(&'a AtomicBool);
+
+impl<'a> ConnectReservation<'a> {
+    fn acquire(flag: &'a AtomicBool) -> Option<Self> {
+        flag.compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .ok()
+            .map(|_| Self(flag))
     }
 }
 
+impl Drop for ConnectReservation<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+async fn close_session(session: &EarSession, reason: DisconnectReason) -> Result<(), EarError> {
+    tracing::info!(
+        "Closing session {}{}",
+        session.id,
+        session_label_suffix(session.label.read().await.as_deref())
+    );
+    // Waits for in-flight commands, then closes the socket even if other
+    // handles to the session are still alive.
+    let connection = session.connection.lock().await;
+    connection.mark_closed(reason);
+    let result = connection.close().await;
+    result
+}
+
 #[derive(Clone)]
 pub struct EarSessionHandle {
     inner: Arc<EarSession>,
@@ -82,8 +694,229 @@ pub struct EarSessionHandle {
 struct EarSession {
     id: Uuid,
     port_path: String,
-    connection: Mutex<EarConnection>,
+    connection: CommandQueue,
     model: RwLock<Option<ModelDescriptor>>,
+    events: EventBus,
+    last_worn: Mutex<Option<(Option<bool>, Option<bool>)>>,
+    last_battery: Mutex<Option<BatteryStatus>>,
+    last_anc: Mutex<Option<AncStatus>>,
+    last_eq: Mutex<Option<EqMode>>,
+    /// Case LED pixels as last reported by the case; the count is fixed
+    /// per device, so writes check against it without a fresh read.
+    led_count: Mutex<Option<usize>>,
+    battery_alert: RwLock<Option<u8>>,
+    device_name: Mutex<Option<String>>,
+    identity: Mutex<Option<SerialIdentity>>,
+    label: RwLock<Option<String>>,
+    cache: ReadCache,
+    debounce: WriteDebounce,
+    /// How the session was opened, for [`EarManager::reconnect`]; `None`
+    /// for sessions over an arbitrary stream.
+    target: RwLock<Option<ConnectTarget>>,
+    /// Last time the session was handed out by [`EarManager::session`].
+    last_activity: std::sync::Mutex<Instant>,
+    /// Bumped by [`EarSessionHandle::cancel_ear_fit`] to stop running
+    /// [`EarSessionHandle::await_ear_fit`] calls.
+    ear_fit_cancel: watch::Sender<u64>,
+    gesture_conflicts: GestureConflictPolicy,
+}
+
+impl EarSession {
+    fn touch(&self) {
+        if let Ok(mut last) = self.last_activity.lock() {
+            *last = Instant::now();
+        }
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_activity
+            .lock()
+            .map(|last| last.elapsed())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Clone)]
+struct ConnectTarget {
+    address: bluer::Address,
+    channel: u8,
+    adapter: Option<String>,
+}
+
+/// Short-lived copies of frequently polled reads, so bursts of requests don't
+/// saturate the single RFCOMM channel.
+struct ReadCache {
+    ttl: Duration,
+    battery: Cached<BatteryStatus>,
+    anc: Cached<AncStatus>,
+    eq: Cached<EqMode>,
+}
+
+impl ReadCache {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            battery: Cached::default(),
+            anc: Cached::default(),
+            eq: Cached::default(),
+        }
+    }
+}
+
+struct Cached<T> {
+    slot: Mutex<Option<(Instant, T)>>,
+}
+
+impl<T> Default for Cached<T> {
+    fn default() -> Self {
+        Self {
+            slot: Mutex::new(None),
+        }
+    }
+}
+
+impl<T: Clone> Cached<T> {
+    async fn get(&self, ttl: Duration) -> Option<T> {
+        let slot = self.slot.lock().await;
+        slot.as_ref()
+            .filter(|(stored_at, _)| stored_at.elapsed() < ttl)
+            .map(|(_, value)| value.clone())
+    }
+
+    async fn store(&self, value: &T) {
+        *self.slot.lock().await = Some((Instant::now(), value.clone()));
+    }
+
+    async fn invalidate(&self) {
+        *self.slot.lock().await = None;
+    }
+}
+
+/// Settings whose writes can be debounced.
+#[derive(Clone, Copy)]
+enum DebouncedWrite {
+    Anc,
+    CustomEq,
+}
+
+/// Trailing-edge debounce for writes a UI slider can fire in bursts. Each
+/// write takes a ticket and waits out the window; only the holder of the
+/// newest ticket sends.
+struct WriteDebounce {
+    window: Option<Duration>,
+    tickets: [AtomicU64; 2],
+}
+
+impl WriteDebounce {
+    fn new(window: Option<Duration>) -> Self {
+        Self {
+            window,
+            tickets: [AtomicU64::new(0), AtomicU64::new(0)],
+        }
+    }
+
+    fn ticket(&self, write: DebouncedWrite) -> &AtomicU64 {
+        &self.tickets[write as usize]
+    }
+}
+
+/// Serializes access to the connection. Waiters are served first come, first
+/// served (tokio's `Mutex` is fair), so a slow transaction delays later
+/// requests but never lets them overtake each other. Between requests the
+/// notification listener holds the connection and yields it when `wanted`
+/// fires.
+struct CommandQueue {
+    connection: Mutex<EarConnection>,
+    depth: AtomicUsize,
+    wanted: Notify,
+    /// Never read; kept to hand out fresh receivers.
+    notifications: broadcast::Receiver<EarPacket>,
+    operation_id: Arc<AtomicU8>,
+    last_seen: LastSeen,
+    crc_errors: Arc<AtomicU64>,
+    closed: watch::Receiver<Option<DisconnectReason>>,
+}
+
+/// Holds the connection; the request leaves the queue when this is dropped.
+struct QueueGuard<'a> {
+    connection: MutexGuard<'a, EarConnection>,
+    _ticket: QueueTicket<'a>,
+}
+
+/// Counts a request from the moment it joins the queue, including while it
+/// waits, and stays correct if the waiting request is cancelled.
+struct QueueTicket<'a>(&'a AtomicUsize);
+
+impl<'a> QueueTicket<'a> {
+    fn new(depth: &'a AtomicUsize) -> Self {
+        depth.fetch_add(1, Ordering::SeqCst);
+        Self(depth)
+    }
+}
+
+impl Drop for QueueTicket<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl CommandQueue {
+    fn new(connection: EarConnection) -> Self {
+        Self {
+            last_seen: connection.last_seen(),
+            crc_errors: connection.crc_errors(),
+            closed: connection.closed(),
+            operation_id: connection.operation_id_handle(),
+            notifications: connection.notifications(),
+            connection: Mutex::new(connection),
+            depth: AtomicUsize::new(0),
+            wanted: Notify::new(),
+        }
+    }
+
+    async fn lock(&self) -> QueueGuard<'_> {
+        let ticket = QueueTicket::new(&self.depth);
+        self.wanted.notify_one();
+        QueueGuard {
+            connection: self.connection.lock().await,
+            _ticket: ticket,
+        }
+    }
+
+    /// The connection for the notification listener, which doesn't count
+    /// towards the depth.
+    async fn listen_turn(&self) -> MutexGuard<'_, EarConnection> {
+        self.connection.lock().await
+    }
+
+    fn notifications(&self) -> broadcast::Receiver<EarPacket> {
+        self.notifications.resubscribe()
+    }
+
+    fn operation_id(&self) -> u8 {
+        self.operation_id.load(Ordering::SeqCst)
+    }
+
+    fn last_seen(&self) -> &LastSeen {
+        &self.last_seen
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.borrow().is_some()
+    }
+
+    /// Requests holding or waiting for the connection.
+    fn depth(&self) -> usize {
+        self.depth.load(Ordering::SeqCst)
+    }
+}
+
+impl Deref for QueueGuard<'_> {
+    type Target = EarConnection;
+
+    fn deref(&self) -> &EarConnection {
+        &self.connection
+    }
 }
 
 #[derive(Clone)]
@@ -96,6 +929,34 @@ struct ModelDescriptor {
 }
 
 impl ModelDescriptor {
+    /// Folds detected info into what the session already knows. A serial read
+    /// from the device always wins; a SKU, model or base that was already set
+    /// (e.g. by the user) is kept and detection only fills the gaps.
+    fn merge(self, detected: ModelDescriptor) -> ModelDescriptor {
+        if let (Some(known), Some(found)) = (&self.sku, &detected.sku) {
+            if known != found {
+                tracing::debug!("keeping SKU {} over detected {}", known, found);
+            }
+        }
+        let base = match self.base {
+            ModelBase::Unknown => detected.base,
+            base => base,
+        };
+        // The model id and name describe the SKU, so they travel with it.
+        let (model_id, name) = if self.sku.is_some() || self.model_id.is_some() {
+            (self.model_id, self.name.or(detected.name))
+        } else {
+            (detected.model_id, detected.name.or(self.name))
+        };
+        ModelDescriptor {
+            base,
+            model_id,
+            name,
+            sku: self.sku.or(detected.sku),
+            serial: detected.serial.or(self.serial),
+        }
+    }
+
     fn summary(&self) -> ModelSummary {
         ModelSummary {
             id: self.model_id.clone(),
@@ -126,11 +987,57 @@ impl EarSessionHandle {
 
     pub async fn info(&self) -> SessionInfo {
         let model = self.inner.model.read().await.clone().map(|m| m.summary());
+        // Don't wait behind an in-flight transaction just to report the id.
+        let queue_depth = self.inner.connection.depth();
+        let operation_id = Some(self.inner.connection.operation_id());
         SessionInfo {
             id: self.inner.id,
             port_path: self.inner.port_path.clone(),
             model,
+            operation_id,
+            queue_depth,
+            label: self.inner.label.read().await.clone(),
+            last_seen_ms: self.inner.connection.last_seen().unix_ms(),
+            write_debounce_ms: self
+                .inner
+                .debounce
+                .window
+                .map(|window| window.as_millis() as u64),
+            connected: !self.inner.connection.is_closed(),
+            crc_errors: self.inner.connection.crc_errors.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Attaches a user-facing label, shown in session info and logs. Blank
+    /// labels clear it.
+    pub async fn set_label(&self, label: Option<String>) -> Result<(), EarError> {
+        let label = label
+            .map(|label| label.trim().to_string())
+            .filter(|label| !label.is_empty());
+        if let Some(label) = &label {
+            if label.chars().count() > MAX_SESSION_LABEL_LEN {
+                return Err(EarError::InvalidInput(format!(
+                    "session label is limited to {} characters",
+                    MAX_SESSION_LABEL_LEN
+                )));
+            }
         }
+        tracing::info!(
+            "Session {} label set{}",
+            self.inner.id,
+            session_label_suffix(label.as_deref())
+        );
+        *self.inner.label.write().await = label;
+        Ok(())
+    }
+
+    pub async fn reset_operation_id(&self) {
+        self.inner
+            .connection
+            .lock()
+            .await
+            .reset_operation_id()
+            .await;
     }
 
     pub async fn set_model_by_id(&self, id: &str) -> Result<ModelSummary, EarError> {
@@ -142,8 +1049,7 @@ impl EarSessionHandle {
             sku: None,
             serial: None,
         };
-        *self.inner.model.write().await = Some(descriptor.clone());
-        Ok(descriptor.summary())
+        Ok(self.override_model(descriptor).await)
     }
 
     pub async fn set_model_base(&self, base: ModelBase) -> ModelSummary {
@@ -154,8 +1060,16 @@ impl EarSessionHandle {
             sku: None,
             serial: None,
         };
-        *self.inner.model.write().await = Some(descriptor.clone());
-        descriptor.summary()
+        self.override_model(descriptor).await
+    }
+
+    /// Replaces the model and drops the cached identity, which describes
+    /// the model it replaces; the next detection reads the serial again.
+    async fn override_model(&self, descriptor: ModelDescriptor) -> ModelSummary {
+        let summary = descriptor.summary();
+        *self.inner.model.write().await = Some(descriptor);
+        *self.inner.identity.lock().await = None;
+        summary
     }
 
     pub async fn set_model_from_sku(
@@ -171,155 +1085,553 @@ impl EarSessionHandle {
             sku: Some(sku.to_string()),
             serial,
         };
-        *self.inner.model.write().await = Some(descriptor.clone());
-        Ok(descriptor.summary())
+        Ok(self.override_model(descriptor).await)
     }
 
     /// Initialize device by querying all its states (like ear-web's initDevice)
-    pub async fn init_device(&self) -> Result<(), EarError> {
+    pub async fn init_device(&self) -> Result<DeviceState, EarError> {
         use tokio::time::{Duration, sleep};
 
         tracing::debug!("Starting device initialization...");
 
         // Request battery
-        let _ = self.read_battery().await;
+        let battery = self.read_battery().await.ok();
         sleep(Duration::from_millis(100)).await;
 
         // Request EQ
-        let _ = self.read_eq().await;
+        let eq = self.read_eq().await.ok();
         sleep(Duration::from_millis(100)).await;
 
         // Request in-ear status
-        let _ = self.read_in_ear().await;
+        let in_ear = self.read_in_ear().await.ok();
         sleep(Duration::from_millis(100)).await;
 
         // Request latency status
-        let _ = self.read_latency().await;
+        let latency = self.read_latency().await.ok();
         sleep(Duration::from_millis(100)).await;
 
         tracing::debug!("Device initialization complete");
-        Ok(())
+        Ok(DeviceState {
+            battery,
+            eq,
+            in_ear,
+            latency,
+        })
     }
 
-    pub async fn detect_serial(&self) -> Result<SerialIdentity, EarError> {
-        let payload = {
-            let conn = self.inner.connection.lock().await;
-            conn.transact(
-                command::REQUEST_SERIAL,
-                &[],
-                |packet| {
-                    if packet.command == response::SERIAL {
-                        Some(packet.payload.clone())
-                    } else {
-                        None
-                    }
-                },
-                "serial",
+    /// Returns custom EQ, the EQ preset, ANC, latency and gestures to their
+    /// defaults and reads the resulting state back. The protocol has no reset
+    /// command, so this is one write per setting, skipping what the model
+    /// lacks: flat custom bands, preset 0, ANC off, normal latency and
+    /// [`ModelBase::default_gesture_action`] for each reported slot. Stops at
+    /// the first failed write, leaving earlier settings reset.
+    pub async fn factory_reset(&self) -> Result<DeviceState, EarError> {
+        let base = self.model_base().await;
+        tracing::warn!("Resetting settings of session {}", self.inner.id);
+        if base.supports_custom_eq() {
+            let flat = CustomEq {
+                bass: 0.0,
+                mid: 0.0,
+                treble: 0.0,
+            };
+            self.set_custom_eq(0, flat).await?;
+        }
+        // After the custom bands, which switch the buds to the custom preset.
+        self.set_eq_mode(0).await?;
+        if base.supports_anc() {
+            self.set_anc(AncLevel::Off).await?;
+        }
+        self.set_latency_mode(LatencyMode::Normal).await?;
+        if base.gesture_capabilities().is_some() {
+            for slot in self.read_gestures().await? {
+                let default = GestureType::from_device(slot.gesture_type)
+                    .and_then(|gesture| base.default_gesture_action(gesture));
+                if let Some(action) = default.map(GestureAction::to_device) {
+                    if action != slot.action {
+                        self.write_gesture(&GestureSlot { action, ..slot }).await?;
+                    }
+                }
+            }
+        }
+        self.init_device().await
+    }
+
+    /// Reads the settings a [`SoundProfile`] holds, leaving out the ones the
+    /// model doesn't support. A listening mode read in place of the EQ
+    /// preset is left out too, since applying it would set the wrong preset.
+    pub async fn capture_profile(&self) -> Result<SoundProfile, EarError> {
+        let latency = supported(self.read_latency().await)?;
+        let eq = supported(self.read_eq().await)?;
+        Ok(SoundProfile {
+            anc: supported(self.read_anc().await)?.map(|status| status.level),
+            eq_mode: eq.filter(|eq| eq.source == EqSource::Eq).map(|eq| eq.mode),
+            custom_eq: supported(self.get_custom_eq(0).await)?,
+            enhanced_bass: supported(self.read_enhanced_bass().await)?,
+            latency: latency.map(|state| match state.mode {
+                Some(mode) => mode,
+                None if state.low_latency_enabled => LatencyMode::Low,
+                None => LatencyMode::Normal,
+            }),
+        })
+    }
+
+    /// Records the Bluetooth device name, used by [`Self::detect_serial`] when
+    /// the buds don't identify themselves through their serial number.
+    pub async fn set_device_name(&self, name: impl Into<String>) {
+        *self.inner.device_name.lock().await = Some(name.into());
+    }
+
+    /// Returns the identity found by an earlier detection on this session,
+    /// asking the device only the first time. See [`Self::refresh_serial`].
+    pub async fn detect_serial(&self) -> Result<SerialIdentity, EarError> {
+        if let Some(identity) = self.inner.identity.lock().await.clone() {
+            return Ok(identity);
+        }
+        self.refresh_serial().await
+    }
+
+    /// Always re-reads the serial number, replacing the cached identity.
+    pub async fn refresh_serial(&self) -> Result<SerialIdentity, EarError> {
+        let identity = self.query_identity().await?;
+        *self.inner.identity.lock().await = Some(identity.clone());
+        Ok(identity)
+    }
+
+    async fn query_identity(&self) -> Result<SerialIdentity, EarError> {
+        let result = {
+            let conn = self.inner.connection.lock().await;
+            conn.transact(
+                command::REQUEST_SERIAL,
+                &[],
+                |packet| {
+                    if packet.command == response::SERIAL {
+                        Some(packet.payload.clone())
+                    } else {
+                        None
+                    }
+                },
+                "serial",
             )
-            .await?
+            .await
+        };
+        let payload = match result {
+            Ok(payload) => payload,
+            Err(err) => {
+                return match self.detect_from_name(None, None).await {
+                    Some(identity) => {
+                        tracing::warn!("serial request failed ({}), matched model by name", err);
+                        Ok(identity)
+                    }
+                    None => Err(err),
+                };
+            }
         };
 
         let serial = parse_serial_number(&payload);
         let mut sku = None;
         let mut model_summary = None;
         if let Some(ref serial_number) = serial {
-            if let Some(detected_sku) = derive_sku_from_serial(serial_number) {
-                if let Some(info) = model_from_sku(detected_sku.as_str()) {
+            if let Some(detected) = sku_from_serial(serial_number) {
+                tracing::debug!(
+                    "serial {} matched SKU rule {}",
+                    serial_for_log(serial_number),
+                    detected.rule
+                );
+                if let Some(info) = model_from_sku(&detected.sku) {
                     model_summary = Some(info);
                 }
-                sku = Some(detected_sku);
+                sku = Some(detected.sku);
             }
         }
 
-        if let Some(info) = model_summary {
-            let descriptor = ModelDescriptor {
-                base: info.base,
-                model_id: Some(info.id.to_string()),
-                name: Some(info.name.to_string()),
-                sku: sku.clone(),
-                serial: serial.clone(),
-            };
-            *self.inner.model.write().await = Some(descriptor);
-        }
+        let Some(info) = model_summary else {
+            if let Some(identity) = self.detect_from_name(serial.clone(), sku.clone()).await {
+                return Ok(identity);
+            }
+            return Ok(SerialIdentity {
+                serial_number: serial,
+                sku,
+                model_id: None,
+                source: None,
+            });
+        };
+
+        self.merge_model(ModelDescriptor {
+            base: info.base,
+            model_id: Some(info.id.to_string()),
+            name: Some(info.name.to_string()),
+            sku: sku.clone(),
+            serial: serial.clone(),
+        })
+        .await;
 
         Ok(SerialIdentity {
             serial_number: serial,
             sku,
-            model_id: model_summary.map(|info| info.id.to_string()),
+            model_id: Some(info.id.to_string()),
+            source: Some(DetectionSource::Serial),
+        })
+    }
+
+    /// Falls back to the Bluetooth name. Only the base is known, so no model
+    /// id is reported.
+    async fn detect_from_name(
+        &self,
+        serial: Option<String>,
+        sku: Option<String>,
+    ) -> Option<SerialIdentity> {
+        let name = self.inner.device_name.lock().await.clone()?;
+        let info = model_from_name(&name)?;
+        self.merge_model(ModelDescriptor {
+            base: info.base,
+            model_id: None,
+            name: Some(info.name.to_string()),
+            sku: sku.clone(),
+            serial: serial.clone(),
+        })
+        .await;
+        Some(SerialIdentity {
+            serial_number: serial,
+            sku,
+            model_id: None,
+            source: Some(DetectionSource::BluetoothName),
         })
     }
 
+    async fn merge_model(&self, detected: ModelDescriptor) {
+        let mut model = self.inner.model.write().await;
+        let merged = match model.take() {
+            Some(existing) => existing.merge(detected),
+            None => detected,
+        };
+        *model = Some(merged);
+    }
+
+    /// Battery status, served from the read cache when fresh enough.
     pub async fn read_battery(&self) -> Result<BatteryStatus, EarError> {
+        let cache = &self.inner.cache;
+        if let Some(status) = cache.battery.get(cache.ttl).await {
+            return Ok(status);
+        }
+        self.refresh_battery().await
+    }
+
+    /// Queries the device for battery status, bypassing the read cache.
+    /// Some models report the buds and the case in separate packets, so
+    /// readings are merged until every expected device is present or no
+    /// further packet follows.
+    pub async fn refresh_battery(&self) -> Result<BatteryStatus, EarError> {
+        let with_case = self.model_base().await.has_charging_case();
         let conn = self.inner.connection.lock().await;
-        conn.transact(
-            command::REQUEST_BATTERY,
-            &[],
-            |packet| match packet.command {
-                response::BATTERY_PRIMARY | response::BATTERY_SECONDARY => {
-                    Some(parse_battery_payload(&packet.payload))
+        let status = conn
+            .transact_merge(
+                command::REQUEST_BATTERY,
+                &[],
+                BatteryStatus::empty(),
+                |status, packet| merge_battery_packet(status, packet, with_case),
+                "battery",
+            )
+            .await?;
+        self.record_battery(&status).await;
+        Ok(status)
+    }
+
+    /// Caches a reading and publishes it if it differs from the last one.
+    async fn record_battery(&self, status: &BatteryStatus) {
+        self.inner.cache.battery.store(status).await;
+        let mut last_battery = self.inner.last_battery.lock().await;
+        if last_battery.as_ref() != Some(status) {
+            self.inner.events.publish(EarEvent::BatteryChanged {
+                battery: status.clone(),
+            });
+            *last_battery = Some(status.clone());
+        }
+    }
+
+    /// The low-battery threshold with the sides currently below it. Uses the
+    /// last reading when there is one, so checking the alert doesn't poll.
+    pub async fn battery_alert(&self) -> Result<BatteryAlertState, EarError> {
+        let threshold = *self.inner.battery_alert.read().await;
+        let Some(threshold) = threshold else {
+            return Ok(BatteryAlertState {
+                threshold: None,
+                active: false,
+                low: Vec::new(),
+            });
+        };
+        let last = self.inner.last_battery.lock().await.clone();
+        let battery = match last {
+            Some(battery) => battery,
+            None => self.read_battery().await?,
+        };
+        let low = battery.sides_below(threshold);
+        Ok(BatteryAlertState {
+            threshold: Some(threshold),
+            active: !low.is_empty(),
+            low,
+        })
+    }
+
+    pub async fn battery_alert_threshold(&self) -> Option<u8> {
+        *self.inner.battery_alert.read().await
+    }
+
+    /// Stores the low-battery threshold for this session. Keeping it beyond
+    /// the session is up to the caller, see [`Self::address`].
+    pub async fn set_battery_alert(&self, threshold: Option<u8>) -> Result<(), EarError> {
+        if let Some(threshold) = threshold {
+            if !(1..=100).contains(&threshold) {
+                return Err(EarError::InvalidInput(format!(
+                    "battery threshold must be 1-100, got {}",
+                    threshold
+                )));
+            }
+        }
+        *self.inner.battery_alert.write().await = threshold;
+        Ok(())
+    }
+
+    /// Bluetooth address the session was opened with; `None` for sessions
+    /// over other streams.
+    pub async fn address(&self) -> Option<bluer::Address> {
+        self.inner
+            .target
+            .read()
+            .await
+            .as_ref()
+            .map(|target| target.address)
+    }
+
+    /// Applies a packet the buds sent on their own, such as an ANC change
+    /// made with a gesture. Packets that aren't state are ignored.
+    async fn handle_notification(&self, packet: &EarPacket) {
+        match packet.command {
+            response::ANC_PRIMARY | response::ANC_SECONDARY => {
+                if let Some(status) = parse_anc_payload(&packet.payload) {
+                    self.record_anc(status).await;
                 }
-                _ => None,
-            },
-            "battery",
-        )
-        .await
+            }
+            response::IN_EAR => {
+                if let Some(state) = parse_in_ear_payload(&packet.payload) {
+                    self.record_worn(&state, true).await;
+                }
+            }
+            // Often only the parts that changed; the rest keep their last level.
+            response::BATTERY_PRIMARY | response::BATTERY_SECONDARY => {
+                let last = self.inner.last_battery.lock().await.clone();
+                let mut status = last.unwrap_or_else(BatteryStatus::empty);
+                status.merge(parse_battery_payload(&packet.payload));
+                self.record_battery(&status).await;
+            }
+            _ => {}
+        }
+    }
+
+    pub async fn read_anc(&self) -> Result<AncStatus, EarError> {
+        let cache = &self.inner.cache;
+        if let Some(level) = cache.anc.get(cache.ttl).await {
+            return Ok(level);
+        }
+        self.refresh_anc().await
     }
 
-    pub async fn read_anc(&self) -> Result<AncLevel, EarError> {
-        self.require_support("ANC read", |base| base != ModelBase::B157)
+    pub async fn refresh_anc(&self) -> Result<AncStatus, EarError> {
+        self.require_support("ANC read", |base| base.supports_anc())
             .await?;
         let conn = self.inner.connection.lock().await;
-        conn.transact(
-            command::REQUEST_ANC,
-            &[],
-            |packet| match packet.command {
-                response::ANC_PRIMARY | response::ANC_SECONDARY => packet
-                    .payload
-                    .get(1)
-                    .and_then(|&value| AncLevel::from_device(value)),
-                _ => None,
+        let status = conn
+            .transact(
+                command::REQUEST_ANC,
+                &[],
+                |packet| match packet.command {
+                    response::ANC_PRIMARY | response::ANC_SECONDARY => {
+                        parse_anc_payload(&packet.payload)
+                    }
+                    _ => None,
+                },
+                "anc",
+            )
+            .await?;
+        self.record_anc(status).await;
+        Ok(status)
+    }
+
+    pub async fn anc_capabilities(&self) -> AncCapabilities {
+        let model = self.model_base().await;
+        match model.anc_levels() {
+            Some(levels) => AncCapabilities {
+                model,
+                restricted: true,
+                levels: levels.to_vec(),
             },
-            "anc",
-        )
-        .await
+            None => AncCapabilities {
+                model,
+                restricted: false,
+                levels: Vec::new(),
+            },
+        }
     }
 
-    pub async fn set_anc(&self, level: AncLevel) -> Result<(), EarError> {
-        self.require_support("ANC write", |base| base != ModelBase::B157)
+    /// Rejects levels the model lacks, which the buds would silently ignore.
+    async fn check_anc_level(&self, level: AncLevel) -> Result<(), EarError> {
+        self.require_support("ANC write", |base| base.supports_anc())
             .await?;
+        let capabilities = self.anc_capabilities().await;
+        if capabilities.restricted && !capabilities.levels.contains(&level) {
+            let levels: Vec<String> = capabilities
+                .levels
+                .iter()
+                .map(AncLevel::to_string)
+                .collect();
+            return Err(EarError::InvalidInput(format!(
+                "ANC level {} is not available on {}; expected one of {}",
+                level,
+                capabilities.model,
+                levels.join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    pub async fn set_anc(&self, level: AncLevel) -> Result<(), EarError> {
+        self.check_anc_level(level).await?;
+        let session = self.clone();
+        self.debounced(DebouncedWrite::Anc, async move {
+            session.write_anc(level).await
+        })
+        .await
+    }
+
+    async fn write_anc(&self, level: AncLevel) -> Result<(), EarError> {
         let conn = self.inner.connection.lock().await;
         let mut payload = [0x01u8, 0x01, 0x00];
         payload[1] = level.to_device();
         conn.send_command(command::CMD_SET_ANC, &payload).await?;
+        self.inner.cache.anc.invalidate().await;
         Ok(())
     }
 
+    /// [`Self::set_anc`] followed by a fresh read, for callers that need to
+    /// know the mode took: some models silently ignore levels they lack.
+    /// Never debounced, since a superseded write would fail verification.
+    pub async fn set_anc_verified(&self, level: AncLevel) -> Result<AncStatus, EarError> {
+        self.check_anc_level(level).await?;
+        self.write_anc(level).await?;
+        tokio::time::sleep(ANC_SETTLE_DELAY).await;
+        let status = self.refresh_anc().await?;
+        if status.level != level {
+            return Err(EarError::NotApplied {
+                setting: "ANC mode",
+                requested: level.to_string(),
+                actual: status.level.to_string(),
+            });
+        }
+        Ok(status)
+    }
+
+    /// Caches a reading and publishes it if it differs from the last one.
+    async fn record_anc(&self, status: AncStatus) {
+        self.inner.cache.anc.store(&status).await;
+        let mut last_anc = self.inner.last_anc.lock().await;
+        if *last_anc != Some(status) {
+            self.inner
+                .events
+                .publish(EarEvent::AncChanged { anc: status });
+            *last_anc = Some(status);
+        }
+    }
+
     pub async fn read_eq(&self) -> Result<EqMode, EarError> {
+        let cache = &self.inner.cache;
+        if let Some(mode) = cache.eq.get(cache.ttl).await {
+            return Ok(mode);
+        }
+        self.refresh_eq().await
+    }
+
+    /// Reads the EQ preset from the `EQ_PRIMARY` response only. Models with
+    /// listening modes may not answer the EQ request at all; for those a
+    /// timeout falls back to [`Self::read_listening_mode`], marked with
+    /// [`EqSource::ListeningMode`].
+    pub async fn refresh_eq(&self) -> Result<EqMode, EarError> {
+        let result = {
+            let conn = self.inner.connection.lock().await;
+            conn.transact(command::REQUEST_EQ, &[], eq_from_packet, "eq")
+                .await
+        };
+        let mode = match result {
+            Err(EarError::Timeout(..)) if self.model_base().await.supports_listening_modes() => {
+                let listening = self.read_listening_mode().await?;
+                EqMode::new(listening.mode, EqSource::ListeningMode)
+            }
+            other => other?,
+        }
+        .labelled(self.model_base().await);
+        self.inner.cache.eq.store(&mode).await;
+        let mut last_eq = self.inner.last_eq.lock().await;
+        if last_eq.as_ref() != Some(&mode) {
+            self.inner
+                .events
+                .publish(EarEvent::EqChanged { eq: mode.clone() });
+            *last_eq = Some(mode.clone());
+        }
+        Ok(mode)
+    }
+
+    pub async fn read_listening_mode(&self) -> Result<ListeningMode, EarError> {
+        self.require_support("listening mode", |base| base.supports_listening_modes())
+            .await?;
         let conn = self.inner.connection.lock().await;
         conn.transact(
-            command::REQUEST_EQ,
+            command::REQUEST_LISTENING_MODE,
             &[],
-            |packet| match packet.command {
-                response::EQ_PRIMARY | response::EQ_LISTENING_MODE => {
-                    packet.payload.first().copied().map(|mode| EqMode { mode })
-                }
-                _ => None,
-            },
-            "eq",
+            listening_mode_from_packet,
+            "listening_mode",
         )
         .await
     }
 
+    /// The presets the connected model offers, named as in the Nothing X
+    /// app, along with the currently selected one.
+    pub async fn eq_presets(&self) -> Result<EqPresets, EarError> {
+        let base = self.model_base().await;
+        let current = self.read_eq().await?.mode;
+        let presets = base
+            .eq_presets()
+            .iter()
+            .map(|(mode, name)| EqPreset {
+                mode: *mode,
+                name: name.to_string(),
+            })
+            .collect();
+        Ok(EqPresets {
+            model: base,
+            current,
+            presets,
+        })
+    }
+
     pub async fn set_eq_mode(&self, mode: u8) -> Result<(), EarError> {
+        let base = self.model_base().await;
+        if let Some(modes) = base.eq_modes() {
+            if !modes.contains(&mode) {
+                return Err(EarError::InvalidInput(format!(
+                    "EQ mode {} is not available on {}; expected one of {:?}",
+                    mode, base, modes
+                )));
+            }
+        }
         let conn = self.inner.connection.lock().await;
         conn.send_command(command::CMD_SET_EQ, &[mode, 0x00])
             .await?;
+        self.inner.cache.eq.invalidate().await;
         Ok(())
     }
 
-    pub async fn get_custom_eq(&self) -> Result<CustomEq, EarError> {
+    pub async fn get_custom_eq(&self, slot: u8) -> Result<CustomEq, EarError> {
         self.require_support("custom EQ", |base| base.supports_custom_eq())
             .await?;
+        self.check_custom_eq_slot(slot).await?;
         let conn = self.inner.connection.lock().await;
         conn.transact(
             command::REQUEST_CUSTOM_EQ,
@@ -336,14 +1648,85 @@ impl EarSessionHandle {
         .await
     }
 
-    pub async fn set_custom_eq(&self, eq: CustomEq) -> Result<(), EarError> {
+    /// Reads the custom EQ bands and the selected preset so callers can tell
+    /// whether a built-in preset is overriding the custom bands.
+    pub async fn read_custom_eq_state(&self, slot: u8) -> Result<CustomEqState, EarError> {
+        let eq = self.get_custom_eq(slot).await?;
+        let mode = self.read_eq().await?;
+        Ok(CustomEqState {
+            slot,
+            eq,
+            mode: mode.mode,
+            active: mode.is_custom(),
+        })
+    }
+
+    /// Reads every custom EQ slot the model stores.
+    pub async fn list_custom_eq_presets(&self) -> Result<Vec<CustomEqState>, EarError> {
+        let slots = self.model_base().await.custom_eq_slots();
+        let mut presets = Vec::with_capacity(slots.into());
+        for slot in 0..slots {
+            presets.push(self.read_custom_eq_state(slot).await?);
+        }
+        Ok(presets)
+    }
+
+    async fn check_custom_eq_slot(&self, slot: u8) -> Result<(), EarError> {
+        let slots = self.model_base().await.custom_eq_slots();
+        if slot >= slots {
+            return Err(EarError::InvalidInput(format!(
+                "custom EQ slot {} does not exist; the buds store {} (starting at 0)",
+                slot, slots
+            )));
+        }
+        Ok(())
+    }
+
+    pub async fn set_custom_eq(&self, slot: u8, eq: CustomEq) -> Result<(), EarError> {
         self.require_support("custom EQ", |base| base.supports_custom_eq())
             .await?;
-        let conn = self.inner.connection.lock().await;
-        let payload = encode_custom_eq(eq);
-        conn.send_command(command::CMD_SET_CUSTOM_EQ, &payload)
-            .await?;
-        Ok(())
+        self.check_custom_eq_slot(slot).await?;
+        let payload = encode_custom_eq(clamp_custom_eq(eq)?);
+        let session = self.clone();
+        self.debounced(DebouncedWrite::CustomEq, async move {
+            let conn = session.inner.connection.lock().await;
+            conn.send_command(command::CMD_SET_CUSTOM_EQ, &payload)
+                .await?;
+            // Applying custom bands switches the active preset.
+            session.inner.cache.eq.invalidate().await;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Runs `send` once the debounce window passes without a newer write of
+    /// the same kind; superseded writes return `Ok` without sending. The
+    /// wait runs in its own task, so the last write of a burst is sent even
+    /// if its request is dropped.
+    async fn debounced<F>(&self, write: DebouncedWrite, send: F) -> Result<(), EarError>
+    where
+        F: std::future::Future<Output = Result<(), EarError>> + Send + 'static,
+    {
+        let Some(window) = self.inner.debounce.window else {
+            return send.await;
+        };
+        let ticket = self
+            .inner
+            .debounce
+            .ticket(write)
+            .fetch_add(1, Ordering::SeqCst)
+            + 1;
+        let session = self.inner.clone();
+        let task = tokio::spawn(async move {
+            tokio::time::sleep(window).await;
+            if session.debounce.ticket(write).load(Ordering::SeqCst) != ticket {
+                tracing::debug!("write superseded within the debounce window");
+                return Ok(());
+            }
+            send.await
+        });
+        task.await
+            .map_err(|err| EarError::Io(std::io::Error::other(err)))?
     }
 
     pub async fn read_enhanced_bass(&self) -> Result<EnhancedBassState, EarError> {
@@ -412,27 +1795,134 @@ impl EarSessionHandle {
         Ok(())
     }
 
+    /// No model reports the media volume, so volume is only controlled
+    /// relatively: this maps `gesture` to volume up on the right bud and
+    /// volume down on the left, through [`Self::set_gesture`]. Models whose
+    /// gestures can't change volume answer [`EarError::Unsupported`].
+    pub async fn set_volume_gestures(
+        &self,
+        gesture: GestureType,
+    ) -> Result<Vec<GestureConflict>, EarError> {
+        let mut conflicts = Vec::new();
+        for (side, action) in [
+            (EarSide::Right, GestureAction::VolumeUp),
+            (EarSide::Left, GestureAction::VolumeDown),
+        ] {
+            let slot = GestureSlot {
+                device: side.device_id(),
+                common: GESTURE_COMMON_DEFAULT,
+                gesture_type: gesture.to_device(),
+                action: action.to_device(),
+            };
+            conflicts.extend(self.set_gesture(&slot).await?);
+        }
+        Ok(conflicts)
+    }
+
     pub async fn read_in_ear(&self) -> Result<InEarState, EarError> {
         self.require_support("in-ear detection", |base| base.supports_in_ear_detection())
             .await?;
-        let conn = self.inner.connection.lock().await;
-        conn.transact(
-            command::REQUEST_IN_EAR_STATUS,
-            &[],
-            |packet| {
-                if packet.command == response::IN_EAR {
-                    packet.payload.get(2).map(|&value| InEarState {
-                        detection_enabled: value == 1,
-                    })
-                } else {
-                    None
-                }
-            },
-            "in_ear",
-        )
-        .await
+        let state = {
+            let conn = self.inner.connection.lock().await;
+            conn.transact(
+                command::REQUEST_IN_EAR_STATUS,
+                &[],
+                |packet| {
+                    if packet.command == response::IN_EAR {
+                        parse_in_ear_payload(&packet.payload)
+                    } else {
+                        None
+                    }
+                },
+                "in_ear",
+            )
+            .await?
+        };
+        self.record_worn(&state, false).await;
+        Ok(state)
+    }
+
+    /// Remembers which buds are worn and publishes a change. A read only
+    /// publishes against an earlier value; a packet the buds pushed is a
+    /// change in itself, so it is published even when nothing was known.
+    async fn record_worn(&self, state: &InEarState, pushed: bool) {
+        let worn = (state.left_in_ear, state.right_in_ear);
+        if worn == (None, None) {
+            return;
+        }
+        let mut last_worn = self.inner.last_worn.lock().await;
+        let changed = match *last_worn {
+            Some(previous) => previous != worn,
+            None => pushed,
+        };
+        if changed {
+            self.inner.events.publish(EarEvent::InEarChanged {
+                left_in_ear: state.left_in_ear,
+                right_in_ear: state.right_in_ear,
+            });
+        }
+        *last_worn = Some(worn);
+    }
+
+    /// Auto-pause follows in-ear detection, so this reports the detection flag.
+    pub async fn read_auto_pause(&self) -> Result<AutoPauseState, EarError> {
+        let state = self.read_in_ear().await?;
+        Ok(AutoPauseState {
+            enabled: state.detection_enabled,
+        })
+    }
+
+    /// Rejected on every known model: the in-ear command switches the sensor
+    /// and auto-pause together, and there is no separate auto-pause command
+    /// to send. Use [`Self::set_in_ear_detection`] instead.
+    pub async fn set_auto_pause(&self, _state: AutoPauseState) -> Result<(), EarError> {
+        const LABEL: &str = "auto-pause separately from in-ear detection";
+        self.require_support(LABEL, |base| base.supports_separate_auto_pause())
+            .await?;
+        Err(EarError::Unsupported(LABEL))
+    }
+
+    /// Rejected on every model: the command code is unknown, see
+    /// [`ModelBase::supports_conversation_mode`].
+    pub async fn get_conversation_mode(&self) -> Result<ConversationModeState, EarError> {
+        self.require_support(CONVERSATION_MODE_LABEL, |base| {
+            base.supports_conversation_mode()
+        })
+        .await?;
+        Err(EarError::Unsupported(CONVERSATION_MODE_LABEL))
+    }
+
+    /// Rejected on every model, like [`Self::get_conversation_mode`].
+    pub async fn set_conversation_mode(
+        &self,
+        _state: ConversationModeState,
+    ) -> Result<(), EarError> {
+        self.require_support(CONVERSATION_MODE_LABEL, |base| {
+            base.supports_conversation_mode()
+        })
+        .await?;
+        Err(EarError::Unsupported(CONVERSATION_MODE_LABEL))
+    }
+
+    /// Rejected until [`ModelBase::supports_spatial_audio`] lists a model.
+    pub async fn get_spatial_audio(&self) -> Result<SpatialAudioState, EarError> {
+        self.require_support(SPATIAL_AUDIO_LABEL, |base| base.supports_spatial_audio())
+            .await?;
+        Err(EarError::Unsupported(SPATIAL_AUDIO_LABEL))
+    }
+
+    /// Rejected until [`ModelBase::supports_spatial_audio`] lists a model.
+    pub async fn set_spatial_audio(&self, _state: SpatialAudioState) -> Result<(), EarError> {
+        self.require_support(SPATIAL_AUDIO_LABEL, |base| base.supports_spatial_audio())
+            .await?;
+        // TODO: spatial audio has no known command, reply or payload. Send
+        // `state` here (likely `[enabled, head_tracking]` like the other
+        // toggles), read it back the same way in `get_spatial_audio`, then
+        // enable the bases in `ModelBase::supports_spatial_audio`.
+        Err(EarError::Unsupported(SPATIAL_AUDIO_LABEL))
     }
 
+    /// Turns the wear sensor on or off. Media auto-pause is tied to it.
     pub async fn set_in_ear_detection(&self, enabled: bool) -> Result<(), EarError> {
         self.require_support("in-ear detection", |base| base.supports_in_ear_detection())
             .await?;
@@ -449,9 +1939,10 @@ impl EarSessionHandle {
             &[],
             |packet| {
                 if packet.command == response::LATENCY {
-                    packet.payload.get(0).map(|&value| LatencyState {
-                        low_latency_enabled: value == 1,
-                    })
+                    packet
+                        .payload
+                        .first()
+                        .map(|&value| LatencyState::from_device(value))
                 } else {
                     None
                 }
@@ -462,13 +1953,23 @@ impl EarSessionHandle {
     }
 
     pub async fn set_latency(&self, enabled: bool) -> Result<(), EarError> {
+        let mode = if enabled {
+            LatencyMode::Low
+        } else {
+            LatencyMode::Normal
+        };
+        self.set_latency_mode(mode).await
+    }
+
+    pub async fn set_latency_mode(&self, mode: LatencyMode) -> Result<(), EarError> {
         let conn = self.inner.connection.lock().await;
-        let payload = if enabled { [0x01, 0x00] } else { [0x02, 0x00] };
-        conn.send_command(command::CMD_SET_LATENCY, &payload)
+        conn.send_command(command::CMD_SET_LATENCY, &mode.to_payload())
             .await?;
         Ok(())
     }
 
+    /// The combined version, plus each bud's and the case's when the
+    /// payload lists them separately.
     pub async fn read_firmware(&self) -> Result<FirmwareInfo, EarError> {
         let conn = self.inner.connection.lock().await;
         conn.transact(
@@ -476,9 +1977,7 @@ impl EarSessionHandle {
             &[],
             |packet| {
                 if packet.command == response::FIRMWARE {
-                    Some(FirmwareInfo {
-                        version: String::from_utf8_lossy(&packet.payload).trim().to_string(),
-                    })
+                    Some(parse_firmware_payload(&packet.payload))
                 } else {
                     None
                 }
@@ -488,6 +1987,19 @@ impl EarSessionHandle {
         .await
     }
 
+    /// Rejected on every model, see [`ModelBase::supports_dfu`]. Once the
+    /// trigger is known this leaves the buds unresponsive until an update
+    /// completes or they are power-cycled, so it must stay behind a flag.
+    pub async fn enter_dfu_mode(&self) -> Result<(), EarError> {
+        const LABEL: &str = "firmware update mode";
+        self.require_support(LABEL, |base| base.supports_dfu())
+            .await?;
+        Err(EarError::Unsupported(LABEL))
+    }
+
+    /// Starts the test without waiting for a reply, so the connection isn't
+    /// held while the buds are slow to react; the result reads report
+    /// progress.
     pub async fn launch_ear_fit_test(&self) -> Result<(), EarError> {
         let conn = self.inner.connection.lock().await;
         conn.send_command(command::CMD_START_EAR_FIT_TEST, &[0x01])
@@ -502,9 +2014,9 @@ impl EarSessionHandle {
             &[0x00],
             |packet| {
                 if packet.command == response::EAR_FIT_RESULT {
-                    let left = packet.payload.get(0).copied().unwrap_or_default();
-                    let right = packet.payload.get(1).copied().unwrap_or_default();
-                    Some(EarFitResult { left, right })
+                    let left = packet.payload.first().copied().unwrap_or(0xFF);
+                    let right = packet.payload.get(1).copied().unwrap_or(0xFF);
+                    Some(EarFitResult::from_raw(left, right))
                 } else {
                     None
                 }
@@ -514,6 +2026,76 @@ impl EarSessionHandle {
         .await
     }
 
+    /// Polls the ear-fit result until both buds report a verdict, or fails
+    /// with [`EarError::Timeout`] once `timeout` elapses.
+    pub async fn wait_for_ear_fit_result(
+        &self,
+        timeout: Duration,
+    ) -> Result<EarFitResult, EarError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let result = self.read_ear_fit_result().await?;
+            if result.complete {
+                return Ok(result);
+            }
+            if Instant::now() + EAR_FIT_POLL_INTERVAL > deadline {
+                return Err(EarError::Timeout("ear fit result", Vec::new()));
+            }
+            tokio::time::sleep(EAR_FIT_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Starts the ear-fit test and polls for the result until both buds
+    /// have a verdict, `timeout` elapses or [`Self::cancel_ear_fit`] is
+    /// called. Reads the buds don't answer in time are retried on the next
+    /// poll, so only a broken link ends the wait early with an error.
+    pub async fn await_ear_fit(&self, timeout: Duration) -> Result<EarFitProgress, EarError> {
+        let mut cancelled = self.inner.ear_fit_cancel.subscribe();
+        self.launch_ear_fit_test().await?;
+        let started = Instant::now();
+        let deadline = started + timeout;
+        let mut result = None;
+        let state = loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break EarFitState::InProgress;
+            }
+            tokio::select! {
+                _ = cancelled.changed() => break EarFitState::Cancelled,
+                read = tokio::time::timeout(remaining, self.read_ear_fit_result()) => match read {
+                    Ok(Ok(read)) => {
+                        let complete = read.complete;
+                        result = Some(read);
+                        if complete {
+                            break EarFitState::Complete;
+                        }
+                    }
+                    Ok(Err(EarError::Timeout(..))) | Err(_) => {}
+                    Ok(Err(err)) => return Err(err),
+                },
+            }
+            let pause =
+                EAR_FIT_POLL_INTERVAL.min(deadline.saturating_duration_since(Instant::now()));
+            tokio::select! {
+                _ = cancelled.changed() => break EarFitState::Cancelled,
+                _ = tokio::time::sleep(pause) => {}
+            }
+        };
+        Ok(EarFitProgress {
+            state,
+            result,
+            elapsed_ms: started.elapsed().as_millis() as u64,
+        })
+    }
+
+    /// Ends any [`Self::await_ear_fit`] in progress. No stop command is
+    /// known, so the buds finish the test on their own.
+    pub fn cancel_ear_fit(&self) {
+        self.inner
+            .ear_fit_cancel
+            .send_modify(|generation| *generation = generation.wrapping_add(1));
+    }
+
     pub async fn read_gestures(&self) -> Result<Vec<GestureSlot>, EarError> {
         let conn = self.inner.connection.lock().await;
         conn.transact(
@@ -531,7 +2113,41 @@ impl EarSessionHandle {
         .await
     }
 
-    pub async fn set_gesture(&self, slot: &GestureSlot) -> Result<(), EarError> {
+    pub async fn gesture_capabilities(&self) -> GestureCapabilities {
+        let base = self.model_base().await;
+        base.gesture_capabilities()
+            .unwrap_or_else(|| GestureCapabilities::unrestricted(base))
+    }
+
+    /// Writes one gesture slot after applying the session's
+    /// [`GestureConflictPolicy`]. Returns the conflicts found under `warn`;
+    /// under `reject` they fail the write with [`EarError::GestureConflict`].
+    pub async fn set_gesture(&self, slot: &GestureSlot) -> Result<Vec<GestureConflict>, EarError> {
+        let conflicts = match self.inner.gesture_conflicts {
+            GestureConflictPolicy::Off => Vec::new(),
+            policy => {
+                let conflicts = find_gesture_conflicts(slot, &self.read_gestures().await?);
+                if policy == GestureConflictPolicy::Reject && !conflicts.is_empty() {
+                    return Err(EarError::GestureConflict(conflicts));
+                }
+                for conflict in &conflicts {
+                    tracing::warn!("gesture write conflicts: {}", conflict);
+                }
+                conflicts
+            }
+        };
+        self.write_gesture(slot).await?;
+        Ok(conflicts)
+    }
+
+    /// Writes one gesture slot, rejecting combinations the model's
+    /// [`GestureCapabilities`] don't list. Imports and resets go through
+    /// here, since they pass through conflicting states on the way.
+    async fn write_gesture(&self, slot: &GestureSlot) -> Result<(), EarError> {
+        self.gesture_capabilities()
+            .await
+            .check(slot)
+            .map_err(EarError::Unsupported)?;
         let conn = self.inner.connection.lock().await;
         let payload = [
             0x01,
@@ -545,56 +2161,164 @@ impl EarSessionHandle {
         Ok(())
     }
 
+    pub async fn export_gestures(&self) -> Result<GestureBackup, EarError> {
+        let gestures = self.read_gestures().await?;
+        Ok(GestureBackup {
+            version: GESTURE_BACKUP_VERSION,
+            model_base: Some(self.model_base().await),
+            gestures: gestures.into_iter().map(GestureConfig::from).collect(),
+        })
+    }
+
+    /// Writes every slot of `backup` after checking it against the slots the
+    /// buds currently report. A failed write doesn't stop the remaining slots.
+    /// A backup taken from another model base is refused unless `force`.
+    pub async fn import_gestures(
+        &self,
+        backup: &GestureBackup,
+        force: bool,
+    ) -> Result<GestureImportReport, EarError> {
+        let current = self.read_gestures().await?;
+        let base = self.model_base().await;
+        validate_gesture_backup(backup, &current, base, force)?;
+        let mut results = Vec::with_capacity(backup.gestures.len());
+        for GestureConfig { slot, .. } in &backup.gestures {
+            let error = self
+                .write_gesture(slot)
+                .await
+                .err()
+                .map(|err| err.to_string());
+            results.push(GestureImportResult {
+                slot: slot.clone(),
+                ok: error.is_none(),
+                error,
+            });
+        }
+        let applied = results.iter().filter(|result| result.ok).count();
+        Ok(GestureImportReport {
+            applied,
+            failed: results.len() - applied,
+            results,
+        })
+    }
+
     pub async fn read_led_case_colors(&self) -> Result<LedColorSet, EarError> {
-        self.require_support("case led color", |base| base.supports_case_led())
+        self.require_support(CASE_LED_LABEL, |base| base.supports_case_led())
             .await?;
-        let conn = self.inner.connection.lock().await;
-        conn.transact(
-            command::REQUEST_LED_CASE_COLORS,
-            &[],
-            |packet| {
-                if packet.command == response::LED_CASE_COLORS {
-                    Some(parse_led_colors(&packet.payload))
-                } else {
-                    None
-                }
-            },
-            "case_led_colors",
-        )
-        .await
+        let colors = {
+            let conn = self.inner.connection.lock().await;
+            conn.transact(
+                command::REQUEST_LED_CASE_COLORS,
+                &[],
+                |packet| {
+                    if packet.command == response::LED_CASE_COLORS {
+                        Some(parse_led_colors(&packet.payload))
+                    } else {
+                        None
+                    }
+                },
+                "case_led_colors",
+            )
+            .await?
+        };
+        if !colors.pixels.is_empty() {
+            *self.inner.led_count.lock().await = Some(colors.pixels.len());
+        }
+        Ok(colors)
     }
 
-    pub async fn set_led_case_colors(&self, colors: &LedColorSet) -> Result<(), EarError> {
-        self.require_support("case led color", |base| base.supports_case_led())
+    /// Sets every case pixel at once and returns whether the buds
+    /// acknowledged it. The pixel count has to match what the case reports,
+    /// which is read once per session; in dry-run mode it can't be checked.
+    pub async fn set_led_case_colors(&self, colors: &LedColorSet) -> Result<bool, EarError> {
+        self.require_support(CASE_LED_LABEL, |base| base.supports_case_led())
             .await?;
-        let conn = self.inner.connection.lock().await;
-        let mut payload = Vec::with_capacity(1 + colors.pixels.len() * 4);
-        payload.push(colors.pixels.len() as u8);
-        for (index, LedColor(rgb)) in colors.pixels.iter().cloned().enumerate() {
-            payload.push((index + 1) as u8);
-            payload.extend_from_slice(&rgb);
+        let payload = encode_led_colors(colors)?;
+        let known = *self.inner.led_count.lock().await;
+        let expected = match known {
+            Some(count) => Some(count),
+            None => match self.read_led_case_colors().await {
+                Ok(current) => Some(current.pixels.len()),
+                Err(EarError::DryRun(_)) => None,
+                Err(err) => return Err(err),
+            },
+        };
+        if let Some(expected) = expected.filter(|&count| count != colors.pixels.len()) {
+            return Err(EarError::InvalidInput(format!(
+                "the case has {} LED pixels, got {}",
+                expected,
+                colors.pixels.len()
+            )));
         }
-        conn.send_command(command::CMD_SET_LED_CASE_COLORS, &payload)
+        let conn = self.inner.connection.lock().await;
+        let ack = conn
+            .send_command_confirmed(
+                command::CMD_SET_LED_CASE_COLORS,
+                &payload,
+                ack_command(command::CMD_SET_LED_CASE_COLORS),
+                DEFAULT_ACK_TIMEOUT,
+            )
             .await?;
-        Ok(())
+        if !ack.acknowledged {
+            tracing::warn!(
+                "case LED write (operation {}) was not acknowledged",
+                ack.operation_id
+            );
+        }
+        Ok(ack.acknowledged)
     }
 
+    /// Starts or stops the Find My Buds tone on one bud, or on both when
+    /// `side` is `None`. See [`ring_payloads`] for the per-model encoding.
     pub async fn ring_buds(&self, enable: bool, side: Option<EarSide>) -> Result<(), EarError> {
         let base = self.model_base().await;
+        let payloads = ring_payloads(base, enable, side)?;
         let conn = self.inner.connection.lock().await;
-        let payload = if base == ModelBase::B181 {
-            if enable { vec![0x01] } else { vec![0x00] }
-        } else {
-            let device = match side {
-                Some(EarSide::Left) => 0x02,
-                _ => 0x03,
-            };
-            vec![device, if enable { 0x01 } else { 0x00 }]
-        };
-        conn.send_command(command::CMD_RING, &payload).await?;
+        for payload in payloads {
+            conn.send_command(command::CMD_RING, &payload).await?;
+        }
         Ok(())
     }
 
+    /// Sends an arbitrary command and returns the operation id it was sent
+    /// with. When `expect_response` is set, also waits for the first packet
+    /// carrying that response id and returns it.
+    pub async fn send_raw(
+        &self,
+        command: u16,
+        payload: &[u8],
+        expect_response: Option<u16>,
+    ) -> Result<(u8, Option<EarPacket>), EarError> {
+        if payload.len() > MAX_PAYLOAD_LEN {
+            return Err(EarError::InvalidInput(format!(
+                "payload is {} bytes; the maximum is {}",
+                payload.len(),
+                MAX_PAYLOAD_LEN
+            )));
+        }
+        let conn = self.inner.connection.lock().await;
+        let Some(expected) = expect_response else {
+            let operation = conn.send_command(command, payload).await?;
+            return Ok((operation, None));
+        };
+        // Raw commands may not be idempotent, so they are never re-sent.
+        let (operation, packet) = conn
+            .transact_once(
+                command,
+                payload,
+                |packet| {
+                    if packet.command == expected {
+                        Some(packet.clone())
+                    } else {
+                        None
+                    }
+                },
+                "raw response",
+            )
+            .await?;
+        Ok((operation, Some(packet)))
+    }
+
     async fn model_base(&self) -> ModelBase {
         self.inner
             .model
@@ -618,7 +2342,25 @@ impl EarSessionHandle {
     }
 }
 
-fn parse_serial_number(payload: &[u8]) -> Option<String> {
+/// `Ok(None)` for a setting the model doesn't have.
+fn supported<T>(result: Result<T, EarError>) -> Result<Option<T>, EarError> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(EarError::Unsupported(_)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn session_label_suffix(label: Option<&str>) -> String {
+    label
+        .map(|label| format!(" ({})", label))
+        .unwrap_or_default()
+}
+
+/// The serial response starts with a 7-byte header followed by
+/// `part,field,value` text lines; field 4 is the serial number. `None` when
+/// the payload is short or has no non-empty serial line.
+pub(crate) fn parse_serial_number(payload: &[u8]) -> Option<String> {
     if payload.len() < 8 {
         return None;
     }
@@ -637,28 +2379,90 @@ fn parse_serial_number(payload: &[u8]) -> Option<String> {
     None
 }
 
-fn derive_sku_from_serial(serial: &str) -> Option<String> {
-    if serial == "12345678901234567" {
-        return Some("01".to_string());
+/// Full packets carry `[left worn, right worn, detection enabled]`; some models
+/// only send the detection toggle. A two-byte payload has the worn flags
+/// without the toggle, which the buds only report while detection is on.
+fn parse_in_ear_payload(payload: &[u8]) -> Option<InEarState> {
+    match payload {
+        [left, right, enabled, ..] => Some(InEarState {
+            detection_enabled: *enabled == 1,
+            left_in_ear: Some(*left == 1),
+            right_in_ear: Some(*right == 1),
+        }),
+        [left, right] => Some(InEarState {
+            detection_enabled: true,
+            left_in_ear: Some(*left == 1),
+            right_in_ear: Some(*right == 1),
+        }),
+        [enabled] => Some(InEarState {
+            detection_enabled: *enabled == 1,
+            left_in_ear: None,
+            right_in_ear: None,
+        }),
+        _ => None,
     }
-    if serial.len() < 6 {
-        return None;
+}
+
+fn eq_from_packet(packet: &EarPacket) -> Option<EqMode> {
+    if packet.command == response::EQ_PRIMARY {
+        packet
+            .payload
+            .first()
+            .map(|&mode| EqMode::new(mode, EqSource::Eq))
+    } else {
+        None
+    }
+}
+
+fn listening_mode_from_packet(packet: &EarPacket) -> Option<ListeningMode> {
+    if packet.command == response::EQ_LISTENING_MODE {
+        packet.payload.first().map(|&mode| ListeningMode { mode })
+    } else {
+        None
     }
-    let head = &serial[..2];
-    if head == "MA" {
-        let year = &serial[6..8];
-        if year == "22" || year == "23" {
-            return Some("14".to_string());
-        } else if year == "24" {
-            return Some("11200005".to_string());
+}
+
+/// Payload is `[_, mode, strength, ..]`; the strength byte is only meaningful
+/// in adaptive mode.
+fn parse_anc_payload(payload: &[u8]) -> Option<AncStatus> {
+    let level = AncLevel::from_device(*payload.get(1)?)?;
+    let adaptive_strength = match level {
+        AncLevel::NoiseCancellationAdaptive => payload.get(2).copied(),
+        _ => None,
+    };
+    Some(AncStatus {
+        level,
+        adaptive_strength,
+    })
+}
+
+fn merge_battery_packet(
+    status: &mut BatteryStatus,
+    packet: &EarPacket,
+    with_case: bool,
+) -> Option<bool> {
+    match packet.command {
+        response::BATTERY_PRIMARY | response::BATTERY_SECONDARY => {
+            status.merge(parse_battery_payload(&packet.payload));
+            Some(status.is_complete(with_case))
         }
-    } else if head == "SH" || head == "13" {
-        return serial.get(4..6).map(|value| value.to_string());
+        _ => None,
     }
-    None
 }
 
-fn parse_battery_payload(payload: &[u8]) -> BatteryStatus {
+/// Payload is `[count, (device id, level)*count]`. Level bit 7 is the
+/// charging flag. A part missing from the payload (e.g. a bud that isn't
+/// connected while the other is worn) stays disconnected, which is distinct
+/// from a 0% level; so does an entry above 100%.
+///
+/// On the case entry (id 0x04) the charging bit means the case itself is on
+/// a charger. No bit says the case is charging the buds; that is derived
+/// from the buds' own charging bits, see [`BatteryStatus::update_case_flags`].
+///
+/// A count larger than the entries present keeps the complete entries.
+/// Bytes after the entries are ignored: some firmware sends one, possibly the
+/// case lid, but without a capture to confirm it nothing is read from it.
+pub(crate) fn parse_battery_payload(payload: &[u8]) -> BatteryStatus {
     let mut status = BatteryStatus::empty();
     if payload.is_empty() {
         return status;
@@ -673,20 +2477,81 @@ fn parse_battery_payload(payload: &[u8]) -> BatteryStatus {
         let level_byte = payload[idx + 1];
         let level = level_byte & 0x7F;
         let charging = (level_byte & 0x80) == 0x80;
-        let reading = BatteryReading::Level {
-            percent: level,
-            charging,
-        };
-        match device_id {
-            0x02 => status.left = reading,
-            0x03 => status.right = reading,
-            0x04 => status.case = reading,
-            _ => {}
+        let reading = if level > BATTERY_MAX_PERCENT {
+            tracing::debug!(
+                "ignoring battery level {} for device id 0x{:02x}",
+                level,
+                device_id
+            );
+            BatteryReading::Disconnected
+        } else {
+            BatteryReading::Level {
+                percent: level,
+                charging,
+            }
+        };
+        match EarSide::from_device_id(device_id) {
+            Some(EarSide::Left) => status.left = reading,
+            Some(EarSide::Right) => status.right = reading,
+            Some(EarSide::Case) => status.case = reading,
+            None => tracing::debug!("ignoring battery entry for device id 0x{:02x}", device_id),
         }
     }
+    status.update_case_flags();
     status
 }
 
+fn parse_firmware_payload(payload: &[u8]) -> FirmwareInfo {
+    let raw = String::from_utf8_lossy(payload)
+        .trim_matches(|c: char| c.is_whitespace() || c.is_control())
+        .to_string();
+    let components: Vec<&str> = raw
+        .split(|c: char| c == ',' || c == ';' || c.is_whitespace() || c.is_control())
+        .filter(|part| !part.is_empty())
+        .collect();
+
+    let (left, right, case) = match components.as_slice() {
+        [left, right] => (Some(left), Some(right), None),
+        [left, right, case] => (Some(left), Some(right), Some(case)),
+        _ => (None, None, None),
+    };
+    // The buds normally share a version; the case firmware is versioned separately.
+    let version = match (components.as_slice(), left, right) {
+        ([single], _, _) => single.to_string(),
+        (_, Some(left), Some(right)) if left == right => left.to_string(),
+        _ => raw.clone(),
+    };
+    let (major, minor) = if version == raw && components.len() != 1 {
+        (None, None)
+    } else {
+        let mut numbers = version.split('.').map(|part| part.parse::<u32>().ok());
+        (numbers.next().flatten(), numbers.next().flatten())
+    };
+
+    FirmwareInfo {
+        version,
+        major,
+        minor,
+        left: left.map(|value| value.to_string()),
+        right: right.map(|value| value.to_string()),
+        case: case.map(|value| value.to_string()),
+        raw,
+    }
+}
+
+/// Custom EQ payload, shared by `CUSTOM_EQ` replies and `CMD_SET_CUSTOM_EQ`:
+///
+/// | offset | len | field                                           |
+/// |--------|-----|-------------------------------------------------|
+/// | 0      | 1   | band count (3)                                  |
+/// | 1      | 4   | total gain: minus the highest band, as a float  |
+/// | 5 + 13n| 1   | band n filter type                              |
+/// | 6 + 13n| 4   | band n gain in dB                               |
+/// | 10+13n | 4   | band n center frequency in Hz                   |
+/// | 14+13n | 4   | band n Q                                        |
+///
+/// Bands are mid (980 Hz), treble (3.5 kHz) and bass (140 Hz). There is no
+/// slot field: the payload always describes the active custom preset.
 fn decode_custom_eq(payload: &[u8]) -> Option<CustomEq> {
     if payload.len() < 45 {
         return None;
@@ -711,6 +2576,24 @@ fn decode_custom_eq(payload: &[u8]) -> Option<CustomEq> {
     }
 }
 
+fn clamp_custom_eq(eq: CustomEq) -> Result<CustomEq, EarError> {
+    let clamp = |name: &str, value: f32| {
+        if value.is_finite() {
+            Ok(value.clamp(CUSTOM_EQ_MIN_DB, CUSTOM_EQ_MAX_DB))
+        } else {
+            Err(EarError::InvalidInput(format!(
+                "custom EQ {} must be a finite number of dB, got {}",
+                name, value
+            )))
+        }
+    };
+    Ok(CustomEq {
+        bass: clamp("bass", eq.bass)?,
+        mid: clamp("mid", eq.mid)?,
+        treble: clamp("treble", eq.treble)?,
+    })
+}
+
 fn encode_custom_eq(eq: CustomEq) -> Vec<u8> {
     let mut payload = vec![
         0x03, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x75, 0x44, 0xc3,
@@ -719,44 +2602,159 @@ fn encode_custom_eq(eq: CustomEq) -> Vec<u8> {
         0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
     ];
     let values = [eq.mid, eq.treble, eq.bass];
-    let highest = values.iter().fold(0.0_f32, |acc, &v| acc.max(v)).abs();
-    let total_bytes = encode_eq_float(-highest, true);
-    payload[1..5].copy_from_slice(&total_bytes);
+    // Headroom for the loudest boosted band. With no boost this is -0.0,
+    // which is also what the Nothing X app sends (`00 00 00 80`).
+    let highest = values.iter().fold(0.0_f32, |acc, &v| acc.max(v));
+    payload[1..5].copy_from_slice(&encode_eq_float(-highest));
     for (index, value) in values.iter().enumerate() {
-        let bytes = encode_eq_float(*value, false);
         let offset = 6 + index * 13;
-        payload[offset..offset + 4].copy_from_slice(&bytes);
+        payload[offset..offset + 4].copy_from_slice(&encode_eq_float(*value));
     }
     payload
 }
 
-fn encode_eq_float(value: f32, total: bool) -> [u8; 4] {
-    if total && value >= 0.0 {
-        return [0x00, 0x00, 0x00, 0x80];
+/// EQ gains, frequencies and Q values are IEEE 754 binary32, little-endian.
+/// Subnormals and signed zeros need no special casing.
+fn encode_eq_float(value: f32) -> [u8; 4] {
+    value.to_le_bytes()
+}
+
+/// Inverse of [`encode_eq_float`]; short input decodes as 0 dB.
+fn decode_eq_float(bytes: &[u8]) -> f32 {
+    match bytes.get(..4) {
+        Some(&[b0, b1, b2, b3]) => f32::from_le_bytes([b0, b1, b2, b3]),
+        _ => 0.0,
+    }
+}
+
+/// `CMD_RING` payloads for one ring request. The Ear (1) (B181) has a single
+/// tone for both buds and takes just the on/off byte, so `side` is ignored
+/// there. Other models ring one bud per command, addressed as `0x02` (left)
+/// or `0x03` (right); ringing both sends one command per bud.
+fn ring_payloads(
+    base: ModelBase,
+    enable: bool,
+    side: Option<EarSide>,
+) -> Result<Vec<Vec<u8>>, EarError> {
+    let state = u8::from(enable);
+    if side == Some(EarSide::Case) {
+        return Err(EarError::InvalidInput(
+            "the case has no speaker; ring `left`, `right` or omit the side for both".into(),
+        ));
     }
-    let mut bytes = value.to_bits().to_be_bytes();
-    if value != 0.0 && bytes[0] == 0 && bytes[1] == 0 && bytes[2] == 0 {
-        bytes[3] |= 0x80;
+    if base == ModelBase::B181 {
+        if side.is_some() {
+            tracing::debug!("Ear (1) rings both buds together; ignoring the side");
+        }
+        return Ok(vec![vec![state]]);
     }
-    bytes.swap(0, 3);
-    bytes.swap(1, 2);
-    bytes
+    let payloads = match side {
+        Some(EarSide::Left) => vec![vec![0x02, state]],
+        Some(EarSide::Right) => vec![vec![0x03, state]],
+        _ => vec![vec![0x02, state], vec![0x03, state]],
+    };
+    Ok(payloads)
 }
 
-fn decode_eq_float(bytes: &[u8]) -> f32 {
-    if bytes.len() < 4 {
-        return 0.0;
+/// Slots on another bud that already map `slot`'s gesture to its action.
+/// Leaving gestures unassigned never conflicts.
+fn find_gesture_conflicts(slot: &GestureSlot, current: &[GestureSlot]) -> Vec<GestureConflict> {
+    if slot.action == GestureAction::NoAction.to_device() {
+        return Vec::new();
     }
-    let mut slice = [bytes[3], bytes[2], bytes[1], bytes[0]];
-    if slice[0] == 0 && slice[1] == 0 && slice[2] == 0 && (slice[3] & 0x80) == 0x80 {
-        slice[3] &= 0x7F;
-        -f32::from_bits(u32::from_be_bytes(slice))
-    } else {
-        f32::from_bits(u32::from_be_bytes(slice))
+    current
+        .iter()
+        .filter(|other| {
+            other.device != slot.device
+                && other.gesture_type == slot.gesture_type
+                && other.action == slot.action
+        })
+        .map(|other| GestureConflict {
+            requested: GestureConfig::from(slot.clone()),
+            existing: GestureConfig::from(other.clone()),
+        })
+        .collect()
+}
+
+fn validate_gesture_backup(
+    backup: &GestureBackup,
+    current: &[GestureSlot],
+    base: ModelBase,
+    force: bool,
+) -> Result<(), EarError> {
+    if !(1..=GESTURE_BACKUP_VERSION).contains(&backup.version) {
+        return Err(EarError::InvalidInput(format!(
+            "unsupported gesture backup version {}; expected 1 to {}",
+            backup.version, GESTURE_BACKUP_VERSION
+        )));
+    }
+    match backup.model_base {
+        Some(from) if from != base && from != ModelBase::Unknown && !force => {
+            return Err(EarError::InvalidInput(format!(
+                "gesture backup was taken from {:?} but the buds are {:?}; use force to import anyway",
+                from, base
+            )));
+        }
+        _ => {}
     }
+    for config in &backup.gestures {
+        validate_gesture_names(config)?;
+    }
+    if backup.gestures.len() != current.len() {
+        return Err(EarError::InvalidInput(format!(
+            "gesture backup has {} slots but the buds report {}",
+            backup.gestures.len(),
+            current.len()
+        )));
+    }
+    for GestureConfig { slot, .. } in &backup.gestures {
+        let known = current.iter().any(|existing| {
+            existing.device == slot.device && existing.gesture_type == slot.gesture_type
+        });
+        if !known {
+            return Err(EarError::InvalidInput(format!(
+                "gesture slot for device {:#04x}, gesture {:#04x} does not exist on the buds",
+                slot.device, slot.gesture_type
+            )));
+        }
+    }
+    Ok(())
 }
 
-fn parse_gestures(payload: &[u8]) -> Vec<GestureSlot> {
+/// Names in a backup are informational, but when present they must be what
+/// the raw bytes decode to. A mismatch means the file was edited by hand or
+/// came from a model that numbers its gestures differently.
+fn validate_gesture_names(config: &GestureConfig) -> Result<(), EarError> {
+    let decoded = GestureConfig::from(config.slot.clone());
+    let mismatch = |field: &str, named: &dyn std::fmt::Debug, raw: &dyn std::fmt::Debug| {
+        EarError::InvalidInput(format!(
+            "gesture slot for device {:#04x}, gesture {:#04x} names {} {:?} but its bytes decode to {:?}",
+            config.slot.device, config.slot.gesture_type, field, named, raw
+        ))
+    };
+    if config.side.is_some() && config.side != decoded.side {
+        return Err(mismatch("side", &config.side, &decoded.side));
+    }
+    if config.gesture_name.is_some() && config.gesture_name != decoded.gesture_name {
+        return Err(mismatch(
+            "gesture",
+            &config.gesture_name,
+            &decoded.gesture_name,
+        ));
+    }
+    if config.action_name.is_some() && config.action_name != decoded.action_name {
+        return Err(mismatch(
+            "action",
+            &config.action_name,
+            &decoded.action_name,
+        ));
+    }
+    Ok(())
+}
+
+/// Payload is `[count, (device, common, gesture type, action)*count]`.
+/// An incomplete trailing slot is dropped.
+pub(crate) fn parse_gestures(payload: &[u8]) -> Vec<GestureSlot> {
     if payload.is_empty() {
         return Vec::new();
     }
@@ -777,9 +2775,39 @@ fn parse_gestures(payload: &[u8]) -> Vec<GestureSlot> {
     gestures
 }
 
-fn parse_led_colors(payload: &[u8]) -> LedColorSet {
+/// `[count, (index, r, g, b)*count]`, with pixel indices starting at 1.
+/// Brightness and pattern have no known bytes, so setting either is refused
+/// rather than dropped.
+fn encode_led_colors(colors: &LedColorSet) -> Result<Vec<u8>, EarError> {
+    if colors.brightness.is_some() || colors.pattern.is_some() {
+        return Err(EarError::Unsupported(CASE_LED_STYLE_LABEL));
+    }
+    if colors.pixels.is_empty() || colors.pixels.len() > LED_MAX_PIXELS {
+        return Err(EarError::InvalidInput(format!(
+            "expected 1-{} LED pixels, got {}",
+            LED_MAX_PIXELS,
+            colors.pixels.len()
+        )));
+    }
+    let mut payload = Vec::with_capacity(1 + colors.pixels.len() * 4);
+    payload.push(colors.pixels.len() as u8);
+    for (index, LedColor(rgb)) in colors.pixels.iter().cloned().enumerate() {
+        payload.push((index + 1) as u8);
+        payload.extend_from_slice(&rgb);
+    }
+    Ok(payload)
+}
+
+/// The reverse of `encode_led_colors`: `[count, (index, r, g, b)*count]`,
+/// so the RGB of pixel `n` starts at `2 + 4n`. A truncated pixel list keeps
+/// the complete pixels.
+pub(crate) fn parse_led_colors(payload: &[u8]) -> LedColorSet {
     if payload.is_empty() {
-        return LedColorSet { pixels: Vec::new() };
+        return LedColorSet {
+            pixels: Vec::new(),
+            brightness: None,
+            pattern: None,
+        };
     }
     let count = payload[0] as usize;
     let mut colors = Vec::with_capacity(count);
@@ -794,5 +2822,1465 @@ fn parse_led_colors(payload: &[u8]) -> LedColorSet {
             payload[base + 2],
         ]));
     }
-    LedColorSet { pixels: colors }
+    LedColorSet {
+        pixels: colors,
+        brightness: None,
+        pattern: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, time::Duration};
+
+    use proptest::prelude::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    use super::{
+        CUSTOM_EQ_MAX_DB, CUSTOM_EQ_MIN_DB, CommandQueue, EarManager, EarSessionHandle,
+        ModelDescriptor, clamp_custom_eq, decode_custom_eq, decode_eq_float, encode_custom_eq,
+        encode_eq_float, encode_led_colors, eq_from_packet, find_gesture_conflicts,
+        listening_mode_from_packet, merge_battery_packet, parse_anc_payload, parse_battery_payload,
+        parse_firmware_payload, parse_gestures, parse_in_ear_payload, parse_led_colors,
+        parse_serial_number, ring_payloads, validate_gesture_backup,
+    };
+    use crate::{
+        connection::EarConnection,
+        error::EarError,
+        events::EarEvent,
+        models::ModelBase,
+        protocol::{EarPacket, command, response},
+        types::{
+            AncLevel, AncStatus, AutoPauseState, BatteryReading, BatteryStatus,
+            ConversationModeState, CustomEq, DetectionSource, DisconnectReason, EarFitState,
+            EarSide, EqMode, EqSource, FitQuality, GESTURE_BACKUP_VERSION, GestureAction,
+            GestureBackup, GestureConfig, GestureConflictPolicy, GestureSlot, GestureType,
+            LatencyMode, LedColor, LedColorSet, ListeningMode, SerialIdentity, SpatialAudioState,
+        },
+    };
+
+    /// Answers every ear-fit result read with the raw right-bud byte that
+    /// `right` returns for that read; the left bud is always done.
+    async fn answer_ear_fit(device: DuplexStream, right: impl Fn(usize) -> u8) {
+        let (mut rx, mut tx) = tokio::io::split(device);
+        let mut buffer = Vec::new();
+        let mut reads = 0;
+        loop {
+            let mut chunk = [0u8; 64];
+            let Ok(n @ 1..) = rx.read(&mut chunk).await else {
+                return;
+            };
+            buffer.extend_from_slice(&chunk[..n]);
+            while let Some(request) = EarPacket::try_parse(&mut buffer).unwrap() {
+                if request.command == command::CMD_START_EAR_FIT_TEST && request.payload == [0x00] {
+                    reads += 1;
+                    let reply = EarPacket::encode(
+                        response::EAR_FIT_RESULT,
+                        request.operation_id,
+                        &[0x00, right(reads)],
+                    );
+                    tx.write_all(&reply).await.unwrap();
+                }
+            }
+        }
+    }
+
+    /// Answers each request in `replies` with its response command and
+    /// payload; other requests go unanswered.
+    async fn answer_requests(device: DuplexStream, replies: Vec<(u16, u16, Vec<u8>)>) {
+        let (mut rx, mut tx) = tokio::io::split(device);
+        let mut buffer = Vec::new();
+        loop {
+            let mut chunk = [0u8; 64];
+            let Ok(n @ 1..) = rx.read(&mut chunk).await else {
+                return;
+            };
+            buffer.extend_from_slice(&chunk[..n]);
+            while let Some(request) = EarPacket::try_parse(&mut buffer).unwrap() {
+                let reply = replies
+                    .iter()
+                    .find(|(command, ..)| *command == request.command);
+                if let Some((_, response, payload)) = reply {
+                    let reply = EarPacket::encode(*response, request.operation_id, payload);
+                    tx.write_all(&reply).await.unwrap();
+                }
+            }
+        }
+    }
+
+    fn packet(command: u16, payload: &[u8]) -> EarPacket {
+        EarPacket {
+            command,
+            operation_id: 1,
+            payload: payload.to_vec(),
+        }
+    }
+
+    #[test]
+    fn battery_single_bud_reports_other_as_disconnected() {
+        let status = parse_battery_payload(&[0x01, 0x02, 0x00]);
+        assert_eq!(
+            status.left,
+            BatteryReading::Level {
+                percent: 0,
+                charging: false
+            }
+        );
+        assert_eq!(status.right, BatteryReading::Disconnected);
+        assert_eq!(status.case, BatteryReading::Disconnected);
+    }
+
+    #[test]
+    fn battery_payload_from_a_full_report() {
+        // Synthetic, not a capture: left 100%, right 98% charging, case 55%.
+        let status = parse_battery_payload(&[0x03, 0x02, 0x64, 0x03, 0xE2, 0x04, 0x37]);
+        assert_eq!(
+            status.left,
+            BatteryReading::Level {
+                percent: 100,
+                charging: false
+            }
+        );
+        assert_eq!(
+            status.right,
+            BatteryReading::Level {
+                percent: 98,
+                charging: true
+            }
+        );
+        assert_eq!(
+            status.case,
+            BatteryReading::Level {
+                percent: 55,
+                charging: false
+            }
+        );
+    }
+
+    #[test]
+    fn battery_payload_bounds() {
+        // Hand-built payloads throughout, cut at each field boundary.
+        for payload in [&[][..], &[0x03], &[0x03, 0x02], &[0xFF, 0x02, 0x50, 0x03]] {
+            let status = parse_battery_payload(payload);
+            assert_eq!(
+                status.right,
+                BatteryReading::Disconnected,
+                "{:02x?}",
+                payload
+            );
+        }
+        let partial = parse_battery_payload(&[0x02, 0x02, 0x50, 0x03]);
+        assert!(matches!(
+            partial.left,
+            BatteryReading::Level { percent: 80, .. }
+        ));
+        // Unknown device ids are skipped, not mapped to a side.
+        let unknown = parse_battery_payload(&[0x01, 0x09, 0x50]);
+        assert_eq!(unknown.left, BatteryReading::Disconnected);
+    }
+
+    #[test]
+    fn serial_number_is_read_from_field_4() {
+        // Synthetic: a zeroed header and made-up serials in the captured layout.
+        let mut payload = vec![0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        payload.extend_from_slice(b"1,1,B155\n1,4,SH10241200017\n2,4,SH10241200018\n");
+        assert_eq!(
+            parse_serial_number(&payload).as_deref(),
+            Some("SH10241200017")
+        );
+
+        assert_eq!(parse_serial_number(&payload[..7]), None);
+        assert_eq!(parse_serial_number(&[]), None);
+        let mut empty_value = payload[..7].to_vec();
+        empty_value.extend_from_slice(b"1,4, \n1,2,x");
+        assert_eq!(parse_serial_number(&empty_value), None);
+    }
+
+    #[test]
+    fn gesture_payload_bounds() {
+        // Synthetic: three slots announced, the last one cut short.
+        let payload = [
+            0x03, 0x02, 0x01, 0x02, 0x0B, 0x03, 0x01, 0x02, 0x0B, 0x02, 0x01, 0x03,
+        ];
+        let slots = parse_gestures(&payload);
+        assert_eq!(slots.len(), 2, "the incomplete third slot is dropped");
+        assert_eq!(
+            slots[1],
+            GestureSlot {
+                device: 0x03,
+                common: 0x01,
+                gesture_type: 0x02,
+                action: 0x0B,
+            }
+        );
+        assert!(parse_gestures(&[]).is_empty());
+        assert!(parse_gestures(&[0x01, 0x02, 0x01, 0x02]).is_empty());
+    }
+
+    #[test]
+    fn led_payload_offsets_and_bounds() {
+        // Synthetic payloads. The pixel index byte precedes each color and
+        // isn't part of it.
+        let set = parse_led_colors(&[0x01, 0x07, 0x10, 0x20, 0x30]);
+        assert_eq!(set.pixels, vec![LedColor([0x10, 0x20, 0x30])]);
+
+        let truncated = parse_led_colors(&[0x02, 0x01, 0xFF, 0x00, 0x00, 0x02, 0x00, 0xFF]);
+        assert_eq!(truncated.pixels, vec![LedColor([0xFF, 0x00, 0x00])]);
+        assert!(parse_led_colors(&[]).pixels.is_empty());
+        assert!(parse_led_colors(&[0x00]).pixels.is_empty());
+    }
+
+    #[test]
+    fn battery_trailing_byte_is_ignored() {
+        let entries = [0x01, 0x04, 0x50];
+        let trailing = [0x01, 0x04, 0x50, 0x01];
+        assert_eq!(
+            parse_battery_payload(&trailing),
+            parse_battery_payload(&entries)
+        );
+    }
+
+    #[test]
+    fn battery_levels_above_100_are_ignored() {
+        let status = parse_battery_payload(&[0x02, 0x02, 0x50, 0x03, 0x7F]);
+        assert!(matches!(
+            status.left,
+            BatteryReading::Level { percent: 80, .. }
+        ));
+        assert_eq!(status.right, BatteryReading::Disconnected);
+    }
+
+    #[test]
+    fn case_charging_flags_cover_each_combination() {
+        // (left, right, case) level bytes -> (case on charger, charging buds)
+        let cases = [
+            ([0x50, 0x50, 0x3C], Some(false), Some(false)),
+            ([0xD0, 0x50, 0x3C], Some(false), Some(true)),
+            ([0x50, 0x50, 0xBC], Some(true), Some(false)),
+            ([0xD0, 0xD0, 0xBC], Some(true), Some(true)),
+        ];
+        for ([left, right, case], on_charger, charging_buds) in cases {
+            let status = parse_battery_payload(&[0x03, 0x02, left, 0x03, right, 0x04, case]);
+            assert_eq!(status.case_on_charger, on_charger);
+            assert_eq!(status.case_charging_buds, charging_buds);
+        }
+
+        let buds_only = parse_battery_payload(&[0x01, 0x02, 0xD0]);
+        assert_eq!(buds_only.case_on_charger, None);
+        assert_eq!(buds_only.case_charging_buds, Some(true));
+        let case_only = parse_battery_payload(&[0x01, 0x04, 0xBC]);
+        assert_eq!(case_only.case_on_charger, Some(true));
+        assert_eq!(case_only.case_charging_buds, None);
+    }
+
+    #[test]
+    fn battery_merges_buds_and_case_from_split_packets() {
+        let mut status = BatteryStatus::empty();
+        let buds = packet(response::BATTERY_PRIMARY, &[0x02, 0x02, 0x50, 0x03, 0xC8]);
+        assert_eq!(merge_battery_packet(&mut status, &buds, true), Some(false));
+        let case = packet(response::BATTERY_SECONDARY, &[0x01, 0x04, 0x3C]);
+        assert_eq!(merge_battery_packet(&mut status, &case, true), Some(true));
+
+        assert_eq!(
+            status.left,
+            BatteryReading::Level {
+                percent: 80,
+                charging: false
+            }
+        );
+        assert_eq!(
+            status.right,
+            BatteryReading::Level {
+                percent: 72,
+                charging: true
+            }
+        );
+        assert_eq!(
+            status.case,
+            BatteryReading::Level {
+                percent: 60,
+                charging: false
+            }
+        );
+    }
+
+    #[test]
+    fn battery_merge_keeps_earlier_levels_and_ignores_other_packets() {
+        let mut status = BatteryStatus::empty();
+        let case = packet(response::BATTERY_SECONDARY, &[0x01, 0x04, 0x3C]);
+        assert_eq!(merge_battery_packet(&mut status, &case, false), Some(false));
+        let left_only = packet(response::BATTERY_PRIMARY, &[0x01, 0x02, 0x50]);
+        assert_eq!(
+            merge_battery_packet(&mut status, &left_only, false),
+            Some(false)
+        );
+        let unrelated = packet(response::EQ_PRIMARY, &[0x03]);
+        assert_eq!(merge_battery_packet(&mut status, &unrelated, false), None);
+        assert!(matches!(
+            status.case,
+            BatteryReading::Level { percent: 60, .. }
+        ));
+        assert_eq!(status.right, BatteryReading::Disconnected);
+    }
+
+    #[test]
+    fn anc_payload_in_adaptive_mode_reports_strength() {
+        let status = parse_anc_payload(&[0x01, 0x04, 0x03]).unwrap();
+        assert!(matches!(status.level, AncLevel::NoiseCancellationAdaptive));
+        assert_eq!(status.adaptive_strength, Some(3));
+    }
+
+    #[test]
+    fn anc_payload_ignores_strength_outside_adaptive_mode() {
+        let status = parse_anc_payload(&[0x01, 0x01, 0x03]).unwrap();
+        assert!(matches!(status.level, AncLevel::NoiseCancellationHigh));
+        assert_eq!(status.adaptive_strength, None);
+        assert!(parse_anc_payload(&[0x01]).is_none());
+    }
+
+    #[test]
+    fn in_ear_payload_with_worn_flags() {
+        let state = parse_in_ear_payload(&[0x01, 0x00, 0x01]).unwrap();
+        assert!(state.detection_enabled);
+        assert_eq!(state.left_in_ear, Some(true));
+        assert_eq!(state.right_in_ear, Some(false));
+    }
+
+    #[test]
+    fn in_ear_payload_with_only_worn_flags() {
+        let state = parse_in_ear_payload(&[0x00, 0x01]).unwrap();
+        assert!(state.detection_enabled);
+        assert_eq!(state.left_in_ear, Some(false));
+        assert_eq!(state.right_in_ear, Some(true));
+    }
+
+    #[test]
+    fn in_ear_payload_with_only_toggle() {
+        let state = parse_in_ear_payload(&[0x00]).unwrap();
+        assert!(!state.detection_enabled);
+        assert_eq!(state.left_in_ear, None);
+        assert_eq!(state.right_in_ear, None);
+        assert!(parse_in_ear_payload(&[]).is_none());
+    }
+
+    #[test]
+    fn firmware_single_version_string() {
+        let info = parse_firmware_payload(b"1.0.1.50\0");
+        assert_eq!(info.version, "1.0.1.50");
+        assert_eq!(info.major, Some(1));
+        assert_eq!(info.minor, Some(0));
+        assert!(info.left.is_none() && info.right.is_none() && info.case.is_none());
+        assert_eq!(info.raw, "1.0.1.50");
+    }
+
+    #[test]
+    fn firmware_multi_component_payload() {
+        let info = parse_firmware_payload(b"2.1.0.11,2.1.0.11,1.4.0.2");
+        assert_eq!(info.left.as_deref(), Some("2.1.0.11"));
+        assert_eq!(info.right.as_deref(), Some("2.1.0.11"));
+        assert_eq!(info.case.as_deref(), Some("1.4.0.2"));
+        assert_eq!(info.version, "2.1.0.11");
+        assert_eq!(info.major, Some(2));
+        assert_eq!(info.minor, Some(1));
+
+        let mismatched = parse_firmware_payload(b"3.2.0 3.1.9");
+        assert_eq!(mismatched.version, "3.2.0 3.1.9");
+        assert_eq!(mismatched.major, None);
+        assert!(mismatched.case.is_none());
+    }
+
+    #[test]
+    fn firmware_unknown_format_keeps_raw_string() {
+        let info = parse_firmware_payload(b"v-beta");
+        assert_eq!(info.version, "v-beta");
+        assert_eq!(info.major, None);
+        assert_eq!(info.raw, "v-beta");
+    }
+
+    #[tokio::test]
+    async fn eq_and_listening_mode_responses_are_kept_apart() {
+        let mut queued = EarPacket::encode(response::EQ_LISTENING_MODE, 1, &[0x02]);
+        queued.extend(EarPacket::encode(response::EQ_PRIMARY, 1, &[0x03]));
+
+        let conn = EarConnection::from_io("test", Cursor::new(queued.clone()), tokio::io::sink());
+        let eq = conn
+            .transact(command::REQUEST_EQ, &[], eq_from_packet, "eq")
+            .await
+            .unwrap();
+        assert_eq!(eq, EqMode::new(3, EqSource::Eq));
+
+        let conn = EarConnection::from_io("test", Cursor::new(queued), tokio::io::sink());
+        let listening = conn
+            .transact(
+                command::REQUEST_LISTENING_MODE,
+                &[],
+                listening_mode_from_packet,
+                "listening_mode",
+            )
+            .await
+            .unwrap();
+        assert_eq!(listening, ListeningMode { mode: 2 });
+    }
+
+    #[test]
+    fn gesture_backup_must_match_reported_slots() {
+        let slot = |device, gesture_type| GestureSlot {
+            device,
+            common: 0x01,
+            gesture_type,
+            action: 0x02,
+        };
+        let current = vec![slot(0x02, 0x02), slot(0x03, 0x02)];
+        let backup = |version, slots: Vec<GestureSlot>| GestureBackup {
+            version,
+            model_base: None,
+            gestures: slots.into_iter().map(GestureConfig::from).collect(),
+        };
+        let validate = |backup: &GestureBackup| {
+            validate_gesture_backup(backup, &current, ModelBase::B172, false)
+        };
+
+        assert!(validate(&backup(GESTURE_BACKUP_VERSION, current.clone())).is_ok());
+        assert!(validate(&backup(1, current.clone())).is_ok());
+        let v1: GestureBackup = serde_json::from_str(
+            r#"{"version":1,"model":"B172","gestures":[{"device":2,"common":1,"gesture_type":2,"action":2}]}"#,
+        )
+        .unwrap();
+        assert_eq!(v1.model_base, Some(ModelBase::B172));
+        assert!(v1.gestures[0].side.is_none());
+        assert!(validate(&backup(99, current.clone())).is_err());
+        assert!(validate(&backup(GESTURE_BACKUP_VERSION, vec![slot(0x02, 0x02)])).is_err());
+        assert!(
+            validate(&backup(
+                GESTURE_BACKUP_VERSION,
+                vec![slot(0x02, 0x02), slot(0x03, 0x07)]
+            ))
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn gesture_backup_from_another_model_needs_force() {
+        let current = vec![GestureSlot {
+            device: 0x02,
+            common: 0x01,
+            gesture_type: 0x02,
+            action: 0x02,
+        }];
+        let mut backup = GestureBackup {
+            version: GESTURE_BACKUP_VERSION,
+            model_base: Some(ModelBase::B162),
+            gestures: current.iter().cloned().map(GestureConfig::from).collect(),
+        };
+
+        let err = validate_gesture_backup(&backup, &current, ModelBase::B172, false).unwrap_err();
+        assert!(err.to_string().contains("B162"), "{err}");
+        assert!(validate_gesture_backup(&backup, &current, ModelBase::B172, true).is_ok());
+        assert!(validate_gesture_backup(&backup, &current, ModelBase::B162, false).is_ok());
+
+        // Names that disagree with the bytes are refused even with force.
+        backup.gestures[0].side = Some(EarSide::Right);
+        assert!(validate_gesture_backup(&backup, &current, ModelBase::B162, true).is_err());
+        backup.gestures[0].side = None;
+        backup.gestures[0].gesture_name = GestureType::from_device(0x07);
+        assert!(validate_gesture_backup(&backup, &current, ModelBase::B162, true).is_err());
+    }
+
+    #[test]
+    fn led_colors_round_trip() {
+        let plain = LedColorSet {
+            pixels: vec![LedColor([0xFF, 0x00, 0x00]), LedColor([0x00, 0x00, 0xFF])],
+            brightness: None,
+            pattern: None,
+        };
+        let payload = encode_led_colors(&plain).unwrap();
+        assert_eq!(payload.len(), 1 + 2 * 4);
+        assert_eq!(parse_led_colors(&payload), plain);
+
+        for (brightness, pattern) in [(Some(60), None), (None, Some(1))] {
+            let styled = LedColorSet {
+                brightness,
+                pattern,
+                ..plain.clone()
+            };
+            assert!(matches!(
+                encode_led_colors(&styled),
+                Err(EarError::Unsupported(_))
+            ));
+        }
+
+        // The count is one byte, so 255 pixels is the most that fits.
+        for count in [0, 256] {
+            let pixels = LedColorSet {
+                pixels: vec![LedColor([0, 0, 0]); count],
+                brightness: None,
+                pattern: None,
+            };
+            assert!(matches!(
+                encode_led_colors(&pixels),
+                Err(EarError::InvalidInput(_))
+            ));
+        }
+        let most = LedColorSet {
+            pixels: vec![LedColor([0, 0, 0]); 255],
+            brightness: None,
+            pattern: None,
+        };
+        let payload = encode_led_colors(&most).unwrap();
+        assert_eq!((payload[0], payload[1 + 254 * 4]), (255, 255));
+    }
+
+    #[test]
+    fn custom_eq_gains_are_clamped() {
+        let eq = clamp_custom_eq(CustomEq {
+            bass: 12.0,
+            mid: -2.5,
+            treble: -40.0,
+        })
+        .unwrap();
+        assert_eq!(
+            eq,
+            CustomEq {
+                bass: 6.0,
+                mid: -2.5,
+                treble: -6.0,
+            }
+        );
+    }
+
+    #[test]
+    fn custom_eq_rejects_non_finite_gains() {
+        for value in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            let eq = CustomEq {
+                bass: 0.0,
+                mid: value,
+                treble: 0.0,
+            };
+            assert!(clamp_custom_eq(eq).is_err());
+        }
+    }
+
+    #[test]
+    fn ring_payloads_per_model() {
+        assert_eq!(
+            ring_payloads(ModelBase::B181, true, Some(EarSide::Left)).unwrap(),
+            vec![vec![0x01]]
+        );
+        assert_eq!(
+            ring_payloads(ModelBase::B181, false, None).unwrap(),
+            vec![vec![0x00]]
+        );
+        assert_eq!(
+            ring_payloads(ModelBase::B155, true, Some(EarSide::Left)).unwrap(),
+            vec![vec![0x02, 0x01]]
+        );
+        assert_eq!(
+            ring_payloads(ModelBase::B155, false, Some(EarSide::Right)).unwrap(),
+            vec![vec![0x03, 0x00]]
+        );
+        assert_eq!(
+            ring_payloads(ModelBase::B171, true, None).unwrap(),
+            vec![vec![0x02, 0x01], vec![0x03, 0x01]]
+        );
+        for base in [ModelBase::B181, ModelBase::B155] {
+            assert!(matches!(
+                ring_payloads(base, true, Some(EarSide::Case)),
+                Err(EarError::InvalidInput(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn eq_float_edge_cases_round_trip() {
+        let cases = [
+            0.0,
+            -0.0,
+            f32::MIN_POSITIVE,
+            -f32::MIN_POSITIVE,
+            f32::from_bits(1),
+            -f32::from_bits(1),
+            f32::from_bits(0x80),
+            CUSTOM_EQ_MIN_DB,
+            CUSTOM_EQ_MAX_DB,
+        ];
+        for value in cases {
+            let decoded = decode_eq_float(&encode_eq_float(value));
+            assert_eq!(decoded.to_bits(), value.to_bits(), "{:e}", value);
+        }
+        assert_eq!(encode_eq_float(-0.0), [0x00, 0x00, 0x00, 0x80]);
+        assert_eq!(encode_eq_float(-6.0), [0x00, 0x00, 0xC0, 0xC0]);
+        assert_eq!(decode_eq_float(&[0x00, 0x00]), 0.0);
+    }
+
+    #[test]
+    fn custom_eq_total_gain_is_headroom_for_the_loudest_band() {
+        let cut = encode_custom_eq(CustomEq {
+            bass: -3.0,
+            mid: -1.0,
+            treble: 0.0,
+        });
+        assert_eq!(cut[1..5], [0x00, 0x00, 0x00, 0x80]);
+        let boost = encode_custom_eq(CustomEq {
+            bass: 4.5,
+            mid: -1.0,
+            treble: 2.0,
+        });
+        assert_eq!(decode_eq_float(&boost[1..5]), -4.5);
+    }
+
+    proptest! {
+        #[test]
+        fn payload_parsers_never_panic(payload in proptest::collection::vec(any::<u8>(), 0..64)) {
+            parse_battery_payload(&payload);
+            parse_gestures(&payload);
+            parse_led_colors(&payload);
+            parse_serial_number(&payload);
+        }
+
+        #[test]
+        fn eq_float_round_trips(value in proptest::num::f32::ANY) {
+            let decoded = decode_eq_float(&encode_eq_float(value));
+            prop_assert_eq!(decoded.to_bits(), value.to_bits());
+        }
+
+        #[test]
+        fn custom_eq_round_trips(
+            bass in CUSTOM_EQ_MIN_DB..=CUSTOM_EQ_MAX_DB,
+            mid in CUSTOM_EQ_MIN_DB..=CUSTOM_EQ_MAX_DB,
+            treble in CUSTOM_EQ_MIN_DB..=CUSTOM_EQ_MAX_DB,
+        ) {
+            let eq = CustomEq { bass, mid, treble };
+            let decoded = decode_custom_eq(&encode_custom_eq(eq.clone())).unwrap();
+            prop_assert!((decoded.bass - eq.bass).abs() < 1e-6);
+            prop_assert!((decoded.mid - eq.mid).abs() < 1e-6);
+            prop_assert!((decoded.treble - eq.treble).abs() < 1e-6);
+        }
+    }
+
+    #[tokio::test]
+    async fn command_queue_is_fifo_and_reports_depth() {
+        let connection = EarConnection::from_io("test", tokio::io::empty(), tokio::io::sink());
+        let queue = std::sync::Arc::new(CommandQueue::new(connection));
+        let order = std::sync::Arc::new(tokio::sync::Mutex::new(Vec::new()));
+
+        let held = queue.lock().await;
+        let mut waiters = Vec::new();
+        for index in 0..3 {
+            let (queue, order) = (queue.clone(), order.clone());
+            waiters.push(tokio::spawn(async move {
+                let _conn = queue.lock().await;
+                order.lock().await.push(index);
+            }));
+            // Let each waiter join the queue before the next one.
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(queue.depth(), 4);
+
+        let cancelled = {
+            let queue = queue.clone();
+            tokio::spawn(async move {
+                let _conn = queue.lock().await;
+            })
+        };
+        tokio::task::yield_now().await;
+        cancelled.abort();
+        let _ = cancelled.await;
+        assert_eq!(queue.depth(), 4);
+
+        drop(held);
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+        assert_eq!(*order.lock().await, vec![0, 1, 2]);
+        assert_eq!(queue.depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn disconnect_then_reconnect_succeeds() {
+        let manager = EarManager::builder().dry_run(true).build();
+        let address = bluer::Address::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+
+        let first = manager.connect(address, 1).await.unwrap();
+        manager.disconnect().await.unwrap();
+        assert!(manager.session().await.is_err());
+
+        let second = manager.connect(address, 1).await.unwrap();
+        assert_ne!(first.info().await.id, second.info().await.id);
+        assert!(matches!(
+            manager.disconnect().await.and(manager.disconnect().await),
+            Err(EarError::NoSession)
+        ));
+    }
+
+    #[tokio::test]
+    async fn reconnect_reuses_the_connect_parameters_and_model() {
+        let manager = EarManager::builder().dry_run(true).build();
+        let address = bluer::Address::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+
+        let first = manager.connect(address, 7).await.unwrap();
+        first.set_model_base(ModelBase::B171).await;
+        first.set_label(Some("desk".into())).await.unwrap();
+        *first.inner.last_battery.lock().await = Some(BatteryStatus::empty());
+        *first.inner.last_anc.lock().await = Some(AncStatus {
+            level: AncLevel::Transparency,
+            adaptive_strength: None,
+        });
+        let mut events = manager.events().subscribe();
+
+        let second = manager.reconnect().await.unwrap();
+        let info = second.info().await;
+        assert_ne!(info.id, first.id());
+        assert_eq!(info.port_path, first.info().await.port_path);
+        assert_eq!(info.label.as_deref(), Some("desk"));
+        assert_eq!(second.model_base().await, ModelBase::B171);
+        assert_eq!(manager.session().await.unwrap().id(), second.id());
+        let reconnected = loop {
+            match events.recv().await.unwrap() {
+                EarEvent::Reconnected {
+                    previous_session_id,
+                    session_id,
+                } => break (previous_session_id, session_id),
+                _ => continue,
+            }
+        };
+        assert_eq!(reconnected, (first.id(), second.id()));
+        assert!(matches!(
+            manager.event_snapshot().await,
+            EarEvent::Snapshot {
+                session_id: Some(id),
+                battery: Some(_),
+                anc: Some(AncStatus { level: AncLevel::Transparency, .. }),
+                eq: None,
+                ..
+            } if id == second.id()
+        ));
+
+        let stream = EarManager::new();
+        let (_device, host) = tokio::io::duplex(64);
+        let (host_read, host_write) = tokio::io::split(host);
+        let session = stream.new_session(EarConnection::from_io("test", host_read, host_write));
+        *stream.session.write().await = Some(session);
+        assert!(matches!(
+            stream.reconnect().await,
+            Err(EarError::InvalidInput(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn failed_heartbeats_close_the_session() {
+        let manager = EarManager::builder()
+            .timeout(Duration::from_millis(20))
+            .read_retries(0)
+            .heartbeat(Duration::from_millis(10))
+            .heartbeat_failures(2)
+            .build();
+        // A reader that never yields data, like a link that dropped silently.
+        let (_device, host) = tokio::io::duplex(64);
+        let (reader, _) = tokio::io::split(host);
+        let session =
+            manager.new_session(EarConnection::from_io("test", reader, tokio::io::sink()));
+        *manager.session.write().await = Some(session.clone());
+        manager.spawn_heartbeat(session);
+        assert!(manager.session().await.is_ok());
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(matches!(
+            manager.session().await.map(|_| ()),
+            Err(EarError::NoSession)
+        ));
+    }
+
+    #[tokio::test]
+    async fn idle_sessions_are_closed_and_announced() {
+        let manager = EarManager::builder()
+            .dry_run(true)
+            .idle_timeout(Duration::from_millis(100))
+            .build();
+        let mut events = manager.events().subscribe();
+        let address = "00:11:22:33:44:55".parse().unwrap();
+        let id = manager.connect(address, 15).await.unwrap().id();
+
+        // Activity keeps pushing the deadline out.
+        for _ in 0..4 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert!(manager.session().await.is_ok());
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert!(matches!(
+            manager.session().await.map(|_| ()),
+            Err(EarError::NoSession)
+        ));
+        let EarEvent::IdleDisconnected { session_id, .. } = events.recv().await.unwrap() else {
+            panic!("expected idle_disconnected first");
+        };
+        assert_eq!(session_id, id);
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            EarEvent::Disconnected {
+                reason: DisconnectReason::Idle,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn detected_serial_merges_into_sku_model() {
+        let from_sku = ModelDescriptor {
+            base: ModelBase::B155,
+            model_id: Some("ear_2_white".to_string()),
+            name: Some("Nothing Ear (2)".to_string()),
+            sku: Some("01".to_string()),
+            serial: None,
+        };
+        let detected = ModelDescriptor {
+            base: ModelBase::B155,
+            model_id: Some("ear_2_black".to_string()),
+            name: Some("Nothing Ear (2)".to_string()),
+            sku: Some("02".to_string()),
+            serial: Some("SH10012345".to_string()),
+        };
+        let merged = from_sku.clone().merge(detected);
+        assert_eq!(merged.sku.as_deref(), Some("01"));
+        assert_eq!(merged.model_id.as_deref(), Some("ear_2_white"));
+        assert_eq!(merged.serial.as_deref(), Some("SH10012345"));
+
+        let serial_only = ModelDescriptor {
+            serial: Some("SH10012345".to_string()),
+            ..ModelDescriptor::default()
+        };
+        let merged = from_sku.clone().merge(serial_only);
+        assert_eq!(merged.base, ModelBase::B155);
+        assert_eq!(merged.name.as_deref(), Some("Nothing Ear (2)"));
+        assert_eq!(merged.serial.as_deref(), Some("SH10012345"));
+
+        let base_only = ModelDescriptor {
+            base: ModelBase::B171,
+            ..ModelDescriptor::default()
+        };
+        let merged = ModelDescriptor::default().merge(from_sku);
+        assert_eq!(merged.model_id.as_deref(), Some("ear_2_white"));
+        let merged = base_only.merge(ModelDescriptor {
+            base: ModelBase::B155,
+            model_id: Some("ear_2_white".to_string()),
+            sku: Some("01".to_string()),
+            ..ModelDescriptor::default()
+        });
+        assert_eq!(merged.base, ModelBase::B171);
+        assert_eq!(merged.sku.as_deref(), Some("01"));
+    }
+
+    #[tokio::test]
+    async fn auto_pause_cannot_be_set_apart_from_in_ear_detection() {
+        let manager = EarManager::new();
+        let connection = EarConnection::from_io("test", tokio::io::empty(), tokio::io::sink());
+        let session = EarSessionHandle {
+            inner: manager.new_session(connection),
+        };
+        session.set_model_base(ModelBase::B155).await;
+        let result = session
+            .set_auto_pause(AutoPauseState { enabled: false })
+            .await;
+        assert!(matches!(result, Err(EarError::Unsupported(_))));
+    }
+
+    #[tokio::test]
+    async fn dfu_mode_is_unsupported_everywhere() {
+        let manager = EarManager::new();
+        let connection = EarConnection::from_io("test", tokio::io::empty(), tokio::io::sink());
+        let session = EarSessionHandle {
+            inner: manager.new_session(connection),
+        };
+        session.set_model_base(ModelBase::B172).await;
+        assert!(matches!(
+            session.enter_dfu_mode().await,
+            Err(EarError::Unsupported(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn conversation_mode_is_unsupported_everywhere() {
+        let manager = EarManager::new();
+        let connection = EarConnection::from_io("test", tokio::io::empty(), tokio::io::sink());
+        let session = EarSessionHandle {
+            inner: manager.new_session(connection),
+        };
+        session.set_model_base(ModelBase::B172).await;
+        assert!(matches!(
+            session.get_conversation_mode().await,
+            Err(EarError::Unsupported(_))
+        ));
+        assert!(matches!(
+            session
+                .set_conversation_mode(ConversationModeState { enabled: true })
+                .await,
+            Err(EarError::Unsupported(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn spatial_audio_is_unsupported_everywhere() {
+        let manager = EarManager::new();
+        let connection = EarConnection::from_io("test", tokio::io::empty(), tokio::io::sink());
+        let session = EarSessionHandle {
+            inner: manager.new_session(connection),
+        };
+        session.set_model_base(ModelBase::B172).await;
+        assert!(matches!(
+            session.get_spatial_audio().await,
+            Err(EarError::Unsupported(_))
+        ));
+        let state = SpatialAudioState {
+            enabled: true,
+            head_tracking: true,
+        };
+        assert!(matches!(
+            session.set_spatial_audio(state).await,
+            Err(EarError::Unsupported(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn racing_connects_leave_one_session() {
+        let manager = EarManager::builder().dry_run(true).build();
+        let address = bluer::Address::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+
+        let (first, second) =
+            tokio::join!(manager.connect(address, 1), manager.connect(address, 1));
+        let results = [first.map(|_| ()), second.map(|_| ())];
+        assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+        assert!(
+            results
+                .iter()
+                .any(|result| matches!(result, Err(EarError::AlreadyConnected)))
+        );
+
+        // The reservation is released, so the next attempt sees the session.
+        assert!(matches!(
+            manager.connect(address, 1).await.map(|_| ()),
+            Err(EarError::AlreadyConnected)
+        ));
+        manager.disconnect().await.unwrap();
+        assert!(manager.connect(address, 1).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn model_override_drops_the_cached_identity() {
+        let connection = EarConnection::from_io("test", Cursor::new(Vec::new()), tokio::io::sink());
+        let session = EarSessionHandle {
+            inner: EarManager::new().new_session(connection),
+        };
+        let detected = SerialIdentity {
+            serial_number: None,
+            sku: None,
+            model_id: Some("cleffa_black".into()),
+            source: Some(DetectionSource::BluetoothName),
+        };
+
+        *session.inner.identity.lock().await = Some(detected.clone());
+        session.set_model_base(ModelBase::B181).await;
+        assert!(session.inner.identity.lock().await.is_none());
+
+        *session.inner.identity.lock().await = Some(detected);
+        session.set_model_by_id("ear_1_white").await.unwrap();
+        assert!(session.inner.identity.lock().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn led_colors_must_cover_every_reported_pixel() {
+        let pixels = vec![LedColor([0xFF, 0, 0]); 5];
+        let reported = encode_led_colors(&LedColorSet {
+            pixels: pixels.clone(),
+            brightness: None,
+            pattern: None,
+        })
+        .unwrap();
+        let queued = EarPacket::encode(response::LED_CASE_COLORS, 1, &reported);
+        let connection = EarConnection::from_io("test", Cursor::new(queued), tokio::io::sink());
+        let session = EarSessionHandle {
+            inner: EarManager::new().new_session(connection),
+        };
+
+        let colors = LedColorSet {
+            pixels: pixels[..3].to_vec(),
+            brightness: None,
+            pattern: None,
+        };
+        assert!(matches!(
+            session.set_led_case_colors(&colors).await,
+            Err(EarError::Unsupported(_))
+        ));
+        session.set_model_base(ModelBase::B181).await;
+        assert!(matches!(
+            session.set_led_case_colors(&colors).await,
+            Err(EarError::InvalidInput(message)) if message.contains("5 LED pixels, got 3")
+        ));
+
+        // Only one reply was queued: the count is remembered, not re-read.
+        let too_many = LedColorSet {
+            pixels: vec![LedColor([0xFF, 0, 0]); 6],
+            brightness: None,
+            pattern: None,
+        };
+        assert!(matches!(
+            session.set_led_case_colors(&too_many).await,
+            Err(EarError::InvalidInput(message)) if message.contains("5 LED pixels, got 6")
+        ));
+    }
+
+    #[tokio::test]
+    async fn verified_anc_write_reports_an_ignored_level() {
+        let reported = AncLevel::Transparency.to_device();
+        let queued = EarPacket::encode(response::ANC_PRIMARY, 1, &[0x01, reported, 0x00]);
+        let connection = EarConnection::from_io("test", Cursor::new(queued), tokio::io::sink());
+        let session = EarSessionHandle {
+            inner: EarManager::new().new_session(connection),
+        };
+        session.set_model_base(ModelBase::B155).await;
+
+        let result = session
+            .set_anc_verified(AncLevel::NoiseCancellationAdaptive)
+            .await;
+        assert!(matches!(
+            result,
+            Err(EarError::NotApplied { actual, .. }) if actual == "transparency"
+        ));
+    }
+
+    #[tokio::test]
+    async fn custom_eq_has_only_the_active_slot() {
+        let session = EarSessionHandle {
+            inner: EarManager::new().new_session(EarConnection::from_io(
+                "test",
+                tokio::io::empty(),
+                tokio::io::sink(),
+            )),
+        };
+        session.set_model_base(ModelBase::B155).await;
+        let eq = CustomEq {
+            bass: 1.0,
+            mid: 0.0,
+            treble: -1.0,
+        };
+        assert!(matches!(
+            session.set_custom_eq(1, eq.clone()).await,
+            Err(EarError::InvalidInput(_))
+        ));
+        assert!(session.set_custom_eq(0, eq).await.is_ok());
+
+        session.set_model_base(ModelBase::B181).await;
+        assert!(session.list_custom_eq_presets().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn anc_levels_the_model_lacks_are_rejected() {
+        let (_device, host) = tokio::io::duplex(4096);
+        let (host_read, host_write) = tokio::io::split(host);
+        let session = EarSessionHandle {
+            inner: EarManager::new()
+                .new_session(EarConnection::from_io("test", host_read, host_write)),
+        };
+        session.set_model_base(ModelBase::B181).await;
+        let err = session
+            .set_anc(AncLevel::NoiseCancellationMid)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, EarError::InvalidInput(_)), "{err}");
+        assert!(
+            session
+                .set_anc(AncLevel::NoiseCancellationLow)
+                .await
+                .is_ok()
+        );
+
+        session.set_model_base(ModelBase::Unknown).await;
+        assert!(!session.anc_capabilities().await.restricted);
+        assert!(
+            session
+                .set_anc(AncLevel::NoiseCancellationMid)
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn debounced_anc_burst_sends_only_the_last_level() {
+        let (device, host) = tokio::io::duplex(4096);
+        let (host_read, host_write) = tokio::io::split(host);
+        let manager = EarManager::builder()
+            .write_debounce(Duration::from_millis(30))
+            .build();
+        let session = EarSessionHandle {
+            inner: manager.new_session(EarConnection::from_io("test", host_read, host_write)),
+        };
+        session.set_model_base(ModelBase::B155).await;
+        assert_eq!(session.info().await.write_debounce_ms, Some(30));
+
+        let (first, second, last) = tokio::join!(
+            session.set_anc(AncLevel::NoiseCancellationHigh),
+            session.set_anc(AncLevel::Transparency),
+            session.set_anc(AncLevel::Off),
+        );
+        assert!(first.is_ok() && second.is_ok() && last.is_ok());
+
+        let (mut device_read, _device_write) = tokio::io::split(device);
+        let mut buffer = Vec::new();
+        let mut chunk = [0u8; 256];
+        let _ = tokio::time::timeout(Duration::from_millis(50), async {
+            loop {
+                let n = device_read.read(&mut chunk).await.unwrap();
+                buffer.extend_from_slice(&chunk[..n]);
+            }
+        })
+        .await;
+        let mut levels = Vec::new();
+        while let Some(packet) = EarPacket::try_parse(&mut buffer).unwrap() {
+            assert_eq!(packet.command, command::CMD_SET_ANC);
+            levels.push(packet.payload[1]);
+        }
+        assert_eq!(levels, vec![AncLevel::Off.to_device()]);
+    }
+
+    #[tokio::test]
+    async fn stream_eof_marks_the_session_disconnected() {
+        let manager = EarManager::new();
+        let mut events = manager.events().subscribe();
+        let connection = EarConnection::from_io("test", tokio::io::empty(), tokio::io::sink());
+        let session = EarSessionHandle {
+            inner: manager.new_session(connection),
+        };
+        assert!(session.info().await.connected);
+
+        assert!(session.refresh_battery().await.is_err());
+        assert!(!session.info().await.connected);
+        let event = tokio::time::timeout(Duration::from_secs(1), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            event,
+            EarEvent::Disconnected { session_id, reason: DisconnectReason::RemoteClosed }
+                if session_id == session.id()
+        ));
+    }
+
+    #[tokio::test]
+    async fn profiles_leave_out_a_listening_mode_read_as_the_eq() {
+        let (device, host) = tokio::io::duplex(4096);
+        let (host_read, host_write) = tokio::io::split(host);
+        let mut connection = EarConnection::from_io("test", host_read, host_write);
+        connection.set_timeout(Duration::from_millis(50));
+        connection.set_read_retries(0);
+        let session = EarSessionHandle {
+            inner: EarManager::new().new_session(connection),
+        };
+        session.set_model_base(ModelBase::B168).await;
+        let eq = CustomEq {
+            bass: 1.0,
+            mid: 0.0,
+            treble: -1.0,
+        };
+        // No reply to the EQ request, so the read falls back to the listening mode.
+        tokio::spawn(answer_requests(
+            device,
+            vec![
+                (
+                    command::REQUEST_LATENCY_STATUS,
+                    response::LATENCY,
+                    vec![0x01],
+                ),
+                (
+                    command::REQUEST_ANC,
+                    response::ANC_PRIMARY,
+                    vec![0x01, AncLevel::Transparency.to_device(), 0x00],
+                ),
+                (
+                    command::REQUEST_LISTENING_MODE,
+                    response::EQ_LISTENING_MODE,
+                    vec![0x03],
+                ),
+                (
+                    command::REQUEST_CUSTOM_EQ,
+                    response::CUSTOM_EQ,
+                    encode_custom_eq(eq.clone()),
+                ),
+                (
+                    command::REQUEST_ENHANCED_BASS,
+                    response::ENHANCED_BASS,
+                    vec![0x01, 0x04],
+                ),
+            ],
+        ));
+
+        assert_eq!(
+            session.read_eq().await.unwrap().source,
+            EqSource::ListeningMode
+        );
+        let profile = session.capture_profile().await.unwrap();
+        assert_eq!(profile.eq_mode, None);
+        assert_eq!(profile.anc, Some(AncLevel::Transparency));
+        assert_eq!(profile.latency, Some(LatencyMode::Low));
+        assert_eq!(profile.custom_eq, Some(eq));
+    }
+
+    #[tokio::test]
+    async fn ear_fit_runs_until_both_buds_report() {
+        let (device, host) = tokio::io::duplex(4096);
+        let (host_read, host_write) = tokio::io::split(host);
+        let session = EarSessionHandle {
+            inner: EarManager::new()
+                .new_session(EarConnection::from_io("test", host_read, host_write)),
+        };
+        tokio::spawn(answer_ear_fit(
+            device,
+            |read| if read == 1 { 0xFF } else { 0x01 },
+        ));
+
+        let progress = session.await_ear_fit(Duration::from_secs(5)).await.unwrap();
+        assert_eq!(progress.state, EarFitState::Complete);
+        let result = progress.result.unwrap();
+        assert_eq!(result.left, FitQuality::Good);
+        assert_eq!(result.right, FitQuality::Adjust);
+    }
+
+    #[tokio::test]
+    async fn ear_fit_run_stops_on_cancel_with_the_partial_result() {
+        let (device, host) = tokio::io::duplex(4096);
+        let (host_read, host_write) = tokio::io::split(host);
+        let session = EarSessionHandle {
+            inner: EarManager::new()
+                .new_session(EarConnection::from_io("test", host_read, host_write)),
+        };
+        tokio::spawn(answer_ear_fit(device, |_| 0xFF));
+
+        let canceller = session.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            canceller.cancel_ear_fit();
+        });
+        let progress = session.await_ear_fit(Duration::from_secs(5)).await.unwrap();
+        assert_eq!(progress.state, EarFitState::Cancelled);
+        let result = progress.result.unwrap();
+        assert_eq!(result.left, FitQuality::Good);
+        assert!(!result.complete);
+        assert!(progress.elapsed_ms < 1000);
+    }
+
+    #[tokio::test]
+    async fn volume_gestures_go_up_on_the_right_and_down_on_the_left() {
+        let (device, host) = tokio::io::duplex(4096);
+        let (host_read, host_write) = tokio::io::split(host);
+        let session = EarSessionHandle {
+            inner: EarManager::new()
+                .new_session(EarConnection::from_io("test", host_read, host_write)),
+        };
+        session.set_model_base(ModelBase::B181).await;
+        assert!(matches!(
+            session.set_volume_gestures(GestureType::TripleTap).await,
+            Err(EarError::Unsupported(_))
+        ));
+
+        session.set_model_base(ModelBase::B155).await;
+        session
+            .set_volume_gestures(GestureType::TripleTap)
+            .await
+            .unwrap();
+        let (mut rx, _tx) = tokio::io::split(device);
+        let mut buffer = Vec::new();
+        let mut writes = Vec::new();
+        while writes.len() < 2 {
+            let mut chunk = [0u8; 64];
+            let n = rx.read(&mut chunk).await.unwrap();
+            buffer.extend_from_slice(&chunk[..n]);
+            while let Some(packet) = EarPacket::try_parse(&mut buffer).unwrap() {
+                assert_eq!(packet.command, command::CMD_SET_GESTURE);
+                writes.push(packet.payload);
+            }
+        }
+        let triple = GestureType::TripleTap.to_device();
+        assert_eq!(
+            writes,
+            [
+                vec![
+                    0x01,
+                    0x03,
+                    0x01,
+                    triple,
+                    GestureAction::VolumeUp.to_device()
+                ],
+                vec![
+                    0x01,
+                    0x02,
+                    0x01,
+                    triple,
+                    GestureAction::VolumeDown.to_device()
+                ],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn gesture_writes_are_checked_against_the_other_bud() {
+        let slot = |device, action| GestureSlot {
+            device,
+            common: 0x01,
+            gesture_type: GestureType::DoubleTap.to_device(),
+            action,
+        };
+        let play = GestureAction::PlayPause.to_device();
+        let next = GestureAction::NextTrack.to_device();
+        let none = GestureAction::NoAction.to_device();
+        let current = vec![slot(0x02, play), slot(0x03, next)];
+
+        let conflicts = find_gesture_conflicts(&slot(0x02, next), &current);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].existing.slot.device, 0x03);
+        assert!(find_gesture_conflicts(&slot(0x03, next), &current).is_empty());
+        let unassigned = vec![slot(0x02, play), slot(0x03, none)];
+        assert!(find_gesture_conflicts(&slot(0x02, none), &unassigned).is_empty());
+
+        let reported = |slots: &[GestureSlot]| {
+            let mut payload = vec![slots.len() as u8];
+            for slot in slots {
+                payload.extend([slot.device, slot.common, slot.gesture_type, slot.action]);
+            }
+            EarPacket::encode(response::GESTURES, 1, &payload)
+        };
+        let mut queued = reported(&current);
+        queued.extend(reported(&current));
+        let manager = EarManager::builder()
+            .gesture_conflicts(GestureConflictPolicy::Reject)
+            .build();
+        let session = EarSessionHandle {
+            inner: manager.new_session(EarConnection::from_io(
+                "test",
+                Cursor::new(queued),
+                tokio::io::sink(),
+            )),
+        };
+        let err = session.set_gesture(&slot(0x02, next)).await.unwrap_err();
+        assert!(
+            matches!(&err, EarError::GestureConflict(conflicts) if conflicts.len() == 1),
+            "{err}"
+        );
+        assert!(
+            session
+                .set_gesture(&slot(0x02, none))
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn anc_changes_are_published_once() {
+        let anc = |level: AncLevel| {
+            EarPacket::encode(response::ANC_PRIMARY, 1, &[0x01, level.to_device()])
+        };
+        let queued = [
+            anc(AncLevel::Off),
+            anc(AncLevel::Off),
+            anc(AncLevel::Transparency),
+        ]
+        .concat();
+        let manager = EarManager::new();
+        let mut events = manager.events().subscribe();
+        let session = EarSessionHandle {
+            inner: manager.new_session(EarConnection::from_io(
+                "test",
+                Cursor::new(queued),
+                tokio::io::sink(),
+            )),
+        };
+        for _ in 0..3 {
+            session.refresh_anc().await.unwrap();
+        }
+        let mut published = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let EarEvent::AncChanged { anc } = event {
+                published.push(anc.level);
+            }
+        }
+        assert_eq!(published, vec![AncLevel::Off, AncLevel::Transparency]);
+    }
+
+    #[tokio::test]
+    async fn notifications_are_applied_between_requests() {
+        let (device, host) = tokio::io::duplex(4096);
+        let (host_read, host_write) = tokio::io::split(host);
+        let manager = EarManager::new();
+        let mut events = manager.events().subscribe();
+        let session = manager
+            .attach(EarConnection::from_io("test", host_read, host_write))
+            .await
+            .unwrap();
+        session.set_model_base(ModelBase::B155).await;
+
+        // An ANC change made on the buds, with no request in flight.
+        let (rx, mut tx) = tokio::io::split(device);
+        let pushed = EarPacket::encode(
+            response::ANC_PRIMARY,
+            0,
+            &[0x01, AncLevel::Transparency.to_device()],
+        );
+        tx.write_all(&pushed).await.unwrap();
+        let anc = loop {
+            if let EarEvent::AncChanged { anc } = events.recv().await.unwrap() {
+                break anc;
+            }
+        };
+        assert_eq!(anc.level, AncLevel::Transparency);
+
+        // Battery pushes reach subscribers such as the low-battery hook.
+        tx.write_all(&EarPacket::encode(
+            response::BATTERY_PRIMARY,
+            0,
+            &[0x01, 0x02, 0x0F],
+        ))
+        .await
+        .unwrap();
+        let battery = loop {
+            if let EarEvent::BatteryChanged { battery } = events.recv().await.unwrap() {
+                break battery;
+            }
+        };
+        assert_eq!(
+            battery.left,
+            BatteryReading::Level {
+                percent: 15,
+                charging: false
+            }
+        );
+
+        // Taking a bud out is published without an earlier read.
+        tx.write_all(&EarPacket::encode(response::IN_EAR, 0, &[0x01, 0x00]))
+            .await
+            .unwrap();
+        let worn = loop {
+            if let EarEvent::InEarChanged {
+                left_in_ear,
+                right_in_ear,
+            } = events.recv().await.unwrap()
+            {
+                break (left_in_ear, right_in_ear);
+            }
+        };
+        assert_eq!(worn, (Some(true), Some(false)));
+
+        // The listener gives the connection up for a request.
+        let device = rx.unsplit(tx);
+        tokio::spawn(answer_requests(
+            device,
+            vec![(
+                command::REQUEST_ANC,
+                response::ANC_PRIMARY,
+                vec![0x01, AncLevel::Off.to_device()],
+            )],
+        ));
+        let status = tokio::time::timeout(Duration::from_secs(1), session.refresh_anc())
+            .await
+            .expect("the request waited out the listener")
+            .unwrap();
+        assert_eq!(status.level, AncLevel::Off);
+        assert_eq!(session.info().await.queue_depth, 0);
+    }
 }
@@ -1,20 +1,43 @@
+//! Types shared by the service, the HTTP API and the CLI. In JSON, enum
+//! values are snake_case strings (`"noise_cancellation_high"`,
+//! `"double_tap"`); [`ModelBase`] uses its model code and the deprecated
+//! [`BatteryReading`] spelling keeps its variant names.
+
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::{fmt, str::FromStr};
 use uuid::Uuid;
 
 use crate::models::ModelBase;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// `"Disconnected"` or `{ "Level": { "percent": 80, "charging": false } }`.
+/// Still capitalized so existing clients keep working; this spelling is
+/// deprecated and the snake_case one (`"disconnected"`, `"level"`) is
+/// accepted as input already.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub enum BatteryReading {
+    #[serde(alias = "disconnected")]
     Disconnected,
+    #[serde(alias = "level")]
     Level { percent: u8, charging: bool },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Battery levels of each part. There is no case lid state: no captured
+/// battery frame shows which bit would carry it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct BatteryStatus {
     pub left: BatteryReading,
     pub right: BatteryReading,
     pub case: BatteryReading,
+    /// The case entry's charging bit: the case itself is on a charger.
+    /// `None` when the case isn't reported.
+    #[serde(default)]
+    pub case_on_charger: Option<bool>,
+    /// Whether the case is topping up the buds. The payload has no bit for
+    /// this; buds only charge while docked, so it mirrors their charging
+    /// bits. `None` when neither bud reports a level.
+    #[serde(default)]
+    pub case_charging_buds: Option<bool>,
 }
 
 impl BatteryStatus {
@@ -23,11 +46,64 @@ impl BatteryStatus {
             left: BatteryReading::Disconnected,
             right: BatteryReading::Disconnected,
             case: BatteryReading::Disconnected,
+            case_on_charger: None,
+            case_charging_buds: None,
+        }
+    }
+
+    /// Recomputes the derived case flags from the per-side readings.
+    pub fn update_case_flags(&mut self) {
+        let charging = |reading: &BatteryReading| match reading {
+            BatteryReading::Level { charging, .. } => Some(*charging),
+            BatteryReading::Disconnected => None,
+        };
+        self.case_on_charger = charging(&self.case);
+        self.case_charging_buds = match (charging(&self.left), charging(&self.right)) {
+            (None, None) => None,
+            (left, right) => Some(left == Some(true) || right == Some(true)),
+        };
+    }
+
+    /// Sides reporting a level under `threshold` percent while not charging.
+    pub fn sides_below(&self, threshold: u8) -> Vec<EarSide> {
+        [
+            (EarSide::Left, &self.left),
+            (EarSide::Right, &self.right),
+            (EarSide::Case, &self.case),
+        ]
+        .into_iter()
+        .filter_map(|(side, reading)| match *reading {
+            BatteryReading::Level {
+                percent,
+                charging: false,
+            } if percent < threshold => Some(side),
+            _ => None,
+        })
+        .collect()
+    }
+
+    /// Takes every side `other` has a level for, keeping the rest.
+    pub fn merge(&mut self, other: BatteryStatus) {
+        for (slot, reading) in [
+            (&mut self.left, other.left),
+            (&mut self.right, other.right),
+            (&mut self.case, other.case),
+        ] {
+            if matches!(reading, BatteryReading::Level { .. }) {
+                *slot = reading;
+            }
         }
+        self.update_case_flags();
+    }
+
+    /// Whether both buds, and the case when `with_case` is set, report a level.
+    pub fn is_complete(&self, with_case: bool) -> bool {
+        let has_level = |reading: &BatteryReading| matches!(reading, BatteryReading::Level { .. });
+        has_level(&self.left) && has_level(&self.right) && (!with_case || has_level(&self.case))
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum EarSide {
     Left,
@@ -35,7 +111,7 @@ pub enum EarSide {
     Case,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum AncLevel {
     Off,
@@ -101,6 +177,44 @@ impl FromStr for AncLevel {
     }
 }
 
+/// The ANC levels a model accepts, from `GET /api/anc/capabilities`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AncCapabilities {
+    pub model: ModelBase,
+    /// `false` for unknown models: every level is sent and `levels` is empty.
+    pub restricted: bool,
+    pub levels: Vec<AncLevel>,
+}
+
+/// ANC readback. In adaptive mode the buds also report the strength they
+/// currently apply, which moves with ambient noise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct AncStatus {
+    pub level: AncLevel,
+    #[serde(default)]
+    pub adaptive_strength: Option<u8>,
+}
+
+impl EarSide {
+    /// Device id used for this side in battery, ring and gesture payloads.
+    pub fn device_id(self) -> u8 {
+        match self {
+            EarSide::Left => 0x02,
+            EarSide::Right => 0x03,
+            EarSide::Case => 0x04,
+        }
+    }
+
+    pub fn from_device_id(value: u8) -> Option<Self> {
+        match value {
+            0x02 => Some(EarSide::Left),
+            0x03 => Some(EarSide::Right),
+            0x04 => Some(EarSide::Case),
+            _ => None,
+        }
+    }
+}
+
 impl fmt::Display for EarSide {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let label = match self {
@@ -125,51 +239,326 @@ impl FromStr for EarSide {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// EQ preset id the device switches to when the custom EQ bands are applied.
+pub const CUSTOM_EQ_MODE: u8 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct EqMode {
+    /// The raw byte the buds reported.
     pub mode: u8,
+    /// Which response the value was read from.
+    #[serde(default)]
+    pub source: EqSource,
+    /// `mode` is one of the connected model's presets. Always `false` for an
+    /// unknown model, which has no preset table, and for listening modes.
+    #[serde(default)]
+    pub known: bool,
+    /// The preset's name in the Nothing X app, when `known`.
+    #[serde(default)]
+    pub name: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EqSource {
+    /// The standard EQ response (`EQ_PRIMARY`).
+    #[default]
+    Eq,
+    /// The listening-mode response, used on models that don't answer the
+    /// EQ request; the value is the listening mode, not an EQ preset id.
+    ListeningMode,
+}
+
+impl EqMode {
+    /// A reading that hasn't been checked against a preset table yet.
+    pub fn new(mode: u8, source: EqSource) -> Self {
+        Self {
+            mode,
+            source,
+            known: false,
+            name: None,
+        }
+    }
+
+    /// Looks `mode` up in `base`'s preset table, so a stray byte isn't
+    /// mistaken for a preset.
+    pub fn labelled(mut self, base: ModelBase) -> Self {
+        let preset = match self.source {
+            EqSource::Eq => base
+                .eq_presets()
+                .iter()
+                .find(|(mode, _)| *mode == self.mode),
+            EqSource::ListeningMode => None,
+        };
+        self.known = preset.is_some();
+        self.name = preset.map(|(_, name)| name.to_string());
+        self
+    }
+
+    pub fn is_custom(&self) -> bool {
+        self.mode == CUSTOM_EQ_MODE
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct EqPreset {
+    pub mode: u8,
+    pub name: String,
+}
+
+/// The EQ presets the connected model offers and which one is selected.
+/// `presets` is empty when the model is unknown.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct EqPresets {
+    pub model: ModelBase,
+    pub current: u8,
+    pub presets: Vec<EqPreset>,
+}
+
+/// The listening mode reported by CMF buds (`0x4050`). It is a separate
+/// setting from the EQ preset (`0x401F`) even though both are a single byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ListeningMode {
+    pub mode: u8,
+}
+
+/// Lowest gain, in dB, the Nothing X app offers for a custom EQ band.
+pub const CUSTOM_EQ_MIN_DB: f32 = -6.0;
+/// Highest gain, in dB, the Nothing X app offers for a custom EQ band.
+pub const CUSTOM_EQ_MAX_DB: f32 = 6.0;
+
+/// Custom EQ gains in dB. Writes clamp each band to
+/// [`CUSTOM_EQ_MIN_DB`]..=[`CUSTOM_EQ_MAX_DB`] and reject NaN or infinite values.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct CustomEq {
     pub bass: f32,
     pub mid: f32,
     pub treble: f32,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Custom EQ bands together with whether they are the active preset.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CustomEqState {
+    /// Custom preset slot the bands were read from.
+    #[serde(default)]
+    pub slot: u8,
+    #[serde(flatten)]
+    pub eq: CustomEq,
+    pub mode: u8,
+    pub active: bool,
+}
+
+/// A low-battery threshold in percent (1-100); `null` clears it.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BatteryAlertConfig {
+    pub threshold: Option<u8>,
+}
+
+/// The session's low-battery threshold checked against the latest reading.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BatteryAlertState {
+    pub threshold: Option<u8>,
+    /// Set when any side in `low` is below the threshold.
+    pub active: bool,
+    /// Sides under the threshold that aren't charging.
+    pub low: Vec<EarSide>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct EnhancedBassState {
     pub enabled: bool,
     pub level: u8,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct PersonalizedAncState {
     pub enabled: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Switching to transparency while the wearer talks, see
+/// [`ModelBase::supports_conversation_mode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ConversationModeState {
+    pub enabled: bool,
+}
+
+/// Spatial audio, see [`ModelBase::supports_spatial_audio`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SpatialAudioState {
+    pub enabled: bool,
+    /// Follow head movement; only meaningful while `enabled`.
+    #[serde(default)]
+    pub head_tracking: bool,
+}
+
+/// Sound settings saved under a name and applied in one go. Settings the
+/// model lacks are left out when saving; applying them elsewhere reports
+/// them as failed operations.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct SoundProfile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anc: Option<AncLevel>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eq_mode: Option<u8>,
+    /// Bands of custom slot 0.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom_eq: Option<CustomEq>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enhanced_bass: Option<EnhancedBassState>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency: Option<LatencyMode>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LatencyMode {
+    Normal,
+    Low,
+}
+
+impl LatencyMode {
+    pub fn from_device(value: u8) -> Option<Self> {
+        match value {
+            0x00 | 0x02 => Some(Self::Normal),
+            0x01 => Some(Self::Low),
+            _ => None,
+        }
+    }
+
+    /// Payload for `CMD_SET_LATENCY`.
+    pub fn to_payload(self) -> [u8; 2] {
+        match self {
+            Self::Normal => [0x02, 0x00],
+            Self::Low => [0x01, 0x00],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct LatencyState {
     pub low_latency_enabled: bool,
+    #[serde(default)]
+    pub mode: Option<LatencyMode>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl LatencyState {
+    pub fn from_mode(mode: LatencyMode) -> Self {
+        Self {
+            low_latency_enabled: mode != LatencyMode::Normal,
+            mode: Some(mode),
+        }
+    }
+
+    /// Bytes outside the known modes keep the plain `== 1` flag, with no mode.
+    pub fn from_device(value: u8) -> Self {
+        match LatencyMode::from_device(value) {
+            Some(mode) => Self::from_mode(mode),
+            None => Self {
+                low_latency_enabled: value == 1,
+                mode: None,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct InEarState {
     pub detection_enabled: bool,
+    /// Whether each bud is currently worn; absent when the device only reports the toggle.
+    #[serde(default)]
+    pub left_in_ear: Option<bool>,
+    #[serde(default)]
+    pub right_in_ear: Option<bool>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Media auto-pause when a bud is taken out. On every known model this is
+/// the in-ear detection switch, so it reads the same as `detection_enabled`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AutoPauseState {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct FirmwareInfo {
+    /// Combined version; the raw string when the payload format is not recognised.
     pub version: String,
+    pub major: Option<u32>,
+    pub minor: Option<u32>,
+    pub left: Option<String>,
+    pub right: Option<String>,
+    pub case: Option<String>,
+    pub raw: String,
+}
+
+/// Seal quality reported per bud by the ear-fit test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FitQuality {
+    Good,
+    Adjust,
+    Poor,
+    /// No verdict yet; the test is still running or was never started.
+    Pending,
+}
+
+impl FitQuality {
+    pub fn from_device(value: u8) -> Self {
+        match value {
+            0x00 => Self::Good,
+            0x01 => Self::Adjust,
+            0x02 => Self::Poor,
+            _ => Self::Pending,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct EarFitResult {
-    pub left: u8,
-    pub right: u8,
+    pub left: FitQuality,
+    pub right: FitQuality,
+    pub raw_left: u8,
+    pub raw_right: u8,
+    /// Both buds have a verdict.
+    pub complete: bool,
+}
+
+/// Where `POST /api/ear-fit/run` left the test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EarFitState {
+    /// Both buds have a verdict.
+    Complete,
+    /// The wait ran out first; the test may still finish on the buds.
+    InProgress,
+    /// Stopped through `POST /api/ear-fit/cancel`.
+    Cancelled,
+}
+
+/// An ear-fit run with the last result read, which is partial unless the
+/// state is `complete`. `result` is `None` if the buds never answered.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct EarFitProgress {
+    pub state: EarFitState,
+    pub result: Option<EarFitResult>,
+    pub elapsed_ms: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl EarFitResult {
+    pub fn from_raw(raw_left: u8, raw_right: u8) -> Self {
+        let left = FitQuality::from_device(raw_left);
+        let right = FitQuality::from_device(raw_right);
+        Self {
+            left,
+            right,
+            raw_left,
+            raw_right,
+            complete: left != FitQuality::Pending && right != FitQuality::Pending,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct GestureSlot {
     pub device: u8,
     pub common: u8,
@@ -177,22 +566,356 @@ pub struct GestureSlot {
     pub action: u8,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Value sent in the `common` byte of a gesture slot by the Nothing X app.
+pub const GESTURE_COMMON_DEFAULT: u8 = 0x01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GestureType {
+    SingleTap,
+    DoubleTap,
+    TripleTap,
+    TapAndHold,
+    DoubleTapAndHold,
+}
+
+impl GestureType {
+    pub fn from_device(value: u8) -> Option<Self> {
+        match value {
+            0x01 => Some(Self::SingleTap),
+            0x02 => Some(Self::DoubleTap),
+            0x03 => Some(Self::TripleTap),
+            0x07 => Some(Self::TapAndHold),
+            0x09 => Some(Self::DoubleTapAndHold),
+            _ => None,
+        }
+    }
+
+    pub fn to_device(self) -> u8 {
+        match self {
+            GestureType::SingleTap => 0x01,
+            GestureType::DoubleTap => 0x02,
+            GestureType::TripleTap => 0x03,
+            GestureType::TapAndHold => 0x07,
+            GestureType::DoubleTapAndHold => 0x09,
+        }
+    }
+}
+
+impl fmt::Display for GestureType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            GestureType::SingleTap => "single-tap",
+            GestureType::DoubleTap => "double-tap",
+            GestureType::TripleTap => "triple-tap",
+            GestureType::TapAndHold => "tap-and-hold",
+            GestureType::DoubleTapAndHold => "double-tap-and-hold",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+impl FromStr for GestureType {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace('_', "-").as_str() {
+            "single-tap" | "tap" => Ok(GestureType::SingleTap),
+            "double-tap" => Ok(GestureType::DoubleTap),
+            "triple-tap" => Ok(GestureType::TripleTap),
+            "tap-and-hold" | "hold" => Ok(GestureType::TapAndHold),
+            "double-tap-and-hold" => Ok(GestureType::DoubleTapAndHold),
+            _ => Err("invalid gesture type"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GestureAction {
+    NoAction,
+    PlayPause,
+    NextTrack,
+    PreviousTrack,
+    VoiceAssistant,
+    NoiseControl,
+    /// `0x12`. The step size is fixed by the firmware; no model exposes a
+    /// command to configure it.
+    VolumeUp,
+    /// `0x13`, see [`GestureAction::VolumeUp`].
+    VolumeDown,
+}
+
+impl GestureAction {
+    pub fn from_device(value: u8) -> Option<Self> {
+        match value {
+            0x01 => Some(Self::NoAction),
+            0x02 => Some(Self::PlayPause),
+            0x08 => Some(Self::NextTrack),
+            0x09 => Some(Self::PreviousTrack),
+            0x0B => Some(Self::VoiceAssistant),
+            0x0A => Some(Self::NoiseControl),
+            0x12 => Some(Self::VolumeUp),
+            0x13 => Some(Self::VolumeDown),
+            _ => None,
+        }
+    }
+
+    pub fn is_volume(self) -> bool {
+        matches!(self, Self::VolumeUp | Self::VolumeDown)
+    }
+
+    pub fn to_device(self) -> u8 {
+        match self {
+            GestureAction::NoAction => 0x01,
+            GestureAction::PlayPause => 0x02,
+            GestureAction::NextTrack => 0x08,
+            GestureAction::PreviousTrack => 0x09,
+            GestureAction::VoiceAssistant => 0x0B,
+            GestureAction::NoiseControl => 0x0A,
+            GestureAction::VolumeUp => 0x12,
+            GestureAction::VolumeDown => 0x13,
+        }
+    }
+}
+
+impl fmt::Display for GestureAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            GestureAction::NoAction => "no-action",
+            GestureAction::PlayPause => "play-pause",
+            GestureAction::NextTrack => "next-track",
+            GestureAction::PreviousTrack => "previous-track",
+            GestureAction::VoiceAssistant => "voice-assistant",
+            GestureAction::NoiseControl => "noise-control",
+            GestureAction::VolumeUp => "volume-up",
+            GestureAction::VolumeDown => "volume-down",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+impl FromStr for GestureAction {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace('_', "-").as_str() {
+            "no-action" | "none" => Ok(GestureAction::NoAction),
+            "play-pause" => Ok(GestureAction::PlayPause),
+            "next-track" | "next" => Ok(GestureAction::NextTrack),
+            "previous-track" | "previous" | "prev" => Ok(GestureAction::PreviousTrack),
+            "voice-assistant" => Ok(GestureAction::VoiceAssistant),
+            "noise-control" | "anc" => Ok(GestureAction::NoiseControl),
+            "volume-up" => Ok(GestureAction::VolumeUp),
+            "volume-down" => Ok(GestureAction::VolumeDown),
+            _ => Err("invalid gesture action"),
+        }
+    }
+}
+
+/// A gesture slot with decoded names. The raw bytes are always kept so slots
+/// with unmapped values still round-trip.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GestureConfig {
+    pub side: Option<EarSide>,
+    pub gesture_name: Option<GestureType>,
+    pub action_name: Option<GestureAction>,
+    #[serde(flatten)]
+    pub slot: GestureSlot,
+}
+
+impl From<GestureSlot> for GestureConfig {
+    fn from(slot: GestureSlot) -> Self {
+        Self {
+            side: EarSide::from_device_id(slot.device),
+            gesture_name: GestureType::from_device(slot.gesture_type),
+            action_name: GestureAction::from_device(slot.action),
+            slot,
+        }
+    }
+}
+
+/// Two slots that map the same gesture to the same action on different
+/// buds, which some firmware refuses without saying so.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GestureConflict {
+    /// The slot being written.
+    pub requested: GestureConfig,
+    /// The slot it collides with, as the buds currently report it.
+    pub existing: GestureConfig,
+}
+
+impl fmt::Display for GestureConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let slot = &self.existing.slot;
+        write!(
+            f,
+            "device {:#04x} already maps gesture {:#04x} to action {:#04x}",
+            slot.device, slot.gesture_type, slot.action
+        )
+    }
+}
+
+/// What `POST /api/gestures` does about a [`GestureConflict`]: nothing
+/// (`off`, the default, which skips the extra read), write anyway and
+/// report it (`warn`), or refuse the write (`reject`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GestureConflictPolicy {
+    #[default]
+    Off,
+    Warn,
+    Reject,
+}
+
+impl fmt::Display for GestureConflictPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Off => "off",
+            Self::Warn => "warn",
+            Self::Reject => "reject",
+        })
+    }
+}
+
+impl FromStr for GestureConflictPolicy {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "warn" => Ok(Self::Warn),
+            "reject" => Ok(Self::Reject),
+            _ => Err("expected off, warn or reject"),
+        }
+    }
+}
+
+/// The gesture slots and actions a model accepts, from
+/// `GET /api/gestures/capabilities`. Any combination of the listed side,
+/// gesture and action is assignable.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GestureCapabilities {
+    pub model: ModelBase,
+    /// `false` for unknown models: every slot is accepted and the lists are empty.
+    pub restricted: bool,
+    pub sides: Vec<EarSide>,
+    pub gestures: Vec<GestureType>,
+    pub actions: Vec<GestureAction>,
+}
+
+impl GestureCapabilities {
+    pub fn unrestricted(model: ModelBase) -> Self {
+        Self {
+            model,
+            restricted: false,
+            sides: Vec::new(),
+            gestures: Vec::new(),
+            actions: Vec::new(),
+        }
+    }
+
+    /// Checks the side, gesture and action bytes of `slot`. Only named
+    /// values the model doesn't list are rejected; bytes without a known
+    /// name are passed through as raw values, since the tables can't say
+    /// anything about them.
+    pub fn check(&self, slot: &GestureSlot) -> Result<(), &'static str> {
+        if !self.restricted {
+            return Ok(());
+        }
+        let side = EarSide::from_device_id(slot.device);
+        if side.is_some_and(|side| !self.sides.contains(&side)) {
+            return Err("gesture on this side");
+        }
+        let gesture = GestureType::from_device(slot.gesture_type);
+        if gesture.is_some_and(|gesture| !self.gestures.contains(&gesture)) {
+            return Err("this gesture type");
+        }
+        let action = GestureAction::from_device(slot.action);
+        if action.is_some_and(|action| !self.actions.contains(&action)) {
+            return Err("this gesture action");
+        }
+        Ok(())
+    }
+}
+
+/// Version written into [`GestureBackup`] documents.
+/// Version 2 added `model_base` (formerly `model`) and the decoded names.
+/// Version 1 backups are still accepted.
+pub const GESTURE_BACKUP_VERSION: u32 = 2;
+
+/// Portable dump of every gesture slot, produced by `GET /api/gestures/export`
+/// and accepted by `POST /api/gestures/import`. The raw bytes are what gets
+/// written back; the names make the file reviewable and catch backups taken
+/// from another model.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GestureBackup {
+    pub version: u32,
+    #[serde(default, alias = "model")]
+    pub model_base: Option<ModelBase>,
+    pub gestures: Vec<GestureConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GestureImportResult {
+    pub slot: GestureSlot,
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GestureImportReport {
+    pub applied: usize,
+    pub failed: usize,
+    pub results: Vec<GestureImportResult>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct LedColor(pub [u8; 3]);
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Case LED state on the Ear (1). The case also has a brightness and an
+/// animation pattern, but where they sit in the payload is unknown, so reads
+/// never report them and writes that set them are rejected.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct LedColorSet {
     pub pixels: Vec<LedColor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub brightness: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<u8>,
+}
+
+/// Snapshot gathered by [`init_device`](crate::EarSessionHandle::init_device);
+/// a field is `None` when that read failed or isn't supported.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct DeviceState {
+    pub battery: Option<BatteryStatus>,
+    pub eq: Option<EqMode>,
+    pub in_ear: Option<InEarState>,
+    pub latency: Option<LatencyState>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct SerialIdentity {
     pub serial_number: Option<String>,
     pub sku: Option<String>,
     pub model_id: Option<String>,
+    /// How the model was identified; `None` when it wasn't.
+    #[serde(default)]
+    pub source: Option<DetectionSource>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DetectionSource {
+    /// SKU derived from the serial number reported by the buds.
+    Serial,
+    /// Fallback match on the Bluetooth device name.
+    BluetoothName,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ModelSummary {
     pub id: Option<String>,
     pub name: Option<String>,
@@ -201,9 +924,371 @@ pub struct ModelSummary {
     pub base: ModelBase,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// RFCOMM channel to connect on: a number, or `"auto"` to look it up with
+/// SDP first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "ChannelRepr", into = "ChannelRepr")]
+pub enum RfcommChannel {
+    Fixed(u8),
+    Auto,
+}
+
+/// Wire form of [`RfcommChannel`]: `1` or `"auto"` (`"1"` is accepted too).
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum ChannelRepr {
+    Number(u8),
+    Text(String),
+}
+
+impl TryFrom<ChannelRepr> for RfcommChannel {
+    type Error = &'static str;
+
+    fn try_from(repr: ChannelRepr) -> Result<Self, Self::Error> {
+        match repr {
+            ChannelRepr::Number(channel) => Ok(Self::Fixed(channel)),
+            ChannelRepr::Text(text) => text.parse(),
+        }
+    }
+}
+
+impl From<RfcommChannel> for ChannelRepr {
+    fn from(channel: RfcommChannel) -> Self {
+        match channel {
+            RfcommChannel::Fixed(channel) => Self::Number(channel),
+            RfcommChannel::Auto => Self::Text("auto".to_string()),
+        }
+    }
+}
+
+impl JsonSchema for RfcommChannel {
+    fn schema_name() -> String {
+        "RfcommChannel".to_string()
+    }
+
+    fn json_schema(generator: &mut schemars::r#gen::SchemaGenerator) -> schemars::schema::Schema {
+        ChannelRepr::json_schema(generator)
+    }
+}
+
+impl fmt::Display for RfcommChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fixed(channel) => write!(f, "{}", channel),
+            Self::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+impl FromStr for RfcommChannel {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Self::Auto);
+        }
+        s.parse()
+            .map(Self::Fixed)
+            .map_err(|_| "channel must be 0-255 or \"auto\"")
+    }
+}
+
+/// Why a session's connection ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DisconnectReason {
+    /// The buds closed the RFCOMM stream, e.g. they were docked or powered off.
+    RemoteClosed,
+    /// The session was closed through the API.
+    Closed,
+    /// Heartbeats kept failing; see `--heartbeat-failures`.
+    Unresponsive,
+    /// No API activity within `--idle-timeout-mins`.
+    Idle,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct SessionInfo {
     pub id: Uuid,
     pub port_path: String,
     pub model: Option<ModelSummary>,
+    /// Last operation id sent; `None` while a command is in flight.
+    #[serde(default)]
+    pub operation_id: Option<u8>,
+    /// Requests currently holding or waiting for the device connection.
+    #[serde(default)]
+    pub queue_depth: usize,
+    /// User-supplied name for this session, e.g. "office buds".
+    #[serde(default)]
+    pub label: Option<String>,
+    /// Unix time in milliseconds of the last packet from the device.
+    #[serde(default)]
+    pub last_seen_ms: Option<u64>,
+    /// `false` once the link is gone; the session stays until it is closed.
+    #[serde(default = "connected_default")]
+    pub connected: bool,
+    /// Window in which ANC and custom EQ writes are coalesced; `None` when
+    /// every write is sent.
+    #[serde(default)]
+    pub write_debounce_ms: Option<u64>,
+    /// Frames from the buds dropped for a bad CRC.
+    #[serde(default)]
+    pub crc_errors: u64,
+}
+
+fn connected_default() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use serde::{Serialize, de::DeserializeOwned};
+
+    use super::{
+        AncLevel, BatteryReading, BatteryStatus, DetectionSource, DisconnectReason, EarFitResult,
+        EarFitState, EarSide, EqMode, EqSource, FitQuality, GestureAction, GestureConfig,
+        GestureConflictPolicy, GestureSlot, GestureType, LatencyMode, LatencyState, RfcommChannel,
+    };
+    use crate::models::ModelBase;
+
+    fn round_trip<T>(value: T, json: serde_json::Value)
+    where
+        T: Serialize + DeserializeOwned + PartialEq + Debug,
+    {
+        assert_eq!(serde_json::to_value(&value).unwrap(), json);
+        assert_eq!(serde_json::from_value::<T>(json).unwrap(), value);
+    }
+
+    #[test]
+    fn eq_modes_are_checked_against_the_model_presets() {
+        let label = |mode, base| {
+            let eq = EqMode::new(mode, EqSource::Eq).labelled(base);
+            eq.known.then(|| eq.name.unwrap())
+        };
+        assert_eq!(label(3, ModelBase::B181).as_deref(), Some("More Bass"));
+        // The Ear (1) has no custom slot; the Ear (2) does.
+        assert_eq!(label(5, ModelBase::B181), None);
+        assert_eq!(label(5, ModelBase::B155).as_deref(), Some("Custom"));
+        // CMF buds number their presets differently.
+        assert_eq!(label(6, ModelBase::B163).as_deref(), Some("Classical"));
+        assert_eq!(label(6, ModelBase::B155), None);
+        assert_eq!(label(0xFF, ModelBase::B163), None);
+        assert_eq!(label(0, ModelBase::Unknown), None);
+
+        let listening = EqMode::new(1, EqSource::ListeningMode).labelled(ModelBase::B171);
+        assert!(!listening.known);
+        assert_eq!(listening.mode, 1);
+    }
+
+    #[test]
+    fn enums_round_trip_as_snake_case() {
+        use serde_json::json;
+
+        round_trip(EarSide::Left, json!("left"));
+        round_trip(EarSide::Right, json!("right"));
+        round_trip(EarSide::Case, json!("case"));
+
+        round_trip(AncLevel::Off, json!("off"));
+        round_trip(AncLevel::Transparency, json!("transparency"));
+        round_trip(
+            AncLevel::NoiseCancellationLow,
+            json!("noise_cancellation_low"),
+        );
+        round_trip(
+            AncLevel::NoiseCancellationMid,
+            json!("noise_cancellation_mid"),
+        );
+        round_trip(
+            AncLevel::NoiseCancellationHigh,
+            json!("noise_cancellation_high"),
+        );
+        round_trip(
+            AncLevel::NoiseCancellationAdaptive,
+            json!("noise_cancellation_adaptive"),
+        );
+
+        round_trip(EqSource::Eq, json!("eq"));
+        round_trip(EqSource::ListeningMode, json!("listening_mode"));
+
+        round_trip(LatencyMode::Normal, json!("normal"));
+        round_trip(LatencyMode::Low, json!("low"));
+
+        round_trip(FitQuality::Good, json!("good"));
+        round_trip(FitQuality::Adjust, json!("adjust"));
+        round_trip(FitQuality::Poor, json!("poor"));
+        round_trip(FitQuality::Pending, json!("pending"));
+
+        round_trip(EarFitState::Complete, json!("complete"));
+        round_trip(EarFitState::InProgress, json!("in_progress"));
+        round_trip(EarFitState::Cancelled, json!("cancelled"));
+
+        round_trip(GestureConflictPolicy::Off, json!("off"));
+        round_trip(GestureConflictPolicy::Warn, json!("warn"));
+        round_trip(GestureConflictPolicy::Reject, json!("reject"));
+
+        round_trip(GestureType::SingleTap, json!("single_tap"));
+        round_trip(GestureType::DoubleTap, json!("double_tap"));
+        round_trip(GestureType::TripleTap, json!("triple_tap"));
+        round_trip(GestureType::TapAndHold, json!("tap_and_hold"));
+        round_trip(GestureType::DoubleTapAndHold, json!("double_tap_and_hold"));
+
+        round_trip(GestureAction::NoAction, json!("no_action"));
+        round_trip(GestureAction::PlayPause, json!("play_pause"));
+        round_trip(GestureAction::NextTrack, json!("next_track"));
+        round_trip(GestureAction::PreviousTrack, json!("previous_track"));
+        round_trip(GestureAction::VoiceAssistant, json!("voice_assistant"));
+        round_trip(GestureAction::NoiseControl, json!("noise_control"));
+        round_trip(GestureAction::VolumeUp, json!("volume_up"));
+        round_trip(GestureAction::VolumeDown, json!("volume_down"));
+
+        round_trip(DetectionSource::Serial, json!("serial"));
+        round_trip(DetectionSource::BluetoothName, json!("bluetooth_name"));
+
+        round_trip(DisconnectReason::RemoteClosed, json!("remote_closed"));
+        round_trip(DisconnectReason::Closed, json!("closed"));
+        round_trip(DisconnectReason::Unresponsive, json!("unresponsive"));
+        round_trip(DisconnectReason::Idle, json!("idle"));
+
+        round_trip(RfcommChannel::Auto, json!("auto"));
+        round_trip(RfcommChannel::Fixed(5), json!(5));
+
+        round_trip(BatteryReading::Disconnected, json!("Disconnected"));
+        round_trip(
+            BatteryReading::Level {
+                percent: 80,
+                charging: true,
+            },
+            json!({ "Level": { "percent": 80, "charging": true } }),
+        );
+        let snake_case: BatteryReading =
+            serde_json::from_value(json!({ "level": { "percent": 5, "charging": false } }))
+                .unwrap();
+        assert_eq!(
+            snake_case,
+            BatteryReading::Level {
+                percent: 5,
+                charging: false
+            }
+        );
+    }
+
+    #[test]
+    fn rfcomm_channel_accepts_numbers_and_auto() {
+        let parse = |value| serde_json::from_value::<RfcommChannel>(value);
+        assert_eq!(
+            parse(serde_json::json!(3)).unwrap(),
+            RfcommChannel::Fixed(3)
+        );
+        assert_eq!(
+            parse(serde_json::json!("4")).unwrap(),
+            RfcommChannel::Fixed(4)
+        );
+        assert_eq!(
+            parse(serde_json::json!("AUTO")).unwrap(),
+            RfcommChannel::Auto
+        );
+        assert!(parse(serde_json::json!("next")).is_err());
+        assert!(parse(serde_json::json!(300)).is_err());
+        assert_eq!(
+            serde_json::to_value(RfcommChannel::Auto).unwrap(),
+            serde_json::json!("auto")
+        );
+    }
+
+    #[test]
+    fn charging_sides_are_not_reported_low() {
+        let mut status = BatteryStatus::empty();
+        status.left = BatteryReading::Level {
+            percent: 10,
+            charging: false,
+        };
+        status.right = BatteryReading::Level {
+            percent: 10,
+            charging: true,
+        };
+        status.case = BatteryReading::Level {
+            percent: 20,
+            charging: false,
+        };
+        assert_eq!(status.sides_below(20), vec![EarSide::Left]);
+        assert_eq!(status.sides_below(21), vec![EarSide::Left, EarSide::Case]);
+    }
+
+    #[test]
+    fn gesture_names_round_trip_through_device_bytes() {
+        for gesture in [
+            GestureType::SingleTap,
+            GestureType::DoubleTap,
+            GestureType::TripleTap,
+            GestureType::TapAndHold,
+            GestureType::DoubleTapAndHold,
+        ] {
+            assert_eq!(GestureType::from_device(gesture.to_device()), Some(gesture));
+            assert_eq!(gesture.to_string().parse::<GestureType>(), Ok(gesture));
+        }
+        for action in [
+            GestureAction::NoAction,
+            GestureAction::PlayPause,
+            GestureAction::NextTrack,
+            GestureAction::PreviousTrack,
+            GestureAction::VoiceAssistant,
+            GestureAction::NoiseControl,
+            GestureAction::VolumeUp,
+            GestureAction::VolumeDown,
+        ] {
+            assert_eq!(GestureAction::from_device(action.to_device()), Some(action));
+            assert_eq!(action.to_string().parse::<GestureAction>(), Ok(action));
+        }
+        assert!(GestureAction::from_device(0x12).is_some_and(GestureAction::is_volume));
+        assert!(!GestureAction::PlayPause.is_volume());
+    }
+
+    #[test]
+    fn gesture_config_keeps_unmapped_bytes() {
+        let config = GestureConfig::from(GestureSlot {
+            device: 0x02,
+            common: 0x01,
+            gesture_type: 0x7F,
+            action: 0x02,
+        });
+        assert!(matches!(config.side, Some(EarSide::Left)));
+        assert_eq!(config.gesture_name, None);
+        assert_eq!(config.action_name, Some(GestureAction::PlayPause));
+        assert_eq!(config.slot.gesture_type, 0x7F);
+    }
+
+    #[test]
+    fn ear_fit_result_maps_raw_bytes() {
+        let result = EarFitResult::from_raw(0x00, 0x02);
+        assert_eq!(result.left, FitQuality::Good);
+        assert_eq!(result.right, FitQuality::Poor);
+        assert!(result.complete);
+
+        let running = EarFitResult::from_raw(0x01, 0xFF);
+        assert_eq!(running.left, FitQuality::Adjust);
+        assert_eq!(running.right, FitQuality::Pending);
+        assert!(!running.complete);
+    }
+
+    #[test]
+    fn latency_mode_payloads_round_trip() {
+        assert_eq!(LatencyMode::Normal.to_payload(), [0x02, 0x00]);
+        assert_eq!(LatencyMode::Low.to_payload(), [0x01, 0x00]);
+        for mode in [LatencyMode::Normal, LatencyMode::Low] {
+            assert_eq!(LatencyMode::from_device(mode.to_payload()[0]), Some(mode));
+        }
+        assert_eq!(LatencyMode::from_device(0x00), Some(LatencyMode::Normal));
+        assert_eq!(LatencyMode::from_device(0x07), None);
+    }
+
+    #[test]
+    fn latency_state_keeps_simple_flag() {
+        assert!(!LatencyState::from_mode(LatencyMode::Normal).low_latency_enabled);
+        assert!(LatencyState::from_mode(LatencyMode::Low).low_latency_enabled);
+        let unknown = LatencyState::from_device(0x07);
+        assert!(!unknown.low_latency_enabled);
+        assert_eq!(unknown.mode, None);
+    }
 }
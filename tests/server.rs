@@ -0,0 +1,253 @@
+//! Drives `server::router` end to end against an in-memory device: requests
+//! go through routing, the session's command queue and the wire protocol,
+//! and come back through the error mapping.
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    http::{Request, StatusCode},
+};
+use ear_api::{
+    AncLevel, AncStatus, ApiState, BatteryReading, BatteryStatus, EarConnection, EarManager,
+    EqMode, ModelBase, ProfileStore,
+    protocol::{EarPacket, command, response},
+    server::router,
+};
+use serde_json::{Value, json};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, DuplexStream},
+    sync::Mutex,
+};
+use tower::ServiceExt;
+
+/// What the fake buds currently have set, as raw device bytes.
+#[derive(Debug)]
+struct BudsState {
+    anc: u8,
+    eq: u8,
+}
+
+/// Answers reads from `state` and applies writes to it, like the buds on the
+/// far end of the RFCOMM socket. Set commands aren't acknowledged.
+async fn run_buds(device: DuplexStream, state: Arc<Mutex<BudsState>>) {
+    let (mut reader, mut writer) = tokio::io::split(device);
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 256];
+    loop {
+        let n = match reader.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => n,
+        };
+        buffer.extend_from_slice(&chunk[..n]);
+        while let Some(packet) = EarPacket::try_parse(&mut buffer).unwrap() {
+            let mut state = state.lock().await;
+            let reply = match packet.command {
+                command::REQUEST_BATTERY => Some((
+                    response::BATTERY_PRIMARY,
+                    vec![3, 0x02, 80, 0x03, 0x80 | 60, 0x04, 45],
+                )),
+                command::REQUEST_ANC => Some((response::ANC_PRIMARY, vec![0x01, state.anc])),
+                command::REQUEST_EQ => Some((response::EQ_PRIMARY, vec![state.eq])),
+                command::CMD_SET_ANC => {
+                    state.anc = packet.payload[1];
+                    None
+                }
+                command::CMD_SET_EQ => {
+                    state.eq = packet.payload[0];
+                    None
+                }
+                _ => None,
+            };
+            if let Some((command, payload)) = reply {
+                let frame = EarPacket::encode(command, packet.operation_id, &payload);
+                if writer.write_all(&frame).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn api_state(manager: Arc<EarManager>) -> ApiState {
+    ApiState {
+        manager,
+        unsafe_raw: false,
+        auth_token: None,
+        auth_exempt: Vec::new(),
+        cors_origins: Vec::new(),
+        shutdown_timeout: Duration::from_secs(1),
+        shell_retries: 0,
+        profiles: Arc::new(ProfileStore::in_memory()),
+    }
+}
+
+/// A router with a B181 session attached to fake buds that start with ANC
+/// off and EQ preset 0.
+async fn connected() -> (Router, Arc<Mutex<BudsState>>) {
+    let manager = Arc::new(
+        EarManager::builder()
+            .timeout(Duration::from_millis(500))
+            .build(),
+    );
+    let (device, host) = tokio::io::duplex(4096);
+    let (host_read, host_write) = tokio::io::split(host);
+    let session = manager
+        .attach(EarConnection::from_io("mock", host_read, host_write))
+        .await
+        .unwrap();
+    session.set_model_base(ModelBase::B181).await;
+
+    let state = Arc::new(Mutex::new(BudsState {
+        anc: AncLevel::Off.to_device(),
+        eq: 0,
+    }));
+    tokio::spawn(run_buds(device, state.clone()));
+    (router(api_state(manager)), state)
+}
+
+async fn call(app: &Router, method: &str, uri: &str, body: Option<Value>) -> (StatusCode, Value) {
+    let (status, bytes) = send(app, Request::builder().method(method).uri(uri), body).await;
+    let body = if bytes.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_slice(&bytes).unwrap()
+    };
+    (status, body)
+}
+
+async fn send(
+    app: &Router,
+    request: axum::http::request::Builder,
+    body: Option<Value>,
+) -> (StatusCode, axum::body::Bytes) {
+    let request = match body {
+        Some(body) => request
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string())),
+        None => request.body(Body::empty()),
+    }
+    .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    (
+        status,
+        to_bytes(response.into_body(), usize::MAX).await.unwrap(),
+    )
+}
+
+#[tokio::test]
+async fn battery_is_read_from_the_device() {
+    let (app, _) = connected().await;
+    let (status, body) = call(&app, "GET", "/api/battery?fresh=true", None).await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    let battery: BatteryStatus = serde_json::from_value(body).unwrap();
+    assert_eq!(
+        battery.left,
+        BatteryReading::Level {
+            percent: 80,
+            charging: false
+        }
+    );
+    assert_eq!(
+        battery.right,
+        BatteryReading::Level {
+            percent: 60,
+            charging: true
+        }
+    );
+}
+
+#[tokio::test]
+async fn anc_and_eq_writes_reach_the_device_and_read_back() {
+    let (app, buds) = connected().await;
+
+    let (status, body) = call(
+        &app,
+        "POST",
+        "/api/anc",
+        Some(json!({ "level": "transparency" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    let (status, body) = call(&app, "POST", "/api/eq", Some(json!({ "mode": 2 }))).await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+
+    let (_, body) = call(&app, "GET", "/api/anc", None).await;
+    let anc: AncStatus = serde_json::from_value(body).unwrap();
+    assert_eq!(anc.level, AncLevel::Transparency);
+    let (_, body) = call(&app, "GET", "/api/eq", None).await;
+    let eq: EqMode = serde_json::from_value(body).unwrap();
+    assert_eq!(eq.mode, 2);
+    assert_eq!(eq.name.as_deref(), Some("More Treble"));
+
+    // The reads were answered after the writes, so the buds have both.
+    let buds = buds.lock().await;
+    assert_eq!(buds.anc, AncLevel::Transparency.to_device());
+    assert_eq!(buds.eq, 2);
+}
+
+#[tokio::test]
+async fn concurrent_requests_share_the_session() {
+    let (app, _) = connected().await;
+    let (battery, anc, eq, set) = tokio::join!(
+        call(&app, "GET", "/api/battery?fresh=true", None),
+        call(&app, "GET", "/api/anc?fresh=true", None),
+        call(&app, "GET", "/api/eq?fresh=true", None),
+        call(&app, "POST", "/api/eq", Some(json!({ "mode": 1 }))),
+    );
+    for (status, body) in [battery, anc, eq, set] {
+        assert_eq!(status, StatusCode::OK, "{body}");
+    }
+}
+
+#[tokio::test]
+async fn errors_map_to_status_codes() {
+    let app = router(api_state(Arc::new(EarManager::new())));
+    let (status, body) = call(&app, "GET", "/api/battery", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "no_session");
+
+    let (app, _) = connected().await;
+    let (status, body) = call(&app, "GET", "/api/enhanced-bass", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "unsupported");
+
+    // The Ear (1) has no mid noise cancellation level.
+    let (status, body) = call(
+        &app,
+        "POST",
+        "/api/anc",
+        Some(json!({ "level": "noise_cancellation_mid" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+    assert_eq!(body["code"], "invalid_input");
+}
+
+#[tokio::test]
+async fn msgpack_is_returned_when_accepted() {
+    let (app, _) = connected().await;
+    let accept = |uri: &str| {
+        Request::builder()
+            .uri(uri)
+            .header("accept", "application/x-msgpack")
+    };
+    let (status, bytes) = send(&app, accept("/api/battery?fresh=true"), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let battery: BatteryStatus = rmp_serde::from_slice(&bytes).unwrap();
+    assert_eq!(
+        battery.left,
+        BatteryReading::Level {
+            percent: 80,
+            charging: false
+        }
+    );
+
+    let app = router(api_state(Arc::new(EarManager::new())));
+    let (status, bytes) = send(&app, accept("/api/battery"), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let error: Value = rmp_serde::from_slice(&bytes).unwrap();
+    assert_eq!(error["code"], "no_session");
+}